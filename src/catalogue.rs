@@ -0,0 +1,147 @@
+use crate::generic::CRCProperties;
+
+/// Named CRC model, with its checksum of the ASCII string `123456789`.
+pub struct Model {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub props: CRCProperties,
+    pub check: u64,
+}
+
+/// Helper to declare catalogue entries.
+const fn model(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    (width, g, i, refin, refout, f): (u32, u64, u64, bool, bool, u64),
+    check: u64,
+) -> Model {
+    Model {
+        name,
+        aliases,
+        props: CRCProperties {
+            width,
+            g,
+            i,
+            f,
+            refin,
+            refout,
+        },
+        check,
+    }
+}
+
+/// Catalogue of well-known CRC models, from the CRC RevEng catalogue.
+/// Parameters are given as `(width, poly, init, refin, refout, xorout)`.
+#[rustfmt::skip]
+pub const CATALOGUE: &[Model] = &[
+    model("CRC-8/AUTOSAR", &[], (8, 0x2f, 0xff, false, false, 0xff), 0xdf),
+    model("CRC-8/BLUETOOTH", &[], (8, 0xa7, 0x00, true, true, 0x00), 0x26),
+    model("CRC-8/CDMA2000", &[], (8, 0x9b, 0xff, false, false, 0x00), 0xda),
+    model("CRC-8/DARC", &[], (8, 0x39, 0x00, true, true, 0x00), 0x15),
+    model("CRC-8/DVB-S2", &[], (8, 0xd5, 0x00, false, false, 0x00), 0xbc),
+    model("CRC-8/GSM-A", &[], (8, 0x1d, 0x00, false, false, 0x00), 0x37),
+    model("CRC-8/GSM-B", &[], (8, 0x49, 0x00, false, false, 0xff), 0x94),
+    model("CRC-8/HITAG", &[], (8, 0x1d, 0xff, false, false, 0x00), 0xb4),
+    model("CRC-8/I-432-1", &[], (8, 0x07, 0x00, false, false, 0x55), 0xa1),
+    model("CRC-8/I-CODE", &[], (8, 0x1d, 0xfd, false, false, 0x00), 0x7e),
+    model("CRC-8/LTE", &[], (8, 0x9b, 0x00, false, false, 0x00), 0xea),
+    model("CRC-8/MAXIM-DOW", &[], (8, 0x31, 0x00, true, true, 0x00), 0xa1),
+    model("CRC-8/MIFARE-MAD", &[], (8, 0x1d, 0xc7, false, false, 0x00), 0x99),
+    model("CRC-8/NRSC-5", &[], (8, 0x31, 0xff, false, false, 0x00), 0xf7),
+    model("CRC-8/OPENSAFETY", &[], (8, 0x2f, 0x00, false, false, 0x00), 0x3e),
+    model("CRC-8/ROHC", &[], (8, 0x07, 0xff, true, true, 0x00), 0xd0),
+    model("CRC-8/SAE-J1850", &[], (8, 0x1d, 0xff, false, false, 0xff), 0x4b),
+    model("CRC-8/SMBUS", &["CRC-8"], (8, 0x07, 0x00, false, false, 0x00), 0xf4),
+    model("CRC-8/TECH-3250", &[], (8, 0x1d, 0xff, true, true, 0x00), 0x97),
+    model("CRC-8/WCDMA", &[], (8, 0x9b, 0x00, true, true, 0x00), 0x25),
+    model("CRC-16/ARC", &["CRC-16"], (16, 0x8005, 0x0000, true, true, 0x0000), 0xbb3d),
+    model("CRC-16/CDMA2000", &[], (16, 0xc867, 0xffff, false, false, 0x0000), 0x4c06),
+    model("CRC-16/CMS", &[], (16, 0x8005, 0xffff, false, false, 0x0000), 0xaee7),
+    model("CRC-16/DDS-110", &[], (16, 0x8005, 0x800d, false, false, 0x0000), 0x9ecf),
+    model("CRC-16/DECT-R", &[], (16, 0x0589, 0x0000, false, false, 0x0001), 0x007e),
+    model("CRC-16/DECT-X", &[], (16, 0x0589, 0x0000, false, false, 0x0000), 0x007f),
+    model("CRC-16/DNP", &[], (16, 0x3d65, 0x0000, true, true, 0xffff), 0xea82),
+    model("CRC-16/EN-13757", &[], (16, 0x3d65, 0x0000, false, false, 0xffff), 0xc2b7),
+    model("CRC-16/GENIBUS", &[], (16, 0x1021, 0xffff, false, false, 0xffff), 0xd64e),
+    model("CRC-16/GSM", &[], (16, 0x1021, 0x0000, false, false, 0xffff), 0xce3c),
+    model("CRC-16/IBM-3740", &["CRC-16/CCITT-FALSE"], (16, 0x1021, 0xffff, false, false, 0x0000), 0x29b1),
+    model("CRC-16/IBM-SDLC", &["CRC-16/X-25"], (16, 0x1021, 0xffff, true, true, 0xffff), 0x906e),
+    model("CRC-16/ISO-IEC-14443-3-A", &[], (16, 0x1021, 0xc6c6, true, true, 0x0000), 0xbf05),
+    model("CRC-16/KERMIT", &["CRC-16/CCITT"], (16, 0x1021, 0x0000, true, true, 0x0000), 0x2189),
+    model("CRC-16/LJ1200", &[], (16, 0x6f63, 0x0000, false, false, 0x0000), 0xbdf4),
+    model("CRC-16/M17", &[], (16, 0x5935, 0xffff, false, false, 0x0000), 0x772b),
+    model("CRC-16/MAXIM-DOW", &[], (16, 0x8005, 0x0000, true, true, 0xffff), 0x44c2),
+    model("CRC-16/MCRF4XX", &[], (16, 0x1021, 0xffff, true, true, 0x0000), 0x6f91),
+    model("CRC-16/MODBUS", &[], (16, 0x8005, 0xffff, true, true, 0x0000), 0x4b37),
+    model("CRC-16/NRSC-5", &[], (16, 0x080b, 0xffff, true, true, 0x0000), 0xa066),
+    model("CRC-16/OPENSAFETY-A", &[], (16, 0x5935, 0x0000, false, false, 0x0000), 0x5d38),
+    model("CRC-16/OPENSAFETY-B", &[], (16, 0x755b, 0x0000, false, false, 0x0000), 0x20fe),
+    model("CRC-16/PROFIBUS", &[], (16, 0x1dcf, 0xffff, false, false, 0xffff), 0xa819),
+    model("CRC-16/RIELLO", &[], (16, 0x1021, 0xb2aa, true, true, 0x0000), 0x63d0),
+    model("CRC-16/SPI-FUJITSU", &[], (16, 0x1021, 0x1d0f, false, false, 0x0000), 0xe5cc),
+    model("CRC-16/T10-DIF", &[], (16, 0x8bb7, 0x0000, false, false, 0x0000), 0xd0db),
+    model("CRC-16/TELEDISK", &[], (16, 0xa097, 0x0000, false, false, 0x0000), 0x0fb3),
+    model("CRC-16/TMS37157", &[], (16, 0x1021, 0x89ec, true, true, 0x0000), 0x26b1),
+    model("CRC-16/UMTS", &[], (16, 0x8005, 0x0000, false, false, 0x0000), 0xfee8),
+    model("CRC-16/USB", &[], (16, 0x8005, 0xffff, true, true, 0xffff), 0xb4c8),
+    model("CRC-16/XMODEM", &[], (16, 0x1021, 0x0000, false, false, 0x0000), 0x31c3),
+    model("CRC-32/AIXM", &[], (32, 0x814141ab, 0x00000000, false, false, 0x00000000), 0x3010bf7f),
+    model("CRC-32/AUTOSAR", &[], (32, 0xf4acfb13, 0xffffffff, true, true, 0xffffffff), 0x1697d06a),
+    model("CRC-32/BASE91-D", &[], (32, 0xa833982b, 0xffffffff, true, true, 0xffffffff), 0x87315576),
+    model("CRC-32/BZIP2", &[], (32, 0x04c11db7, 0xffffffff, false, false, 0xffffffff), 0xfc891918),
+    model("CRC-32/CD-ROM-EDC", &[], (32, 0x8001801b, 0x00000000, true, true, 0x00000000), 0x6ec2edc4),
+    model("CRC-32/CKSUM", &[], (32, 0x04c11db7, 0x00000000, false, false, 0xffffffff), 0x765e7680),
+    model("CRC-32/ISCSI", &["CRC-32C"], (32, 0x1edc6f41, 0xffffffff, true, true, 0xffffffff), 0xe3069283),
+    model("CRC-32/ISO-HDLC", &["CRC-32"], (32, 0x04c11db7, 0xffffffff, true, true, 0xffffffff), 0xcbf43926),
+    model("CRC-32/JAMCRC", &[], (32, 0x04c11db7, 0xffffffff, true, true, 0x00000000), 0x340bc6d9),
+    model("CRC-32/MEF", &[], (32, 0x741b8cd7, 0xffffffff, true, true, 0x00000000), 0xd2c22f51),
+    model("CRC-32/MPEG-2", &[], (32, 0x04c11db7, 0xffffffff, false, false, 0x00000000), 0x0376e6e7),
+    model("CRC-32/XFER", &[], (32, 0x000000af, 0x00000000, false, false, 0x00000000), 0xbd0be338),
+    model("CRC-64/ECMA-182", &["CRC-64"], (64, 0x42f0e1eba9ea3693, 0x0000000000000000, false, false, 0x0000000000000000), 0x6c40df5f0b497347),
+    model("CRC-64/GO-ISO", &[], (64, 0x000000000000001b, 0xffffffffffffffff, true, true, 0xffffffffffffffff), 0xb90956c775a41001),
+    model("CRC-64/MS", &[], (64, 0x259c84cba6426349, 0xffffffffffffffff, true, true, 0x0000000000000000), 0x75d4b74f024eceea),
+    model("CRC-64/NVME", &[], (64, 0xad93d23594c93659, 0xffffffffffffffff, true, true, 0xffffffffffffffff), 0xae8b14860a799888),
+    model("CRC-64/REDIS", &[], (64, 0xad93d23594c935a9, 0x0000000000000000, true, true, 0x0000000000000000), 0xe9c6d914c4b8d9ca),
+    model("CRC-64/WE", &[], (64, 0x42f0e1eba9ea3693, 0xffffffffffffffff, false, false, 0xffffffffffffffff), 0x62ec59e3f1a4f00a),
+    model("CRC-64/XZ", &[], (64, 0x42f0e1eba9ea3693, 0xffffffffffffffff, true, true, 0xffffffffffffffff), 0x995dc9bbdf1939fa),
+];
+
+/// Find model by name or alias, ignoring case.
+pub fn find(name: &str) -> Option<&'static Model> {
+    CATALOGUE.iter().find(|model| {
+        model.name.eq_ignore_ascii_case(name)
+            || model
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::catalogue::{CATALOGUE, find};
+    use crate::generic::CRC;
+
+    #[test]
+    pub fn test_catalogue_check() {
+        for model in CATALOGUE {
+            let crc = CRC::new(model.props).unwrap();
+            assert_eq!(
+                crc.checksum(b"123456789".to_owned().bytes())
+                    .expect("Failed to compute checksum"),
+                model.check,
+                "{}",
+                model.name
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_find() {
+        assert_eq!(find("crc-32c").unwrap().name, "CRC-32/ISCSI");
+        assert_eq!(find("CRC-16/MODBUS").unwrap().check, 0x4b37);
+        assert!(find("CRC-17").is_none());
+    }
+}
@@ -1,15 +1,18 @@
-use std::{fmt::Debug, u32};
+use std::fmt::{Debug, Display};
 
 use crate::error::{CRCResult, Error};
 use crate::math::{Polynomial, PolynomialRepr, reverse_u32};
 
 const CRC32_LOOKUP_SIZE: usize = 0x100;
 
-/// CRC32 properties: generator polynomial and init and final xor values.
+/// CRC32 properties: generator polynomial, init and final xor values and reflection settings, as in Rocksoft notation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct CRC32Properties {
     pub g: u32, // Generator polynomial with highest term is stripped (implicitely of degree 32), as usually given
     pub i: u32, // I, value initially xored with input
     pub f: u32, // F, value eventually xored with output
+    pub refin: bool, // Input bytes are processed LSB first
+    pub refout: bool, // Register is reflected before final xor
 }
 
 impl Default for CRC32Properties {
@@ -18,13 +21,52 @@ impl Default for CRC32Properties {
             g: 0x04c11db7u32,
             i: 0xffffffff,
             f: 0xffffffff,
+            refin: true,
+            refout: true,
         }
     }
 }
 
+impl CRC32Properties {
+    /// CRC-32C (Castagnoli), as used by iSCSI, ext4, btrfs and SSE4.2.
+    pub fn castagnoli() -> Self {
+        Self {
+            g: 0x1edc6f41,
+            ..Self::default()
+        }
+    }
+}
+
+/// Display properties in Rocksoft notation.
+impl Display for CRC32Properties {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "width=32  poly=0x{:08x}  init=0x{:08x}  refin={}  refout={}  xorout=0x{:08x}",
+            self.g, self.i, self.refin, self.refout, self.f
+        )
+    }
+}
+
+/// Constant added to rotated CRCs by masking.
+const MASK_DELTA: u32 = 0xa282ead8;
+
+/// Mask CRC `crc` as LevelDB, Snappy framing and TFRecord do for stored CRC32C, so that computing the CRC of data
+/// holding CRCs does not give degenerate values. Masking is not linear over GF(2), so forging a masked CRC amounts to
+/// forging the unmasked one.
+pub fn mask(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(MASK_DELTA)
+}
+
+/// Unmask masked CRC `masked`.
+pub fn unmask(masked: u32) -> u32 {
+    masked.wrapping_sub(MASK_DELTA).rotate_left(15)
+}
+
 /// Fast CRC implementation over simple polynomial operations.
 pub struct CRC32 {
     props: CRC32Properties,          // Generator G
+    init: u32,                       // Initial register, in reverse representation
     g: Polynomial<u64>,              // Generator polynomial, not strippped
     table: [u32; CRC32_LOOKUP_SIZE], // 8 bit lookup side
     xn_inv: Polynomial<u32>,         // (X^N)-1 mod G
@@ -51,8 +93,8 @@ impl CRC32 {
     pub fn new(props: CRC32Properties) -> CRCResult<Self> {
         // Precompute table
         let mut table = [0u32; CRC32_LOOKUP_SIZE];
-        for i in 0..CRC32_LOOKUP_SIZE {
-            table[i] = precompute_table(i as u8, reverse_u32(props.g));
+        for (i, mask) in table.iter_mut().enumerate() {
+            *mask = precompute_table(i as u8, reverse_u32(props.g));
         }
 
         // Compute full generator polynomial
@@ -64,13 +106,14 @@ impl CRC32 {
 
         Ok(Self {
             props,
+            init: reverse_u32(props.i),
             g,
             table,
             xn_inv,
         })
     }
 
-    /**
+    /*
      * Fast checksum computation
      */
 
@@ -85,19 +128,43 @@ impl CRC32 {
 
     /// Efficiently compute remainder of data by generator polynomial using precomputed tables.
     /// `data` is the data to process and `reg` is the initial CRC register.
-    fn fast_rem<T>(&self, mut data: T, mut reg: u32) -> CRCResult<u32>
+    fn fast_rem<T>(&self, data: T, mut reg: u32) -> CRCResult<u32>
     where
         T: Iterator<Item = Result<u8, std::io::Error>>,
     {
         // Step through division
-        while let Some(b) = data.next() {
+        for b in data {
             self.step(&mut reg, b?);
         }
         Ok(reg)
     }
 
+    /// Map input byte `b` to its reverse representation, where LSB is the term of highest degree.
+    /// This is an involution, also used to map computed bytes back to data.
+    fn input(&self, b: u8) -> u8 {
+        match self.props.refin {
+            true => b,
+            false => b.reverse_bits(),
+        }
+    }
+
+    /// Map register `reg` to CRC output, applying output reflection and final XOR.
+    fn output(&self, reg: u32) -> u32 {
+        match self.props.refout {
+            true => reg ^ self.props.f,
+            false => reverse_u32(reg) ^ self.props.f,
+        }
+    }
+
+    /// Map CRC value `crc` back to its register, the inverse of `output`.
+    fn register(&self, crc: u32) -> u32 {
+        match self.props.refout {
+            true => crc ^ self.props.f,
+            false => reverse_u32(crc ^ self.props.f),
+        }
+    }
+
     /// Efficiently compute CRC32 checksum of `data` using precomputed tables.
-    /// Input data must be in little-endian representation.
     /// `data` is the data to process, `i` is the initial XOR mask, `f` is the final XOR mask.
     pub fn checksum<T>(&self, data: T) -> CRCResult<u32>
     where
@@ -105,25 +172,39 @@ impl CRC32 {
     {
         // CRC is remainder of data times X^N where N = deg(G)
         // In CRC32, N = 32 bits
-        let mut data = data.chain([const { Ok(0u8) }; 4]);
+        let mut data = data
+            .map(|b| b.map(|b| self.input(b)))
+            .chain([const { Ok(0u8) }; 4]);
 
         // First populate CRC register.
         // Append leading zeros to data polynomial if less than 4 bytes
         let mut reg = self.fast_rem(data.by_ref().take(4), 0)?;
 
         // XOR with initial register value
-        reg ^= self.props.i;
+        reg ^= self.init;
 
         // Compute remainder mod G
         reg = self.fast_rem(data, reg)?;
 
-        // XOR with final register value
-        reg ^= self.props.f;
+        // Reflect and XOR with final register value
+        Ok(self.output(reg))
+    }
 
-        Ok(reg)
+    /// Continue the CRC computation over `data`, `crc` being the CRC of the preceding data.
+    /// The CRC of a concatenation is thus computed piecewise, without buffering it.
+    pub fn update<T>(&self, crc: u32, data: T) -> CRCResult<u32>
+    where
+        T: Iterator<Item = Result<u8, std::io::Error>>,
+    {
+        let mut reg = self.register(crc);
+        for b in data {
+            let index = (reg ^ u32::from(self.input(b?))) & 0xff;
+            reg = (reg >> 8) ^ self.table[index as usize];
+        }
+        Ok(self.output(reg))
     }
 
-    /**
+    /*
      * Suffix computation
      */
 
@@ -141,12 +222,12 @@ impl CRC32 {
     where
         T: Iterator<Item = Result<u8, std::io::Error>>,
     {
-        let c = Polynomial::from(PolynomialRepr::Reverse(self.checksum(data)?));
-        let cp = Polynomial::from(PolynomialRepr::Reverse(target_crc));
-        let f = Polynomial::from(PolynomialRepr::Reverse(self.props.f));
+        // Work on registers R = C + F and R' = C' + F, before output reflection
+        let r = Polynomial::from(PolynomialRepr::Reverse(self.register(self.checksum(data)?)));
+        let rp = Polynomial::from(PolynomialRepr::Reverse(self.register(target_crc)));
         // Compute inserted data: (C' + F) X^N^-1 + C + F mod G
-        let res = self.generator_remainder((cp + f) * self.xn_inv);
-        Ok(res + c + f)
+        let res = self.generator_remainder(rp * self.xn_inv);
+        Ok(res + r)
     }
 
     /// Compute 4-byte suffix to `data` so that resulting CRC is `target_crc`.
@@ -157,10 +238,11 @@ impl CRC32 {
         Ok(self
             .compute_suffix_polynomial(data, target_crc)?
             .repr()
-            .to_le_bytes())
+            .to_le_bytes()
+            .map(|b| self.input(b)))
     }
 
-    /**
+    /*
      * Inserted data computation
      */
 
@@ -171,7 +253,7 @@ impl CRC32 {
     /// Returns the triple `(reg, suffix_rem, suffix_size)`.
     fn fast_pair_rem<T, U>(
         &self,
-        mut data: T,
+        data: T,
         init: &mut U,
         mut reg: u32,
     ) -> CRCResult<(u32, u32, usize)>
@@ -182,8 +264,8 @@ impl CRC32 {
         let mut size = 0;
         let mut reg_0 = 0;
         // Step through division
-        while let Some(b) = data.next() {
-            let b = b?;
+        for b in data {
+            let b = self.input(b?);
             self.step(&mut reg, b ^ init.next().unwrap_or(0));
             self.step(&mut reg_0, b);
             size += 1;
@@ -205,15 +287,14 @@ impl CRC32 {
         // Constraint: T cannot be cloned to seek in byte stream (network stream for example)
         // => C must be computed at the same time as the suffix and M (suffix size)
         // Iterate through data only once
-        let prefix = data.by_ref().take(offset).into_iter();
-        let mut i = self.props.i.to_le_bytes().into_iter();
+        let prefix = data.by_ref().take(offset);
+        let mut i = self.init.to_le_bytes().into_iter();
         let (reg, _, data_size) = self.fast_pair_rem(prefix, &mut i, 0)?;
         if data_size < offset {
             return Err(Error::OutOfBoundsError);
         }
         let (reg, suffix_rem, suffix_size) = self.fast_pair_rem(data, &mut i, reg)?;
         let (reg, _, _) = self.fast_pair_rem([const { Ok(0u8) }; 4].into_iter(), &mut i, reg)?;
-        let checksum = reg ^ self.props.f;
 
         // Prepare polynomials
         let suffix_poly = Polynomial::from(PolynomialRepr::Reverse(suffix_rem));
@@ -227,13 +308,13 @@ impl CRC32 {
             .try_into()
             .unwrap();
         let xn = Polynomial::from(PolynomialRepr::Normal(1u64 << 32));
-        let cp = Polynomial::from(PolynomialRepr::Reverse(target_crc));
-        let c = Polynomial::from(PolynomialRepr::Reverse(checksum));
-        let f = Polynomial::from(PolynomialRepr::Reverse(self.props.f));
+        // Work on registers R = C + F and R' = C' + F, before output reflection
+        let rp = Polynomial::from(PolynomialRepr::Reverse(self.register(target_crc)));
+        let r = Polynomial::from(PolynomialRepr::Reverse(reg));
 
         // Compute recipe formula
-        let inserted = self.generator_remainder((cp + f) * self.xn_inv);
-        let inserted = inserted + c + f;
+        let inserted = self.generator_remainder(rp * self.xn_inv);
+        let inserted = inserted + r;
         let inserted = inserted + suffix_poly;
         let inserted = inserted + self.generator_remainder(xn * suffix_poly);
         let inserted = self.generator_remainder(inserted * xm_inv);
@@ -249,7 +330,23 @@ impl CRC32 {
         Ok(self
             .compute_inserted_polynomial(data, offset, target_crc)?
             .repr()
-            .to_le_bytes())
+            .to_le_bytes()
+            .map(|b| self.input(b)))
+    }
+
+    /*
+     * Patched data computation
+     */
+
+    /// Compute 4 bytes overwriting `data` at offset `offset` so that resulting CRC is `target_crc`.
+    /// This is an insertion into `data` with the overwritten bytes left out.
+    pub fn compute_patch(&self, data: &[u8], offset: usize, target_crc: u32) -> CRCResult<[u8; 4]> {
+        let end = offset
+            .checked_add(4)
+            .filter(|end| *end <= data.len())
+            .ok_or(Error::OutOfBoundsError)?;
+        let kept = data[..offset].iter().chain(&data[end..]).map(|b| Ok(*b));
+        self.compute_inserted(kept, offset, target_crc)
     }
 }
 
@@ -258,7 +355,7 @@ impl Debug for CRC32 {
         writeln!(
             f,
             "Generator = {:?}",
-            Polynomial::from(PolynomialRepr::Normal(1u64 << 32)) + self.g.into()
+            Polynomial::from(PolynomialRepr::Normal(1u64 << 32)) + self.g
         )?;
         writeln!(f, "Table:")?;
         for i in 0..0x20 {
@@ -266,7 +363,7 @@ impl Debug for CRC32 {
             for j in 0..8 {
                 write!(f, "{:08x} ", self.table[i * 8 + j])?;
             }
-            writeln!(f, "")?;
+            writeln!(f)?;
         }
         Ok(())
     }
@@ -276,7 +373,7 @@ impl Debug for CRC32 {
 mod tests {
     use std::io::Read;
 
-    use crate::core::{CRC32, CRC32Properties};
+    use crate::core::{CRC32, CRC32Properties, mask, unmask};
     use crate::error::Error;
     use crate::math::{Polynomial, PolynomialRepr};
 
@@ -465,12 +562,7 @@ mod tests {
         let inserted = crc
             .compute_inserted(data.bytes(), offset, target_c)
             .expect("Failed to compute inserted data");
-        let edited_data = [
-            &data[..offset as usize],
-            &inserted[..],
-            &data[offset as usize..],
-        ]
-        .concat();
+        let edited_data = [&data[..offset], &inserted[..], &data[offset..]].concat();
         println!("edited = {:?}", edited_data);
         let new_c = crc
             .checksum(edited_data.bytes())
@@ -488,12 +580,7 @@ mod tests {
         let inserted = crc
             .compute_inserted(data.bytes(), offset, target_c)
             .expect("Failed to compute inserted data");
-        let edited_data = [
-            &data[..offset as usize],
-            &inserted[..],
-            &data[offset as usize..],
-        ]
-        .concat();
+        let edited_data = [&data[..offset], &inserted[..], &data[offset..]].concat();
         println!("edited = {:?}", edited_data);
         let new_c = crc
             .checksum(edited_data.bytes())
@@ -511,12 +598,7 @@ mod tests {
         let inserted = crc
             .compute_inserted(data.bytes(), offset, target_c)
             .expect("Failed to compute inserted data");
-        let edited_data = [
-            &data[..offset as usize],
-            &inserted[..],
-            &data[offset as usize..],
-        ]
-        .concat();
+        let edited_data = [&data[..offset], &inserted[..], &data[offset..]].concat();
         println!("edited = {:?}", edited_data);
         let new_c = crc
             .checksum(edited_data.bytes())
@@ -532,9 +614,112 @@ mod tests {
         let offset = data.len() + 1;
 
         let inserted = crc.compute_inserted(data.bytes(), offset, target_c);
-        assert!(inserted.is_err_and(|x| match x {
-            Error::OutOfBoundsError => true,
-            _ => false,
-        }));
+        assert!(inserted.is_err_and(|x| matches!(x, Error::OutOfBoundsError)));
+    }
+
+    #[test]
+    pub fn test_unreflected() {
+        let bzip2 = CRC32::new(CRC32Properties {
+            refin: false,
+            refout: false,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            bzip2
+                .checksum(b"123456789".to_owned().bytes())
+                .expect("Failed to compute checksum"),
+            0xfc891918
+        );
+        let mpeg2 = CRC32::new(CRC32Properties {
+            f: 0,
+            refin: false,
+            refout: false,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            mpeg2
+                .checksum(b"123456789".to_owned().bytes())
+                .expect("Failed to compute checksum"),
+            0x0376e6e7
+        );
+    }
+
+    #[test]
+    pub fn test_unreflected_forging() {
+        let crc = CRC32::new(CRC32Properties {
+            i: 0x12345678,
+            refin: false,
+            refout: false,
+            ..Default::default()
+        })
+        .unwrap();
+        let data = b"lorem ipsum".to_owned();
+        let target_c = 0x42424242;
+
+        let suffix = crc
+            .compute_suffix(data.bytes(), target_c)
+            .expect("Failed to compute suffix");
+        let data_suffixed = [&data[..], &suffix[..]].concat();
+        let new_c = crc
+            .checksum(data_suffixed.bytes())
+            .expect("Failed to compute checksum");
+        assert_eq!(new_c, target_c);
+
+        let offset = 5;
+        let inserted = crc
+            .compute_inserted(data.bytes(), offset, target_c)
+            .expect("Failed to compute inserted data");
+        let edited_data = [&data[..offset], &inserted[..], &data[offset..]].concat();
+        let new_c = crc
+            .checksum(edited_data.bytes())
+            .expect("Failed to compute checksum");
+        assert_eq!(new_c, target_c);
+    }
+
+    #[test]
+    pub fn test_patch() {
+        let crc = CRC32::new(CRC32Properties::default()).unwrap();
+        let mut data = b"lorem ipsum dolor sit amet".to_owned();
+        let target_c = 0x42424242;
+
+        let patch = crc
+            .compute_patch(&data, 6, target_c)
+            .expect("Failed to compute patch");
+        data[6..10].copy_from_slice(&patch);
+        let new_c = crc
+            .checksum(data.bytes())
+            .expect("Failed to compute checksum");
+        assert_eq!(new_c, target_c);
+        assert!(crc.compute_patch(&data, data.len() - 3, target_c).is_err());
+    }
+
+    #[test]
+    pub fn test_update() {
+        let data = b"lorem ipsum dolor sit amet";
+        let castagnoli = CRC32Properties::castagnoli();
+        let unreflected = CRC32Properties {
+            refin: false,
+            refout: false,
+            ..Default::default()
+        };
+        for props in [CRC32Properties::default(), castagnoli, unreflected] {
+            let crc = CRC32::new(props).unwrap();
+            let head = crc.checksum(data[..10].bytes()).unwrap();
+            let c = crc.update(head, data[10..].bytes()).unwrap();
+            assert_eq!(c, crc.checksum(data.bytes()).unwrap());
+        }
+        let crc = CRC32::new(castagnoli).unwrap();
+        assert_eq!(crc.checksum(b"123456789".bytes()).unwrap(), 0xe3069283);
+    }
+
+    #[test]
+    pub fn test_mask() {
+        assert_eq!(mask(0), 0xa282ead8);
+        assert_eq!(mask(0xe3069283), 0xc78ab0e5);
+        for crc in [0, 1, 0x7fff, 0xe3069283, 0xffffffff] {
+            assert_eq!(unmask(mask(crc)), crc);
+        }
     }
 }
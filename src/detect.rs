@@ -0,0 +1,563 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{BufRead, Read},
+};
+
+use crate::catalogue::{CATALOGUE, Model};
+use crate::error::{CRCResult, Error};
+use crate::generic::CRC;
+use crate::recover::{RecoveredModel, Sample, decode_hex, recover_models};
+
+/// Highest number of bytes tried after the CRC field, when its end position is unknown.
+const MAX_TRAILER: usize = 8;
+
+/// Highest number of bytes tried before the CRC field or the range it covers, in corpus analysis.
+const MAX_HEADER: usize = 8;
+
+/// Highest number of samples used for polynomial recovery, the whole corpus being only used to check candidates.
+const MAX_RECOVERY_SAMPLES: usize = 16;
+
+/// CRC widths tried, in bits.
+const WIDTHS: [u32; 4] = [8, 16, 32, 64];
+
+/// Least amount of evidence for a fully known model to be plausible, in bits.
+const MIN_EVIDENCE: u32 = 32;
+
+/// Byte order of a CRC field.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Endianness {
+    Big,
+    Little,
+}
+
+impl Endianness {
+    /// Byte orders a field of `size` bytes can be stored with.
+    pub fn candidates(size: usize) -> &'static [Endianness] {
+        match size {
+            1 => &[Endianness::Big],
+            _ => &[Endianness::Big, Endianness::Little],
+        }
+    }
+
+    /// Read unsigned integer from `bytes`.
+    pub fn read(&self, bytes: &[u8]) -> u64 {
+        let fold = |acc, b: &u8| (acc << 8) | u64::from(*b);
+        match self {
+            Endianness::Big => bytes.iter().fold(0, fold),
+            Endianness::Little => bytes.iter().rev().fold(0, fold),
+        }
+    }
+}
+
+impl Display for Endianness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endianness::Big => write!(f, "big-endian"),
+            Endianness::Little => write!(f, "little-endian"),
+        }
+    }
+}
+
+/// Position of the CRC field in frames, along with the range of bytes it covers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layout {
+    /// CRC field followed by `trailer` bytes, covering the bytes before it but the `header` first ones
+    Trailing { header: usize, trailer: usize },
+    /// CRC field at offset `offset`, covering all the bytes after it
+    Leading { offset: usize },
+}
+
+impl Layout {
+    /// Split `frame` between covered bytes and CRC field of `size` bytes, if it is long enough.
+    pub fn split<'a>(&self, frame: &'a [u8], size: usize) -> Option<(&'a [u8], &'a [u8])> {
+        match *self {
+            Layout::Trailing { header, trailer } => {
+                let end = frame.len().checked_sub(trailer + size)?;
+                Some((frame.get(header..end)?, &frame[end..end + size]))
+            }
+            Layout::Leading { offset } => {
+                let field = frame.get(offset..offset + size)?;
+                Some((&frame[offset + size..], field))
+            }
+        }
+    }
+
+    /// Number of bytes left out of the CRC: the fewer, the simpler the layout.
+    fn excluded(&self) -> usize {
+        match *self {
+            Layout::Trailing { header, trailer } => header + trailer,
+            Layout::Leading { offset } => offset,
+        }
+    }
+
+    /// Check whether CRC fields of both layouts are at the same position.
+    fn same_field(&self, other: &Layout) -> bool {
+        match (*self, *other) {
+            (Layout::Trailing { trailer: a, .. }, Layout::Trailing { trailer: b, .. }) => a == b,
+            (Layout::Leading { offset: a }, Layout::Leading { offset: b }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Layout::Trailing { header: 0, trailer } => write!(f, "{} trailing bytes", trailer),
+            Layout::Trailing { header, trailer } => {
+                write!(f, "{} header bytes, {} trailing bytes", header, trailer)
+            }
+            Layout::Leading { offset } => write!(f, "CRC at offset {}", offset),
+        }
+    }
+}
+
+/// Plausible CRC model for a set of frames.
+pub struct Detection {
+    pub name: Option<&'static str>, // Catalogue name of the model
+    pub model: RecoveredModel,
+    pub endianness: Endianness,
+    pub layout: Layout,
+    pub matches: Option<usize>, // Number of frames matching the model, when all its parameters are known
+    pub frames: usize,
+}
+
+impl Detection {
+    /// Amount of evidence for the model in bits: each matching frame makes a chance match 2^width less likely.
+    /// Models with unknown parameters only come from polynomial recovery, which already requires them to be consistent.
+    fn evidence(&self) -> u32 {
+        self.matches.unwrap_or(0) as u32 * self.model.width
+    }
+
+    /// Check whether the final XOR value is a conventional one, with all bits equal.
+    fn conventional(&self) -> bool {
+        let mask = u64::MAX >> (64 - self.model.width);
+        self.model.xorout.is_some_and(|f| f == 0 || f == mask)
+    }
+
+    /// Check whether `other` describes the same CRC field with the same model, or an equivalent one:
+    /// a partial model it completes, or the same model over a range differing by constant header bytes.
+    fn covers(&self, other: &Detection) -> bool {
+        let (a, b) = (&self.model, &other.model);
+        self.endianness == other.endianness
+            && self.layout.same_field(&other.layout)
+            && (a.width, a.poly, a.refin, a.refout) == (b.width, b.poly, b.refin, b.refout)
+            && (b.xorout.is_none() || a.xorout == b.xorout)
+    }
+}
+
+impl Display for Detection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let matches = match self.matches {
+            Some(matches) => format!("{}/{} frames", matches, self.frames),
+            None => "partially recovered".to_string(),
+        };
+        write!(
+            f,
+            "{}  {}  [{}, {}, {}]",
+            self.name.unwrap_or("(unknown)"),
+            self.model,
+            self.endianness,
+            self.layout,
+            matches
+        )
+    }
+}
+
+/// Parse frames, one hex string per line. Whitespace is ignored, as well as lines starting with `#`.
+pub fn parse_frames<R>(reader: R) -> CRCResult<Vec<Vec<u8>>>
+where
+    R: BufRead,
+{
+    let mut frames = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        let line: String = line.split_whitespace().collect();
+        if !line.is_empty() {
+            frames.push(decode_hex(&line)?);
+        }
+    }
+    Ok(frames)
+}
+
+/// A model matching more than half of the frames, with enough evidence, is plausible.
+/// This leaves room for a few corrupted frames.
+fn plausible(matches: usize, width: u32, frames: usize) -> bool {
+    2 * matches > frames && matches as u32 * width >= MIN_EVIDENCE
+}
+
+/// Pick at most `MAX_RECOVERY_SAMPLES` samples for polynomial recovery: pairs of same length first, from which
+/// generators are recovered, then samples of other lengths, from which init and final XOR are solved.
+fn recovery_samples(samples: &[Sample]) -> Vec<Sample> {
+    let mut groups = BTreeMap::<usize, Vec<&Sample>>::new();
+    for sample in samples {
+        groups.entry(sample.message.len()).or_default().push(sample);
+    }
+
+    let mut picked = Vec::<&Sample>::new();
+    for group in groups.values().filter(|group| group.len() > 1) {
+        let n = group.len().min(MAX_RECOVERY_SAMPLES / 2 - picked.len());
+        if n < 2 {
+            break;
+        }
+        picked.extend(&group[..n]);
+    }
+    for group in groups.values() {
+        if picked.len() >= MAX_RECOVERY_SAMPLES {
+            break;
+        }
+        if !picked
+            .iter()
+            .any(|s| s.message.len() == group[0].message.len())
+        {
+            picked.push(group[0]);
+        }
+    }
+    picked.into_iter().cloned().collect()
+}
+
+/// Check catalogue models and recover unknown ones for `frames` holding CRC fields of `width` bits laid out as `layout`,
+/// adding plausible ones to `detections`. Frames too short for the layout count as mismatches.
+/// Recovered models are checked against all frames, and only kept when partially recovered if `partial` is set.
+fn detect_layout(
+    frames: &[Vec<u8>],
+    layout: Layout,
+    width: u32,
+    catalogue: &[(&Model, CRC)],
+    partial: bool,
+    detections: &mut Vec<Detection>,
+) -> CRCResult<()> {
+    // Split frames between covered bytes and CRC field
+    let size = (width / 8) as usize;
+    let split = frames
+        .iter()
+        .filter_map(|frame| layout.split(frame, size))
+        .collect::<Vec<_>>();
+    if split.is_empty() {
+        return Ok(());
+    }
+    let values = Endianness::candidates(size)
+        .iter()
+        .map(|endianness| {
+            let values = split.iter().map(|(_, field)| endianness.read(field));
+            (*endianness, values.collect::<Vec<u64>>())
+        })
+        .collect::<Vec<_>>();
+
+    // Check catalogue models, computing their checksums only once for both byte orders
+    for (model, crc) in catalogue.iter().filter(|(m, _)| m.props.width == width) {
+        let checksums = split
+            .iter()
+            .map(|(message, _)| crc.checksum(message.bytes()))
+            .collect::<CRCResult<Vec<u64>>>()?;
+        for (endianness, values) in &values {
+            let matches = checksums.iter().zip(values).filter(|(c, v)| c == v).count();
+            if plausible(matches, width, frames.len()) {
+                detections.push(Detection {
+                    name: Some(model.name),
+                    model: model.props.into(),
+                    endianness: *endianness,
+                    layout,
+                    matches: Some(matches),
+                    frames: frames.len(),
+                });
+            }
+        }
+    }
+
+    // Recover unknown models from a few samples
+    for (endianness, values) in &values {
+        let samples = split
+            .iter()
+            .zip(values)
+            .map(|((message, _), crc)| Sample {
+                message: message.to_vec(),
+                crc: *crc,
+                width,
+            })
+            .collect::<Vec<Sample>>();
+        let Ok(models) = recover_models(&recovery_samples(&samples)) else {
+            continue;
+        };
+        for model in models {
+            let matches = match model.props() {
+                Some(props) => {
+                    let crc = CRC::new(props)?;
+                    let matches = samples
+                        .iter()
+                        .filter(|s| crc.checksum(s.message.bytes()).is_ok_and(|c| c == s.crc))
+                        .count();
+                    if !plausible(matches, width, frames.len()) {
+                        continue;
+                    }
+                    Some(matches)
+                }
+                None if partial => None,
+                None => continue,
+            };
+            detections.push(Detection {
+                name: None,
+                model,
+                endianness: *endianness,
+                layout,
+                matches,
+                frames: frames.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Rank `detections`, dropping the ones already covered by a better one.
+/// Fully known models come first, then by evidence, preferring catalogue models, conventional final XOR values and
+/// simpler layouts. Ties are common: a CRC field followed by trailing bytes also matches a CRC over its own first bytes,
+/// with another final XOR.
+fn rank(mut detections: Vec<Detection>) -> Vec<Detection> {
+    detections.sort_by_key(|d| {
+        (
+            d.matches.is_none(),
+            u32::MAX - d.evidence(),
+            d.name.is_none(),
+            !d.conventional(),
+            d.layout.excluded(),
+            matches!(d.layout, Layout::Leading { .. }),
+        )
+    });
+    let mut ranked: Vec<Detection> = Vec::new();
+    for detection in detections {
+        if !ranked.iter().any(|d| d.covers(&detection)) {
+            ranked.push(detection);
+        }
+    }
+    ranked
+}
+
+/// Build CRC engines for all catalogue models.
+fn catalogue() -> CRCResult<Vec<(&'static Model, CRC)>> {
+    CATALOGUE
+        .iter()
+        .map(|model| Ok((model, CRC::new(model.props)?)))
+        .collect()
+}
+
+/// Detect plausible CRC models for `frames`, each ending with a CRC field followed by `trailer` bytes.
+/// All trailer sizes up to `MAX_TRAILER` are tried if it is unknown.
+/// Every width, byte order and reflection setting is tried, both against the catalogue and with polynomial recovery.
+/// Returns detections from the most to the least plausible.
+pub fn detect(frames: &[Vec<u8>], trailer: Option<usize>) -> CRCResult<Vec<Detection>> {
+    if frames.is_empty() {
+        return Err(Error::InsufficientDataError);
+    }
+    let catalogue = catalogue()?;
+    let trailers = match trailer {
+        Some(trailer) => trailer..=trailer,
+        None => 0..=MAX_TRAILER,
+    };
+
+    let mut detections = Vec::new();
+    for trailer in trailers {
+        let layout = Layout::Trailing { header: 0, trailer };
+        for width in WIDTHS {
+            detect_layout(frames, layout, width, &catalogue, true, &mut detections)?;
+        }
+    }
+    Ok(rank(detections))
+}
+
+/// Analyze a corpus of `frames` to find their CRC field and the full CRC model.
+/// CRC fields are searched at both ends of frames: trailing fields may be followed by up to `MAX_TRAILER` bytes and leave
+/// up to `MAX_HEADER` header bytes out of the CRC, while leading fields may come after up to `MAX_HEADER` bytes.
+/// Only fully known models are kept, recovered ones being checked against the whole corpus.
+/// Returns detections from the most to the least plausible.
+pub fn analyze(frames: &[Vec<u8>]) -> CRCResult<Vec<Detection>> {
+    if frames.is_empty() {
+        return Err(Error::InsufficientDataError);
+    }
+    let catalogue = catalogue()?;
+    let trailing = (0..=MAX_TRAILER).flat_map(|trailer| {
+        (0..=MAX_HEADER).map(move |header| Layout::Trailing { header, trailer })
+    });
+    let leading = (0..=MAX_HEADER).map(|offset| Layout::Leading { offset });
+
+    let mut detections = Vec::new();
+    for layout in trailing.chain(leading) {
+        for width in WIDTHS {
+            detect_layout(frames, layout, width, &catalogue, false, &mut detections)?;
+        }
+    }
+    Ok(rank(detections))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::catalogue::find;
+    use crate::detect::{Endianness, Layout, analyze, detect, parse_frames};
+    use crate::generic::{CRC, CRCProperties};
+
+    fn frames(name: &str, endianness: Endianness, trailer: &[u8]) -> Vec<Vec<u8>> {
+        let crc = CRC::new(find(name).unwrap().props).unwrap();
+        let size = (crc.props().width / 8) as usize;
+        [
+            &b"\x01\x03\x00\x00\x00\x0a"[..],
+            b"\x01\x10\x00\x01",
+            b"\x11\x03\x00\x6b\x00\x03",
+            b"\x02\x01\x00\x13",
+        ]
+        .into_iter()
+        .map(|message| {
+            let c = crc.checksum(message.bytes()).unwrap().to_be_bytes();
+            let mut field = c[8 - size..].to_vec();
+            if endianness == Endianness::Little {
+                field.reverse();
+            }
+            [message, &field, trailer].concat()
+        })
+        .collect()
+    }
+
+    #[test]
+    pub fn test_endianness() {
+        assert_eq!(Endianness::Big.read(&[0x12, 0x34]), 0x1234);
+        assert_eq!(Endianness::Little.read(&[0x12, 0x34]), 0x3412);
+    }
+
+    #[test]
+    pub fn test_detect_modbus() {
+        let frames = frames("CRC-16/MODBUS", Endianness::Little, &[]);
+        let detections = detect(&frames, None).expect("Failed to detect");
+        let best = &detections[0];
+        assert_eq!(best.name, Some("CRC-16/MODBUS"));
+        assert_eq!(
+            (best.endianness, best.layout),
+            (
+                Endianness::Little,
+                Layout::Trailing {
+                    header: 0,
+                    trailer: 0
+                }
+            )
+        );
+        assert_eq!(best.matches, Some(4));
+    }
+
+    #[test]
+    pub fn test_detect_crc32_trailer() {
+        let frames = frames("CRC-32/BZIP2", Endianness::Big, b"\x7e");
+        let detections = detect(&frames, None).expect("Failed to detect");
+        let best = &detections[0];
+        assert_eq!(best.name, Some("CRC-32/BZIP2"));
+        assert_eq!(
+            (best.endianness, best.layout),
+            (
+                Endianness::Big,
+                Layout::Trailing {
+                    header: 0,
+                    trailer: 1
+                }
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_detect_unknown() {
+        // Same model as CRC-16/XMODEM with another generator
+        let mut props = find("CRC-16/XMODEM").unwrap().props;
+        props.g = 0x2f15;
+        let crc = CRC::new(props).unwrap();
+        let frames = [&b"lorem ipsum"[..], b"dolor sit a", b"consectetur"]
+            .into_iter()
+            .map(|m| [m, &(crc.checksum(m.bytes()).unwrap() as u16).to_be_bytes()].concat())
+            .collect::<Vec<_>>();
+        let detections = detect(&frames, Some(0)).expect("Failed to detect");
+        assert!(detections.iter().any(|d| d.name.is_none()
+            && d.model.poly == 0x2f15
+            && d.endianness == Endianness::Big));
+    }
+
+    /// Frames of various lengths made of a sequence number and some text.
+    fn corpus() -> Vec<Vec<u8>> {
+        [
+            &b"lorem ipsum"[..],
+            b"dolor sit a",
+            b"consectetur",
+            b"adipiscing",
+            b"elit sed do",
+            b"eiusmod",
+            b"tempor",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| [&[i as u8 * 17][..], text].concat())
+        .collect()
+    }
+
+    #[test]
+    pub fn test_analyze_header() {
+        // Unknown model over all bytes but the sequence number, followed by an end of frame marker
+        let crc = CRC::new(CRCProperties {
+            width: 16,
+            g: 0x2f15,
+            i: 0x1234,
+            f: 0,
+            refin: false,
+            refout: false,
+        })
+        .unwrap();
+        let frames = corpus()
+            .into_iter()
+            .map(|frame| {
+                let c = crc.checksum(frame[1..].bytes()).unwrap() as u16;
+                [&frame[..], &c.to_be_bytes(), b"\x7e"].concat()
+            })
+            .collect::<Vec<_>>();
+        let detections = analyze(&frames).expect("Failed to analyze");
+        let best = &detections[0];
+        assert_eq!(best.name, None);
+        assert_eq!(
+            best.model.to_string(),
+            "width=16  poly=0x2f15  init=0x1234  refin=false  refout=false  xorout=0x0000"
+        );
+        assert_eq!(
+            (best.endianness, best.layout, best.matches),
+            (
+                Endianness::Big,
+                Layout::Trailing {
+                    header: 1,
+                    trailer: 1
+                },
+                Some(7)
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_analyze_leading() {
+        let crc = CRC::new(find("CRC-32C").unwrap().props).unwrap();
+        let frames = corpus()
+            .into_iter()
+            .map(|frame| {
+                let c = crc.checksum(frame[1..].bytes()).unwrap() as u32;
+                [&frame[..1], &c.to_le_bytes(), &frame[1..]].concat()
+            })
+            .collect::<Vec<_>>();
+        let detections = analyze(&frames).expect("Failed to analyze");
+        let best = &detections[0];
+        assert_eq!(best.name, Some("CRC-32/ISCSI"));
+        assert_eq!(
+            (best.endianness, best.layout),
+            (Endianness::Little, Layout::Leading { offset: 1 })
+        );
+    }
+
+    #[test]
+    pub fn test_parse_frames() {
+        let frames = parse_frames(&b"# frames\n01 02 03\n\n0a0b\n"[..]).unwrap();
+        assert_eq!(frames, [vec![1, 2, 3], vec![10, 11]]);
+    }
+}
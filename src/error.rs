@@ -5,6 +5,10 @@ pub enum Error {
     IOError(std::io::Error),
     EncodingError,
     OutOfBoundsError,
+    ParseError,
+    InsufficientDataError,
+    UnknownModelError,
+    UnsupportedError,
 }
 
 pub type CRCResult<T> = Result<T, Error>;
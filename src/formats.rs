@@ -0,0 +1,88 @@
+use std::fmt::{Display, LowerHex};
+
+pub mod btrfs;
+mod bwt;
+pub mod bzip2;
+mod deflate;
+pub mod flac;
+pub mod fsimage;
+pub mod gpt;
+pub mod gzip;
+pub mod hdlc;
+pub mod kafka;
+mod lzma;
+pub mod mkv;
+pub mod ogg;
+pub mod pcap;
+pub mod png;
+pub mod rar5;
+pub mod records;
+pub mod sevenzip;
+pub mod ts;
+pub mod uimage;
+pub mod xz;
+pub mod zip;
+
+/// Little-endian field of `N` bytes at offset `offset` of `bytes`.
+fn le<const N: usize>(bytes: &[u8], offset: usize) -> u64 {
+    bytes[offset..offset + N]
+        .iter()
+        .rev()
+        .fold(0, |acc, b| (acc << 8) | u64::from(*b))
+}
+
+/// Big-endian field of `N` bytes at offset `offset` of `bytes`.
+fn be<const N: usize>(bytes: &[u8], offset: usize) -> u64 {
+    bytes[offset..offset + N]
+        .iter()
+        .fold(0, |acc, b| (acc << 8) | u64::from(*b))
+}
+
+/// CRC stored in a file, with the CRC computed over the data it covers.
+/// Displayed as its stored value followed by `ok`, or by the expected value, on as many hex digits as the width of the
+/// formatter, which defaults to the size of `T`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Crc<T> {
+    pub stored: T,
+    pub computed: T,
+}
+
+impl<T: PartialEq> Crc<T> {
+    pub fn new(stored: T, computed: T) -> Self {
+        Self { stored, computed }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.stored == self.computed
+    }
+}
+
+impl<T: PartialEq + LowerHex> Display for Crc<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = f.width().unwrap_or(2 * size_of::<T>());
+        write!(f, "0x{:0digits$x}  ", self.stored)?;
+        match self.is_valid() {
+            true => write!(f, "ok"),
+            false => write!(f, "BAD (expected 0x{:0digits$x})", self.computed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formats::Crc;
+
+    #[test]
+    pub fn test_crc() {
+        assert!(Crc::new(0x1234u16, 0x1234).is_valid());
+        assert_eq!(Crc::new(0xcafeu16, 0xcafe).to_string(), "0xcafe  ok");
+        assert_eq!(
+            Crc::new(0x42u32, 0x1337).to_string(),
+            "0x00000042  BAD (expected 0x00001337)"
+        );
+        assert_eq!(
+            format!("{:4}", Crc::new(0x42u64, 0x1337)),
+            "0x0042  BAD (expected 0x1337)"
+        );
+    }
+}
@@ -0,0 +1,663 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::formats::le;
+
+/// Offsets of the superblock copies, and size of superblocks.
+const SUPERBLOCK_OFFSETS: [u64; 3] = [0x10000, 0x4000000, 0x4000000000];
+const SUPERBLOCK_SIZE: usize = 4096;
+
+/// Size of the checksum area starting superblocks and tree blocks. Checksums cover everything after it.
+const CSUM_SIZE: usize = 32;
+
+/// Offsets of fields in superblocks.
+const SB_FSID: usize = 32;
+const SB_MAGIC: usize = 64;
+const SB_ROOT: usize = 80;
+const SB_CHUNK_ROOT: usize = 88;
+const SB_LOG_ROOT: usize = 96;
+const SB_NODESIZE: usize = 148;
+const SB_SYS_CHUNK_ARRAY_SIZE: usize = 160;
+const SB_INCOMPAT_FLAGS: usize = 188;
+const SB_CSUM_TYPE: usize = 196;
+const SB_DEVID: usize = 201;
+const SB_METADATA_UUID: usize = 571;
+const SB_SYS_CHUNK_ARRAY: usize = 811;
+const SB_PADDING_END: usize = SUPERBLOCK_SIZE - 4; // Last 4 bytes of padding, used to forge superblock checksums
+
+const MAGIC: &[u8; 8] = b"_BHRfS_M";
+const CSUM_TYPE_CRC32C: u64 = 0;
+const INCOMPAT_METADATA_UUID: u64 = 0x400;
+
+/// Offsets of fields in tree block headers, and size of headers.
+const HEADER_FSID: usize = 32;
+const HEADER_BYTENR: usize = 48;
+const HEADER_OWNER: usize = 88;
+const HEADER_NRITEMS: usize = 96;
+const HEADER_LEVEL: usize = 100;
+const HEADER_SIZE: usize = 101;
+
+/// Keys, leaf items and node key pointers.
+const KEY_SIZE: usize = 17;
+const ITEM_SIZE: usize = KEY_SIZE + 8;
+const KEY_PTR_SIZE: usize = KEY_SIZE + 16;
+const ROOT_ITEM_KEY: u8 = 132;
+const CHUNK_ITEM_KEY: u8 = 228;
+
+/// Offsets of fields in root items and chunk items, and size of chunk stripes.
+const ROOT_ITEM_BYTENR: usize = 176;
+const CHUNK_TYPE: usize = 24;
+const CHUNK_NUM_STRIPES: usize = 44;
+const CHUNK_STRIPES: usize = 48;
+const STRIPE_SIZE: usize = 32;
+
+/// Chunk profiles striping data over devices, which are not supported.
+const BLOCK_GROUP_STRIPED: u64 = 0x8 | 0x40 | 0x80 | 0x100; // RAID0, RAID10, RAID5, RAID6
+
+/// Maximum depth of trees.
+const MAX_LEVEL: u8 = 8;
+
+/// Structure holding a checksum.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Superblock(usize), // Superblock copy
+    TreeBlock { logical: u64, owner: u64, level: u8 },
+}
+
+/// Name of tree `owner`.
+fn tree_name(owner: u64) -> String {
+    match owner as i64 {
+        1 => "root".to_string(),
+        2 => "extent".to_string(),
+        3 => "chunk".to_string(),
+        4 => "dev".to_string(),
+        5 => "fs".to_string(),
+        7 => "csum".to_string(),
+        8 => "quota".to_string(),
+        9 => "uuid".to_string(),
+        10 => "free space".to_string(),
+        11 => "block group".to_string(),
+        -6 => "log".to_string(),
+        -9 => "data reloc".to_string(),
+        owner => format!("subvolume {}", owner),
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Superblock(copy) => write!(f, "superblock {}", copy),
+            Kind::TreeBlock {
+                logical,
+                owner,
+                level,
+            } => write!(
+                f,
+                "{} tree block 0x{:x} level {}",
+                tree_name(*owner),
+                logical,
+                level
+            ),
+        }
+    }
+}
+
+/// Checksum of a superblock or tree block, with its stored and actual value.
+#[derive(Clone)]
+pub struct Field {
+    pub kind: Kind,
+    pub offset: u64,     // Physical offset of the block in image
+    size: usize,         // Size of the block, checksum included
+    slot: Option<usize>, // Offset of 4 bytes of slack space in block, used to forge the checksum
+    pub crc: Crc<u32>,
+}
+
+impl Field {
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{:012x}  {}  crc={}", self.offset, self.kind, self.crc)
+    }
+}
+
+/// Chunk mapping a range of logical addresses to stripes on devices.
+struct Chunk {
+    logical: u64,
+    length: u64,
+    kind: u64,
+    stripes: Vec<(u64, u64)>, // Device identifier and physical offset of each stripe
+}
+
+/// Parse chunk item `item` mapping logical address `logical`.
+fn parse_chunk(logical: u64, item: &[u8]) -> CRCResult<Chunk> {
+    let header = item.get(..CHUNK_STRIPES).ok_or(Error::ParseError)?;
+    let count = le::<2>(header, CHUNK_NUM_STRIPES) as usize;
+    let stripes = item
+        .get(CHUNK_STRIPES..CHUNK_STRIPES + count * STRIPE_SIZE)
+        .ok_or(Error::ParseError)?;
+    Ok(Chunk {
+        logical,
+        length: le::<8>(header, 0),
+        kind: le::<8>(header, CHUNK_TYPE),
+        stripes: stripes
+            .chunks(STRIPE_SIZE)
+            .map(|stripe| (le::<8>(stripe, 0), le::<8>(stripe, 8)))
+            .collect(),
+    })
+}
+
+/// Items of leaf `block`, as their key type, key offset and data.
+fn leaf_items(block: &[u8]) -> CRCResult<Vec<(u8, u64, &[u8])>> {
+    let count = le::<4>(block, HEADER_NRITEMS) as usize;
+    (0..count)
+        .map(|i| {
+            let item = HEADER_SIZE + i * ITEM_SIZE;
+            let item = block.get(item..item + ITEM_SIZE).ok_or(Error::ParseError)?;
+            let start = HEADER_SIZE + le::<4>(item, KEY_SIZE) as usize;
+            let size = le::<4>(item, KEY_SIZE + 4) as usize;
+            let data = block.get(start..start + size).ok_or(Error::ParseError)?;
+            Ok((item[8], le::<8>(item, 9), data))
+        })
+        .collect()
+}
+
+/// Offset of 4 bytes of slack space of tree block `block`, between leaf item headers and item data, or after node key
+/// pointers, if any.
+fn tree_block_slot(block: &[u8]) -> Option<usize> {
+    let count = le::<4>(block, HEADER_NRITEMS) as usize;
+    let (used, free_end) = match block[HEADER_LEVEL] {
+        0 => {
+            let used = HEADER_SIZE + count * ITEM_SIZE;
+            let items = block.get(HEADER_SIZE..used)?;
+            let data_start = items
+                .chunks(ITEM_SIZE)
+                .map(|item| HEADER_SIZE + le::<4>(item, KEY_SIZE) as usize)
+                .min()
+                .unwrap_or(block.len());
+            (used, data_start)
+        }
+        _ => (HEADER_SIZE + count * KEY_PTR_SIZE, block.len()),
+    };
+    (used + 4 <= free_end && free_end <= block.len()).then_some(free_end - 4)
+}
+
+/// btrfs filesystem image of a single device, accessed in place so that images of any size are supported.
+/// Tree blocks are found by walking the chunk tree, the root tree and every tree it references, from the primary
+/// superblock. All copies of tree blocks held by this device are checked.
+pub struct Btrfs<F: Read + Write + Seek> {
+    image: F,
+    fields: Vec<Field>,
+    crc: CRC32,
+    nodesize: usize,
+    devid: u64,
+    fsid: Vec<u8>, // Identifier found in tree block headers
+    chunks: Vec<Chunk>,
+}
+
+impl<F: Read + Write + Seek> Btrfs<F> {
+    /// Parse superblocks and tree blocks of btrfs image `image`.
+    pub fn parse(image: F) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::castagnoli())?;
+        let mut btrfs = Self {
+            image,
+            fields: Vec::new(),
+            crc,
+            nodesize: 0,
+            devid: 0,
+            fsid: Vec::new(),
+            chunks: Vec::new(),
+        };
+        btrfs.parse_superblocks()?;
+        btrfs.parse_trees()?;
+        Ok(btrfs)
+    }
+
+    fn read_at(&mut self, offset: u64, size: usize) -> CRCResult<Vec<u8>> {
+        let mut bytes = vec![0; size];
+        self.image.seek(SeekFrom::Start(offset))?;
+        self.image.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> CRCResult<()> {
+        self.image.seek(SeekFrom::Start(offset))?;
+        self.image.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Build field of block `block` at offset `offset`.
+    fn field(
+        &self,
+        kind: Kind,
+        offset: u64,
+        block: &[u8],
+        slot: Option<usize>,
+    ) -> CRCResult<Field> {
+        Ok(Field {
+            kind,
+            offset,
+            size: block.len(),
+            slot,
+            crc: Crc::new(
+                le::<4>(block, 0) as u32,
+                self.crc.checksum(block[CSUM_SIZE..].bytes())?,
+            ),
+        })
+    }
+
+    /// Parse superblock copies, the primary one giving the geometry of the filesystem and the system chunks.
+    fn parse_superblocks(&mut self) -> CRCResult<()> {
+        let size = self.image.seek(SeekFrom::End(0))?;
+        for (copy, offset) in SUPERBLOCK_OFFSETS.into_iter().enumerate() {
+            if offset + SUPERBLOCK_SIZE as u64 > size {
+                break;
+            }
+            let sb = self.read_at(offset, SUPERBLOCK_SIZE)?;
+            if &sb[SB_MAGIC..SB_MAGIC + 8] != MAGIC {
+                match copy {
+                    0 => return Err(Error::ParseError),
+                    _ => continue,
+                }
+            }
+            if le::<2>(&sb, SB_CSUM_TYPE) != CSUM_TYPE_CRC32C {
+                return Err(Error::UnsupportedError);
+            }
+            let field = self.field(Kind::Superblock(copy), offset, &sb, Some(SB_PADDING_END))?;
+            self.fields.push(field);
+            if copy == 0 {
+                self.parse_geometry(&sb)?;
+            }
+        }
+        if self.fields.is_empty() {
+            return Err(Error::ParseError);
+        }
+        Ok(())
+    }
+
+    /// Parse node size, device identifier, filesystem identifier and system chunks of superblock `sb`.
+    fn parse_geometry(&mut self, sb: &[u8]) -> CRCResult<()> {
+        self.nodesize = le::<4>(sb, SB_NODESIZE) as usize;
+        if !(HEADER_SIZE..=1 << 16).contains(&self.nodesize) {
+            return Err(Error::ParseError);
+        }
+        self.devid = le::<8>(sb, SB_DEVID);
+        let fsid = match le::<8>(sb, SB_INCOMPAT_FLAGS) & INCOMPAT_METADATA_UUID {
+            0 => SB_FSID,
+            _ => SB_METADATA_UUID,
+        };
+        self.fsid = sb[fsid..fsid + 16].to_vec();
+
+        // System chunk array is made of keys followed by chunk items
+        let size = le::<4>(sb, SB_SYS_CHUNK_ARRAY_SIZE) as usize;
+        let array = sb
+            .get(SB_SYS_CHUNK_ARRAY..SB_SYS_CHUNK_ARRAY + size)
+            .ok_or(Error::ParseError)?;
+        let mut offset = 0;
+        while offset < array.len() {
+            let key = array
+                .get(offset..offset + KEY_SIZE)
+                .ok_or(Error::ParseError)?;
+            let chunk = parse_chunk(le::<8>(key, 9), &array[offset + KEY_SIZE..])?;
+            offset += KEY_SIZE + CHUNK_STRIPES + chunk.stripes.len() * STRIPE_SIZE;
+            self.chunks.push(chunk);
+        }
+        Ok(())
+    }
+
+    /// Physical offsets of the copies of logical address `logical` held by this device.
+    fn map(&self, logical: u64) -> CRCResult<Vec<u64>> {
+        let chunk = self
+            .chunks
+            .iter()
+            .find(|chunk| (chunk.logical..chunk.logical + chunk.length).contains(&logical))
+            .ok_or(Error::ParseError)?;
+        if chunk.kind & BLOCK_GROUP_STRIPED != 0 {
+            return Err(Error::UnsupportedError);
+        }
+        Ok(chunk
+            .stripes
+            .iter()
+            .filter(|(devid, _)| *devid == self.devid)
+            .map(|(_, offset)| offset + logical - chunk.logical)
+            .collect())
+    }
+
+    /// Walk the chunk tree, then the root tree, the trees it references and the log tree.
+    fn parse_trees(&mut self) -> CRCResult<()> {
+        let sb = self.read_at(SUPERBLOCK_OFFSETS[0], SUPERBLOCK_SIZE)?;
+        let mut visited = HashSet::new();
+        let mut roots = Vec::new();
+        self.parse_tree(
+            le::<8>(&sb, SB_CHUNK_ROOT),
+            MAX_LEVEL,
+            &mut visited,
+            &mut roots,
+        )?;
+        self.parse_tree(le::<8>(&sb, SB_ROOT), MAX_LEVEL, &mut visited, &mut roots)?;
+        let log_root = le::<8>(&sb, SB_LOG_ROOT);
+        if log_root != 0 {
+            roots.push(log_root);
+        }
+        let mut next = 0;
+        while let Some(root) = roots.get(next).copied() {
+            self.parse_tree(root, MAX_LEVEL, &mut visited, &mut roots)?;
+            next += 1;
+        }
+        Ok(())
+    }
+
+    /// Parse tree block at logical address `logical` and its children, skipping blocks in `visited`.
+    /// Chunk items are added to the chunk map, and root nodes referenced by root items are appended to `roots`.
+    fn parse_tree(
+        &mut self,
+        logical: u64,
+        max_level: u8,
+        visited: &mut HashSet<u64>,
+        roots: &mut Vec<u64>,
+    ) -> CRCResult<()> {
+        if !visited.insert(logical) {
+            return Ok(());
+        }
+        let copies = self.map(logical)?;
+        let Some(first) = copies.first() else {
+            return Ok(()); // Held by other devices
+        };
+        let block = self.read_at(*first, self.nodesize)?;
+        if le::<8>(&block, HEADER_BYTENR) != logical
+            || block[HEADER_FSID..HEADER_FSID + 16] != self.fsid
+            || block[HEADER_LEVEL] > max_level
+        {
+            return Err(Error::ParseError);
+        }
+        let kind = Kind::TreeBlock {
+            logical,
+            owner: le::<8>(&block, HEADER_OWNER),
+            level: block[HEADER_LEVEL],
+        };
+        for copy in copies {
+            let block = self.read_at(copy, self.nodesize)?;
+            let field = self.field(kind, copy, &block, tree_block_slot(&block))?;
+            self.fields.push(field);
+        }
+
+        if block[HEADER_LEVEL] == 0 {
+            for (key_type, key_offset, data) in leaf_items(&block)? {
+                match key_type {
+                    CHUNK_ITEM_KEY if !self.chunks.iter().any(|c| c.logical == key_offset) => {
+                        self.chunks.push(parse_chunk(key_offset, data)?);
+                    }
+                    ROOT_ITEM_KEY => {
+                        let bytenr = data
+                            .get(ROOT_ITEM_BYTENR..ROOT_ITEM_BYTENR + 8)
+                            .ok_or(Error::ParseError)?;
+                        roots.push(le::<8>(bytenr, 0));
+                    }
+                    _ => (),
+                }
+            }
+            return Ok(());
+        }
+        let count = le::<4>(&block, HEADER_NRITEMS) as usize;
+        for i in 0..count {
+            let ptr = HEADER_SIZE + i * KEY_PTR_SIZE + KEY_SIZE;
+            let child = block.get(ptr..ptr + 8).ok_or(Error::ParseError)?;
+            self.parse_tree(le::<8>(child, 0), block[HEADER_LEVEL] - 1, visited, roots)?;
+        }
+        Ok(())
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Write checksum `crc` of field `index`.
+    fn write_field(&mut self, index: usize, crc: u32) -> CRCResult<()> {
+        let offset = self.fields[index].offset;
+        self.write_at(offset, &crc.to_le_bytes())?;
+        let field = &mut self.fields[index];
+        field.crc.stored = crc;
+        field.crc.computed = crc;
+        Ok(())
+    }
+
+    /// Rewrite invalid checksums, returning their number.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let invalid = (0..self.fields.len())
+            .filter(|index| !self.fields[*index].is_valid())
+            .collect::<Vec<_>>();
+        for index in &invalid {
+            self.write_field(*index, self.fields[*index].crc.computed)?;
+        }
+        Ok(invalid.len())
+    }
+
+    /// Forge checksum of field `index` to `target_crc`, by patching 4 bytes of slack space of its block: superblock
+    /// padding, free space between leaf items and their data, or free space after node key pointers.
+    /// Every copy of a tree block is forged, so that mirrors stay interchangeable.
+    pub fn forge(&mut self, index: usize, target_crc: u32) -> CRCResult<()> {
+        let kind = self.fields.get(index).ok_or(Error::OutOfBoundsError)?.kind;
+        let copies = (0..self.fields.len())
+            .filter(|copy| self.fields[*copy].kind == kind)
+            .collect::<Vec<_>>();
+        for copy in copies {
+            let field = &self.fields[copy];
+            let slot = field.slot.ok_or(Error::OutOfBoundsError)?;
+            let (offset, size) = (field.offset, field.size);
+            let block = self.read_at(offset, size)?;
+            let patch =
+                self.crc
+                    .compute_patch(&block[CSUM_SIZE..], slot - CSUM_SIZE, target_crc)?;
+            self.write_at(offset + slot as u64, &patch)?;
+            self.write_field(copy, target_crc)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::error::Error;
+    use crate::formats::btrfs::{Btrfs, Kind};
+
+    const FSID: &[u8; 16] = b"btrfs filesystem";
+
+    /// Chunk item of `length` bytes of type `kind`, with stripes at physical offsets `stripes` of device 1.
+    fn chunk(length: u64, kind: u64, stripes: &[u64]) -> Vec<u8> {
+        let mut item = [length, 2, 0x10000, kind].map(u64::to_le_bytes).concat();
+        item.extend([0; 12]);
+        item.extend((stripes.len() as u16).to_le_bytes());
+        item.extend([0; 2]);
+        for stripe in stripes {
+            item.extend([1u64, *stripe].map(u64::to_le_bytes).concat());
+            item.extend([0; 16]);
+        }
+        item
+    }
+
+    /// Key of object `objectid`, of type `key_type` and offset `offset`.
+    fn key(objectid: u64, key_type: u8, offset: u64) -> Vec<u8> {
+        [
+            &objectid.to_le_bytes()[..],
+            &[key_type],
+            &offset.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Write tree block of 4096 bytes at logical address `logical` to physical offsets `copies`, with items `items`
+    /// (keys and data) for leaves or key pointers `items` (keys and children) for nodes.
+    fn tree_block(
+        image: &mut [u8],
+        copies: &[usize],
+        logical: u64,
+        owner: u64,
+        level: u8,
+        items: &[(Vec<u8>, Vec<u8>)],
+    ) {
+        let mut block = vec![0; 4096];
+        block[32..48].copy_from_slice(FSID);
+        block[48..56].copy_from_slice(&logical.to_le_bytes());
+        block[88..96].copy_from_slice(&owner.to_le_bytes());
+        block[96..100].copy_from_slice(&(items.len() as u32).to_le_bytes());
+        block[100] = level;
+        let mut end = 4096 - 101;
+        for (i, (key, data)) in items.iter().enumerate() {
+            match level {
+                0 => {
+                    end -= data.len();
+                    let item = 101 + i * 25;
+                    block[item..item + 17].copy_from_slice(key);
+                    block[item + 17..item + 21].copy_from_slice(&(end as u32).to_le_bytes());
+                    block[item + 21..item + 25].copy_from_slice(&(data.len() as u32).to_le_bytes());
+                    block[101 + end..101 + end + data.len()].copy_from_slice(data);
+                }
+                _ => {
+                    let ptr = 101 + i * 33;
+                    block[ptr..ptr + 17].copy_from_slice(key);
+                    block[ptr + 17..ptr + 25].copy_from_slice(data);
+                }
+            }
+        }
+        let crc = CRC32::new(CRC32Properties::castagnoli()).unwrap();
+        let c = crc.checksum(block[32..].bytes()).unwrap();
+        block[..4].copy_from_slice(&c.to_le_bytes());
+        for copy in copies {
+            image[*copy..*copy + 4096].copy_from_slice(&block);
+        }
+    }
+
+    /// Build image of 4 MiB, with a system chunk mapped at 1 MiB and a DUP metadata chunk mapped at 2 and 3 MiB.
+    fn image() -> Vec<u8> {
+        let mut image = vec![0; 0x400000];
+        let system = chunk(0x100000, 0x2, &[0x100000]);
+        let metadata = chunk(0x100000, 0x4 | 0x20, &[0x200000, 0x300000]);
+
+        let sb = &mut image[0x10000..0x11000];
+        sb[32..48].copy_from_slice(FSID);
+        sb[64..72].copy_from_slice(b"_BHRfS_M");
+        sb[80..88].copy_from_slice(&0x1000000u64.to_le_bytes());
+        sb[88..96].copy_from_slice(&0x100000u64.to_le_bytes());
+        sb[148..152].copy_from_slice(&4096u32.to_le_bytes());
+        let array = [key(256, 228, 0x100000), system.clone()].concat();
+        sb[160..164].copy_from_slice(&(array.len() as u32).to_le_bytes());
+        sb[201..209].copy_from_slice(&1u64.to_le_bytes());
+        sb[811..811 + array.len()].copy_from_slice(&array);
+        let crc = CRC32::new(CRC32Properties::castagnoli()).unwrap();
+        let c = crc.checksum(sb[32..].bytes()).unwrap();
+        sb[..4].copy_from_slice(&c.to_le_bytes());
+
+        let chunks = [
+            (key(256, 228, 0x100000), system),
+            (key(256, 228, 0x1000000), metadata),
+        ];
+        tree_block(&mut image, &[0x100000], 0x100000, 3, 0, &chunks);
+        let mut root_item = vec![0; 439];
+        root_item[176..184].copy_from_slice(&0x1002000u64.to_le_bytes());
+        let ptrs = [(key(5, 132, 0), 0x1001000u64.to_le_bytes().to_vec())];
+        tree_block(&mut image, &[0x200000, 0x300000], 0x1000000, 1, 1, &ptrs);
+        let roots = [(key(5, 132, 0), root_item)];
+        tree_block(&mut image, &[0x201000, 0x301000], 0x1001000, 1, 0, &roots);
+        tree_block(&mut image, &[0x202000, 0x302000], 0x1002000, 5, 0, &[]);
+        image
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let btrfs = Btrfs::parse(Cursor::new(image())).expect("Failed to parse btrfs image");
+        let blocks = btrfs.fields().iter().map(|f| (f.kind, f.offset));
+        let tree_block = |logical, owner, level| Kind::TreeBlock {
+            logical,
+            owner,
+            level,
+        };
+        assert_eq!(
+            blocks.collect::<Vec<_>>(),
+            [
+                (Kind::Superblock(0), 0x10000),
+                (tree_block(0x100000, 3, 0), 0x100000),
+                (tree_block(0x1000000, 1, 1), 0x200000),
+                (tree_block(0x1000000, 1, 1), 0x300000),
+                (tree_block(0x1001000, 1, 0), 0x201000),
+                (tree_block(0x1001000, 1, 0), 0x301000),
+                (tree_block(0x1002000, 5, 0), 0x202000),
+                (tree_block(0x1002000, 5, 0), 0x302000),
+            ]
+        );
+        assert!(btrfs.fields().iter().all(|f| f.is_valid()));
+        assert!(Btrfs::parse(Cursor::new(vec![0; 0x20000])).is_err());
+    }
+
+    #[test]
+    pub fn test_oversized_nritems() {
+        // Set item count of a copy of the fs tree leaf beyond its block, keeping its checksum valid
+        let oversized = |mut image: Vec<u8>, copy: usize| {
+            image[copy + 96..copy + 100].copy_from_slice(&0xffffu32.to_le_bytes());
+            let crc = CRC32::new(CRC32Properties::castagnoli()).unwrap();
+            let c = crc.checksum(image[copy + 32..copy + 4096].bytes()).unwrap();
+            image[copy..copy + 4].copy_from_slice(&c.to_le_bytes());
+            image
+        };
+        let btrfs = Btrfs::parse(Cursor::new(oversized(image(), 0x302000)))
+            .expect("Failed to parse btrfs image");
+        assert!(btrfs.fields().iter().all(|f| f.is_valid()));
+        assert!(btrfs.fields()[7].slot.is_none());
+        assert!(matches!(
+            Btrfs::parse(Cursor::new(oversized(image(), 0x202000))),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut image = image();
+        image[0x10000 + 299] = b'L'; // Label
+        image[0x302000 + 4000] = 0x42;
+        let mut btrfs = Btrfs::parse(Cursor::new(image)).expect("Failed to parse btrfs image");
+        let invalid = btrfs
+            .fields()
+            .iter()
+            .filter(|f| !f.is_valid())
+            .map(|f| f.offset);
+        assert_eq!(invalid.collect::<Vec<_>>(), [0x10000, 0x302000]);
+        assert_eq!(btrfs.repair().unwrap(), 2);
+        let btrfs = Btrfs::parse(btrfs.image).unwrap();
+        assert!(btrfs.fields().iter().all(|f| f.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge() {
+        let mut btrfs = Btrfs::parse(Cursor::new(image())).expect("Failed to parse btrfs image");
+        let targets = [
+            (0, 0x42424242),
+            (1, 0x13371337),
+            (2, 0xdeadbeef),
+            (6, 0xcafebabe),
+        ];
+        for (index, target) in targets {
+            btrfs
+                .forge(index, target)
+                .expect("Failed to forge checksum");
+        }
+        assert!(btrfs.forge(8, 0).is_err());
+        let btrfs = Btrfs::parse(btrfs.image).unwrap();
+        assert!(btrfs.fields().iter().all(|f| f.is_valid()));
+        for (index, target) in targets {
+            assert_eq!(btrfs.fields()[index].crc.stored, target);
+        }
+        assert_eq!(btrfs.fields()[3].crc.stored, 0xdeadbeef); // Mirror of field 2
+        assert_eq!(btrfs.fields()[7].crc.stored, 0xcafebabe); // Mirror of field 6
+    }
+}
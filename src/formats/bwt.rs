@@ -0,0 +1,472 @@
+use std::{cmp::Reverse, collections::BinaryHeap, iter::repeat_n, ops::Range};
+
+use crate::error::{CRCResult, Error};
+
+/// Maximum length of Huffman codes when decoding, and when encoding.
+const MAX_BITS: usize = 20;
+const MAX_ENCODED_BITS: usize = 17;
+
+/// Number of symbols coded with each selected Huffman table.
+const GROUP_SIZE: usize = 50;
+
+/// Number of Huffman tables of encoded blocks, the minimum allowed.
+const ENCODED_TABLES: u64 = 2;
+
+/// Symbols coding runs of the first MTF symbol, in bijective base 2.
+const RUNA: u16 = 0;
+const RUNB: u16 = 1;
+
+/// Maximum length of runs coded by the initial run-length encoding: 4 bytes and a repeat count.
+const MAX_RUN: usize = 4 + 255;
+
+/// Bit reader over a bzip2 stream, reading bits from MSB to LSB.
+pub struct Bits<'a> {
+    data: &'a [u8],
+    pub pos: usize, // Position in bits
+}
+
+impl<'a> Bits<'a> {
+    pub fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    pub fn bit(&mut self) -> CRCResult<bool> {
+        let byte = self.data.get(self.pos / 8).ok_or(Error::ParseError)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1 == 1;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    pub fn bits(&mut self, n: u32) -> CRCResult<u64> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | u64::from(self.bit()?);
+        }
+        Ok(value)
+    }
+}
+
+/// Bit writer, writing bits from MSB to LSB. The last byte is padded with null bits.
+#[derive(Default)]
+pub struct BitWriter {
+    pub data: Vec<u8>,
+    len: usize, // Length in bits
+}
+
+impl BitWriter {
+    fn bit(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.data.push(0);
+        }
+        if bit {
+            self.data[self.len / 8] |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    /// Write the `n` least significant bits of `value`.
+    pub fn bits(&mut self, value: u64, n: u32) {
+        for i in (0..n).rev() {
+            self.bit((value >> i) & 1 == 1);
+        }
+    }
+
+    /// Copy bits `range` of `data`.
+    pub fn copy(&mut self, data: &[u8], range: Range<usize>) {
+        for pos in range {
+            self.bit((data[pos / 8] >> (7 - pos % 8)) & 1 == 1);
+        }
+    }
+
+    pub fn append(&mut self, other: &BitWriter) {
+        self.copy(&other.data, 0..other.len);
+    }
+}
+
+/// Canonical Huffman code, given by the number of codes of each length and symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build code from code lengths of all symbols.
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        let mut symbols = (0..lengths.len() as u16).collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| lengths[*symbol as usize]);
+        Self { counts, symbols }
+    }
+
+    /// Decode next symbol, reading its code bit by bit.
+    fn decode(&self, bits: &mut Bits) -> CRCResult<u16> {
+        // Codes of each length are consecutive, starting from `first`
+        let (mut code, mut first, mut index) = (0usize, 0usize, 0usize);
+        for count in &self.counts[1..] {
+            code |= usize::from(bits.bit()?);
+            let count = *count as usize;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::ParseError)
+    }
+}
+
+/// Compute lengths of Huffman codes of symbols of frequencies `frequencies`, up to `MAX_ENCODED_BITS`.
+/// Every symbol gets a code, as required by bzip2. Frequencies are flattened until codes are short enough.
+fn code_lengths(frequencies: &[usize]) -> Vec<u8> {
+    let mut weights = frequencies.iter().map(|f| (*f).max(1)).collect::<Vec<_>>();
+    loop {
+        // Merge the two lightest nodes until a single tree is left
+        let n = weights.len();
+        let mut parents = vec![0; n];
+        let mut heap = weights
+            .iter()
+            .enumerate()
+            .map(|(i, w)| Reverse((*w, i)))
+            .collect::<BinaryHeap<_>>();
+        while heap.len() > 1 {
+            let Reverse((a, i)) = heap.pop().unwrap();
+            let Reverse((b, j)) = heap.pop().unwrap();
+            let node = parents.len();
+            parents.push(node);
+            parents[i] = node;
+            parents[j] = node;
+            heap.push(Reverse((a + b, node)));
+        }
+        let root = parents.len() - 1;
+        let lengths = (0..n)
+            .map(|mut node| {
+                let mut length = 0;
+                while node != root {
+                    node = parents[node];
+                    length += 1;
+                }
+                length
+            })
+            .collect::<Vec<u8>>();
+        if lengths.iter().all(|l| *l as usize <= MAX_ENCODED_BITS) {
+            return lengths;
+        }
+        weights.iter_mut().for_each(|w| *w = 1 + *w / 2);
+    }
+}
+
+/// Sort rotations of `data` by prefix doubling, returning their start offsets.
+fn sort_rotations(data: &[u8]) -> Vec<usize> {
+    let n = data.len();
+    let mut rank = data.iter().map(|b| *b as usize).collect::<Vec<_>>();
+    let mut order = (0..n).collect::<Vec<_>>();
+    let mut k = 1;
+    loop {
+        // Rotations are sorted by their first 2k bytes, from the ranks of their first k bytes
+        let key = |i: usize| (rank[i], rank[(i + k) % n]);
+        order.sort_by_key(|i| key(*i));
+        let mut next = vec![0; n];
+        for w in 1..n {
+            next[order[w]] = next[order[w - 1]] + usize::from(key(order[w]) != key(order[w - 1]));
+        }
+        rank = next;
+        if rank[order[n - 1]] == n - 1 || k >= n {
+            return order;
+        }
+        k *= 2;
+    }
+}
+
+/// Initial run-length encoding: runs of 4 to 259 equal bytes are stored as 4 bytes and a repeat count.
+fn run_length_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|b| **b == data[i])
+            .count();
+        match run >= 4 {
+            true => out.extend([data[i], data[i], data[i], data[i], (run - 4) as u8]),
+            false => out.extend(&data[i..i + run]),
+        }
+        i += run;
+    }
+    out
+}
+
+fn run_length_decode(data: &[u8]) -> CRCResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut last, mut run) = (None, 0);
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        out.push(*byte);
+        run = if last == Some(*byte) { run + 1 } else { 1 };
+        last = Some(*byte);
+        if run == 4 {
+            let count = bytes.next().ok_or(Error::ParseError)?;
+            out.extend(repeat_n(*byte, *count as usize));
+            (last, run) = (None, 0);
+        }
+    }
+    Ok(out)
+}
+
+/// Decode block at `bits`, following the block CRC, with a block size of at most `max_size` bytes.
+/// Returns the decompressed data.
+pub fn decode_block(bits: &mut Bits, max_size: usize) -> CRCResult<Vec<u8>> {
+    // Randomised blocks have been deprecated for long
+    if bits.bit()? {
+        return Err(Error::UnsupportedError);
+    }
+    let origin = bits.bits(24)? as usize;
+
+    // Bytes in use, as a bitmap of 16 ranges of 16 bytes
+    let ranges = bits.bits(16)?;
+    let mut used = Vec::new();
+    for i in 0..16 {
+        if (ranges >> (15 - i)) & 1 == 1 {
+            let bytes = bits.bits(16)?;
+            used.extend(
+                (0..16)
+                    .filter(|j| (bytes >> (15 - j)) & 1 == 1)
+                    .map(|j| (16 * i + j) as u8),
+            );
+        }
+    }
+    if used.is_empty() {
+        return Err(Error::ParseError);
+    }
+    let alphabet = used.len() + 2; // Run symbols, MTF indexes but the first one, end of block
+
+    // Selectors of Huffman tables, MTF and unary coded
+    let count = bits.bits(3)? as usize;
+    let selectors = bits.bits(15)? as usize;
+    if !(2..=6).contains(&count) || selectors == 0 {
+        return Err(Error::ParseError);
+    }
+    let mut order = (0..count).collect::<Vec<_>>();
+    let selectors = (0..selectors)
+        .map(|_| {
+            let mut j = 0;
+            while bits.bit()? {
+                j += 1;
+                if j >= count {
+                    return Err(Error::ParseError);
+                }
+            }
+            order[..=j].rotate_right(1);
+            Ok(order[0])
+        })
+        .collect::<CRCResult<Vec<_>>>()?;
+
+    // Code lengths, delta coded
+    let tables = (0..count)
+        .map(|_| {
+            let mut length = bits.bits(5)? as usize;
+            let mut lengths = Vec::new();
+            for _ in 0..alphabet {
+                loop {
+                    if !(1..=MAX_BITS).contains(&length) {
+                        return Err(Error::ParseError);
+                    }
+                    if !bits.bit()? {
+                        break;
+                    }
+                    match bits.bit()? {
+                        true => length -= 1,
+                        false => length += 1,
+                    }
+                }
+                lengths.push(length as u8);
+            }
+            Ok(Huffman::new(&lengths))
+        })
+        .collect::<CRCResult<Vec<_>>>()?;
+
+    // Last column of the sorted rotations, MTF and run-length coded
+    let mut mtf = used;
+    let mut last = Vec::new();
+    let (mut run, mut weight) = (0, 1);
+    for i in 0.. {
+        let table = selectors.get(i / GROUP_SIZE).ok_or(Error::ParseError)?;
+        let symbol = tables[*table].decode(bits)?;
+        if symbol == RUNA || symbol == RUNB {
+            run += (symbol as usize + 1) * weight;
+            weight <<= 1;
+            if last.len() + run > max_size {
+                return Err(Error::ParseError);
+            }
+            continue;
+        }
+        last.extend(repeat_n(mtf[0], run));
+        (run, weight) = (0, 1);
+        if symbol as usize == alphabet - 1 {
+            break;
+        }
+        let index = symbol as usize - 1;
+        mtf[..=index].rotate_right(1);
+        last.push(mtf[0]);
+        if last.len() > max_size {
+            return Err(Error::ParseError);
+        }
+    }
+    if origin >= last.len() {
+        return Err(Error::ParseError);
+    }
+
+    // Inverse transform: `next` links each byte of the last column to the following one in data
+    let mut starts = [0; 256];
+    for byte in &last {
+        starts[*byte as usize] += 1;
+    }
+    let mut start = 0;
+    for count in starts.iter_mut() {
+        (start, *count) = (start + *count, start);
+    }
+    let mut next = vec![0; last.len()];
+    for (i, byte) in last.iter().enumerate() {
+        next[starts[*byte as usize]] = i;
+        starts[*byte as usize] += 1;
+    }
+    let mut data = Vec::with_capacity(last.len());
+    let mut pos = next[origin];
+    for _ in 0..last.len() {
+        data.push(last[pos]);
+        pos = next[pos];
+    }
+    run_length_decode(&data)
+}
+
+/// Encode `data` as a block of less than `max_size` bytes, up to the block CRC excluded.
+/// A single Huffman table is used, written twice as bzip2 requires at least 2 of them.
+pub fn encode_block(data: &[u8], max_size: usize) -> CRCResult<BitWriter> {
+    let data = run_length_encode(data);
+    if data.is_empty() || data.len() >= max_size {
+        return Err(Error::UnsupportedError);
+    }
+    let n = data.len();
+    let order = sort_rotations(&data);
+    let origin = order.iter().position(|i| *i == 0).unwrap();
+    let last = order.iter().map(|i| data[(i + n - 1) % n]);
+
+    let mut used = [false; 256];
+    data.iter().for_each(|byte| used[*byte as usize] = true);
+    let mut mtf = (0..=255u8)
+        .filter(|byte| used[*byte as usize])
+        .collect::<Vec<_>>();
+    let alphabet = mtf.len() + 2;
+    let mut symbols = Vec::new();
+    let mut run = 0;
+    let flush = |symbols: &mut Vec<u16>, run: &mut usize| {
+        while *run > 0 {
+            *run -= 1;
+            symbols.push(if *run & 1 == 0 { RUNA } else { RUNB });
+            *run >>= 1;
+        }
+    };
+    for byte in last {
+        let index = mtf.iter().position(|b| *b == byte).unwrap();
+        if index == 0 {
+            run += 1;
+            continue;
+        }
+        flush(&mut symbols, &mut run);
+        mtf[..=index].rotate_right(1);
+        symbols.push(index as u16 + 1);
+    }
+    flush(&mut symbols, &mut run);
+    symbols.push(alphabet as u16 - 1);
+
+    let mut frequencies = vec![0; alphabet];
+    symbols.iter().for_each(|s| frequencies[*s as usize] += 1);
+    let lengths = code_lengths(&frequencies);
+    let mut codes = vec![0; alphabet];
+    let mut code = 0;
+    for length in 1..=MAX_ENCODED_BITS {
+        for symbol in (0..alphabet).filter(|s| lengths[*s] as usize == length) {
+            codes[symbol] = code;
+            code += 1;
+        }
+        code <<= 1;
+    }
+
+    let mut bits = BitWriter::default();
+    bits.bits(0, 1);
+    bits.bits(origin as u64, 24);
+    let ranges = (0..16)
+        .filter(|i| used[16 * i..16 * i + 16].iter().any(|u| *u))
+        .collect::<Vec<_>>();
+    bits.bits(ranges.iter().fold(0, |acc, i| acc | (0x8000 >> i)), 16);
+    for i in ranges {
+        let bytes = (0..16).filter(|j| used[16 * i + j]);
+        bits.bits(bytes.fold(0, |acc, j| acc | (0x8000 >> j)), 16);
+    }
+    bits.bits(ENCODED_TABLES, 3);
+    bits.bits(symbols.len().div_ceil(GROUP_SIZE) as u64, 15);
+    for _ in 0..symbols.len().div_ceil(GROUP_SIZE) {
+        bits.bits(0, 1);
+    }
+    for _ in 0..ENCODED_TABLES {
+        let mut length = lengths[0];
+        bits.bits(u64::from(length), 5);
+        for target in &lengths {
+            while length < *target {
+                bits.bits(0b10, 2);
+                length += 1;
+            }
+            while length > *target {
+                bits.bits(0b11, 2);
+                length -= 1;
+            }
+            bits.bits(0, 1);
+        }
+    }
+    for symbol in symbols {
+        bits.bits(codes[symbol as usize], u32::from(lengths[symbol as usize]));
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formats::bwt::{Bits, decode_block, encode_block};
+
+    #[test]
+    pub fn test_decode_block() {
+        // Block of a stream compressed by bzip2, after the block magic and CRC
+        let stream = b"\x42\x5a\x68\x39\x31\x41\x59\x26\x53\x59\x83\x13\xb9\x28\x00\x00\x0c\x51\x80\x01\
+                       \x10\x40\x04\x26\x26\xde\x00\x20\x00\x31\x4c\x00\x13\x41\x94\xc9\xa6\x23\x4d\x19\
+                       \x96\xb4\x39\xea\xb1\x72\xa1\x90\x5f\x52\x7c\x28\x45\x59\xb1\x06\x3c\x15\xf6\x3e\
+                       \x2e\xe4\x8a\x70\xa1\x21\x06\x27\x72\x50";
+        let mut bits = Bits::new(stream, 8 * 14);
+        let data = decode_block(&mut bits, 900000).expect("Failed to decode block");
+        assert_eq!(
+            data,
+            b"lorem ipsum dolor sit amet, aaaaaaaaaa lorem ipsum\n"
+        );
+        // End of stream magic follows
+        assert_eq!(bits.bits(48).unwrap(), 0x177245385090);
+        assert!(decode_block(&mut Bits::new(&stream[..40], 8 * 14), 900000).is_err());
+    }
+
+    #[test]
+    pub fn test_encode_block() {
+        let runs = [&[0x42; 1000][..], b"abc", &[0; 259], &[0; 4], b"abababab"].concat();
+        let text = (0..20000u32)
+            .map(|i| (i * i % 251) as u8)
+            .collect::<Vec<_>>();
+        for data in [&b"a"[..], b"banana", &runs, &text] {
+            let bits = encode_block(data, 900000).expect("Failed to encode block");
+            let decoded = decode_block(&mut Bits::new(&bits.data, 0), 900000).unwrap();
+            assert_eq!(decoded, data);
+        }
+        assert!(encode_block(b"", 900000).is_err());
+        assert!(encode_block(&text, 10000).is_err());
+    }
+}
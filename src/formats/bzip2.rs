@@ -0,0 +1,374 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::formats::bwt::{BitWriter, Bits, decode_block, encode_block};
+
+/// Magic number starting bzip2 streams, followed by the block size level.
+const STREAM_MAGIC: &[u8; 3] = b"BZh";
+
+/// 48 bit magic numbers starting blocks and the end of stream marker.
+const BLOCK_MAGIC: u64 = 0x314159265359;
+const END_MAGIC: u64 = 0x177245385090;
+
+/// Size in bits of block magic numbers and CRCs, preceding block data.
+const BLOCK_HEADER_BITS: usize = 48 + 32;
+
+/// Block size of level 1 streams. Level `n` streams have blocks of `n` times this size.
+const BLOCK_SIZE_UNIT: usize = 100000;
+
+/// bzip2 block CRC: non-reflected CRC-32.
+const BZIP2_CRC: CRC32Properties = CRC32Properties {
+    g: 0x04c11db7,
+    i: 0xffffffff,
+    f: 0xffffffff,
+    refin: false,
+    refout: false,
+};
+
+/// Combine block CRCs into the stream CRC, which is rotated left by one bit before each block CRC is XORed.
+fn combine(crcs: &[u32]) -> u32 {
+    crcs.iter().fold(0, |acc, crc| acc.rotate_left(1) ^ crc)
+}
+
+/// bzip2 block, with the stored and actual CRC of its decompressed data.
+#[derive(Clone)]
+pub struct Block {
+    bits: Range<usize>, // Range of the block in file, in bits from its magic number
+    pub size: usize,    // Size of decompressed data
+    pub crc: Crc<u32>,
+}
+
+impl Block {
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Blocks are not byte aligned
+        write!(
+            f,
+            "0x{:08x}.{}  block  {} bytes  crc={}",
+            self.bits.start / 8,
+            self.bits.start % 8,
+            self.size,
+            self.crc
+        )
+    }
+}
+
+/// bzip2 stream, with its blocks and the stored and actual combined CRC.
+#[derive(Clone)]
+pub struct Stream {
+    pub offset: usize, // Offset of the stream in file
+    length: usize,     // Length of the stream, padding included
+    level: usize,      // Block size level, from 1 to 9
+    pub blocks: Vec<Block>,
+    pub crc: Crc<u32>, // Combined CRC stored after the end of stream marker, and combined CRC of actual block CRCs
+}
+
+impl Stream {
+    fn max_size(&self) -> usize {
+        self.level * BLOCK_SIZE_UNIT
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid() && self.blocks.iter().all(|b| b.is_valid())
+    }
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  stream  {} blocks  combined crc={}",
+            self.offset,
+            self.blocks.len(),
+            self.crc
+        )
+    }
+}
+
+/// bzip2 file, split into streams.
+/// Any data following the last stream is kept as is.
+pub struct Bzip2 {
+    data: Vec<u8>,
+    streams: Vec<Stream>,
+    crc: CRC32,
+}
+
+impl Bzip2 {
+    /// Parse bzip2 file `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(BZIP2_CRC)?;
+        let mut bzip2 = Self {
+            data,
+            streams: Vec::new(),
+            crc,
+        };
+        bzip2.parse_streams()?;
+        Ok(bzip2)
+    }
+
+    /// Parse stream at offset `offset`, decompressing its blocks.
+    fn parse_stream(&self, offset: usize) -> CRCResult<Stream> {
+        let level = match self.data.get(offset + 3) {
+            Some(level @ b'1'..=b'9') => (level - b'0') as usize,
+            _ => return Err(Error::ParseError),
+        };
+        let mut bits = Bits::new(&self.data, 8 * (offset + 4));
+        let mut blocks = Vec::new();
+        loop {
+            let start = bits.pos;
+            let magic = bits.bits(48)?;
+            let stored = bits.bits(32)? as u32;
+            match magic {
+                BLOCK_MAGIC => {
+                    let data = decode_block(&mut bits, level * BLOCK_SIZE_UNIT)?;
+                    blocks.push(Block {
+                        bits: start..bits.pos,
+                        size: data.len(),
+                        crc: Crc::new(stored, self.crc.checksum(data.bytes())?),
+                    });
+                }
+                END_MAGIC => {
+                    let computed =
+                        combine(&blocks.iter().map(|b| b.crc.computed).collect::<Vec<_>>());
+                    return Ok(Stream {
+                        offset,
+                        length: bits.pos.div_ceil(8) - offset,
+                        level,
+                        blocks,
+                        crc: Crc::new(stored, computed),
+                    });
+                }
+                _ => return Err(Error::ParseError),
+            }
+        }
+    }
+
+    /// Parse streams, up to the end of file or to trailing data which is not a stream.
+    fn parse_streams(&mut self) -> CRCResult<()> {
+        let mut streams = vec![self.parse_stream(0)?];
+        loop {
+            let last = streams.last().unwrap();
+            let offset = last.offset + last.length;
+            if !self.data[offset..].starts_with(STREAM_MAGIC) {
+                break;
+            }
+            streams.push(self.parse_stream(offset)?);
+        }
+        self.streams = streams;
+        Ok(())
+    }
+
+    pub fn streams(&self) -> &[Stream] {
+        &self.streams
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Write stream `index` again with its stored CRCs, replacing the data of block `block` by `bits` if given,
+    /// then parse streams again.
+    fn write_stream(&mut self, index: usize, block: Option<(usize, BitWriter)>) -> CRCResult<()> {
+        let stream = &self.streams[index];
+        let mut out = BitWriter::default();
+        for byte in &self.data[stream.offset..stream.offset + 4] {
+            out.bits(u64::from(*byte), 8);
+        }
+        for (i, b) in stream.blocks.iter().enumerate() {
+            out.bits(BLOCK_MAGIC, 48);
+            out.bits(u64::from(b.crc.stored), 32);
+            match &block {
+                Some((j, bits)) if *j == i => out.append(bits),
+                _ => out.copy(&self.data, b.bits.start + BLOCK_HEADER_BITS..b.bits.end),
+            }
+        }
+        out.bits(END_MAGIC, 48);
+        out.bits(u64::from(stream.crc.stored), 32);
+        self.data
+            .splice(stream.offset..stream.offset + stream.length, out.data);
+        self.parse_streams()
+    }
+
+    /// Rewrite all invalid block and combined CRCs, returning the number of repaired CRCs.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let mut repaired = 0;
+        for index in 0..self.streams.len() {
+            let stream = &mut self.streams[index];
+            if stream.is_valid() {
+                continue;
+            }
+            for block in stream.blocks.iter_mut().filter(|b| !b.is_valid()) {
+                block.crc.stored = block.crc.computed;
+                repaired += 1;
+            }
+            if !stream.crc.is_valid() {
+                stream.crc.stored = stream.crc.computed;
+                repaired += 1;
+            }
+            self.write_stream(index, None)?;
+        }
+        Ok(repaired)
+    }
+
+    /// Forge the combined CRC of stream `index` to `target_crc`, by setting the CRC of block `block` (the last one
+    /// by default) to the value it requires. Block data is patched with 4 bytes at offset `offset`, or 4 bytes are
+    /// appended to it by default, then the block is compressed again. Other block CRCs are repaired.
+    ///
+    /// The combined CRC is linear in each block CRC: rotations are undone from the blocks following the forged one.
+    pub fn forge_stream(
+        &mut self,
+        index: usize,
+        block: Option<usize>,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let stream = self.streams.get(index).ok_or(Error::OutOfBoundsError)?;
+        let block = match block {
+            Some(block) => block,
+            None => stream
+                .blocks
+                .len()
+                .checked_sub(1)
+                .ok_or(Error::UnsupportedError)?,
+        };
+        let crcs = stream
+            .blocks
+            .iter()
+            .map(|b| b.crc.computed)
+            .collect::<Vec<_>>();
+        if block >= crcs.len() {
+            return Err(Error::OutOfBoundsError);
+        }
+        let following = (crcs.len() - block - 1) as u32;
+        let block_crc = (target_crc ^ combine(&crcs[block + 1..])).rotate_right(following)
+            ^ combine(&crcs[..block]).rotate_left(1);
+
+        let start = stream.blocks[block].bits.start + BLOCK_HEADER_BITS;
+        let mut data = decode_block(&mut Bits::new(&self.data, start), stream.max_size())?;
+        match offset {
+            Some(offset) => {
+                let patch = self.crc.compute_patch(&data, offset, block_crc)?;
+                data[offset..offset + 4].copy_from_slice(&patch);
+            }
+            None => {
+                let suffix = self.crc.compute_suffix(data.bytes(), block_crc)?;
+                data.extend(suffix);
+            }
+        }
+        let bits = encode_block(&data, stream.max_size())?;
+
+        let stream = &mut self.streams[index];
+        for b in stream.blocks.iter_mut() {
+            b.crc.stored = b.crc.computed;
+        }
+        stream.blocks[block].crc.stored = block_crc;
+        stream.crc.stored = target_crc;
+        self.write_stream(index, Some((block, bits)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::CRC32;
+    use crate::formats::bwt::{BitWriter, encode_block};
+    use crate::formats::bzip2::{BLOCK_MAGIC, BZIP2_CRC, Bzip2, END_MAGIC, combine};
+
+    /// Build level 1 bzip2 stream of blocks of `blocks` data.
+    fn stream(blocks: &[&[u8]]) -> Vec<u8> {
+        let crc = CRC32::new(BZIP2_CRC).unwrap();
+        let mut out = BitWriter::default();
+        out.bits(u64::from_be_bytes(*b"\0\0\0\0BZh1"), 32);
+        let mut crcs = Vec::new();
+        for block in blocks {
+            crcs.push(crc.checksum(block.bytes()).unwrap());
+            out.bits(BLOCK_MAGIC, 48);
+            out.bits(u64::from(*crcs.last().unwrap()), 32);
+            out.append(&encode_block(block, 100000).unwrap());
+        }
+        out.bits(END_MAGIC, 48);
+        out.bits(u64::from(combine(&crcs)), 32);
+        out.data
+    }
+
+    /// Build bzip2 file of a stream of 3 blocks, and a single block one.
+    fn bzip2() -> Vec<u8> {
+        let blocks = [&b"lorem ipsum"[..], &[0x42; 1000], b"dolor sit amet"];
+        [stream(&blocks), stream(&[b"consectetur"])].concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let bzip2 = Bzip2::parse(bzip2()).expect("Failed to parse bzip2");
+        let streams = bzip2.streams();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].blocks.len(), 3);
+        assert_eq!(streams[0].blocks[1].size, 1000);
+        assert!(streams.iter().all(|s| s.is_valid()));
+
+        // Stream compressed by bzip2
+        let data = b"\x42\x5a\x68\x39\x31\x41\x59\x26\x53\x59\x83\x13\xb9\x28\x00\x00\x0c\x51\x80\x01\
+                     \x10\x40\x04\x26\x26\xde\x00\x20\x00\x31\x4c\x00\x13\x41\x94\xc9\xa6\x23\x4d\x19\
+                     \x96\xb4\x39\xea\xb1\x72\xa1\x90\x5f\x52\x7c\x28\x45\x59\xb1\x06\x3c\x15\xf6\x3e\
+                     \x2e\xe4\x8a\x70\xa1\x21\x06\x27\x72\x50";
+        let bzip2 = Bzip2::parse(data.to_vec()).expect("Failed to parse bzip2");
+        assert_eq!(bzip2.streams()[0].crc.stored, 0x8313b928);
+        assert!(bzip2.streams()[0].is_valid());
+        assert!(Bzip2::parse(data[..60].to_vec()).is_err());
+
+        let crc = CRC32::new(BZIP2_CRC).unwrap();
+        assert_eq!(crc.checksum(b"123456789".bytes()).unwrap(), 0xfc891918);
+    }
+
+    #[test]
+    pub fn test_repair() {
+        // Swap the blocks of a stream, keeping their CRCs valid but not the combined one
+        let mut bzip2 = Bzip2::parse(stream(&[b"dolor sit amet", b"lorem ipsum"])).unwrap();
+        let crcs = bzip2.streams[0]
+            .blocks
+            .iter()
+            .map(|b| b.crc.stored)
+            .collect::<Vec<_>>();
+        bzip2.streams[0].crc.stored = combine(&[crcs[1], crcs[0]]);
+        bzip2.write_stream(0, None).unwrap();
+        let mut bzip2 = Bzip2::parse(bzip2.data().to_vec()).expect("Failed to parse bzip2");
+        assert!(bzip2.streams()[0].blocks.iter().all(|b| b.is_valid()));
+        assert!(!bzip2.streams()[0].is_valid());
+        assert_eq!(bzip2.repair().unwrap(), 1);
+        let bzip2 = Bzip2::parse(bzip2.data().to_vec()).unwrap();
+        assert!(bzip2.streams()[0].is_valid());
+    }
+
+    #[test]
+    pub fn test_forge_stream() {
+        let mut bzip2 = Bzip2::parse(bzip2()).expect("Failed to parse bzip2");
+        bzip2
+            .forge_stream(0, Some(0), Some(2), 0x42424242)
+            .expect("Failed to forge stream");
+        bzip2
+            .forge_stream(1, None, None, 0x13371337)
+            .expect("Failed to forge stream");
+        assert!(bzip2.forge_stream(0, Some(3), None, 0).is_err());
+        assert!(bzip2.forge_stream(2, None, None, 0).is_err());
+
+        let bzip2 = Bzip2::parse(bzip2.data().to_vec()).unwrap();
+        let streams = bzip2.streams();
+        assert!(streams.iter().all(|s| s.is_valid()));
+        assert_eq!(
+            (streams[0].crc.stored, streams[1].crc.stored),
+            (0x42424242, 0x13371337)
+        );
+        assert_eq!(
+            (streams[0].blocks[0].size, streams[1].blocks[0].size),
+            (11, 15)
+        );
+    }
+}
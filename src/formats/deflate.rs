@@ -0,0 +1,259 @@
+use crate::error::{CRCResult, Error};
+
+/// Maximum length of stored blocks.
+const STORED_BLOCK_SIZE: usize = 0xffff;
+
+/// Maximum length of Huffman codes.
+const MAX_BITS: usize = 15;
+
+/// Base lengths of length symbols 257 to 285, and their number of extra bits.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances of distance symbols, and their number of extra bits.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which code length code lengths are stored in dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Bit reader over a deflate stream, reading bits from LSB to MSB.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize, // Position in bits
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> CRCResult<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            let byte = self.data.get(self.pos / 8).ok_or(Error::ParseError)?;
+            value |= u32::from((byte >> (self.pos % 8)) & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    /// Skip bits up to the next byte boundary.
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+}
+
+/// Canonical Huffman code, given by the number of codes of each length and symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build code from code lengths of all symbols, a null length meaning the symbol is unused.
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = (0..lengths.len() as u16)
+            .filter(|symbol| lengths[*symbol as usize] != 0)
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| lengths[*symbol as usize]);
+        Self { counts, symbols }
+    }
+
+    /// Decode next symbol, reading its code bit by bit.
+    fn decode(&self, bits: &mut Bits) -> CRCResult<u16> {
+        // Codes of each length are consecutive, starting from `first`
+        let (mut code, mut first, mut index) = (0usize, 0usize, 0usize);
+        for count in &self.counts[1..] {
+            code |= bits.bits(1)? as usize;
+            let count = *count as usize;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::ParseError)
+    }
+}
+
+/// Fixed literal/length and distance codes.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Read literal/length and distance codes of a dynamic block.
+fn dynamic_codes(bits: &mut Bits) -> CRCResult<(Huffman, Huffman)> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+
+    let mut lengths = [0u8; 19];
+    for i in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[*i] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+
+    // Literal/length and distance code lengths are run-length encoded together
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match code.decode(bits)? {
+            symbol @ 0..16 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or(Error::ParseError)?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend((0..repeat).map(|_| length));
+    }
+    if lengths.len() != literals + distances {
+        return Err(Error::ParseError);
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+/// Decode a compressed block with codes `literal` and `distance`, appending to `out`.
+fn inflate_block(
+    bits: &mut Bits,
+    literal: &Huffman,
+    distance: &Huffman,
+    out: &mut Vec<u8>,
+) -> CRCResult<()> {
+    loop {
+        let symbol = literal.decode(bits)? as usize;
+        match symbol {
+            0..256 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                let (base, extra) = LENGTH_BASE
+                    .get(symbol)
+                    .zip(LENGTH_EXTRA.get(symbol))
+                    .ok_or(Error::ParseError)?;
+                let length = *base as usize + bits.bits(u32::from(*extra))? as usize;
+                let symbol = distance.decode(bits)? as usize;
+                let (base, extra) = DISTANCE_BASE
+                    .get(symbol)
+                    .zip(DISTANCE_EXTRA.get(symbol))
+                    .ok_or(Error::ParseError)?;
+                let dist = *base as usize + bits.bits(u32::from(*extra))? as usize;
+                let start = out.len().checked_sub(dist).ok_or(Error::ParseError)?;
+                // Copy byte by byte, since the source may overlap the copied bytes
+                for i in start..start + length {
+                    out.push(out[i]);
+                }
+            }
+        }
+    }
+}
+
+/// Decompress deflate stream at the start of `data`.
+/// Returns the decompressed data and the size of the stream in bytes.
+pub fn inflate(data: &[u8]) -> CRCResult<(Vec<u8>, usize)> {
+    let mut bits = Bits { data, pos: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let start = bits.pos / 8;
+                let header = data.get(start..start + 4).ok_or(Error::ParseError)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(Error::ParseError);
+                }
+                let block = data
+                    .get(start + 4..start + 4 + length as usize)
+                    .ok_or(Error::ParseError)?;
+                out.extend(block);
+                bits.pos += 8 * (4 + length as usize);
+            }
+            1 => {
+                let (literal, distance) = fixed_codes();
+                inflate_block(&mut bits, &literal, &distance, &mut out)?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &literal, &distance, &mut out)?;
+            }
+            _ => return Err(Error::ParseError),
+        }
+        if last {
+            return Ok((out, bits.pos.div_ceil(8)));
+        }
+    }
+}
+
+/// Encode `data` as a deflate stream of stored blocks, without compression.
+pub fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut blocks = data.chunks(STORED_BLOCK_SIZE).collect::<Vec<_>>();
+    if blocks.is_empty() {
+        blocks.push(&[]);
+    }
+    let mut out = Vec::with_capacity(data.len() + 5 * blocks.len());
+    for (i, block) in blocks.iter().enumerate() {
+        let length = block.len() as u16;
+        out.push(u8::from(i == blocks.len() - 1));
+        out.extend(length.to_le_bytes());
+        out.extend((!length).to_le_bytes());
+        out.extend(*block);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formats::deflate::{deflate_stored, inflate};
+
+    #[test]
+    pub fn test_inflate_stored() {
+        let data = (0..100000u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let stream = deflate_stored(&data);
+        assert_eq!(inflate(&stream).unwrap(), (data, stream.len()));
+        assert_eq!(inflate(&deflate_stored(b"")).unwrap(), (vec![], 5));
+    }
+
+    #[test]
+    pub fn test_inflate_huffman() {
+        // Fixed codes, with back references
+        let stream = b"\xcb\xc9\x2f\x4a\xcd\x55\xc8\x2c\x28\x2e\xcd\x55\xc8\xc1\xce\x06\x00";
+        let (data, size) = inflate(stream).expect("Failed to inflate");
+        assert_eq!(data, b"lorem ipsum lorem ipsum lorem ipsum");
+        assert_eq!(size, stream.len());
+
+        // Dynamic codes
+        let stream = b"\x8d\xcb\xc9\x11\x80\x20\x10\x44\xd1\x54\x3a\x00\x93\x02\x65\x53\x60\xd8\x11\xa3\
+                       \x77\xca\x08\x3c\x76\xfd\xd7\xcd\x2a\xe4\xee\xf6\x0b\xb2\xd0\x8c\xd0\x74\xe3\xec\
+                       \x21\x55\xd0\x50\x05\x8d\xb3\x17\xcf\xc2\x41\x66\xfb\xd6\x6f\x9c\x04\xbb\xb0\x20\
+                       \x19\x4d\xd7\x2c\xb4\x1b\x8a\xd3\xa3\x22\xbc\xcb\x9d\x0a\x7f\x4d\x7d\x01";
+        let (data, size) = inflate(stream).expect("Failed to inflate");
+        let text = b"the quick brown fox jumps over the lazy dog, ";
+        assert_eq!(
+            data,
+            [&text[..], text, b"pack my box with five dozen liquor jugs"].concat()
+        );
+        assert_eq!(size, stream.len());
+        assert!(inflate(&stream[..10]).is_err());
+    }
+}
@@ -0,0 +1,505 @@
+use std::{fmt::Display, io::Read};
+
+use crate::catalogue::find;
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::formats::bwt::Bits;
+use crate::generic::CRC;
+
+/// Marker starting FLAC streams, after an optional ID3v2 tag.
+const MARKER: &[u8; 4] = b"fLaC";
+
+/// Size of ID3v2 tag headers and footers.
+const ID3_HEADER_SIZE: usize = 10;
+
+/// Size of metadata block headers, and type of the stream information block.
+const BLOCK_HEADER_SIZE: usize = 4;
+const STREAMINFO: u8 = 0;
+
+/// Frame sync code, followed by a reserved bit and the blocking strategy bit.
+const SYNC: [u8; 2] = [0xff, 0xf8];
+
+/// Channel assignments of stereo frames coding a side channel, with one more bit per sample.
+const LEFT_SIDE: u8 = 8;
+const RIGHT_SIDE: u8 = 9;
+const MID_SIDE: u8 = 10;
+
+/// FLAC frame, with its stored and actual header CRC-8 and frame CRC-16.
+/// Damaged frames, which could not be decoded, only have an offset and a size, up to the next frame.
+#[derive(Clone, Default)]
+pub struct Frame {
+    pub offset: usize,
+    pub number: u64, // Frame number, or first sample number for variable block sizes
+    pub variable: bool, // Whether the stream uses variable block sizes
+    pub samples: usize, // Number of samples per channel
+    pub size: usize,
+    header: usize, // Size of the header covered by the CRC-8, which follows it
+    pub header_crc: Crc<u8>,
+    pub crc: Crc<u16>, // CRC-16 of the frame, up to the CRC
+    pub damaged: bool,
+}
+
+impl Frame {
+    pub fn is_valid(&self) -> bool {
+        !self.damaged && self.header_crc.is_valid() && self.crc.is_valid()
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.damaged {
+            return write!(f, "0x{:08x}  {} bytes  DAMAGED", self.offset, self.size);
+        }
+        let unit = match self.variable {
+            true => "sample",
+            false => "frame",
+        };
+        write!(
+            f,
+            "0x{:08x}  {} {}  {} samples  {} bytes",
+            self.offset, unit, self.number, self.samples, self.size
+        )?;
+        write!(f, "  crc8={}  crc16={}", self.header_crc, self.crc)
+    }
+}
+
+/// Read UTF-8 coded number at offset `*pos` of `data`, of up to 36 bits in 7 bytes, advancing `pos`.
+fn read_utf8(data: &[u8], pos: &mut usize) -> CRCResult<u64> {
+    let first = *data.get(*pos).ok_or(Error::ParseError)?;
+    let size = match first.leading_ones() {
+        0 => 1,
+        1 | 8 => return Err(Error::ParseError),
+        n => n as usize,
+    };
+    let bytes = data.get(*pos..*pos + size).ok_or(Error::ParseError)?;
+    *pos += size;
+    let value = match size {
+        1 => u64::from(first),
+        _ => u64::from(first) & (0xff >> (size + 1)),
+    };
+    bytes[1..]
+        .iter()
+        .try_fold(value, |value, byte| match byte >> 6 {
+            0b10 => Ok((value << 6) | u64::from(byte & 0x3f)),
+            _ => Err(Error::ParseError),
+        })
+}
+
+/// FLAC stream: metadata blocks followed by audio frames, each made of a header protected by a CRC-8 and subframes,
+/// one per channel, followed by a CRC-16 of the whole frame.
+/// Any data following the last frame, such as an ID3v1 tag, is kept as is.
+pub struct Flac {
+    data: Vec<u8>,
+    frames: Vec<Frame>,
+    bits_per_sample: u32, // Sample size from the stream information, used by frames which do not code it
+    crc8: CRC,
+    crc16: CRC,
+}
+
+impl Flac {
+    /// Parse FLAC stream `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc8 = find("CRC-8/SMBUS").ok_or(Error::UnknownModelError)?;
+        let crc16 = find("CRC-16/UMTS").ok_or(Error::UnknownModelError)?;
+        let mut flac = Self {
+            data,
+            frames: Vec::new(),
+            bits_per_sample: 0,
+            crc8: CRC::new(crc8.props)?,
+            crc16: CRC::new(crc16.props)?,
+        };
+        let start = flac.parse_metadata()?;
+        flac.parse_frames(start)?;
+        Ok(flac)
+    }
+
+    /// Parse metadata blocks, returning the offset of the first frame.
+    fn parse_metadata(&mut self) -> CRCResult<usize> {
+        let mut pos = 0;
+        if self.data.starts_with(b"ID3") {
+            let header = self.data.get(..ID3_HEADER_SIZE).ok_or(Error::ParseError)?;
+            let size = header[6..]
+                .iter()
+                .fold(0, |size, byte| (size << 7) | usize::from(byte & 0x7f));
+            let footer = match header[5] & 0x10 {
+                0 => 0,
+                _ => ID3_HEADER_SIZE,
+            };
+            pos = ID3_HEADER_SIZE + size + footer;
+        }
+        if self.data.get(pos..pos + MARKER.len()) != Some(MARKER) {
+            return Err(Error::ParseError);
+        }
+        pos += MARKER.len();
+        loop {
+            let header = self
+                .data
+                .get(pos..pos + BLOCK_HEADER_SIZE)
+                .ok_or(Error::ParseError)?;
+            let last = header[0] & 0x80 != 0;
+            let block_type = header[0] & 0x7f;
+            let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            pos += BLOCK_HEADER_SIZE;
+            let block = self.data.get(pos..pos + size).ok_or(Error::ParseError)?;
+            if block_type == STREAMINFO {
+                let info = block.get(12..14).ok_or(Error::ParseError)?;
+                self.bits_per_sample = (u32::from(info[0] & 1) << 4 | u32::from(info[1] >> 4)) + 1;
+            }
+            pos += size;
+            if last {
+                return Ok(pos);
+            }
+        }
+    }
+
+    /// Parse frames from offset `pos`, up to data which is not a frame.
+    /// A frame which cannot be decoded is reported as damaged up to the next frame, found by its sync code and header
+    /// CRC-8, or up to the end of data if there is none.
+    fn parse_frames(&mut self, mut pos: usize) -> CRCResult<()> {
+        self.frames.clear();
+        while pos < self.data.len() {
+            let frame = match self.parse_frame(pos) {
+                Ok(frame) => frame,
+                Err(_) => match (self.is_sync(pos), self.resync(pos + 1)) {
+                    (false, None) => break,
+                    (_, next) => Frame {
+                        offset: pos,
+                        size: next.unwrap_or(self.data.len()) - pos,
+                        damaged: true,
+                        ..Default::default()
+                    },
+                },
+            };
+            pos += frame.size;
+            self.frames.push(frame);
+        }
+        Ok(())
+    }
+
+    /// Check whether data at offset `pos` starts with a frame sync code.
+    fn is_sync(&self, pos: usize) -> bool {
+        self.data
+            .get(pos..pos + 2)
+            .is_some_and(|sync| sync[0] == SYNC[0] && sync[1] & 0xfe == SYNC[1])
+    }
+
+    /// Find the first frame from offset `pos` with a valid header CRC-8.
+    fn resync(&self, pos: usize) -> Option<usize> {
+        (pos..self.data.len()).find(|pos| {
+            self.is_sync(*pos)
+                && self
+                    .parse_header(*pos)
+                    .is_ok_and(|(frame, _, _)| frame.header_crc.is_valid())
+        })
+    }
+
+    /// Parse frame at offset `offset`, decoding its subframes to find its end.
+    fn parse_frame(&self, offset: usize) -> CRCResult<Frame> {
+        if !self.is_sync(offset) {
+            return Err(Error::ParseError);
+        }
+        let (mut frame, bits_per_sample, channel_code) = self.parse_header(offset)?;
+        let data = &self.data;
+        let mut bits = Bits::new(data, 8 * (offset + frame.size));
+        let side = match channel_code {
+            0..=7 => None,
+            LEFT_SIDE | MID_SIDE => Some(1),
+            RIGHT_SIDE => Some(0),
+            _ => return Err(Error::ParseError),
+        };
+        let channels = match side {
+            Some(_) => 2,
+            None => usize::from(channel_code) + 1,
+        };
+        for channel in 0..channels {
+            let extra = u32::from(side == Some(channel));
+            Self::skip_subframe(&mut bits, frame.samples, bits_per_sample + extra)?;
+        }
+        let end = bits.pos.div_ceil(8);
+        let stored = data.get(end..end + 2).ok_or(Error::ParseError)?;
+        frame.size = end + 2 - offset;
+        frame.crc = Crc::new(
+            u16::from_be_bytes([stored[0], stored[1]]),
+            self.crc16.checksum(data[offset..end].bytes())? as u16,
+        );
+        Ok(frame)
+    }
+
+    /// Parse header of frame at offset `offset`, returning the frame up to its header CRC-8, along with its sample size
+    /// and channel assignment.
+    fn parse_header(&self, offset: usize) -> CRCResult<(Frame, u32, u8)> {
+        let data = &self.data;
+        let header = data.get(offset..offset + 4).ok_or(Error::ParseError)?;
+        let variable = header[1] & 1 == 1;
+        let (size_code, rate_code) = (header[2] >> 4, header[2] & 0x0f);
+        let (channel_code, sample_code) = (header[3] >> 4, (header[3] >> 1) & 0x07);
+        let mut pos = offset + 4;
+        let number = read_utf8(data, &mut pos)?;
+        let samples = match size_code {
+            0 => return Err(Error::ParseError),
+            1 => 192,
+            2..=5 => 576 << (size_code - 2),
+            6 | 7 => {
+                let size = usize::from(size_code - 5);
+                let bytes = data.get(pos..pos + size).ok_or(Error::ParseError)?;
+                pos += size;
+                bytes
+                    .iter()
+                    .fold(0, |value, byte| (value << 8) | usize::from(*byte))
+                    + 1
+            }
+            _ => 256 << (size_code - 8),
+        };
+        pos += match rate_code {
+            12 => 1,
+            13 | 14 => 2,
+            15 => return Err(Error::ParseError),
+            _ => 0,
+        };
+        let bits_per_sample = match sample_code {
+            0 => self.bits_per_sample,
+            1 => 8,
+            2 => 12,
+            4 => 16,
+            5 => 20,
+            6 => 24,
+            7 => 32,
+            _ => return Err(Error::ParseError),
+        };
+        let stored_header_crc = *data.get(pos).ok_or(Error::ParseError)?;
+        let header_crc = self.crc8.checksum(data[offset..pos].bytes())? as u8;
+        let frame = Frame {
+            offset,
+            number,
+            variable,
+            samples,
+            size: pos + 1 - offset,
+            header: pos - offset,
+            header_crc: Crc::new(stored_header_crc, header_crc),
+            ..Default::default()
+        };
+        Ok((frame, bits_per_sample, channel_code))
+    }
+
+    /// Skip subframe of `samples` samples of `bits_per_sample` bits.
+    fn skip_subframe(bits: &mut Bits, samples: usize, bits_per_sample: u32) -> CRCResult<()> {
+        if bits.bit()? {
+            return Err(Error::ParseError);
+        }
+        let kind = bits.bits(6)?;
+        let mut wasted = 0;
+        if bits.bit()? {
+            wasted = 1;
+            while !bits.bit()? {
+                wasted += 1;
+            }
+        }
+        let width = bits_per_sample
+            .checked_sub(wasted)
+            .ok_or(Error::ParseError)? as usize;
+        match kind {
+            0 => bits.pos += width,           // Constant
+            1 => bits.pos += width * samples, // Verbatim
+            8..=12 => {
+                let order = kind as usize - 8;
+                bits.pos += width * order;
+                Self::skip_residual(bits, samples, order)?;
+            }
+            32..=63 => {
+                let order = kind as usize - 31;
+                bits.pos += width * order;
+                let precision = bits.bits(4)? as usize + 1;
+                if precision == 16 {
+                    return Err(Error::ParseError);
+                }
+                bits.pos += 5 + precision * order; // Shift and coefficients
+                Self::skip_residual(bits, samples, order)?;
+            }
+            _ => return Err(Error::ParseError),
+        }
+        Ok(())
+    }
+
+    /// Skip Rice-coded residual of `samples` samples, following `order` warm-up samples.
+    fn skip_residual(bits: &mut Bits, samples: usize, order: usize) -> CRCResult<()> {
+        let parameter_bits = match bits.bits(2)? {
+            0 => 4,
+            1 => 5,
+            _ => return Err(Error::ParseError),
+        };
+        let partition_order = bits.bits(4)?;
+        let partition_samples = samples >> partition_order;
+        if partition_samples << partition_order != samples || partition_samples < order {
+            return Err(Error::ParseError);
+        }
+        for partition in 0..1 << partition_order {
+            let count = match partition {
+                0 => partition_samples - order,
+                _ => partition_samples,
+            };
+            let parameter = bits.bits(parameter_bits)?;
+            if parameter == (1 << parameter_bits) - 1 {
+                // Escaped partition of samples coded on a fixed number of bits
+                bits.pos += bits.bits(5)? as usize * count;
+                continue;
+            }
+            for _ in 0..count {
+                while !bits.bit()? {}
+                bits.pos += parameter as usize;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Rewrite the header CRC-8, then the CRC-16 which covers it, of every frame. Damaged frames are left as is.
+    /// Returns the number of repaired frames.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let mut repaired = 0;
+        for frame in self.frames.iter_mut() {
+            if frame.is_valid() || frame.damaged {
+                continue;
+            }
+            let header_end = frame.offset + frame.header;
+            self.data[header_end] = frame.header_crc.computed;
+            let end = frame.offset + frame.size - 2;
+            let crc = self.crc16.checksum(self.data[frame.offset..end].bytes())? as u16;
+            self.data[end..end + 2].copy_from_slice(&crc.to_be_bytes());
+            frame.header_crc.stored = frame.header_crc.computed;
+            frame.crc = Crc::new(crc, crc);
+            repaired += 1;
+        }
+        Ok(repaired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::catalogue::find;
+    use crate::formats::bwt::BitWriter;
+    use crate::formats::flac::{Flac, read_utf8};
+    use crate::generic::CRC;
+    use crate::recover::decode_hex;
+
+    /// Stereo stream of a single sample, decoding example of RFC 9639.
+    const EXAMPLE: &str = "664c6143800000221000100000000f00000f0ac442f0000000013e84b41807dc690307586a3dad1a2e0f\
+        fff869180000bf0358fd03128baa9a";
+
+    /// Frame number `number` of 16 samples of 16 bits, with subframes coded in `subframes`.
+    fn frame(number: u8, subframes: &BitWriter) -> Vec<u8> {
+        let crc8 = CRC::new(find("CRC-8/SMBUS").unwrap().props).unwrap();
+        let crc16 = CRC::new(find("CRC-16/UMTS").unwrap().props).unwrap();
+        let mut frame = vec![0xff, 0xf8, 0x69, 0x18, number, 15];
+        frame.push(crc8.checksum(frame.bytes()).unwrap() as u8);
+        frame.extend(&subframes.data);
+        let crc = crc16.checksum(frame.bytes()).unwrap() as u16;
+        [frame, crc.to_be_bytes().to_vec()].concat()
+    }
+
+    /// Stream of 2 frames, with fixed and LPC subframes, whose residual is Rice-coded in partitions, some escaped.
+    fn stream() -> Vec<u8> {
+        let example = decode_hex(EXAMPLE).unwrap();
+        let mut subframes = BitWriter::default();
+        subframes.bits(8 + 2, 7); // Fixed predictor of order 2
+        subframes.bits(0, 1);
+        subframes.bits(0x1234_5678, 32);
+        subframes.bits(0b00_0001, 6); // 2 partitions with 4 bit parameters
+        subframes.bits(2, 4);
+        for _ in 0..6 {
+            subframes.bits(1, 4); // Quotient 3, remainder 1
+            subframes.bits(1, 2);
+        }
+        subframes.bits(0b1111_00011, 9); // Escaped partition of 3 bit samples
+        subframes.bits(0, 3 * 8);
+        subframes.bits(32 + 1, 7); // LPC of order 2 with 2 wasted bits
+        subframes.bits(1, 1);
+        subframes.bits(0b01, 2);
+        subframes.bits(0x2bad, 28);
+        subframes.bits(0b1011_00011, 9); // 12 bit coefficients, shift 3
+        subframes.bits(0xabcdef, 24);
+        subframes.bits(0b01, 2); // 1 partition with 5 bit null parameter
+        subframes.bits(0, 9);
+        subframes.bits(0x3fff, 14);
+        let mut constant = BitWriter::default();
+        constant.bits(0x00_8000, 24); // Constant subframes
+        constant.bits(0x00_8000, 24);
+        [
+            &example[..42],
+            &frame(0, &subframes),
+            &frame(1, &constant),
+            b"TAG",
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let flac = Flac::parse(decode_hex(EXAMPLE).unwrap()).expect("Failed to parse stream");
+        let frame = &flac.frames()[0];
+        assert_eq!((frame.offset, frame.samples, frame.size), (42, 1, 15));
+        assert_eq!((frame.header_crc.stored, frame.crc.stored), (0xbf, 0xaa9a));
+        assert!(frame.is_valid());
+
+        let flac = Flac::parse(stream()).expect("Failed to parse stream");
+        let frames = flac
+            .frames()
+            .iter()
+            .map(|frame| (frame.number, frame.samples));
+        assert_eq!(frames.collect::<Vec<_>>(), [(0, 16), (1, 16)]);
+        assert!(flac.frames().iter().all(|frame| frame.is_valid()));
+    }
+
+    #[test]
+    pub fn test_damaged() {
+        // Reserved subframe type, broken sync code, and truncated stream
+        let mut reserved = stream();
+        reserved[49] = 0x04;
+        let mut sync = stream();
+        sync[42] = 0;
+        for data in [reserved, sync] {
+            let mut flac = Flac::parse(data).expect("Failed to parse stream");
+            let frames = flac.frames();
+            assert_eq!(frames.len(), 2);
+            assert!(frames[0].damaged && !frames[0].is_valid());
+            assert_eq!(frames[0].offset + frames[0].size, frames[1].offset);
+            assert!(frames[1].is_valid());
+            assert_eq!(flac.repair().unwrap(), 0);
+        }
+        let flac = Flac::parse(stream()[..60].to_vec()).expect("Failed to parse stream");
+        assert_eq!((flac.frames().len(), flac.frames()[0].size), (1, 18));
+        assert!(flac.frames()[0].damaged);
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = stream();
+        data[44] = 0x6a; // Sample rate of 48 kHz
+        data[51] ^= 0x10; // Warm-up sample bit
+        let mut flac = Flac::parse(data).expect("Failed to parse stream");
+        let frame = &flac.frames()[0];
+        assert!(!frame.header_crc.is_valid() && !frame.crc.is_valid());
+        assert_eq!(flac.repair().unwrap(), 1);
+        let flac = Flac::parse(flac.data().to_vec()).unwrap();
+        assert!(flac.frames().iter().all(|frame| frame.is_valid()));
+        assert!(flac.data().ends_with(b"TAG"));
+    }
+
+    #[test]
+    pub fn test_read_utf8() {
+        let data = decode_hex("7fc280e4b880febfbfbfbfbfbf80").unwrap();
+        let mut pos = 0;
+        let numbers = (0..3).map(|_| read_utf8(&data, &mut pos).unwrap());
+        assert_eq!(numbers.collect::<Vec<_>>(), [0x7f, 0x80, 0x4e00]);
+        assert_eq!(read_utf8(&data, &mut pos).unwrap(), 0xfffffffff);
+        assert!(read_utf8(&data, &mut pos).is_err()); // Continuation byte
+    }
+}
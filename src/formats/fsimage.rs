@@ -0,0 +1,895 @@
+use std::{
+    fmt::Display,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::{Crc, be, le};
+use crate::math::solve_gf2;
+
+/// Offset and magic number of ext4 superblocks.
+const EXT4_SUPERBLOCK: u64 = 1024;
+const EXT4_SUPERBLOCK_SIZE: usize = 1024;
+const EXT4_MAGIC: u16 = 0xef53;
+
+/// Offsets of fields in ext4 superblocks.
+const S_BLOCKS_COUNT_LO: usize = 0x04;
+const S_FIRST_DATA_BLOCK: usize = 0x14;
+const S_LOG_BLOCK_SIZE: usize = 0x18;
+const S_CLUSTERS_PER_GROUP: usize = 0x24;
+const S_BLOCKS_PER_GROUP: usize = 0x20;
+const S_INODES_PER_GROUP: usize = 0x28;
+const S_MAGIC: usize = 0x38;
+const S_INODE_SIZE: usize = 0x58;
+const S_FEATURE_INCOMPAT: usize = 0x60;
+const S_FEATURE_RO_COMPAT: usize = 0x64;
+const S_UUID: usize = 0x68;
+const S_DESC_SIZE: usize = 0xfe;
+const S_BLOCKS_COUNT_HI: usize = 0x150;
+const S_CHECKSUM_SEED: usize = 0x270;
+const S_RESERVED_END: usize = 0x3f8; // Last 4 reserved bytes, used to forge the superblock checksum
+const S_CHECKSUM: usize = 0x3fc;
+
+/// ext4 feature flags.
+const INCOMPAT_META_BG: u32 = 0x10;
+const INCOMPAT_64BIT: u32 = 0x80;
+const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+
+/// Offsets of fields in ext4 group descriptors.
+const BG_BLOCK_BITMAP: usize = 0x00;
+const BG_INODE_BITMAP: usize = 0x04;
+const BG_INODE_TABLE: usize = 0x08;
+const BG_FLAGS: usize = 0x12;
+const BG_BLOCK_BITMAP_CSUM_LO: usize = 0x18;
+const BG_INODE_BITMAP_CSUM_LO: usize = 0x1a;
+const BG_CHECKSUM: usize = 0x1e;
+const BG_HI: usize = 0x20; // High 32 bits of block numbers follow at this offset in 64 byte descriptors
+const BG_BLOCK_BITMAP_CSUM_HI: usize = 0x38;
+const BG_INODE_BITMAP_CSUM_HI: usize = 0x3a;
+const BG_RESERVED: usize = 0x3c;
+const BG_LARGE_SIZE: usize = 64;
+
+/// Group descriptor flags of uninitialized bitmaps, whose checksums are not checked.
+const BG_INODE_UNINIT: u16 = 0x1;
+const BG_BLOCK_UNINIT: u16 = 0x2;
+
+/// Offsets of fields in ext4 inodes.
+const I_MODE: usize = 0x00;
+const I_FLAGS: usize = 0x20;
+const I_BLOCK: usize = 0x28;
+const I_GENERATION: usize = 0x64;
+const I_CHECKSUM_LO: usize = 0x7c;
+const I_EXTRA_ISIZE: usize = 0x80;
+const I_CHECKSUM_HI: usize = 0x82;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+
+/// Magic number of extended attributes stored in inodes, after their extra fields.
+const XATTR_MAGIC: u32 = 0xea020000;
+
+/// Inode flags and modes.
+const EXTENTS_FL: u32 = 0x80000;
+const S_IFMT: u16 = 0xf000;
+const S_IFDIR: u16 = 0x4000;
+
+/// Extent tree nodes: magic number of headers, and size of headers and entries.
+const EXTENT_MAGIC: u16 = 0xf30a;
+const EXTENT_ENTRY_SIZE: usize = 12;
+const EH_GENERATION: usize = 8; // Unused by ext4, used to forge extent block checksums
+
+/// Maximum depth of extent trees.
+const MAX_EXTENT_DEPTH: u16 = 5;
+
+/// Directory blocks end with a fake entry holding their checksum.
+const DIRENT_TAIL_SIZE: usize = 12;
+const DIRENT_TAIL_FT: u8 = 0xde;
+
+/// XFS superblocks and allocation group headers, with offsets of their fields.
+const XFS_SB_MAGIC: &[u8; 4] = b"XFSB";
+const XFS_AGF_MAGIC: &[u8; 4] = b"XAGF";
+const XFS_AGI_MAGIC: &[u8; 4] = b"XAGI";
+const XFS_AGFL_MAGIC: &[u8; 4] = b"XAFL";
+const SB_BLOCKSIZE: usize = 4;
+const SB_AGBLOCKS: usize = 84;
+const SB_AGCOUNT: usize = 88;
+const SB_VERSIONNUM: usize = 100;
+const SB_SECTSIZE: usize = 102;
+const SB_CRC: usize = 224;
+const AGF_SPARE: usize = 200; // Last reserved bytes but one, used to forge AGF checksums
+const AGF_CRC: usize = 216;
+const AGI_CRC: usize = 312;
+const AGI_PAD: usize = 316; // Padding following the CRC, used to forge AGI checksums
+const AGFL_CRC: usize = 32;
+
+/// XFS version with metadata CRCs.
+const XFS_SB_VERSION_5: u16 = 5;
+
+/// Metadata structure holding a checksum.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Superblock,
+    GroupDescriptor(u32),
+    BlockBitmap(u32),
+    InodeBitmap(u32),
+    Inode(u32),
+    ExtentBlock(u32),
+    DirectoryBlock(u32),
+    XfsSuperblock(u32),
+    Agf(u32),
+    Agi(u32),
+    Agfl(u32),
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Superblock => write!(f, "superblock"),
+            Kind::GroupDescriptor(group) => write!(f, "group {} descriptor", group),
+            Kind::BlockBitmap(group) => write!(f, "group {} block bitmap", group),
+            Kind::InodeBitmap(group) => write!(f, "group {} inode bitmap", group),
+            Kind::Inode(ino) => write!(f, "inode {}", ino),
+            Kind::ExtentBlock(ino) => write!(f, "inode {} extent block", ino),
+            Kind::DirectoryBlock(ino) => write!(f, "inode {} directory block", ino),
+            Kind::XfsSuperblock(ag) => write!(f, "AG {} superblock", ag),
+            Kind::Agf(ag) => write!(f, "AG {} free space header (AGF)", ag),
+            Kind::Agi(ag) => write!(f, "AG {} inode header (AGI)", ag),
+            Kind::Agfl(ag) => write!(f, "AG {} free list (AGFL)", ag),
+        }
+    }
+}
+
+/// Checksum algorithm of a structure.
+#[derive(Clone, Copy)]
+enum Algorithm {
+    Ext4(u32), // CRC32C register from given seed, without final inversion
+    Xfs,       // CRC32C
+}
+
+/// Checksum of a metadata structure, with its stored and actual value.
+#[derive(Clone)]
+pub struct Field {
+    pub kind: Kind,
+    pub offset: u64,          // Offset of the checksummed structure in image
+    size: usize,              // Size of the checksummed part of the structure
+    parts: Vec<(u64, usize)>, // Offsets and sizes of the little-endian parts of the checksum, low part first
+    algorithm: Algorithm,
+    slot: Option<u64>, // Offset of 4 reserved bytes of the structure, used to forge the checksum
+    pub crc: Crc<u32>,
+}
+
+impl Field {
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+
+    /// Width of the checksum in bits.
+    pub fn width(&self) -> usize {
+        self.parts.iter().map(|(_, size)| 8 * size).sum()
+    }
+
+    fn mask(&self) -> u32 {
+        (u64::MAX >> (64 - self.width())) as u32
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.width() / 4;
+        write!(
+            f,
+            "0x{:012x}  {}  crc={:digits$}",
+            self.offset, self.kind, self.crc
+        )
+    }
+}
+
+/// Geometry of an ext4 filesystem.
+struct Ext4 {
+    block_size: u64,
+    inode_size: usize,
+    inodes_per_group: u32,
+    seed: u32, // Checksum seed, derived from the filesystem UUID
+}
+
+/// ext4 or XFS filesystem image, accessed in place so that images of any size are supported.
+/// Every metadata checksum of ext4 filesystems with `metadata_csum` is checked, except those of htree directory
+/// index blocks, extended attribute blocks and the journal. XFS superblocks and allocation group headers are checked.
+pub struct FsImage<F: Read + Write + Seek> {
+    image: F,
+    size: u64, // Size of the image, bounding every read
+    fields: Vec<Field>,
+    crc: CRC32,
+}
+
+impl<F: Read + Write + Seek> FsImage<F> {
+    /// Parse the metadata checksums of ext4 or XFS filesystem image `image`.
+    pub fn parse(image: F) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::castagnoli())?;
+        let mut image = image;
+        let size = image.seek(SeekFrom::End(0))?;
+        let mut fs = Self {
+            image,
+            size,
+            fields: Vec::new(),
+            crc,
+        };
+        match fs.read_at(0, 4)? == XFS_SB_MAGIC {
+            true => fs.parse_xfs()?,
+            false => fs.parse_ext4()?,
+        }
+        Ok(fs)
+    }
+
+    fn read_at(&mut self, offset: u64, size: usize) -> CRCResult<Vec<u8>> {
+        if offset
+            .checked_add(size as u64)
+            .is_none_or(|end| end > self.size)
+        {
+            return Err(Error::ParseError);
+        }
+        let mut bytes = vec![0; size];
+        self.image.seek(SeekFrom::Start(offset))?;
+        self.image.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> CRCResult<()> {
+        self.image.seek(SeekFrom::Start(offset))?;
+        self.image.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Continue the ext4 checksum register `seed` over `data`.
+    fn ext4_chksum(&self, seed: u32, data: &[u8]) -> CRCResult<u32> {
+        Ok(!self.crc.update(!seed, data.bytes())?)
+    }
+
+    /// Compute the checksum of `field` over the structure `bytes`, with the checksum zeroed when inside it.
+    fn checksum(&self, field: &Field, mut bytes: Vec<u8>) -> CRCResult<u32> {
+        for (offset, size) in &field.parts {
+            let start = offset.checked_sub(field.offset).map(|start| start as usize);
+            if let Some(start) = start.filter(|start| *start < bytes.len()) {
+                bytes
+                    .get_mut(start..start + size)
+                    .ok_or(Error::ParseError)?
+                    .fill(0);
+            }
+        }
+        let crc = match field.algorithm {
+            Algorithm::Ext4(seed) => self.ext4_chksum(seed, &bytes)?,
+            Algorithm::Xfs => self.crc.checksum(bytes.bytes())?,
+        };
+        Ok(crc & field.mask())
+    }
+
+    /// Read the stored value of `field`, and compute its actual one.
+    fn read_field(&mut self, field: &mut Field) -> CRCResult<()> {
+        let mut stored = 0;
+        for (i, (offset, size)) in field.parts.clone().iter().enumerate() {
+            let bytes = self.read_at(*offset, *size)?;
+            stored |= (le::<4>(&[bytes, vec![0; 4]].concat(), 0) as u32) << (16 * i);
+        }
+        field.crc.stored = stored;
+        let bytes = self.read_at(field.offset, field.size)?;
+        field.crc.computed = self.checksum(field, bytes)?;
+        Ok(())
+    }
+
+    /// Build and read field of checksum `parts`, computed over `size` bytes of structure at offset `offset`.
+    fn push_field(
+        &mut self,
+        kind: Kind,
+        offset: u64,
+        size: usize,
+        parts: Vec<(u64, usize)>,
+        algorithm: Algorithm,
+        slot: Option<u64>,
+    ) -> CRCResult<()> {
+        let mut field = Field {
+            kind,
+            offset,
+            size,
+            parts,
+            algorithm,
+            slot,
+            crc: Crc::default(),
+        };
+        self.read_field(&mut field)?;
+        self.fields.push(field);
+        Ok(())
+    }
+
+    /*
+     * ext4
+     */
+
+    /// Parse ext4 superblock, group descriptors, bitmaps and inodes, with extent and directory blocks of the latter.
+    fn parse_ext4(&mut self) -> CRCResult<()> {
+        let sb = self.read_at(EXT4_SUPERBLOCK, EXT4_SUPERBLOCK_SIZE)?;
+        if le::<2>(&sb, S_MAGIC) != u64::from(EXT4_MAGIC) {
+            return Err(Error::ParseError);
+        }
+        let incompat = le::<4>(&sb, S_FEATURE_INCOMPAT) as u32;
+        if le::<4>(&sb, S_FEATURE_RO_COMPAT) as u32 & RO_COMPAT_METADATA_CSUM == 0
+            || incompat & INCOMPAT_META_BG != 0
+        {
+            return Err(Error::UnsupportedError);
+        }
+        let slot = Some(EXT4_SUPERBLOCK + S_RESERVED_END as u64);
+        let parts = vec![(EXT4_SUPERBLOCK + S_CHECKSUM as u64, 4)];
+        self.push_field(
+            Kind::Superblock,
+            EXT4_SUPERBLOCK,
+            S_CHECKSUM,
+            parts,
+            Algorithm::Ext4(!0),
+            slot,
+        )?;
+
+        let log_block_size = le::<4>(&sb, S_LOG_BLOCK_SIZE);
+        if log_block_size > 6 {
+            return Err(Error::ParseError);
+        }
+        let block_size = 1024 << log_block_size;
+        let wide = incompat & INCOMPAT_64BIT != 0;
+        let desc_size = match wide {
+            true => le::<2>(&sb, S_DESC_SIZE) as usize,
+            false => 32,
+        };
+        let mut blocks = le::<4>(&sb, S_BLOCKS_COUNT_LO);
+        if wide {
+            blocks |= le::<4>(&sb, S_BLOCKS_COUNT_HI) << 32;
+        }
+        let first_data_block = le::<4>(&sb, S_FIRST_DATA_BLOCK);
+        let blocks_per_group = le::<4>(&sb, S_BLOCKS_PER_GROUP);
+        let inode_size = le::<2>(&sb, S_INODE_SIZE) as usize;
+        if desc_size < 32
+            || blocks_per_group == 0
+            || blocks <= first_data_block
+            || inode_size < GOOD_OLD_INODE_SIZE
+            || inode_size as u64 > block_size
+        {
+            return Err(Error::ParseError);
+        }
+        let seed = match incompat & INCOMPAT_CSUM_SEED {
+            0 => self.ext4_chksum(!0, &sb[S_UUID..S_UUID + 16])?,
+            _ => le::<4>(&sb, S_CHECKSUM_SEED) as u32,
+        };
+        let ext4 = Ext4 {
+            block_size,
+            inode_size,
+            inodes_per_group: le::<4>(&sb, S_INODES_PER_GROUP) as u32,
+            seed,
+        };
+
+        // Group descriptors follow the superblock, and must fit in the image
+        let groups = (blocks - first_data_block).div_ceil(blocks_per_group);
+        let gdt_offset = (first_data_block + 1)
+            .checked_mul(block_size)
+            .ok_or(Error::ParseError)?;
+        let gdt_size = groups
+            .checked_mul(desc_size as u64)
+            .filter(|size| gdt_offset.saturating_add(*size) <= self.size)
+            .ok_or(Error::ParseError)?;
+        let gdt = self.read_at(gdt_offset, gdt_size as usize)?;
+        let clusters_per_group = le::<4>(&sb, S_CLUSTERS_PER_GROUP) as usize;
+        for group in 0..groups as u32 {
+            let offset = group as usize * desc_size;
+            let desc = &gdt[offset..offset + desc_size];
+            let offset = gdt_offset + offset as u64;
+            let large = desc_size >= BG_LARGE_SIZE;
+            let block = |field: usize| match wide && large {
+                true => le::<4>(desc, field) | (le::<4>(desc, BG_HI + field) << 32),
+                false => le::<4>(desc, field),
+            };
+            let flags = le::<2>(desc, BG_FLAGS) as u16;
+
+            // Bitmap checksums are stored in group descriptors
+            let bitmaps = [
+                (
+                    Kind::BlockBitmap(group),
+                    BG_BLOCK_UNINIT,
+                    block(BG_BLOCK_BITMAP),
+                    clusters_per_group / 8,
+                    (BG_BLOCK_BITMAP_CSUM_LO, BG_BLOCK_BITMAP_CSUM_HI),
+                ),
+                (
+                    Kind::InodeBitmap(group),
+                    BG_INODE_UNINIT,
+                    block(BG_INODE_BITMAP),
+                    ext4.inodes_per_group as usize / 8,
+                    (BG_INODE_BITMAP_CSUM_LO, BG_INODE_BITMAP_CSUM_HI),
+                ),
+            ];
+            for (kind, uninit, bitmap, size, (lo, hi)) in bitmaps {
+                if flags & uninit != 0 {
+                    continue;
+                }
+                let mut parts = vec![(offset + lo as u64, 2)];
+                if large {
+                    parts.push((offset + hi as u64, 2));
+                }
+                let bitmap = bitmap * block_size;
+                self.push_field(kind, bitmap, size, parts, Algorithm::Ext4(seed), None)?;
+            }
+
+            let seed = self.ext4_chksum(seed, &group.to_le_bytes())?;
+            let parts = vec![(offset + BG_CHECKSUM as u64, 2)];
+            let slot = large.then_some(offset + BG_RESERVED as u64);
+            let kind = Kind::GroupDescriptor(group);
+            self.push_field(kind, offset, desc_size, parts, Algorithm::Ext4(seed), slot)?;
+
+            if flags & BG_INODE_UNINIT == 0 {
+                let bitmap = block(BG_INODE_BITMAP) * block_size;
+                let table = block(BG_INODE_TABLE) * block_size;
+                self.parse_inodes(&ext4, group, bitmap, table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse inodes of group `group` in use according to inode bitmap at offset `bitmap`, from inode table at offset
+    /// `table`.
+    fn parse_inodes(&mut self, ext4: &Ext4, group: u32, bitmap: u64, table: u64) -> CRCResult<()> {
+        let bitmap = self.read_at(bitmap, ext4.inodes_per_group.div_ceil(8) as usize)?;
+        for i in 0..ext4.inodes_per_group {
+            if (bitmap[i as usize / 8] >> (i % 8)) & 1 == 0 {
+                continue;
+            }
+            let ino = group * ext4.inodes_per_group + i + 1;
+            let offset = table + u64::from(i) * ext4.inode_size as u64;
+            let inode = self.read_at(offset, ext4.inode_size)?;
+
+            let seed = self.ext4_chksum(ext4.seed, &ino.to_le_bytes())?;
+            let seed = self.ext4_chksum(seed, &inode[I_GENERATION..I_GENERATION + 4])?;
+            let mut parts = vec![(offset + I_CHECKSUM_LO as u64, 2)];
+            let mut slot = None;
+            if ext4.inode_size > GOOD_OLD_INODE_SIZE {
+                let extra = le::<2>(&inode, I_EXTRA_ISIZE) as usize;
+                if extra >= 4 {
+                    parts.push((offset + I_CHECKSUM_HI as u64, 2));
+                }
+                // Space after extra fields is unused, unless it holds extended attributes
+                let end = GOOD_OLD_INODE_SIZE + extra;
+                if end + 8 <= ext4.inode_size && le::<4>(&inode, end) as u32 != XATTR_MAGIC {
+                    slot = Some(offset + ext4.inode_size as u64 - 4);
+                }
+            }
+            let algorithm = Algorithm::Ext4(seed);
+            self.push_field(
+                Kind::Inode(ino),
+                offset,
+                ext4.inode_size,
+                parts,
+                algorithm,
+                slot,
+            )?;
+
+            if le::<4>(&inode, I_FLAGS) as u32 & EXTENTS_FL != 0 {
+                let mut extents = Vec::new();
+                let root = &inode[I_BLOCK..I_BLOCK + 60];
+                self.parse_extents(ext4, ino, seed, root, MAX_EXTENT_DEPTH, &mut extents)?;
+                if le::<2>(&inode, I_MODE) as u16 & S_IFMT == S_IFDIR {
+                    self.parse_directory(ext4, ino, seed, &extents)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse extent tree node `node` of inode `ino`, whose checksum seed is `seed`, appending the physical block
+    /// ranges of its leaves to `extents`.
+    fn parse_extents(
+        &mut self,
+        ext4: &Ext4,
+        ino: u32,
+        seed: u32,
+        node: &[u8],
+        max_depth: u16,
+        extents: &mut Vec<(u64, u64)>,
+    ) -> CRCResult<()> {
+        if le::<2>(node, 0) as u16 != EXTENT_MAGIC {
+            return Err(Error::ParseError);
+        }
+        let entries = le::<2>(node, 2) as usize;
+        let depth = le::<2>(node, 6) as u16;
+        if depth > max_depth || EXTENT_ENTRY_SIZE * (entries + 1) > node.len() {
+            return Err(Error::ParseError);
+        }
+        for i in 1..=entries {
+            let entry = &node[EXTENT_ENTRY_SIZE * i..EXTENT_ENTRY_SIZE * (i + 1)];
+            if depth == 0 {
+                // Lengths above 32768 are those of uninitialized extents, read as zeros
+                let length = le::<2>(entry, 4);
+                let start = le::<4>(entry, 8) | (le::<2>(entry, 6) << 32);
+                if length <= 32768 {
+                    extents.push((start, length));
+                }
+                continue;
+            }
+            let block = (le::<4>(entry, 4) | (le::<2>(entry, 8) << 32)) * ext4.block_size;
+            let child = self.read_at(block, ext4.block_size as usize)?;
+            let size = EXTENT_ENTRY_SIZE * (le::<2>(&child, 4) as usize + 1);
+            if size + 4 > child.len() {
+                return Err(Error::ParseError);
+            }
+            let parts = vec![(block + size as u64, 4)];
+            let slot = Some(block + EH_GENERATION as u64);
+            let kind = Kind::ExtentBlock(ino);
+            self.push_field(kind, block, size, parts, Algorithm::Ext4(seed), slot)?;
+            self.parse_extents(ext4, ino, seed, &child, depth - 1, extents)?;
+        }
+        Ok(())
+    }
+
+    /// Parse directory blocks of directory inode `ino` in `extents`, whose leaf blocks end with a checksum entry.
+    fn parse_directory(
+        &mut self,
+        ext4: &Ext4,
+        ino: u32,
+        seed: u32,
+        extents: &[(u64, u64)],
+    ) -> CRCResult<()> {
+        let block_size = ext4.block_size as usize;
+        for (start, length) in extents {
+            for block in *start..start + length {
+                let offset = block * ext4.block_size;
+                let bytes = self.read_at(offset, block_size)?;
+                let tail = &bytes[block_size - DIRENT_TAIL_SIZE..];
+                if le::<4>(tail, 0) != 0
+                    || le::<2>(tail, 4) != DIRENT_TAIL_SIZE as u64
+                    || tail[6..8] != [0, DIRENT_TAIL_FT]
+                {
+                    continue;
+                }
+                let size = block_size - DIRENT_TAIL_SIZE;
+                let parts = vec![(offset + size as u64 + 8, 4)];
+                let slot = directory_slot(&bytes).map(|slot| offset + slot as u64);
+                let kind = Kind::DirectoryBlock(ino);
+                self.push_field(kind, offset, size, parts, Algorithm::Ext4(seed), slot)?;
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * XFS
+     */
+
+    /// Parse superblocks and allocation group headers of each XFS allocation group.
+    fn parse_xfs(&mut self) -> CRCResult<()> {
+        let sb = self.read_at(0, SB_CRC + 4)?;
+        if be::<2>(&sb, SB_VERSIONNUM) as u16 & 0xf != XFS_SB_VERSION_5 {
+            return Err(Error::UnsupportedError);
+        }
+        let sector = be::<2>(&sb, SB_SECTSIZE) as usize;
+        let ag_size = be::<4>(&sb, SB_BLOCKSIZE) * be::<4>(&sb, SB_AGBLOCKS);
+        if sector < AGI_PAD + 4 || ag_size == 0 {
+            return Err(Error::ParseError);
+        }
+        for ag in 0..be::<4>(&sb, SB_AGCOUNT) as u32 {
+            let offset = u64::from(ag) * ag_size;
+            let headers = [
+                (Kind::XfsSuperblock(ag), XFS_SB_MAGIC, SB_CRC, None),
+                (Kind::Agf(ag), XFS_AGF_MAGIC, AGF_CRC, Some(AGF_SPARE)),
+                (Kind::Agi(ag), XFS_AGI_MAGIC, AGI_CRC, Some(AGI_PAD)),
+                (Kind::Agfl(ag), XFS_AGFL_MAGIC, AGFL_CRC, None),
+            ];
+            for (i, (kind, magic, crc, slot)) in headers.into_iter().enumerate() {
+                let offset = offset + (i * sector) as u64;
+                if self.read_at(offset, 4)? != magic {
+                    return Err(Error::ParseError);
+                }
+                let parts = vec![(offset + crc as u64, 4)];
+                let slot = slot.map(|slot| offset + slot as u64);
+                self.push_field(kind, offset, sector, parts, Algorithm::Xfs, slot)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Write checksum `crc` of field `index`.
+    fn write_field(&mut self, index: usize, crc: u32) -> CRCResult<()> {
+        for (i, (offset, size)) in self.fields[index].parts.clone().into_iter().enumerate() {
+            let bytes = (crc >> (16 * i)).to_le_bytes();
+            self.write_at(offset, &bytes[..size])?;
+        }
+        let mut field = self.fields[index].clone();
+        self.read_field(&mut field)?;
+        self.fields[index] = field;
+        Ok(())
+    }
+
+    /// Rewrite invalid checksums, returning their number.
+    /// Checksums are listed before the structures holding them, so that they are rewritten first.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let mut repaired = 0;
+        for index in 0..self.fields.len() {
+            // The field may be covered by a checksum rewritten before
+            let mut field = self.fields[index].clone();
+            self.read_field(&mut field)?;
+            self.fields[index] = field;
+            if !self.fields[index].is_valid() {
+                self.write_field(index, self.fields[index].crc.computed)?;
+                repaired += 1;
+            }
+        }
+        Ok(repaired)
+    }
+
+    /// Forge checksum of field `index` to `target_crc`, by patching 4 reserved bytes of its structure, after
+    /// repairing the checksums it covers.
+    ///
+    /// The checksum is an affine function of the patched bytes: a linear system over F2 gives them from the effect of
+    /// each of their bits.
+    pub fn forge(&mut self, index: usize, target_crc: u32) -> CRCResult<()> {
+        let field = self.fields.get(index).ok_or(Error::OutOfBoundsError)?;
+        let slot = field.slot.ok_or(Error::UnsupportedError)?;
+        if target_crc & !field.mask() != 0 {
+            return Err(Error::OverflowError(None));
+        }
+        let range = field.offset..field.offset + field.size as u64;
+        for covered in 0..self.fields.len() {
+            let (offset, _) = self.fields[covered].parts[0];
+            if covered != index && range.contains(&offset) && !self.fields[covered].is_valid() {
+                self.write_field(covered, self.fields[covered].crc.computed)?;
+            }
+        }
+
+        let field = self.fields[index].clone();
+        let mut bytes = self.read_at(field.offset, field.size)?;
+        let start = (slot - field.offset) as usize;
+        let mut checksum = |x: u32| {
+            bytes[start..start + 4].copy_from_slice(&x.to_le_bytes());
+            self.checksum(&field, bytes.clone())
+        };
+        let crc0 = checksum(0)?;
+        let columns = (0..32)
+            .map(|bit| Ok(u64::from(checksum(1 << bit)? ^ crc0)))
+            .collect::<CRCResult<Vec<u64>>>()?;
+        let x =
+            solve_gf2(&columns, u64::from(target_crc ^ crc0)).ok_or(Error::NonInvertibleError)?;
+        self.write_at(slot, &(x as u32).to_le_bytes())?;
+        self.write_field(index, target_crc)
+    }
+}
+
+/// Offset of 4 unused bytes at the end of the last entry of directory leaf block `block`, if any.
+fn directory_slot(block: &[u8]) -> Option<usize> {
+    let end = block.len() - DIRENT_TAIL_SIZE;
+    let mut offset = 0;
+    while offset + 8 <= end {
+        let rec_len = le::<2>(block, offset + 4) as usize;
+        if rec_len < 8 {
+            return None;
+        }
+        if offset + rec_len == end {
+            // Entries are made of an inode number, lengths, a file type and a name padded to 4 bytes
+            let used = match le::<4>(block, offset) {
+                0 => 0,
+                _ => (8 + block[offset + 6] as usize).next_multiple_of(4),
+            };
+            return (used + 4 <= rec_len).then_some(end - 4);
+        }
+        offset += rec_len;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::error::Error;
+    use crate::formats::fsimage::{FsImage, Kind};
+
+    /// Build image of an ext4 filesystem of 16 blocks of 1024 bytes and 16 inodes, with a root directory, whose
+    /// checksums are left null.
+    fn ext4() -> Vec<u8> {
+        let mut image = vec![0; 16 * 1024];
+        let sb = &mut image[1024..2048];
+        for (offset, value) in [
+            (0x04, 16),
+            (0x14, 1),
+            (0x20, 8192),
+            (0x24, 8192),
+            (0x28, 16),
+        ] {
+            sb[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(value));
+        }
+        sb[0x38..0x3a].copy_from_slice(&0xef53u16.to_le_bytes());
+        sb[0x58..0x5a].copy_from_slice(&256u16.to_le_bytes());
+        sb[0x60..0x64].copy_from_slice(&0x80u32.to_le_bytes());
+        sb[0x64..0x68].copy_from_slice(&0x400u32.to_le_bytes());
+        sb[0x68..0x78].copy_from_slice(b"0123456789abcdef");
+        sb[0xfe..0x100].copy_from_slice(&64u16.to_le_bytes());
+
+        // Group descriptor, bitmaps at blocks 3 and 4, inode table at block 5, root directory at block 9
+        image[2048..2060].copy_from_slice(b"\x03\0\0\0\x04\0\0\0\x05\0\0\0");
+        image[3 * 1024..3 * 1024 + 2].copy_from_slice(&[0xff, 0x03]);
+        image[4 * 1024] = 0x03;
+        let root = &mut image[5 * 1024 + 256..5 * 1024 + 512];
+        root[..2].copy_from_slice(&0x41edu16.to_le_bytes());
+        root[0x20..0x24].copy_from_slice(&0x80000u32.to_le_bytes());
+        root[0x28..0x34].copy_from_slice(b"\x0a\xf3\x01\0\x04\0\0\0\0\0\0\0");
+        root[0x34..0x40].copy_from_slice(b"\0\0\0\0\x01\0\0\0\x09\0\0\0");
+        root[0x80] = 32;
+        let dir = &mut image[9 * 1024..10 * 1024];
+        dir[..12].copy_from_slice(b"\x02\0\0\0\x0c\0\x01\x02.\0\0\0");
+        dir[12..24].copy_from_slice(b"\x02\0\0\0\xe8\x03\x02\x02..\0\0");
+        dir[1012..1020].copy_from_slice(b"\0\0\0\0\x0c\0\0\xde");
+        image
+    }
+
+    #[test]
+    pub fn test_ext4() {
+        let mut fs = FsImage::parse(Cursor::new(ext4())).expect("Failed to parse ext4 image");
+        let kinds = fs.fields().iter().map(|f| f.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                Kind::Superblock,
+                Kind::BlockBitmap(0),
+                Kind::InodeBitmap(0),
+                Kind::GroupDescriptor(0),
+                Kind::Inode(1),
+                Kind::Inode(2),
+                Kind::DirectoryBlock(2)
+            ]
+        );
+        assert_eq!(fs.repair().unwrap(), 7);
+        assert!(fs.fields().iter().all(|f| f.is_valid()));
+
+        // Superblock checksum is the CRC32C of the superblock, without final inversion
+        let crc = CRC32::new(CRC32Properties::castagnoli()).unwrap();
+        let image = fs.image.into_inner();
+        let checksum = !crc.checksum(image[1024..2044].bytes()).unwrap();
+        assert_eq!(image[2044..2048], checksum.to_le_bytes());
+
+        // Bitmap checksums are covered by the group descriptor checksum
+        let mut edited = image.clone();
+        edited[2048 + 0x1a] ^= 1;
+        edited[3 * 1024 + 5] = 0x01;
+        let mut fs = FsImage::parse(Cursor::new(edited)).unwrap();
+        let invalid = fs.fields().iter().filter(|f| !f.is_valid()).map(|f| f.kind);
+        let invalid = invalid.collect::<Vec<_>>();
+        assert_eq!(
+            invalid,
+            [
+                Kind::BlockBitmap(0),
+                Kind::InodeBitmap(0),
+                Kind::GroupDescriptor(0)
+            ]
+        );
+        assert_eq!(fs.repair().unwrap(), 3);
+        assert!(
+            FsImage::parse(fs.image)
+                .unwrap()
+                .fields()
+                .iter()
+                .all(|f| f.is_valid())
+        );
+    }
+
+    #[test]
+    pub fn test_oversized_ext4() {
+        // Block count beyond the image, whose group descriptors cannot fit in it
+        let mut image = ext4();
+        image[1024 + 0x150..1024 + 0x154].copy_from_slice(&0x00ffffffu32.to_le_bytes());
+        assert!(matches!(
+            FsImage::parse(Cursor::new(image)),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    pub fn test_forge_ext4() {
+        let mut fs = FsImage::parse(Cursor::new(ext4())).expect("Failed to parse ext4 image");
+        fs.repair().unwrap();
+        for (index, target) in [
+            (0, 0x42424242),
+            (3, 0x1337),
+            (5, 0xdeadbeef),
+            (6, 0xcafebabe),
+        ] {
+            fs.forge(index, target).expect("Failed to forge checksum");
+        }
+        assert!(fs.forge(3, 0x10000).is_err());
+        assert!(fs.forge(1, 0).is_err());
+        let fs = FsImage::parse(fs.image).unwrap();
+        assert!(fs.fields().iter().all(|f| f.is_valid()));
+        let stored = fs.fields().iter().map(|f| f.crc.stored).collect::<Vec<_>>();
+        assert_eq!(
+            [stored[0], stored[3], stored[5], stored[6]],
+            [0x42424242, 0x1337, 0xdeadbeef, 0xcafebabe]
+        );
+    }
+
+    /// Build image of a 2 AG XFS filesystem of 4 blocks of 4096 bytes per AG, with 512 byte sectors.
+    fn xfs() -> Vec<u8> {
+        let crc = CRC32::new(CRC32Properties::castagnoli()).unwrap();
+        let mut image = vec![0; 2 * 4 * 4096];
+        for ag in 0..2 {
+            for (i, (magic, field)) in [
+                (b"XFSB", 224),
+                (b"XAGF", 216),
+                (b"XAGI", 312),
+                (b"XAFL", 32),
+            ]
+            .iter()
+            .enumerate()
+            {
+                let offset = ag * 4 * 4096 + i * 512;
+                let sector = &mut image[offset..offset + 512];
+                sector[..4].copy_from_slice(*magic);
+                if i == 0 {
+                    sector[4..8].copy_from_slice(&4096u32.to_be_bytes());
+                    sector[84..88].copy_from_slice(&4u32.to_be_bytes());
+                    sector[88..92].copy_from_slice(&2u32.to_be_bytes());
+                    sector[100..104].copy_from_slice(b"\xb4\xa5\x02\x00");
+                    sector[108..112].copy_from_slice(b"data");
+                }
+                let c = crc.checksum(sector.bytes()).unwrap();
+                sector[*field..field + 4].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        image
+    }
+
+    #[test]
+    pub fn test_parse_xfs() {
+        let fs = FsImage::parse(Cursor::new(xfs())).expect("Failed to parse XFS image");
+        let kinds = fs.fields().iter().map(|f| f.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                Kind::XfsSuperblock(0),
+                Kind::Agf(0),
+                Kind::Agi(0),
+                Kind::Agfl(0),
+                Kind::XfsSuperblock(1),
+                Kind::Agf(1),
+                Kind::Agi(1),
+                Kind::Agfl(1)
+            ]
+        );
+        assert!(fs.fields().iter().all(|f| f.is_valid()));
+        assert!(FsImage::parse(Cursor::new(vec![0; 4096])).is_err());
+    }
+
+    #[test]
+    pub fn test_repair_xfs() {
+        let mut image = xfs();
+        image[108] = b'D';
+        image[4 * 4096 + 512 + 100] = 1;
+        let mut fs = FsImage::parse(Cursor::new(image)).expect("Failed to parse XFS image");
+        let invalid = fs.fields().iter().filter(|f| !f.is_valid()).map(|f| f.kind);
+        assert_eq!(
+            invalid.collect::<Vec<_>>(),
+            [Kind::XfsSuperblock(0), Kind::Agf(1)]
+        );
+        assert_eq!(fs.repair().unwrap(), 2);
+        let fs = FsImage::parse(fs.image).unwrap();
+        assert!(fs.fields().iter().all(|f| f.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge_xfs() {
+        let mut fs = FsImage::parse(Cursor::new(xfs())).expect("Failed to parse XFS image");
+        fs.forge(1, 0x42424242).expect("Failed to forge AGF");
+        fs.forge(6, 0x13371337).expect("Failed to forge AGI");
+        assert!(fs.forge(0, 0).is_err());
+        let fs = FsImage::parse(fs.image).unwrap();
+        assert!(fs.fields().iter().all(|f| f.is_valid()));
+        assert_eq!(
+            (fs.fields()[1].crc.stored, fs.fields()[6].crc.stored),
+            (0x42424242, 0x13371337)
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    fmt::Display,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::formats::le;
+
+/// Signature starting GPT headers.
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// Supported logical sector sizes.
+const SECTOR_SIZES: [u64; 2] = [512, 4096];
+
+/// Minimum size of GPT headers.
+const MIN_HEADER_SIZE: usize = 92;
+
+/// Offsets of fields in GPT headers.
+const HEADER_CRC: usize = 16;
+const RESERVED: usize = 20; // 4 reserved bytes, used to forge the header CRC
+const ALTERNATE_LBA: usize = 32;
+const ENTRIES_LBA: usize = 72;
+const ENTRIES_COUNT: usize = 80;
+const ENTRY_SIZE: usize = 84;
+const ENTRIES_CRC: usize = 88;
+
+/// Maximum size of partition entry arrays.
+const MAX_ENTRIES_SIZE: usize = 1 << 24;
+
+/// GPT header, either primary or backup, with stored and actual CRCs of the header and of the partition entry array.
+#[derive(Clone)]
+pub struct Header {
+    pub offset: u64, // Offset of the header in image
+    pub backup: bool,
+    bytes: Vec<u8>,      // Header bytes, over which the header CRC is computed
+    entries_offset: u64, // Offset of the partition entry array in image
+    entries_size: usize, // Size of the partition entry array
+    pub crc: Crc<u32>,   // Header CRC
+    pub entries_crc: Crc<u32>,
+}
+
+impl Header {
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid() && self.entries_crc.is_valid()
+    }
+}
+
+impl Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.backup { "backup" } else { "primary" };
+        write!(
+            f,
+            "0x{:012x}  {:<7}  header crc={}  entries at 0x{:012x} ({} bytes) crc={}",
+            self.offset, kind, self.crc, self.entries_offset, self.entries_size, self.entries_crc
+        )
+    }
+}
+
+/// GUID partition table of a disk image, accessed in place so that images of any size are supported.
+pub struct Gpt<F: Read + Write + Seek> {
+    image: F,
+    headers: Vec<Header>,
+    crc: CRC32,
+}
+
+impl<F: Read + Write + Seek> Gpt<F> {
+    /// Parse primary and backup GPT headers of disk image `image`.
+    /// The backup header is found from the primary one, or at the last sector of the image.
+    pub fn parse(image: F) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let mut gpt = Self {
+            image,
+            headers: Vec::new(),
+            crc,
+        };
+
+        let (sector, primary) = SECTOR_SIZES
+            .iter()
+            .find_map(|sector| Some((*sector, gpt.parse_header(*sector, 1, false).ok()?)))
+            .ok_or(Error::ParseError)?;
+        let alternate = le::<8>(&primary.bytes, ALTERNATE_LBA);
+        gpt.headers.push(primary);
+
+        let last = gpt.image.seek(SeekFrom::End(0))? / sector - 1;
+        let backup = gpt
+            .parse_header(sector, alternate, true)
+            .or_else(|_| gpt.parse_header(sector, last, true));
+        gpt.headers.extend(backup.ok());
+        Ok(gpt)
+    }
+
+    fn read_at(&mut self, offset: u64, size: usize) -> CRCResult<Vec<u8>> {
+        let mut bytes = vec![0; size];
+        self.image.seek(SeekFrom::Start(offset))?;
+        self.image.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> CRCResult<()> {
+        self.image.seek(SeekFrom::Start(offset))?;
+        self.image.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Compute header CRC of header `bytes`, with a null CRC field.
+    fn header_crc(&self, bytes: &[u8]) -> CRCResult<u32> {
+        let mut bytes = bytes.to_vec();
+        bytes[HEADER_CRC..HEADER_CRC + 4].fill(0);
+        self.crc.checksum(bytes.bytes())
+    }
+
+    /// Parse GPT header at sector `lba` of `sector` bytes, and CRC its partition entry array.
+    fn parse_header(&mut self, sector: u64, lba: u64, backup: bool) -> CRCResult<Header> {
+        let offset = lba.checked_mul(sector).ok_or(Error::ParseError)?;
+        let start = self.read_at(offset, MIN_HEADER_SIZE)?;
+        if !start.starts_with(SIGNATURE) {
+            return Err(Error::ParseError);
+        }
+        let size = le::<4>(&start, 12) as usize;
+        if !(MIN_HEADER_SIZE..=sector as usize).contains(&size) {
+            return Err(Error::ParseError);
+        }
+        let bytes = self.read_at(offset, size)?;
+
+        let entries_offset = le::<8>(&bytes, ENTRIES_LBA)
+            .checked_mul(sector)
+            .ok_or(Error::ParseError)?;
+        let entries_size = (le::<4>(&bytes, ENTRIES_COUNT) * le::<4>(&bytes, ENTRY_SIZE)) as usize;
+        if entries_size > MAX_ENTRIES_SIZE {
+            return Err(Error::ParseError);
+        }
+        let entries = self.read_at(entries_offset, entries_size)?;
+
+        Ok(Header {
+            offset,
+            backup,
+            crc: Crc::new(le::<4>(&bytes, HEADER_CRC) as u32, self.header_crc(&bytes)?),
+            entries_offset,
+            entries_size,
+            entries_crc: Crc::new(
+                le::<4>(&bytes, ENTRIES_CRC) as u32,
+                self.crc.checksum(entries.bytes())?,
+            ),
+            bytes,
+        })
+    }
+
+    pub fn headers(&self) -> &[Header] {
+        &self.headers
+    }
+
+    /// Write header `index` bytes, after updating its CRC.
+    fn write_header(&mut self, index: usize) -> CRCResult<()> {
+        let header = &self.headers[index];
+        let crc = self.header_crc(&header.bytes)?;
+        let header = &mut self.headers[index];
+        header.bytes[HEADER_CRC..HEADER_CRC + 4].copy_from_slice(&crc.to_le_bytes());
+        header.crc.stored = crc;
+        header.crc.computed = crc;
+        let (offset, bytes) = (header.offset, header.bytes.clone());
+        self.write_at(offset, &bytes)
+    }
+
+    /// Store the actual partition entry array CRC in header `index`, without updating the header CRC.
+    fn update_entries_crc(&mut self, index: usize) {
+        let header = &mut self.headers[index];
+        let crc = header.entries_crc.computed;
+        header.bytes[ENTRIES_CRC..ENTRIES_CRC + 4].copy_from_slice(&crc.to_le_bytes());
+        header.entries_crc.stored = crc;
+    }
+
+    /// Rewrite partition entry array CRCs, then header CRCs, of all invalid headers.
+    /// Returns the number of repaired headers.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let invalid = (0..self.headers.len())
+            .filter(|i| !self.headers[*i].is_valid())
+            .collect::<Vec<_>>();
+        for i in &invalid {
+            // Header CRC covers the partition entry array CRC
+            self.update_entries_crc(*i);
+            self.write_header(*i)?;
+        }
+        Ok(invalid.len())
+    }
+
+    /// Forge CRC of header `index` to `target_crc` by patching its reserved bytes, after storing the actual partition
+    /// entry array CRC.
+    pub fn forge_header(&mut self, index: usize, target_crc: u32) -> CRCResult<()> {
+        if index >= self.headers.len() {
+            return Err(Error::OutOfBoundsError);
+        }
+        self.update_entries_crc(index);
+        let header = &self.headers[index];
+        let mut bytes = header.bytes.clone();
+        bytes[HEADER_CRC..HEADER_CRC + 4].fill(0);
+        let patch = self.crc.compute_patch(&bytes, RESERVED, target_crc)?;
+
+        let header = &mut self.headers[index];
+        header.bytes[RESERVED..RESERVED + 4].copy_from_slice(&patch);
+        self.write_header(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::gpt::Gpt;
+
+    /// Build 64 sector image with a primary and a backup GPT, of 512 byte sectors and 4 partition entries.
+    fn image() -> Vec<u8> {
+        let crc = CRC32::new(CRC32Properties::default()).unwrap();
+        let mut image = vec![0; 64 * 512];
+        let mut entries = vec![0; 4 * 128];
+        entries[..16].copy_from_slice(b"partition type g");
+        entries[32..48].copy_from_slice(&[1; 16]);
+        entries[56..64].copy_from_slice(b"l\0o\0r\0e\0");
+        let entries_crc = crc.checksum(entries.bytes()).unwrap();
+
+        for (lba, alternate, entries_lba) in [(1u64, 63u64, 2u64), (63, 1, 62)] {
+            let mut header = b"EFI PART\0\0\x01\0".to_vec();
+            header.extend(92u32.to_le_bytes());
+            header.extend([0; 8]);
+            for field in [lba, alternate, 34, 61] {
+                header.extend(field.to_le_bytes());
+            }
+            header.extend([0x42; 16]);
+            header.extend(entries_lba.to_le_bytes());
+            header.extend(4u32.to_le_bytes());
+            header.extend(128u32.to_le_bytes());
+            header.extend(entries_crc.to_le_bytes());
+            let c = crc.checksum(header.bytes()).unwrap();
+            header[16..20].copy_from_slice(&c.to_le_bytes());
+
+            let offset = lba as usize * 512;
+            image[offset..offset + 92].copy_from_slice(&header);
+            let offset = entries_lba as usize * 512;
+            image[offset..offset + entries.len()].copy_from_slice(&entries);
+        }
+        image
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let gpt = Gpt::parse(Cursor::new(image())).expect("Failed to parse GPT");
+        let headers = gpt.headers();
+        assert_eq!(headers.len(), 2);
+        assert_eq!((headers[0].offset, headers[1].offset), (512, 63 * 512));
+        assert!(headers[1].backup);
+        assert!(headers.iter().all(|h| h.is_valid()));
+        assert!(Gpt::parse(Cursor::new(vec![0; 4096])).is_err());
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut image = image();
+        // Edit a partition name in both entry arrays, and the disk GUID of the backup header
+        image[2 * 512 + 56] = b'L';
+        image[62 * 512 + 56] = b'L';
+        image[63 * 512 + 56] = 0x13;
+        let mut gpt = Gpt::parse(Cursor::new(image)).expect("Failed to parse GPT");
+        assert!(gpt.headers().iter().all(|h| !h.is_valid()));
+        assert_eq!(gpt.repair().unwrap(), 2);
+        let gpt = Gpt::parse(gpt.image).unwrap();
+        assert!(gpt.headers().iter().all(|h| h.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge_header() {
+        let mut image = image();
+        image[2 * 512 + 56] = b'L';
+        let mut gpt = Gpt::parse(Cursor::new(image)).expect("Failed to parse GPT");
+        gpt.forge_header(0, 0x42424242)
+            .expect("Failed to forge header");
+        gpt.forge_header(1, 0x13371337)
+            .expect("Failed to forge header");
+        assert!(gpt.forge_header(2, 0).is_err());
+
+        let gpt = Gpt::parse(gpt.image).unwrap();
+        assert!(gpt.headers().iter().all(|h| h.is_valid()));
+        assert_eq!(gpt.headers()[0].crc.stored, 0x42424242);
+        assert_eq!(gpt.headers()[1].crc.stored, 0x13371337);
+    }
+}
@@ -0,0 +1,462 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::formats::deflate::{deflate_stored, inflate};
+use crate::generic::CRC;
+use crate::math::solve_gf2;
+
+/// Magic number and compression method (deflate) starting gzip members.
+const GZIP_MAGIC: &[u8; 3] = b"\x1f\x8b\x08";
+
+/// Size of the fixed part of member headers.
+const HEADER_SIZE: usize = 10;
+
+/// Size of member trailers: CRC and size of the uncompressed payload.
+const TRAILER_SIZE: usize = 8;
+
+/// Header flags.
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+/// Subfield identifier of the extra field subfield used to forge header CRCs.
+const FORGE_SUBFIELD: &[u8; 2] = b"cf";
+
+/// Number of bytes adjusted to forge header CRCs.
+const FORGE_SIZE: usize = 4;
+
+/// Layout of a member header.
+struct Header {
+    flags: u8,
+    extra: Option<Range<usize>>,   // Extra field, without its length
+    comment: Option<Range<usize>>, // Comment, without its terminating null byte
+    end: usize,                    // End of header, before the header CRC field
+}
+
+/// Parse member header at the start of `data`.
+fn parse_header(data: &[u8]) -> CRCResult<Header> {
+    if !data.starts_with(GZIP_MAGIC) || data.len() < HEADER_SIZE {
+        return Err(Error::ParseError);
+    }
+    let flags = data[3];
+    let mut offset = HEADER_SIZE;
+    let mut extra = None;
+    if flags & FEXTRA != 0 {
+        let length = data.get(offset..offset + 2).ok_or(Error::ParseError)?;
+        let length = u16::from_le_bytes([length[0], length[1]]) as usize;
+        extra = Some(offset + 2..offset + 2 + length);
+        offset += 2 + length;
+    }
+    // Null-terminated strings
+    let mut string = |flag: u8| -> CRCResult<Option<Range<usize>>> {
+        if flags & flag == 0 {
+            return Ok(None);
+        }
+        let rest = data.get(offset..).ok_or(Error::ParseError)?;
+        let length = rest.iter().position(|b| *b == 0).ok_or(Error::ParseError)?;
+        let range = offset..offset + length;
+        offset += length + 1;
+        Ok(Some(range))
+    };
+    string(FNAME)?;
+    let comment = string(FCOMMENT)?;
+    if offset > data.len() {
+        return Err(Error::ParseError);
+    }
+    Ok(Header {
+        flags,
+        extra,
+        comment,
+        end: offset,
+    })
+}
+
+/// gzip member, with its stored and actual CRCs.
+#[derive(Clone)]
+pub struct Member {
+    pub offset: usize, // Offset of the member in file
+    header_end: usize, // End of header, before the header CRC field
+    stream: Range<usize>,
+    payload: Vec<u8>,
+    pub header_crc: Option<Crc<u16>>, // Header CRC, when present
+    pub stored: (u32, u32),           // CRC and size stored in trailer
+    pub computed: (u32, u32),         // CRC and size of the uncompressed payload
+}
+
+impl Member {
+    pub fn is_valid(&self) -> bool {
+        self.stored == self.computed
+            && self
+                .header_crc
+                .is_none_or(|header_crc| header_crc.is_valid())
+    }
+
+    /// Range of the member in file.
+    fn range(&self) -> Range<usize> {
+        self.offset..self.stream.end + TRAILER_SIZE
+    }
+}
+
+impl Display for Member {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  {} bytes  crc=0x{:08x}  isize={}",
+            self.offset,
+            self.payload.len(),
+            self.stored.0,
+            self.stored.1
+        )?;
+        if let Some(header_crc) = self.header_crc {
+            write!(f, "  hcrc=0x{:04x}", header_crc.stored)?;
+        }
+        if self.is_valid() {
+            return write!(f, "  ok");
+        }
+        write!(
+            f,
+            "  BAD (expected crc=0x{:08x}  isize={}",
+            self.computed.0, self.computed.1
+        )?;
+        if let Some(header_crc) = self.header_crc {
+            write!(f, "  hcrc=0x{:04x}", header_crc.computed)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// gzip file, split into members.
+/// Any data following the last member is kept as is.
+pub struct Gzip {
+    data: Vec<u8>,
+    members: Vec<Member>,
+    crc: CRC32,
+    header_crc: CRC, // CRC-32 whose 16 least significant bits are the header CRC
+}
+
+impl Gzip {
+    /// Parse gzip file `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let header_crc = CRC::new(CRC32Properties::default().into())?;
+        let mut gzip = Self {
+            data,
+            members: Vec::new(),
+            crc,
+            header_crc,
+        };
+        gzip.parse_members()?;
+        Ok(gzip)
+    }
+
+    /// Compute header CRC of `header`, up to the header CRC field.
+    fn compute_header_crc(&self, header: &[u8]) -> CRCResult<u16> {
+        Ok(self.header_crc.checksum(header.bytes())? as u16)
+    }
+
+    /// Parse member at offset `offset`, inflating its payload.
+    fn parse_member(&self, offset: usize) -> CRCResult<Member> {
+        let data = &self.data[offset..];
+        let header = parse_header(data)?;
+        let mut start = header.end;
+        let mut header_crc = None;
+        if header.flags & FHCRC != 0 {
+            let stored = data.get(start..start + 2).ok_or(Error::ParseError)?;
+            let stored = u16::from_le_bytes([stored[0], stored[1]]);
+            header_crc = Some(Crc::new(stored, self.compute_header_crc(&data[..start])?));
+            start += 2;
+        }
+
+        let (payload, size) = inflate(&data[start..])?;
+        let trailer = data
+            .get(start + size..start + size + TRAILER_SIZE)
+            .ok_or(Error::ParseError)?;
+        let field = |i: usize| u32::from_le_bytes(trailer[i..i + 4].try_into().unwrap());
+        let computed = (
+            self.crc.checksum(payload.bytes())?,
+            payload.len() as u32, // Size modulo 2^32
+        );
+        Ok(Member {
+            offset,
+            header_end: offset + header.end,
+            stream: offset + start..offset + start + size,
+            payload,
+            header_crc,
+            stored: (field(0), field(4)),
+            computed,
+        })
+    }
+
+    /// Parse members, up to the end of file or to trailing data which is not a member.
+    fn parse_members(&mut self) -> CRCResult<()> {
+        let mut members = vec![self.parse_member(0)?];
+        loop {
+            let offset = members.last().unwrap().range().end;
+            if !self.data[offset..].starts_with(GZIP_MAGIC) {
+                break;
+            }
+            members.push(self.parse_member(offset)?);
+        }
+        self.members = members;
+        Ok(())
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Rewrite trailer and header CRC of all invalid members, returning the number of repaired members.
+    pub fn repair(&mut self) -> usize {
+        let mut repaired = 0;
+        for member in self.members.iter_mut().filter(|member| !member.is_valid()) {
+            let (crc, size) = member.computed;
+            let end = member.stream.end;
+            self.data[end..end + 4].copy_from_slice(&crc.to_le_bytes());
+            self.data[end + 4..end + 8].copy_from_slice(&size.to_le_bytes());
+            member.stored = member.computed;
+            if let Some(header_crc) = member.header_crc.as_mut() {
+                let computed = header_crc.computed;
+                let start = member.header_end;
+                self.data[start..start + 2].copy_from_slice(&computed.to_le_bytes());
+                header_crc.stored = computed;
+            }
+            repaired += 1;
+        }
+        repaired
+    }
+
+    /// Replace member `index` by one made of `header` (up to the header CRC field), deflate stream `stream` and
+    /// uncompressed payload `payload`, then parse members again.
+    fn replace_member(
+        &mut self,
+        index: usize,
+        header: &[u8],
+        stream: &[u8],
+        payload: &[u8],
+    ) -> CRCResult<()> {
+        let mut member = header.to_vec();
+        if header[3] & FHCRC != 0 {
+            member.extend(self.compute_header_crc(header)?.to_le_bytes());
+        }
+        member.extend(stream);
+        member.extend(self.crc.checksum(payload.bytes())?.to_le_bytes());
+        member.extend((payload.len() as u32).to_le_bytes());
+        self.data.splice(self.members[index].range(), member);
+        self.parse_members()
+    }
+
+    /// Forge the CRC of member `index` payload to `target_crc`, by patching 4 bytes at offset `offset` of the
+    /// payload, or by appending 4 bytes to it by default. The payload is stored again as uncompressed deflate blocks.
+    pub fn forge_payload(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let member = self.members.get(index).ok_or(Error::OutOfBoundsError)?;
+        let mut payload = member.payload.clone();
+        match offset {
+            Some(offset) => {
+                let patch = self.crc.compute_patch(&payload, offset, target_crc)?;
+                payload[offset..offset + 4].copy_from_slice(&patch);
+            }
+            None => {
+                let suffix = self.crc.compute_suffix(payload.bytes(), target_crc)?;
+                payload.extend(suffix);
+            }
+        }
+        let header = self.data[member.offset..member.header_end].to_vec();
+        self.replace_member(index, &header, &deflate_stored(&payload), &payload)
+    }
+
+    /// Forge the header CRC of member `index` to `target_crc`, adding the header CRC field if missing.
+    /// Bytes are adjusted in a dedicated extra subfield, or at the end of the comment if `comment` is set, both being
+    /// added if missing. Comment bytes keep their most significant bit set, so that none of them is null.
+    ///
+    /// The header CRC is the 16 least significant bits of the header CRC-32, which is an affine function of the
+    /// adjusted bytes: a linear system over F2 gives them from the effect of each of their bits.
+    pub fn forge_header(&mut self, index: usize, target_crc: u16, comment: bool) -> CRCResult<()> {
+        let member = self.members.get(index).ok_or(Error::OutOfBoundsError)?;
+        let mut header = self.data[member.offset..member.header_end].to_vec();
+        header[3] |= FHCRC;
+        let layout = parse_header(&header)?;
+        let (slot, base) = match comment {
+            true => (comment_slot(&mut header, &layout), 0x80),
+            false => (extra_slot(&mut header, &layout), 0),
+        };
+
+        // Unknowns are the bits of adjusted bytes not set in `base`
+        let bits = (0..8 * FORGE_SIZE)
+            .filter(|bit| (base >> (bit % 8)) & 1 == 0)
+            .collect::<Vec<_>>();
+        let place = |x: u128| {
+            let mut bytes = [base; FORGE_SIZE];
+            for (j, bit) in bits.iter().enumerate() {
+                if (x >> j) & 1 == 1 {
+                    bytes[bit / 8] |= 1 << (bit % 8);
+                }
+            }
+            bytes
+        };
+        let header_crc = |x: u128| -> CRCResult<u16> {
+            let mut header = header.clone();
+            header[slot..slot + FORGE_SIZE].copy_from_slice(&place(x));
+            self.compute_header_crc(&header)
+        };
+        let crc0 = header_crc(0)?;
+        let columns = (0..bits.len())
+            .map(|j| Ok(u64::from(header_crc(1 << j)? ^ crc0)))
+            .collect::<CRCResult<Vec<u64>>>()?;
+        let x =
+            solve_gf2(&columns, u64::from(target_crc ^ crc0)).ok_or(Error::NonInvertibleError)?;
+        header[slot..slot + FORGE_SIZE].copy_from_slice(&place(x));
+
+        let member = &self.members[index];
+        let stream = self.data[member.stream.clone()].to_vec();
+        let payload = member.payload.clone();
+        self.replace_member(index, &header, &stream, &payload)
+    }
+}
+
+/// Find the subfield used to forge the header CRC in the extra field of `header`, adding it and the extra field if
+/// missing. Returns the offset of the subfield data.
+fn extra_slot(header: &mut Vec<u8>, layout: &Header) -> usize {
+    let extra = match &layout.extra {
+        Some(extra) => extra.clone(),
+        None => {
+            header[3] |= FEXTRA;
+            header.splice(HEADER_SIZE..HEADER_SIZE, [0, 0]);
+            HEADER_SIZE + 2..HEADER_SIZE + 2
+        }
+    };
+
+    // Subfields are made of an identifier, a length and data
+    let mut i = extra.start;
+    while i + 4 <= extra.end {
+        let length = u16::from_le_bytes([header[i + 2], header[i + 3]]) as usize;
+        if &header[i..i + 2] == FORGE_SUBFIELD && length == FORGE_SIZE {
+            return i + 4;
+        }
+        i += 4 + length;
+    }
+    let subfield = [
+        &FORGE_SUBFIELD[..],
+        &(FORGE_SIZE as u16).to_le_bytes(),
+        &[0; FORGE_SIZE],
+    ]
+    .concat();
+    let length = (extra.len() + subfield.len()) as u16;
+    header[extra.start - 2..extra.start].copy_from_slice(&length.to_le_bytes());
+    header.splice(extra.end..extra.end, subfield);
+    extra.end + 4
+}
+
+/// Pad the comment of `header` with non-null bytes up to the number of adjusted bytes, adding it if missing.
+/// Returns the offset of the last bytes of the comment.
+fn comment_slot(header: &mut Vec<u8>, layout: &Header) -> usize {
+    let comment = match &layout.comment {
+        Some(comment) => comment.clone(),
+        None => {
+            header[3] |= FCOMMENT;
+            header.insert(layout.end, 0);
+            layout.end..layout.end
+        }
+    };
+    let missing = FORGE_SIZE.saturating_sub(comment.len());
+    header.splice(comment.end..comment.end, vec![0x80; missing]);
+    comment.end + missing - FORGE_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::Crc;
+    use crate::formats::deflate::deflate_stored;
+    use crate::formats::gzip::Gzip;
+
+    /// Build gzip member of `payload` with header flags `flags` followed by optional header fields `fields`.
+    fn member(payload: &[u8], flags: u8, fields: &[u8]) -> Vec<u8> {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        let mut data = [&b"\x1f\x8b\x08"[..], &[flags], &[0; 6], fields].concat();
+        if flags & 2 != 0 {
+            let crc = crc32.checksum(data.bytes()).unwrap() as u16;
+            data.extend(crc.to_le_bytes());
+        }
+        data.extend(deflate_stored(payload));
+        data.extend(crc32.checksum(payload.bytes()).unwrap().to_le_bytes());
+        data.extend((payload.len() as u32).to_le_bytes());
+        data
+    }
+
+    fn gzip() -> Vec<u8> {
+        [
+            member(b"lorem ipsum dolor sit amet", 0x08, b"lorem.txt\0"),
+            member(b"consectetur adipiscing elit", 0x12, b"comment\0"),
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let gzip = Gzip::parse(gzip()).expect("Failed to parse gzip");
+        assert_eq!(gzip.members().len(), 2);
+        assert!(gzip.members().iter().all(|m| m.is_valid()));
+        assert!(gzip.members()[0].header_crc.is_none());
+        assert!(gzip.members()[1].header_crc.is_some());
+        assert!(Gzip::parse(b"\x1f\x8b\x08\x00".to_vec()).is_err());
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = gzip();
+        let offset = data.windows(5).position(|w| w == b"dolor").unwrap();
+        data[offset] = b'D';
+        let offset = data.windows(7).position(|w| w == b"comment").unwrap();
+        data[offset] = b'C';
+        let mut gzip = Gzip::parse(data).expect("Failed to parse gzip");
+        assert!(gzip.members().iter().all(|m| !m.is_valid()));
+        assert_eq!(gzip.repair(), 2);
+        let gzip = Gzip::parse(gzip.data().to_vec()).unwrap();
+        assert!(gzip.members().iter().all(|m| m.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge_payload() {
+        let mut gzip = Gzip::parse(gzip()).expect("Failed to parse gzip");
+        gzip.forge_payload(0, Some(6), 0x42424242)
+            .expect("Failed to forge payload");
+        gzip.forge_payload(1, None, 0x13371337)
+            .expect("Failed to forge payload");
+        let gzip = Gzip::parse(gzip.data().to_vec()).unwrap();
+        assert!(gzip.members().iter().all(|m| m.is_valid()));
+        assert_eq!(gzip.members()[0].stored, (0x42424242, 26));
+        assert_eq!(gzip.members()[1].stored, (0x13371337, 31));
+    }
+
+    #[test]
+    pub fn test_forge_header() {
+        for comment in [false, true] {
+            let mut gzip = Gzip::parse(gzip()).expect("Failed to parse gzip");
+            for (index, target) in [(0, 0x4242), (1, 0x1337), (1, 0xbeef)] {
+                gzip.forge_header(index, target, comment)
+                    .expect("Failed to forge header");
+                let member = &gzip.members()[index];
+                assert_eq!(member.header_crc, Some(Crc::new(target, target)));
+                assert!(member.is_valid());
+            }
+            // Forging bytes are adjusted rather than added again
+            let size = gzip.data().len();
+            gzip.forge_header(1, 0, comment).unwrap();
+            assert_eq!(gzip.data().len(), size);
+        }
+    }
+}
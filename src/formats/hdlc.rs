@@ -0,0 +1,320 @@
+use std::{fmt::Display, io::Read};
+
+use crate::catalogue::find;
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::generic::CRC;
+
+/// Flag delimiting frames, and control escape byte, followed by the escaped byte XORed with 0x20.
+const FLAG: u8 = 0x7e;
+const ESCAPE: u8 = 0x7d;
+const ESCAPE_XOR: u8 = 0x20;
+
+/// Default async control character map of RFC 1662, escaping all control characters.
+pub const DEFAULT_ACCM: u32 = 0xffffffff;
+
+/// HDLC frame, given by its unescaped bytes, with its stored and actual FCS.
+#[derive(Clone)]
+pub struct Frame {
+    pub offset: usize,  // Offset of the stuffed frame, following its opening flag
+    pub size: usize,    // Size of the stuffed frame
+    bytes: Vec<u8>,     // Unescaped frame, FCS included
+    escaped: Vec<bool>, // Whether each unescaped byte was escaped
+    fcs_size: usize,
+    pub fcs: Crc<u32>,
+}
+
+impl Frame {
+    pub fn is_valid(&self) -> bool {
+        self.fcs.is_valid()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  {} bytes  addr=0x{:02x}  ctrl=0x{:02x}  fcs={:width$}",
+            self.offset,
+            self.bytes.len(),
+            self.bytes[0],
+            self.bytes[1],
+            self.fcs,
+            width = 2 * self.fcs_size,
+        )
+    }
+}
+
+/// Stream of HDLC frames, delimited by flags and byte-stuffed as in asynchronous PPP (RFC 1662), each ending with a
+/// FCS-16 or FCS-32 of the unescaped frame, low byte first.
+/// Aborted frames, frames too short to hold an address, a control field and a FCS, and bytes outside frames are kept
+/// as is.
+pub struct Hdlc {
+    data: Vec<u8>,
+    frames: Vec<Frame>,
+    crc: CRC,
+    fcs_size: usize,
+    accm: u32, // Control characters escaped when stuffing rewritten bytes
+}
+
+impl Hdlc {
+    /// Parse HDLC stream `data`, whose frames end with a FCS of `width` bits, 16 or 32.
+    pub fn parse(data: Vec<u8>, width: u32, accm: u32) -> CRCResult<Self> {
+        let model = match width {
+            16 => "CRC-16/IBM-SDLC",
+            32 => "CRC-32/ISO-HDLC",
+            _ => return Err(Error::UnsupportedError),
+        };
+        let model = find(model).ok_or(Error::UnknownModelError)?;
+        let mut hdlc = Self {
+            data,
+            frames: Vec::new(),
+            crc: CRC::new(model.props)?,
+            fcs_size: width as usize / 8,
+            accm,
+        };
+        hdlc.parse_frames()?;
+        Ok(hdlc)
+    }
+
+    fn parse_frames(&mut self) -> CRCResult<()> {
+        self.frames.clear();
+        let flags = self
+            .data
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == FLAG);
+        let flags = flags.map(|(pos, _)| pos).collect::<Vec<_>>();
+        for pair in flags.windows(2) {
+            let (offset, end) = (pair[0] + 1, pair[1]);
+            let mut bytes = Vec::new();
+            let mut escaped = Vec::new();
+            let mut stuffed = self.data[offset..end].iter();
+            while let Some(byte) = stuffed.next() {
+                match *byte {
+                    ESCAPE => match stuffed.next() {
+                        Some(byte) => {
+                            bytes.push(byte ^ ESCAPE_XOR);
+                            escaped.push(true);
+                        }
+                        None => bytes.clear(), // Abort sequence
+                    },
+                    byte => {
+                        bytes.push(byte);
+                        escaped.push(false);
+                    }
+                }
+            }
+            if bytes.len() < self.fcs_size + 2 {
+                continue;
+            }
+            let payload = bytes.len() - self.fcs_size;
+            let stored = bytes[payload..]
+                .iter()
+                .rev()
+                .fold(0, |fcs, byte| (fcs << 8) | u32::from(*byte));
+            let computed = self.crc.checksum(bytes[..payload].bytes())? as u32;
+            self.frames.push(Frame {
+                offset,
+                size: end - offset,
+                bytes,
+                escaped,
+                fcs_size: self.fcs_size,
+                fcs: Crc::new(stored, computed),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Whether `byte` must be escaped when stuffing rewritten bytes.
+    fn needs_escape(&self, byte: u8) -> bool {
+        byte == FLAG || byte == ESCAPE || (byte < 0x20 && self.accm & (1 << byte) != 0)
+    }
+
+    /// Replace frame `index` by unescaped `bytes`, stuffing bytes which were escaped or need to be.
+    /// Frames must be replaced from the last one, since following frames move.
+    fn replace(&mut self, index: usize, bytes: &[u8], escaped: &[bool]) {
+        let mut stuffed = Vec::new();
+        for (byte, escaped) in bytes.iter().zip(escaped) {
+            match *escaped || self.needs_escape(*byte) {
+                true => stuffed.extend([ESCAPE, byte ^ ESCAPE_XOR]),
+                false => stuffed.push(*byte),
+            }
+        }
+        let frame = &self.frames[index];
+        self.data
+            .splice(frame.offset..frame.offset + frame.size, stuffed);
+    }
+
+    /// Rewrite invalid FCS, returning the number of repaired frames.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let mut repaired = 0;
+        for index in (0..self.frames.len()).rev() {
+            let frame = &self.frames[index];
+            if frame.is_valid() {
+                continue;
+            }
+            let payload = frame.bytes.len() - self.fcs_size;
+            let mut bytes = frame.bytes.clone();
+            let mut escaped = frame.escaped.clone();
+            bytes[payload..].copy_from_slice(&frame.fcs.computed.to_le_bytes()[..self.fcs_size]);
+            escaped[payload..].fill(false);
+            self.replace(index, &bytes, &escaped);
+            repaired += 1;
+        }
+        self.parse_frames()?;
+        Ok(repaired)
+    }
+
+    /// Forge the FCS of frame `index` to `target_fcs`, or keep the stored FCS by default, by patching `width / 8 + 1`
+    /// bytes at offset `offset` of the unescaped frame. The first patched byte is chosen so that no patched byte needs
+    /// escaping when possible, so that patching does not grow the stuffed frame.
+    pub fn forge(&mut self, index: usize, offset: usize, target_fcs: Option<u32>) -> CRCResult<()> {
+        let frame = self.frames.get(index).ok_or(Error::OutOfBoundsError)?;
+        let target_fcs = target_fcs.unwrap_or(frame.fcs.stored);
+        if self.fcs_size < 4 && target_fcs >> (8 * self.fcs_size) != 0 {
+            return Err(Error::OverflowError(None));
+        }
+        let payload = frame.bytes.len() - self.fcs_size;
+        if offset
+            .checked_add(self.fcs_size + 1)
+            .is_none_or(|end| end > payload)
+        {
+            return Err(Error::OutOfBoundsError);
+        }
+        let mut bytes = frame.bytes.clone();
+        let mut escaped = frame.escaped.clone();
+        let original = bytes[offset];
+        let mut patch = None;
+        for value in [original].into_iter().chain(0..=0xff) {
+            bytes[offset] = value;
+            let candidate =
+                self.crc
+                    .compute_patch(&bytes[..payload], offset + 1, u64::from(target_fcs))?;
+            let escapes = [value]
+                .iter()
+                .chain(&candidate)
+                .any(|byte| self.needs_escape(*byte));
+            if patch.is_none() || !escapes {
+                patch = Some((value, candidate));
+            }
+            if !escapes {
+                break;
+            }
+        }
+        let (value, patch) = patch.unwrap();
+        bytes[offset] = value;
+        bytes[offset + 1..offset + 1 + self.fcs_size].copy_from_slice(&patch);
+        bytes[payload..].copy_from_slice(&target_fcs.to_le_bytes()[..self.fcs_size]);
+        escaped[offset..offset + 1 + self.fcs_size].fill(false);
+        if target_fcs != frame.fcs.stored {
+            escaped[payload..].fill(false);
+        }
+        self.replace(index, &bytes, &escaped);
+        self.parse_frames()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::catalogue::find;
+    use crate::error::Error;
+    use crate::formats::hdlc::{DEFAULT_ACCM, Hdlc};
+    use crate::generic::CRC;
+    use crate::recover::decode_hex;
+
+    /// Stuffed frame of `payload` with its FCS of `width` bits, escaping control characters.
+    fn frame(payload: &[u8], width: u32) -> Vec<u8> {
+        let model = match width {
+            16 => "CRC-16/IBM-SDLC",
+            _ => "CRC-32/ISO-HDLC",
+        };
+        let crc = CRC::new(find(model).unwrap().props).unwrap();
+        let fcs = crc.checksum(payload.bytes()).unwrap().to_le_bytes();
+        let mut frame = vec![0x7e];
+        for byte in [payload, &fcs[..width as usize / 8]].concat() {
+            match byte < 0x20 || byte == 0x7e || byte == 0x7d {
+                true => frame.extend([0x7d, byte ^ 0x20]),
+                false => frame.push(byte),
+            }
+        }
+        frame
+    }
+
+    /// LCP configure request and IP frame, sharing flags, between noise and an aborted frame.
+    fn stream(width: u32) -> Vec<u8> {
+        let lcp = decode_hex("ff03c021010100140206000000000506a1b2c3d40702").unwrap();
+        let ip = [&b"\xff\x03\x00\x21"[..], b"lorem ipsum"].concat();
+        [
+            &b"ATDT\r"[..],
+            &frame(&lcp, width),
+            &frame(&ip, width),
+            b"\x7eabc\x7d\x7e",
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let hdlc = Hdlc::parse(stream(16), 16, DEFAULT_ACCM).expect("Failed to parse stream");
+        let sizes = hdlc.frames().iter().map(|frame| frame.bytes().len());
+        assert_eq!(sizes.collect::<Vec<_>>(), [24, 17]);
+        assert!(hdlc.frames().iter().all(|frame| frame.is_valid()));
+        assert_eq!(hdlc.frames()[0].bytes()[4], 0x01); // Unescaped
+        let hdlc = Hdlc::parse(stream(32), 32, DEFAULT_ACCM).unwrap();
+        assert!(hdlc.frames().iter().all(|frame| frame.is_valid()));
+        let hdlc = Hdlc::parse(stream(32), 16, DEFAULT_ACCM).unwrap();
+        assert!(hdlc.frames().iter().all(|frame| !frame.is_valid()));
+        assert!(Hdlc::parse(stream(16), 8, DEFAULT_ACCM).is_err());
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = stream(16);
+        let text = data.windows(5).position(|w| w == b"lorem").unwrap();
+        data[text] = b'L';
+        let mut hdlc = Hdlc::parse(data.clone(), 16, DEFAULT_ACCM).unwrap();
+        assert!(!hdlc.frames()[1].is_valid());
+        assert_eq!(hdlc.repair().unwrap(), 1);
+        assert!(hdlc.frames().iter().all(|frame| frame.is_valid()));
+        assert!(hdlc.data().starts_with(&data[..text + 1]));
+        assert!(hdlc.data().ends_with(b"\x7eabc\x7d\x7e"));
+    }
+
+    #[test]
+    pub fn test_forge() {
+        for width in [16, 32] {
+            let mut hdlc = Hdlc::parse(stream(width), width, DEFAULT_ACCM).unwrap();
+            let size = hdlc.data().len();
+            hdlc.forge(1, 4, None).expect("Failed to forge frame");
+            assert_eq!(hdlc.data().len(), size);
+            hdlc.forge(0, 16, Some(0x7e7d))
+                .expect("Failed to forge frame");
+            assert!(hdlc.data().windows(4).any(|w| w == b"\x7d\x5d\x7d\x5e")); // Escaped FCS
+            let hdlc = Hdlc::parse(hdlc.data().to_vec(), width, DEFAULT_ACCM).unwrap();
+            assert!(hdlc.frames().iter().all(|frame| frame.is_valid()));
+            assert_eq!(hdlc.frames()[0].fcs.stored, 0x7e7d);
+        }
+        let mut hdlc = Hdlc::parse(stream(16), 16, DEFAULT_ACCM).unwrap();
+        assert!(matches!(
+            hdlc.forge(0, 0, Some(0x10000)),
+            Err(Error::OverflowError(None))
+        ));
+        assert!(hdlc.forge(1, 13, None).is_err());
+        assert!(hdlc.forge(1, usize::MAX, None).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+
+/// Offsets of record batch fields: the batch length counts bytes following it, and the CRC covers bytes from the
+/// attributes to the end of the batch.
+const BATCH_LENGTH: usize = 8;
+const MAGIC: usize = 16;
+const CRC: usize = 17;
+const ATTRIBUTES: usize = 21;
+const RECORD_COUNT: usize = 57;
+const RECORDS: usize = 61;
+
+/// Attributes: compression codec and control batch flag.
+const COMPRESSION_MASK: u16 = 0x07;
+const CONTROL: u16 = 0x20;
+
+/// Key of the header added to a record when forging a batch CRC, if no header value can be patched.
+const FORGE_HEADER_KEY: &[u8] = b"crc-forge";
+
+/// Read zigzag-encoded variable-length integer at offset `*pos` of `data`, advancing `pos`.
+fn read_varint(data: &[u8], pos: &mut usize) -> CRCResult<i64> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let byte = *data.get(*pos).ok_or(Error::ParseError)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    Err(Error::ParseError)
+}
+
+fn write_varint(value: i64) -> Vec<u8> {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    let mut out = Vec::new();
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+    out
+}
+
+/// Read variable-length byte array at offset `*pos` of `data`, null if its length is negative.
+fn read_bytes(data: &[u8], pos: &mut usize) -> CRCResult<Range<usize>> {
+    let length = read_varint(data, pos)?;
+    let start = *pos;
+    if length >= 0 {
+        *pos = usize::try_from(length)
+            .ok()
+            .and_then(|length| start.checked_add(length))
+            .filter(|end| *end <= data.len())
+            .ok_or(Error::ParseError)?;
+    }
+    Ok(start..*pos)
+}
+
+/// Record of an uncompressed batch, as needed to forge the batch CRC.
+struct Record {
+    range: Range<usize>,        // Whole record, including its length
+    headers: usize,             // Offset of the header count
+    slot: Option<Range<usize>>, // Last header value of at least 4 bytes
+}
+
+/// Kafka record batch (message format v2), with its CRC32C.
+#[derive(Clone)]
+pub struct Batch {
+    pub offset: usize,
+    pub base_offset: i64,
+    pub records: u32,
+    pub attributes: u16,
+    size: usize,
+    pub crc: Crc<u32>,
+}
+
+impl Batch {
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+
+    pub fn compression(&self) -> u16 {
+        self.attributes & COMPRESSION_MASK
+    }
+}
+
+impl Display for Batch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  offset {}  {} records  {} bytes",
+            self.offset, self.base_offset, self.records, self.size
+        )?;
+        match self.compression() {
+            0 => (),
+            1 => write!(f, "  gzip")?,
+            2 => write!(f, "  snappy")?,
+            3 => write!(f, "  lz4")?,
+            4 => write!(f, "  zstd")?,
+            codec => write!(f, "  codec {}", codec)?,
+        }
+        if self.attributes & CONTROL != 0 {
+            write!(f, "  control")?;
+        }
+        write!(f, "  crc={}", self.crc)
+    }
+}
+
+/// Kafka log segment: record batches, each protected by a CRC32C stored big-endian.
+pub struct Kafka {
+    data: Vec<u8>,
+    batches: Vec<Batch>,
+    crc: CRC32,
+}
+
+impl Kafka {
+    /// Parse log segment `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::castagnoli())?;
+        let mut kafka = Self {
+            data,
+            batches: Vec::new(),
+            crc,
+        };
+        kafka.parse_batches()?;
+        Ok(kafka)
+    }
+
+    fn parse_batches(&mut self) -> CRCResult<()> {
+        self.batches.clear();
+        let mut offset = 0;
+        while offset < self.data.len() {
+            let batch = self.parse_batch(offset)?;
+            offset += batch.size;
+            self.batches.push(batch);
+        }
+        Ok(())
+    }
+
+    /// Parse batch at offset `offset`. Older message formats are not supported.
+    fn parse_batch(&self, offset: usize) -> CRCResult<Batch> {
+        let header = self
+            .data
+            .get(offset..offset + RECORDS)
+            .ok_or(Error::ParseError)?;
+        if header[MAGIC] != 2 {
+            return Err(Error::UnsupportedError);
+        }
+        let length = u32::from_be_bytes(header[BATCH_LENGTH..BATCH_LENGTH + 4].try_into().unwrap());
+        let size = usize::try_from(length)
+            .ok()
+            .and_then(|length| length.checked_add(BATCH_LENGTH + 4))
+            .filter(|size| *size >= RECORDS && offset + size <= self.data.len())
+            .ok_or(Error::ParseError)?;
+        let covered = &self.data[offset + ATTRIBUTES..offset + size];
+        Ok(Batch {
+            offset,
+            base_offset: i64::from_be_bytes(header[..8].try_into().unwrap()),
+            records: u32::from_be_bytes(header[RECORD_COUNT..RECORDS].try_into().unwrap()),
+            attributes: u16::from_be_bytes([header[ATTRIBUTES], header[ATTRIBUTES + 1]]),
+            size,
+            crc: Crc::new(
+                u32::from_be_bytes(header[CRC..CRC + 4].try_into().unwrap()),
+                self.crc.checksum(covered.bytes())?,
+            ),
+        })
+    }
+
+    /// Parse records of uncompressed batch `batch`.
+    fn parse_records(&self, batch: &Batch) -> CRCResult<Vec<Record>> {
+        let data = &self.data[..batch.offset + batch.size];
+        let mut records = Vec::new();
+        let mut pos = batch.offset + RECORDS;
+        while pos < data.len() {
+            let start = pos;
+            let length = read_varint(data, &mut pos)?;
+            let end = usize::try_from(length)
+                .ok()
+                .and_then(|length| pos.checked_add(length))
+                .filter(|end| *end <= data.len())
+                .ok_or(Error::ParseError)?;
+            let record = &data[..end];
+            pos += 1; // Attributes
+            read_varint(record, &mut pos)?; // Timestamp delta
+            read_varint(record, &mut pos)?; // Offset delta
+            read_bytes(record, &mut pos)?; // Key
+            read_bytes(record, &mut pos)?; // Value
+            let headers = pos;
+            let mut slot = None;
+            for _ in 0..read_varint(record, &mut pos)? {
+                read_bytes(record, &mut pos)?; // Header key
+                let value = read_bytes(record, &mut pos)?;
+                if value.len() >= 4 {
+                    slot = Some(value);
+                }
+            }
+            if pos != end {
+                return Err(Error::ParseError);
+            }
+            records.push(Record {
+                range: start..end,
+                headers,
+                slot,
+            });
+        }
+        if records.len() != batch.records as usize {
+            return Err(Error::ParseError);
+        }
+        Ok(records)
+    }
+
+    pub fn batches(&self) -> &[Batch] {
+        &self.batches
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Rewrite CRC `crc` of batch `index`.
+    fn write_crc(&mut self, index: usize, crc: u32) {
+        let batch = &mut self.batches[index];
+        let offset = batch.offset + CRC;
+        self.data[offset..offset + 4].copy_from_slice(&crc.to_be_bytes());
+        batch.crc.stored = crc;
+    }
+
+    /// Rewrite invalid batch CRCs. Returns the number of rewritten CRCs.
+    pub fn repair(&mut self) -> usize {
+        let mut repaired = 0;
+        for index in 0..self.batches.len() {
+            let crc = self.batches[index].crc;
+            if !crc.is_valid() {
+                self.write_crc(index, crc.computed);
+                repaired += 1;
+            }
+        }
+        repaired
+    }
+
+    /// Add a header with a zeroed 4-byte value to the last record of batch `index`, updating the record and batch lengths.
+    fn add_header(&mut self, index: usize) -> CRCResult<()> {
+        let batch = self.batches[index].clone();
+        let records = self.parse_records(&batch)?;
+        let record = records.last().ok_or(Error::UnsupportedError)?;
+        let mut body_start = record.range.start;
+        read_varint(&self.data, &mut body_start)?; // Record length
+        let mut headers = record.headers;
+        let count = read_varint(&self.data, &mut headers)?;
+        let mut body = self.data[body_start..record.headers].to_vec();
+        body.extend(write_varint(count + 1));
+        body.extend(&self.data[headers..record.range.end]);
+        body.extend(write_varint(FORGE_HEADER_KEY.len() as i64));
+        body.extend(FORGE_HEADER_KEY);
+        body.extend(write_varint(4));
+        body.extend([0; 4]);
+        let mut record_bytes = write_varint(body.len() as i64);
+        record_bytes.extend(body);
+
+        let growth = record_bytes.len() - record.range.len();
+        let length = u32::try_from(batch.size - BATCH_LENGTH - 4 + growth)?;
+        self.data.splice(record.range.clone(), record_bytes);
+        let offset = batch.offset + BATCH_LENGTH;
+        self.data[offset..offset + 4].copy_from_slice(&length.to_be_bytes());
+        self.parse_batches()
+    }
+
+    /// Patch the last 4 bytes of the last header value of at least 4 bytes in batch `index`, so that its CRC is
+    /// `target_crc`. If there is no such header, one is added to its last record. Compressed and control batches are
+    /// not supported.
+    pub fn forge(&mut self, index: usize, target_crc: u32) -> CRCResult<()> {
+        let batch = self.batches.get(index).ok_or(Error::OutOfBoundsError)?;
+        if batch.compression() != 0 || batch.attributes & CONTROL != 0 {
+            return Err(Error::UnsupportedError);
+        }
+        let slot = self
+            .parse_records(batch)?
+            .into_iter()
+            .filter_map(|record| record.slot)
+            .next_back();
+        let slot = match slot {
+            Some(slot) => slot,
+            None => {
+                self.add_header(index)?;
+                let batch = &self.batches[index];
+                let records = self.parse_records(batch)?;
+                records
+                    .last()
+                    .and_then(|record| record.slot.clone())
+                    .unwrap()
+            }
+        };
+        let batch = &self.batches[index];
+        let covered = batch.offset + ATTRIBUTES..batch.offset + batch.size;
+        let patched = slot.end - 4;
+        let patch = self.crc.compute_patch(
+            &self.data[covered.clone()],
+            patched - covered.start,
+            target_crc,
+        )?;
+        self.data[patched..patched + 4].copy_from_slice(&patch);
+        self.batches[index].crc.computed = target_crc;
+        self.write_crc(index, target_crc);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::Crc;
+    use crate::formats::kafka::{Kafka, write_varint};
+
+    /// Record at offset delta `delta` with value `value` and headers `headers`.
+    fn record(delta: i64, value: &[u8], headers: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut body = [
+            &[0][..],
+            &write_varint(0),
+            &write_varint(delta),
+            &write_varint(-1),
+        ]
+        .concat();
+        body.extend(write_varint(value.len() as i64));
+        body.extend(value);
+        body.extend(write_varint(headers.len() as i64));
+        for (key, value) in headers {
+            body.extend(write_varint(key.len() as i64));
+            body.extend(*key);
+            body.extend(write_varint(value.len() as i64));
+            body.extend(*value);
+        }
+        [write_varint(body.len() as i64), body].concat()
+    }
+
+    /// Batch at base offset `base_offset` holding records `records`.
+    fn batch(base_offset: i64, records: &[Vec<u8>]) -> Vec<u8> {
+        let crc32c = CRC32::new(CRC32Properties::castagnoli()).unwrap();
+        let mut covered = [
+            &0u16.to_be_bytes()[..],                   // Attributes
+            &(records.len() as i32 - 1).to_be_bytes(), // Last offset delta
+            &1760000000000i64.to_be_bytes(),           // Base timestamp
+            &1760000000000i64.to_be_bytes(),           // Max timestamp
+            &(-1i64).to_be_bytes(),                    // Producer ID
+            &(-1i16).to_be_bytes(),                    // Producer epoch
+            &(-1i32).to_be_bytes(),                    // Base sequence
+            &(records.len() as i32).to_be_bytes(),
+        ]
+        .concat();
+        covered.extend(records.concat());
+        let crc = crc32c.checksum(covered.as_slice().bytes()).unwrap();
+        [
+            &base_offset.to_be_bytes()[..],
+            &(covered.len() as i32 + 9).to_be_bytes(),
+            &0i32.to_be_bytes(), // Partition leader epoch
+            &[2],
+            &crc.to_be_bytes(),
+            &covered,
+        ]
+        .concat()
+    }
+
+    fn segment() -> Vec<u8> {
+        [
+            batch(
+                0,
+                &[
+                    record(0, b"lorem ipsum", &[(b"trace", b"0123456789")]),
+                    record(1, b"dolor sit amet", &[]),
+                ],
+            ),
+            batch(2, &[record(0, b"hello world", &[])]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let kafka = Kafka::parse(segment()).expect("Failed to parse segment");
+        let batches = kafka.batches();
+        assert_eq!(batches.len(), 2);
+        assert_eq!((batches[0].base_offset, batches[0].records), (0, 2));
+        assert_eq!((batches[1].base_offset, batches[1].records), (2, 1));
+        assert!(batches.iter().all(|batch| batch.is_valid()));
+        assert_eq!(kafka.parse_records(&batches[0]).unwrap().len(), 2);
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = segment();
+        let offset = data
+            .windows(5)
+            .position(|window| window == b"hello")
+            .unwrap();
+        data[offset] = b'j';
+        let mut kafka = Kafka::parse(data).unwrap();
+        assert!(kafka.batches()[0].is_valid());
+        assert!(!kafka.batches()[1].is_valid());
+        assert_eq!(kafka.repair(), 1);
+        let kafka = Kafka::parse(kafka.data().to_vec()).unwrap();
+        assert!(kafka.batches().iter().all(|batch| batch.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge() {
+        let mut kafka = Kafka::parse(segment()).unwrap();
+        kafka.forge(0, 0xdeadbeef).unwrap();
+        kafka.forge(1, 0xcafebabe).unwrap();
+        let kafka = Kafka::parse(kafka.data().to_vec()).unwrap();
+        let batches = kafka.batches();
+        assert_eq!(batches[0].crc, Crc::new(0xdeadbeef, 0xdeadbeef));
+        assert_eq!(batches[1].crc, Crc::new(0xcafebabe, 0xcafebabe));
+        assert!(kafka.data().windows(6).any(|window| window == b"012345"));
+        let records = kafka.parse_records(&batches[1]).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].slot.is_some());
+        assert!(
+            kafka
+                .data()
+                .windows(11)
+                .any(|window| window == b"hello world")
+        );
+    }
+}
@@ -0,0 +1,444 @@
+use crate::error::{CRCResult, Error};
+
+/// Maximum length of uncompressed LZMA2 chunks.
+const STORED_CHUNK_SIZE: usize = 1 << 16;
+
+/// Number of LZMA states, and of states following a literal.
+const STATES: usize = 12;
+const LITERAL_STATES: usize = 7;
+
+/// Maximum number of position states, given by the `pb` property.
+const POS_STATES: usize = 1 << 4;
+
+/// Number of length states selecting distance slot codes.
+const LENGTH_STATES: usize = 4;
+
+/// First distance slot whose low bits are coded with fixed probabilities, and number of distances below it.
+const END_POS_MODEL_INDEX: u32 = 14;
+const FULL_DISTANCES: usize = 128;
+
+/// Number of distance low bits coded with the align code.
+const ALIGN_BITS: u32 = 4;
+
+/// Minimum length of matches.
+const MATCH_MIN_LENGTH: usize = 2;
+
+/// Initial probability of bits, on 11 bits.
+const PROBABILITY_INIT: u16 = 1 << 10;
+
+/// Range decoder over a compressed LZMA chunk.
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> CRCResult<Self> {
+        match data.get(..5) {
+            Some([0, code @ ..]) => Ok(Self {
+                data,
+                pos: 5,
+                range: u32::MAX,
+                code: u32::from_be_bytes(code.try_into().unwrap()),
+            }),
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    fn normalize(&mut self) -> CRCResult<()> {
+        if self.range < 1 << 24 {
+            let byte = self.data.get(self.pos).ok_or(Error::ParseError)?;
+            self.range <<= 8;
+            self.code = (self.code << 8) | u32::from(*byte);
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// Decode a bit of probability `probability`, which is adapted.
+    fn bit(&mut self, probability: &mut u16) -> CRCResult<usize> {
+        let bound = (self.range >> 11) * u32::from(*probability);
+        let bit = match self.code < bound {
+            true => {
+                self.range = bound;
+                *probability += ((1 << 11) - *probability) >> 5;
+                0
+            }
+            false => {
+                self.range -= bound;
+                self.code -= bound;
+                *probability -= *probability >> 5;
+                1
+            }
+        };
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    /// Decode `n` bits of fixed probability, MSB first.
+    fn direct(&mut self, n: u32) -> CRCResult<usize> {
+        let mut value = 0;
+        for _ in 0..n {
+            self.range >>= 1;
+            let bit = self.code >= self.range;
+            if bit {
+                self.code -= self.range;
+            }
+            value = (value << 1) | usize::from(bit);
+            self.normalize()?;
+        }
+        Ok(value)
+    }
+
+    /// Decode `n` bits with bit tree `probabilities`, MSB first.
+    fn tree(&mut self, probabilities: &mut [u16], n: u32) -> CRCResult<usize> {
+        let mut m = 1;
+        for _ in 0..n {
+            m = (m << 1) | self.bit(&mut probabilities[m])?;
+        }
+        Ok(m - (1 << n))
+    }
+
+    /// Decode `n` bits with bit tree `probabilities`, LSB first.
+    fn reverse_tree(&mut self, probabilities: &mut [u16], n: u32) -> CRCResult<usize> {
+        let (mut m, mut value) = (1, 0);
+        for i in 0..n {
+            let bit = self.bit(&mut probabilities[m])?;
+            m = (m << 1) | bit;
+            value |= bit << i;
+        }
+        Ok(value)
+    }
+}
+
+/// Decoder of match lengths, minus the minimum length.
+struct LengthDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 1 << 3]; POS_STATES],
+    mid: [[u16; 1 << 3]; POS_STATES],
+    high: [u16; 1 << 8],
+}
+
+impl LengthDecoder {
+    fn new() -> Self {
+        Self {
+            choice: PROBABILITY_INIT,
+            choice2: PROBABILITY_INIT,
+            low: [[PROBABILITY_INIT; 1 << 3]; POS_STATES],
+            mid: [[PROBABILITY_INIT; 1 << 3]; POS_STATES],
+            high: [PROBABILITY_INIT; 1 << 8],
+        }
+    }
+
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> CRCResult<usize> {
+        if rc.bit(&mut self.choice)? == 0 {
+            return rc.tree(&mut self.low[pos_state], 3);
+        }
+        if rc.bit(&mut self.choice2)? == 0 {
+            return Ok(8 + rc.tree(&mut self.mid[pos_state], 3)?);
+        }
+        Ok(16 + rc.tree(&mut self.high, 8)?)
+    }
+}
+
+/// LZMA decoder state: properties, probabilities and last match distances.
+struct Lzma {
+    properties: u8,
+    lc: u32, // Number of high bits of the previous byte selecting literal codes
+    lp: u32, // Number of low bits of the position selecting literal codes
+    pb: u32, // Number of low bits of the position selecting position states
+    state: usize,
+    reps: [usize; 4], // Last match distances, minus one
+    is_match: [u16; STATES * POS_STATES],
+    is_rep: [u16; STATES],
+    is_rep0: [u16; STATES],
+    is_rep1: [u16; STATES],
+    is_rep2: [u16; STATES],
+    is_rep0_long: [u16; STATES * POS_STATES],
+    distance_slot: [[u16; 1 << 6]; LENGTH_STATES],
+    distance_special: [u16; 1 + FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+    align: [u16; 1 << ALIGN_BITS],
+    length: LengthDecoder,
+    rep_length: LengthDecoder,
+    literal: Vec<u16>,
+}
+
+impl Lzma {
+    /// Create decoder with properties byte `properties`, holding `lc`, `lp` and `pb`.
+    fn new(properties: u8) -> CRCResult<Self> {
+        let (lc, lp, pb) = (
+            u32::from(properties % 9),
+            u32::from(properties / 9 % 5),
+            u32::from(properties / 45),
+        );
+        if pb > 4 {
+            return Err(Error::ParseError);
+        }
+        Ok(Self {
+            properties,
+            lc,
+            lp,
+            pb,
+            state: 0,
+            reps: [0; 4],
+            is_match: [PROBABILITY_INIT; STATES * POS_STATES],
+            is_rep: [PROBABILITY_INIT; STATES],
+            is_rep0: [PROBABILITY_INIT; STATES],
+            is_rep1: [PROBABILITY_INIT; STATES],
+            is_rep2: [PROBABILITY_INIT; STATES],
+            is_rep0_long: [PROBABILITY_INIT; STATES * POS_STATES],
+            distance_slot: [[PROBABILITY_INIT; 1 << 6]; LENGTH_STATES],
+            distance_special: [PROBABILITY_INIT; 1 + FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+            align: [PROBABILITY_INIT; 1 << ALIGN_BITS],
+            length: LengthDecoder::new(),
+            rep_length: LengthDecoder::new(),
+            literal: vec![PROBABILITY_INIT; 0x300 << (lc + lp)],
+        })
+    }
+
+    /// Decode match distance, minus one, of a match of length `length` minus the minimum length.
+    fn distance(&mut self, rc: &mut RangeDecoder, length: usize) -> CRCResult<usize> {
+        let slot = rc.tree(&mut self.distance_slot[length.min(LENGTH_STATES - 1)], 6)? as u32;
+        if slot < 4 {
+            return Ok(slot as usize);
+        }
+        let direct = (slot >> 1) - 1;
+        let distance = ((2 | (slot & 1)) << direct) as usize;
+        if slot < END_POS_MODEL_INDEX {
+            let probabilities = &mut self.distance_special[distance - slot as usize..];
+            return Ok(distance + rc.reverse_tree(probabilities, direct)?);
+        }
+        let high = rc.direct(direct - ALIGN_BITS)? << ALIGN_BITS;
+        Ok(distance + high + rc.reverse_tree(&mut self.align, ALIGN_BITS)?)
+    }
+
+    /// Decode `size` bytes with range decoder `rc`, appending to `out` whose dictionary starts at `start`.
+    fn decode(
+        &mut self,
+        rc: &mut RangeDecoder,
+        out: &mut Vec<u8>,
+        start: usize,
+        size: usize,
+    ) -> CRCResult<()> {
+        let end = out.len() + size;
+        while out.len() < end {
+            let pos = out.len() - start;
+            let pos_state = pos & ((1 << self.pb) - 1);
+            let state = self.state;
+
+            if rc.bit(&mut self.is_match[state * POS_STATES + pos_state])? == 0 {
+                let previous = if pos > 0 { out[out.len() - 1] } else { 0 };
+                let coder = ((pos & ((1 << self.lp) - 1)) << self.lc)
+                    + (previous >> (8 - self.lc)) as usize;
+                let probabilities = &mut self.literal[0x300 * coder..0x300 * (coder + 1)];
+                let mut symbol = 1;
+                // After a match, bits are coded along those of the byte following the last match
+                if state >= LITERAL_STATES {
+                    let mut matched = *out
+                        .len()
+                        .checked_sub(self.reps[0] + 1)
+                        .and_then(|i| out.get(i))
+                        .ok_or(Error::ParseError)? as usize;
+                    while symbol < 0x100 {
+                        let matched_bit = (matched >> 7) & 1;
+                        matched <<= 1;
+                        let bit = rc.bit(&mut probabilities[((1 + matched_bit) << 8) + symbol])?;
+                        symbol = (symbol << 1) | bit;
+                        if bit != matched_bit {
+                            break;
+                        }
+                    }
+                }
+                while symbol < 0x100 {
+                    symbol = (symbol << 1) | rc.bit(&mut probabilities[symbol])?;
+                }
+                out.push(symbol as u8);
+                self.state = match state {
+                    0..4 => 0,
+                    4..10 => state - 3,
+                    _ => state - 6,
+                };
+                continue;
+            }
+
+            let length = match rc.bit(&mut self.is_rep[state])? {
+                0 => {
+                    let length = self.length.decode(rc, pos_state)?;
+                    self.reps.copy_within(..3, 1);
+                    self.reps[0] = self.distance(rc, length)?;
+                    self.state = if state < LITERAL_STATES { 7 } else { 10 };
+                    length
+                }
+                _ => {
+                    if rc.bit(&mut self.is_rep0[state])? == 0 {
+                        // Short rep: a single byte at the last distance
+                        if rc.bit(&mut self.is_rep0_long[state * POS_STATES + pos_state])? == 0 {
+                            let byte = *out
+                                .len()
+                                .checked_sub(self.reps[0] + 1)
+                                .filter(|i| *i >= start)
+                                .map(|i| &out[i])
+                                .ok_or(Error::ParseError)?;
+                            out.push(byte);
+                            self.state = if state < LITERAL_STATES { 9 } else { 11 };
+                            continue;
+                        }
+                    } else {
+                        let index = match rc.bit(&mut self.is_rep1[state])? {
+                            0 => 1,
+                            _ => 2 + rc.bit(&mut self.is_rep2[state])?,
+                        };
+                        self.reps[..=index].rotate_right(1);
+                    }
+                    self.state = if state < LITERAL_STATES { 8 } else { 11 };
+                    self.rep_length.decode(rc, pos_state)?
+                }
+            };
+
+            // Matches may overlap the bytes they produce
+            let distance = self.reps[0] + 1;
+            if distance > pos {
+                return Err(Error::ParseError);
+            }
+            for _ in 0..(length + MATCH_MIN_LENGTH).min(end - out.len()) {
+                out.push(out[out.len() - distance]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decompress LZMA2 stream at the start of `data`.
+/// Returns the decompressed data and the size of the stream in bytes.
+pub fn lzma2_decode(data: &[u8]) -> CRCResult<(Vec<u8>, usize)> {
+    let mut out = Vec::new();
+    let mut start = None; // Start of the dictionary, reset by the first chunk
+    let mut lzma: Option<Lzma> = None;
+    let mut pos = 0;
+    let field = |pos: usize| -> CRCResult<usize> {
+        let bytes = data.get(pos..pos + 2).ok_or(Error::ParseError)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]) as usize + 1)
+    };
+    loop {
+        let control = *data.get(pos).ok_or(Error::ParseError)?;
+        pos += 1;
+        match control {
+            0x00 => return Ok((out, pos)),
+            0x01 | 0x02 => {
+                if control == 0x01 {
+                    start = Some(out.len());
+                }
+                start.ok_or(Error::ParseError)?;
+                let size = field(pos)?;
+                let chunk = data.get(pos + 2..pos + 2 + size).ok_or(Error::ParseError)?;
+                out.extend(chunk);
+                pos += 2 + size;
+            }
+            0x80.. => {
+                // Resets of the state, of the properties and of the dictionary
+                let reset = (control >> 5) & 3;
+                if reset == 3 {
+                    start = Some(out.len());
+                }
+                let start = start.ok_or(Error::ParseError)?;
+                let size = ((control as usize & 0x1f) << 16) + field(pos)?;
+                let packed = field(pos + 2)?;
+                pos += 4;
+                match reset {
+                    0 => (),
+                    1 => {
+                        let properties = lzma.as_ref().ok_or(Error::ParseError)?.properties;
+                        lzma = Some(Lzma::new(properties)?);
+                    }
+                    _ => {
+                        let properties = *data.get(pos).ok_or(Error::ParseError)?;
+                        let next = Lzma::new(properties)?;
+                        // LZMA2 restricts literal coders
+                        if next.lc + next.lp > 4 {
+                            return Err(Error::ParseError);
+                        }
+                        lzma = Some(next);
+                        pos += 1;
+                    }
+                }
+                let lzma = lzma.as_mut().ok_or(Error::ParseError)?;
+                let chunk = data.get(pos..pos + packed).ok_or(Error::ParseError)?;
+                lzma.decode(&mut RangeDecoder::new(chunk)?, &mut out, start, size)?;
+                pos += packed;
+            }
+            _ => return Err(Error::ParseError),
+        }
+    }
+}
+
+/// Decompress raw LZMA stream `data` of `size` bytes once decompressed, with properties byte `properties`.
+/// The dictionary size is irrelevant, the whole output being kept.
+pub fn lzma_decode(properties: u8, data: &[u8], size: usize) -> CRCResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(size);
+    Lzma::new(properties)?.decode(&mut RangeDecoder::new(data)?, &mut out, 0, size)?;
+    Ok(out)
+}
+
+/// Encode `data` as a LZMA2 stream of uncompressed chunks, the first one resetting the dictionary.
+pub fn lzma2_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (i, chunk) in data.chunks(STORED_CHUNK_SIZE).enumerate() {
+        out.push(if i == 0 { 0x01 } else { 0x02 });
+        out.extend(((chunk.len() - 1) as u16).to_be_bytes());
+        out.extend(chunk);
+    }
+    out.push(0x00);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formats::lzma::{lzma_decode, lzma2_decode, lzma2_stored};
+
+    #[test]
+    pub fn test_lzma2_decode() {
+        // Compressed by liblzma, with literals, matches and repeated matches
+        let stream = b"\xe0\x00\xa6\x00\x4e\x5d\x00\x3a\x1a\x08\xce\x76\xc7\xe5\xe9\xd6\x07\x34\xc3\xd1\
+                       \x0e\xbf\xce\x55\xe1\xaa\xbd\xe0\xe4\x8f\x98\x01\xdd\x8d\xe5\x07\x54\x9e\x65\x25\
+                       \x5f\x27\x3a\x6a\x7e\xb4\xd3\x49\x03\x38\x3f\xdc\x1d\x4a\x0e\xc6\xde\x48\x1d\xfb\
+                       \x8d\xdf\x2a\xa7\xf9\x8a\x3e\x14\x26\xa9\xf7\x11\x31\x4d\x99\xe1\x23\xdf\x21\xaa\
+                       \x8f\x93\x3d\x60\x00\x00";
+        let (data, size) = lzma2_decode(stream).expect("Failed to decode");
+        let text = [
+            &b"the quick brown fox jumps over the lazy dog, the quick brown cat jumps over the lazy fox, "[..],
+            b"the lazy dog sleeps, ",
+            &[b'a'; 40],
+            b" abab abab abab!",
+        ];
+        assert_eq!(data, text.concat());
+        assert_eq!(size, stream.len());
+        assert!(lzma2_decode(&stream[..40]).is_err());
+    }
+
+    #[test]
+    pub fn test_lzma_decode() {
+        // Raw LZMA stream compressed by liblzma with lc=3, lp=0, pb=2, ending with an end marker
+        let stream = b"\x00\x3a\x1a\x08\xce\x76\xc7\xe5\xe9\xd6\x07\x34\xc3\xd1\x0e\xbf\xce\x55\xe1\xaa\
+                       \xbd\xe0\xe4\x8f\x98\x01\xdd\x8d\xe5\x07\x54\x9e\x65\x25\x5f\x27\x3a\x6a\x7e\xb4\
+                       \xd3\x49\x03\x38\x3f\xdc\x1d\x4a\x0e\xc6\xde\x48\x1d\xfb\x8d\xdf\x2a\xa7\xf9\x8a\
+                       \x3e\x14\x26\xa9\xf7\x11\x31\x4d\x99\xe1\x23\xdf\x21\xaa\x8f\xc7\xf9\x63\xff\xff\
+                       \xe7\x90\xf8\x00";
+        let data = lzma_decode(0x5d, stream, 167).expect("Failed to decode");
+        assert!(data.starts_with(b"the quick brown fox") && data.ends_with(b" abab abab abab!"));
+        assert!(lzma_decode(0x5d, &stream[..40], 167).is_err());
+        assert!(lzma_decode(225, stream, 167).is_err());
+    }
+
+    #[test]
+    pub fn test_lzma2_stored() {
+        let data = (0..100000u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let stream = lzma2_stored(&data);
+        assert_eq!(lzma2_decode(&stream).unwrap(), (data, stream.len()));
+        assert_eq!(lzma2_decode(&lzma2_stored(b"")).unwrap(), (vec![], 1));
+        // Chunks must follow a dictionary reset
+        assert!(lzma2_decode(b"\x02\x00\x00a\x00").is_err());
+    }
+}
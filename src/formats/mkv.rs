@@ -0,0 +1,447 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+
+/// IDs of global EBML elements: CRC-32 of the following siblings, and padding.
+const CRC32_ID: u32 = 0xbf;
+const VOID_ID: u32 = 0xec;
+
+/// ID of binary tag values, which may be patched along with Void elements. String tag values are not, as patched
+/// bytes would not be valid UTF-8.
+const TAG_BINARY_ID: u32 = 0x4485;
+
+/// Maximum depth of nested master elements, well beyond the depth of Matroska elements.
+const MAX_DEPTH: usize = 32;
+
+/// IDs of the top-level EBML header and Matroska segment.
+const EBML_ID: u32 = 0x1a45dfa3;
+const SEGMENT_ID: u32 = 0x18538067;
+
+/// Master elements, whose data is made of child elements, with their name.
+const MASTERS: &[(u32, &str)] = &[
+    (EBML_ID, "EBML"),
+    (SEGMENT_ID, "Segment"),
+    (0x114d9b74, "SeekHead"),
+    (0x4dbb, "Seek"),
+    (0x1549a966, "Info"),
+    (0x6924, "ChapterTranslate"),
+    (0x1f43b675, "Cluster"),
+    (0x5854, "SilentTracks"),
+    (0xa0, "BlockGroup"),
+    (0x75a1, "BlockAdditions"),
+    (0xa6, "BlockMore"),
+    (0x8e, "Slices"),
+    (0xe8, "TimeSlice"),
+    (0x1654ae6b, "Tracks"),
+    (0xae, "TrackEntry"),
+    (0x6624, "TrackTranslate"),
+    (0xe0, "Video"),
+    (0x55b0, "Colour"),
+    (0x55d0, "MasteringMetadata"),
+    (0x7670, "Projection"),
+    (0xe1, "Audio"),
+    (0xe2, "TrackOperation"),
+    (0xe3, "TrackCombinePlanes"),
+    (0xe4, "TrackPlane"),
+    (0xe9, "TrackJoinBlocks"),
+    (0x6d80, "ContentEncodings"),
+    (0x6240, "ContentEncoding"),
+    (0x5034, "ContentCompression"),
+    (0x5035, "ContentEncryption"),
+    (0x47e7, "ContentEncAESSettings"),
+    (0x1c53bb6b, "Cues"),
+    (0xbb, "CuePoint"),
+    (0xb7, "CueTrackPositions"),
+    (0xdb, "CueReference"),
+    (0x1941a469, "Attachments"),
+    (0x61a7, "AttachedFile"),
+    (0x1043a770, "Chapters"),
+    (0x45b9, "EditionEntry"),
+    (0xb6, "ChapterAtom"),
+    (0x8f, "ChapterTrack"),
+    (0x80, "ChapterDisplay"),
+    (0x6944, "ChapProcess"),
+    (0x6911, "ChapProcessCommand"),
+    (0x1254c367, "Tags"),
+    (0x7373, "Tag"),
+    (0x63c0, "Targets"),
+    (0x67c8, "SimpleTag"),
+];
+
+/// Name of master element `id`.
+fn master_name(id: u32) -> Option<&'static str> {
+    MASTERS
+        .iter()
+        .find(|(master, _)| *master == id)
+        .map(|(_, name)| *name)
+}
+
+/// Whether element `id` ends master element `master` of unknown size, being a top-level element, or a child of the
+/// segment ending a cluster, such as the next cluster of a live stream.
+fn ends_unknown_size(master: u32, id: u32) -> bool {
+    id == EBML_ID || id == SEGMENT_ID || (master != SEGMENT_ID && id > 0xffffff)
+}
+
+/// Read element ID at offset `*pos` of `data`, keeping its length marker, advancing `pos`.
+fn read_id(data: &[u8], pos: &mut usize) -> CRCResult<u32> {
+    let first = *data.get(*pos).ok_or(Error::ParseError)?;
+    let size = first.leading_zeros() as usize + 1;
+    if size > 4 {
+        return Err(Error::ParseError);
+    }
+    let bytes = data.get(*pos..*pos + size).ok_or(Error::ParseError)?;
+    *pos += size;
+    Ok(bytes
+        .iter()
+        .fold(0, |id, byte| (id << 8) | u32::from(*byte)))
+}
+
+/// Read element data size at offset `*pos` of `data`, advancing `pos`. Returns None for unknown sizes, whose value
+/// bits are all set.
+fn read_size(data: &[u8], pos: &mut usize) -> CRCResult<Option<usize>> {
+    let first = *data.get(*pos).ok_or(Error::ParseError)?;
+    let size = first.leading_zeros() as usize + 1;
+    if size > 8 {
+        return Err(Error::ParseError);
+    }
+    let bytes = data.get(*pos..*pos + size).ok_or(Error::ParseError)?;
+    *pos += size;
+    let marker = 1u64 << (7 * size);
+    let value = bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte))
+        ^ marker;
+    match value == marker - 1 {
+        true => Ok(None),
+        false => Ok(Some(usize::try_from(value).map_err(|_| Error::ParseError)?)),
+    }
+}
+
+/// CRC-32 element, first child of a master element, covering its following siblings.
+#[derive(Clone)]
+pub struct Field {
+    pub offset: usize, // Offset of the master element
+    pub id: u32,       // ID of the master element
+    pub depth: usize,  // Depth of the master element, 0 for top-level elements
+    value: usize,      // Offset of the little-endian CRC value
+    covered: Range<usize>,
+    pub crc: Crc<u32>,
+}
+
+impl Field {
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  {:indent$}",
+            self.offset,
+            "",
+            indent = 2 * self.depth
+        )?;
+        match master_name(self.id) {
+            Some(name) => write!(f, "{}", name)?,
+            None => write!(f, "0x{:x}", self.id)?,
+        }
+        write!(f, "  {} bytes  crc={}", self.covered.len(), self.crc)
+    }
+}
+
+/// Void element or binary tag value of at least 4 bytes, whose last 4 bytes may be patched to forge the innermost CRC-32
+/// covering it.
+struct Slot {
+    data: Range<usize>,
+    field: usize, // Index of the innermost CRC-32 field covering the element
+    void: bool,
+}
+
+/// Matroska or WebM file: a tree of EBML elements, where master elements may start with a CRC-32 element.
+pub struct Mkv {
+    data: Vec<u8>,
+    fields: Vec<Field>,
+    slots: Vec<Slot>,
+    crc: CRC32,
+}
+
+impl Mkv {
+    /// Parse Matroska file `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let mut mkv = Self {
+            data,
+            fields: Vec::new(),
+            slots: Vec::new(),
+            crc,
+        };
+        let mut pos = 0;
+        read_id(&mkv.data, &mut pos)
+            .ok()
+            .filter(|id| *id == EBML_ID)
+            .ok_or(Error::ParseError)?;
+        mkv.walk(0, mkv.data.len(), 0, None, None)?;
+        mkv.check()?;
+        Ok(mkv)
+    }
+
+    /// Walk children of master `parent`, given by its offset, ID and size, from offset `pos` to offset `end`, at depth
+    /// `depth`, within the innermost CRC-32 field `field`. Children of a master of unknown size end at the first
+    /// element which can not be its child. Returns the end offset of the walked elements.
+    fn walk(
+        &mut self,
+        mut pos: usize,
+        end: usize,
+        depth: usize,
+        mut field: Option<usize>,
+        parent: Option<(usize, u32, Option<usize>)>,
+    ) -> CRCResult<usize> {
+        if depth > MAX_DEPTH {
+            return Err(Error::ParseError);
+        }
+        let start = pos;
+        // Masters of unknown size may not hold a CRC-32
+        let sized = match parent {
+            Some((offset, id, Some(_))) => Some((offset, id)),
+            _ => None,
+        };
+        while pos < end {
+            let offset = pos;
+            let id = read_id(&self.data[..end], &mut pos)?;
+            match parent {
+                Some((_, master, None)) if ends_unknown_size(master, id) => return Ok(offset),
+                _ => (),
+            }
+            let size = read_size(&self.data[..end], &mut pos)?;
+            let data_start = pos;
+            let mut data_end = match size {
+                Some(size) => data_start
+                    .checked_add(size)
+                    .filter(|data_end| *data_end <= end)
+                    .ok_or(Error::ParseError)?,
+                None => end,
+            };
+            match id {
+                CRC32_ID if offset == start && size == Some(4) && sized.is_some() => {
+                    let (master, master_id) = sized.unwrap();
+                    self.fields.push(Field {
+                        offset: master,
+                        id: master_id,
+                        depth: depth - 1,
+                        value: data_start,
+                        covered: data_end..end,
+                        crc: Crc::default(),
+                    });
+                    field = Some(self.fields.len() - 1);
+                }
+                VOID_ID | TAG_BINARY_ID if data_end - data_start >= 4 => {
+                    if let Some(field) = field {
+                        self.slots.push(Slot {
+                            data: data_start..data_end,
+                            field,
+                            void: id == VOID_ID,
+                        });
+                    }
+                }
+                _ if master_name(id).is_some() => {
+                    let master = Some((offset, id, size));
+                    data_end = self.walk(data_start, data_end, depth + 1, field, master)?;
+                }
+                _ => (),
+            }
+            pos = data_end;
+        }
+        Ok(pos)
+    }
+
+    /// Compute the CRC of every field.
+    fn check(&mut self) -> CRCResult<()> {
+        for field in self.fields.iter_mut() {
+            field.crc.stored =
+                u32::from_le_bytes(self.data[field.value..field.value + 4].try_into().unwrap());
+            field.crc.computed = self
+                .crc
+                .checksum(self.data[field.covered.clone()].bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Rewrite invalid CRCs, innermost first since they are covered by the CRCs of enclosing masters.
+    /// Returns the number of rewritten CRCs.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let mut order = (0..self.fields.len()).collect::<Vec<_>>();
+        order.sort_by_key(|index| std::cmp::Reverse(self.fields[*index].depth));
+        let mut repaired = 0;
+        for index in order {
+            let field = &self.fields[index];
+            let crc = self
+                .crc
+                .checksum(self.data[field.covered.clone()].bytes())?;
+            if crc != field.crc.stored {
+                let value = field.value;
+                self.data[value..value + 4].copy_from_slice(&crc.to_le_bytes());
+                repaired += 1;
+            }
+        }
+        self.check()?;
+        Ok(repaired)
+    }
+
+    /// Forge the CRC-32 of field `index` to `target_crc`, by patching the last 4 bytes of the first Void element, or
+    /// of the first binary tag value, directly covered by the field. Other CRCs are repaired.
+    pub fn forge(&mut self, index: usize, target_crc: u32) -> CRCResult<()> {
+        let field = self.fields.get(index).ok_or(Error::OutOfBoundsError)?;
+        let covered = field.covered.clone();
+        let value = field.value;
+        let slots = self.slots.iter().filter(|slot| slot.field == index);
+        let slot = slots
+            .min_by_key(|slot| !slot.void)
+            .ok_or(Error::UnsupportedError)?;
+        let offset = slot.data.end - 4;
+        self.repair()?;
+        let patch = self.crc.compute_patch(
+            &self.data[covered.clone()],
+            offset - covered.start,
+            target_crc,
+        )?;
+        self.data[offset..offset + 4].copy_from_slice(&patch);
+        self.data[value..value + 4].copy_from_slice(&target_crc.to_le_bytes());
+        self.repair()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::error::Error;
+    use crate::formats::mkv::Mkv;
+
+    /// Element `id` holding `data`.
+    fn element(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let size = (data.len() as u16 | 0x4000).to_be_bytes();
+        [id, &size, data].concat()
+    }
+
+    /// Master element `id` holding `children`, starting with their CRC-32.
+    fn checked(id: &[u8], children: &[Vec<u8>]) -> Vec<u8> {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        let children = children.concat();
+        let crc = crc32.checksum(children.bytes()).unwrap();
+        let crc = [&[0xbf, 0x84][..], &crc.to_le_bytes()].concat();
+        element(id, &[crc, children].concat())
+    }
+
+    fn file() -> Vec<u8> {
+        let header = element(b"\x1a\x45\xdf\xa3", &element(b"\x42\x82", b"webm"));
+        let info = checked(
+            b"\x15\x49\xa9\x66",
+            &[
+                element(b"\x2a\xd7\xb1", b"\x0f\x42\x40"),
+                element(b"\xec", &[0; 8]),
+            ],
+        );
+        let simple_tag = |name: &[u8], value: Vec<u8>| {
+            element(b"\x67\xc8", &[element(b"\x45\xa3", name), value].concat())
+        };
+        let simple_tags = [
+            simple_tag(b"TITLE", element(b"\x44\x87", b"lorem ipsum")),
+            simple_tag(b"COVER", element(b"\x44\x85", &[0; 8])),
+        ];
+        let tags = checked(b"\x12\x54\xc3\x67", &[checked(b"\x73\x73", &simple_tags)]);
+        let cluster = checked(
+            b"\x1f\x43\xb6\x75",
+            &[
+                element(b"\xe7", b"\x00"),
+                element(b"\xa3", b"\x81\x00\x00\x80data"),
+            ],
+        );
+        // Segment of unknown size
+        let segment = [
+            &b"\x18\x53\x80\x67\x01\xff\xff\xff\xff\xff\xff\xff"[..],
+            &info,
+            &tags,
+            &cluster,
+        ];
+        [&header[..], &segment.concat()].concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let mkv = Mkv::parse(file()).expect("Failed to parse file");
+        let names = mkv.fields().iter().map(|field| (field.id, field.depth));
+        assert_eq!(
+            names.collect::<Vec<_>>(),
+            [
+                (0x1549a966, 1),
+                (0x1254c367, 1),
+                (0x7373, 2),
+                (0x1f43b675, 1)
+            ]
+        );
+        assert!(mkv.fields().iter().all(|field| field.is_valid()));
+        assert!(Mkv::parse(file()[4..].to_vec()).is_err());
+        assert!(Mkv::parse(file()[..100].to_vec()).is_err());
+
+        // Block groups of unknown size nested beyond any Matroska element
+        let nested = [
+            &b"\x1a\x45\xdf\xa3\x80\x18\x53\x80\x67\x01\xff\xff\xff\xff\xff\xff\xff"[..],
+            b"\x1f\x43\xb6\x75\xff",
+            &b"\xa0\xff".repeat(1000),
+        ];
+        assert!(matches!(
+            Mkv::parse(nested.concat()),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = file();
+        let value = data.windows(5).position(|w| w == b"lorem").unwrap();
+        data[value] = b'L';
+        let mut mkv = Mkv::parse(data).expect("Failed to parse file");
+        let invalid = mkv
+            .fields()
+            .iter()
+            .filter(|field| !field.is_valid())
+            .count();
+        assert_eq!(invalid, 2); // Tag and Tags
+        assert_eq!(mkv.repair().unwrap(), 2);
+        let mkv = Mkv::parse(mkv.data().to_vec()).unwrap();
+        assert!(mkv.fields().iter().all(|field| field.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge() {
+        let mut mkv = Mkv::parse(file()).expect("Failed to parse file");
+        mkv.forge(0, 0xdeadbeef)
+            .expect("Failed to forge Void element");
+        mkv.forge(2, 0x13371337)
+            .expect("Failed to forge binary tag value");
+        let mkv = Mkv::parse(mkv.data().to_vec()).unwrap();
+        assert!(mkv.fields().iter().all(|field| field.is_valid()));
+        assert_eq!(mkv.fields()[0].crc.stored, 0xdeadbeef);
+        assert_eq!(mkv.fields()[2].crc.stored, 0x13371337);
+        assert!(mkv.data().windows(11).any(|w| w == b"lorem ipsum"));
+
+        // Tag values are covered by the CRC-32 of Tag, the innermost one
+        let mut mkv = Mkv::parse(file()).unwrap();
+        assert!(mkv.forge(1, 0).is_err());
+        assert!(mkv.forge(4, 0).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+
+/// Capture pattern starting Ogg pages.
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+
+/// Size of the fixed part of page headers, up to the segment table.
+const HEADER_SIZE: usize = 27;
+
+/// Offset of the CRC field in page headers.
+const CRC_OFFSET: usize = 22;
+
+/// Headers of comment packets: Vorbis comment header and Opus tags.
+const COMMENT_HEADERS: [&[u8]; 2] = [b"\x03vorbis", b"OpusTags"];
+
+/// Ogg page CRC: non-reflected, with null init and final XOR.
+const OGG_CRC: CRC32Properties = CRC32Properties {
+    g: 0x04c11db7,
+    i: 0,
+    f: 0,
+    refin: false,
+    refout: false,
+};
+
+/// Ogg page, with its stored and actual CRC.
+#[derive(Clone)]
+pub struct Page {
+    pub offset: usize, // Offset of the page in file
+    pub serial: u32,   // Bitstream serial number
+    pub sequence: u32, // Page sequence number in bitstream
+    lacing: Vec<u8>,   // Segment table
+    pub length: usize, // Length of the page, header included
+    pub crc: Crc<u32>, // CRC stored in page header, and CRC of the page with a null CRC field
+}
+
+impl Page {
+    /// Range of page data in file.
+    fn data(&self) -> Range<usize> {
+        self.offset + HEADER_SIZE + self.lacing.len()..self.offset + self.length
+    }
+
+    /// Offsets in page data of the packets starting in this page.
+    /// The first one is skipped when continuing a packet from the previous page.
+    fn packet_starts(&self, continued: bool) -> Vec<usize> {
+        let mut starts = Vec::new();
+        let (mut offset, mut start) = (0, !continued);
+        for lacing in &self.lacing {
+            if start {
+                starts.push(offset);
+            }
+            offset += *lacing as usize;
+            start = *lacing < 255;
+        }
+        starts
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+}
+
+impl Display for Page {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  serial=0x{:08x}  page {}  {} bytes  crc={}",
+            self.offset, self.serial, self.sequence, self.length, self.crc
+        )
+    }
+}
+
+/// Ogg file, split into pages.
+pub struct Ogg {
+    data: Vec<u8>,
+    pages: Vec<Page>,
+    crc: CRC32,
+}
+
+impl Ogg {
+    /// Parse Ogg file `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(OGG_CRC)?;
+        let mut ogg = Self {
+            data,
+            pages: Vec::new(),
+            crc,
+        };
+        ogg.parse_pages()?;
+        Ok(ogg)
+    }
+
+    /// Copy of page `page`, with a null CRC field.
+    fn zeroed(&self, page: &Page) -> Vec<u8> {
+        let mut bytes = self.data[page.offset..page.offset + page.length].to_vec();
+        bytes[CRC_OFFSET..CRC_OFFSET + 4].fill(0);
+        bytes
+    }
+
+    fn parse_pages(&mut self) -> CRCResult<()> {
+        let field = |offset: usize| -> CRCResult<u32> {
+            let bytes = self.data.get(offset..offset + 4).ok_or(Error::ParseError)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let mut pages = Vec::new();
+        let mut offset = 0;
+        while offset < self.data.len() {
+            if !self.data[offset..].starts_with(CAPTURE_PATTERN) {
+                return Err(Error::ParseError);
+            }
+            let segments = *self
+                .data
+                .get(offset + HEADER_SIZE - 1)
+                .ok_or(Error::ParseError)? as usize;
+            let lacing = self
+                .data
+                .get(offset + HEADER_SIZE..offset + HEADER_SIZE + segments)
+                .ok_or(Error::ParseError)?
+                .to_vec();
+            let length = HEADER_SIZE + segments + lacing.iter().map(|l| *l as usize).sum::<usize>();
+            if offset + length > self.data.len() {
+                return Err(Error::ParseError);
+            }
+            let mut page = Page {
+                offset,
+                serial: field(offset + 14)?,
+                sequence: field(offset + 18)?,
+                lacing,
+                length,
+                crc: Crc::new(field(offset + CRC_OFFSET)?, 0),
+            };
+            page.crc.computed = self.crc.checksum(self.zeroed(&page).bytes())?;
+            pages.push(page);
+            offset += length;
+        }
+        self.pages = pages;
+        Ok(())
+    }
+
+    pub fn pages(&self) -> &[Page] {
+        &self.pages
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Write CRC `crc` of page `index`.
+    fn write_crc(&mut self, index: usize, crc: u32) {
+        let page = &mut self.pages[index];
+        let offset = page.offset + CRC_OFFSET;
+        page.crc.stored = crc;
+        self.data[offset..offset + 4].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Rewrite all invalid page CRCs, returning the number of repaired pages.
+    pub fn repair(&mut self) -> usize {
+        let invalid = (0..self.pages.len())
+            .filter(|i| !self.pages[*i].is_valid())
+            .collect::<Vec<_>>();
+        for i in &invalid {
+            self.write_crc(*i, self.pages[*i].crc.computed);
+        }
+        invalid.len()
+    }
+
+    /// Find the last 4 bytes of the last string (vendor string or comment) of a comment packet starting in page
+    /// `index`, that lie in this page. Returns their offset in page data.
+    fn comment_slot(&self, index: usize) -> Option<usize> {
+        let page = &self.pages[index];
+        let data = &self.data[page.data()];
+        // A packet continues from the previous page when its last segment is full
+        let continued = index > 0
+            && self.pages[..index]
+                .iter()
+                .rev()
+                .find(|previous| previous.serial == page.serial)
+                .and_then(|previous| previous.lacing.last())
+                .is_some_and(|lacing| *lacing == 255);
+
+        let length = |offset: usize| -> Option<usize> {
+            let bytes = data.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let mut slot = None;
+        for start in page.packet_starts(continued) {
+            let Some(header) = COMMENT_HEADERS
+                .iter()
+                .find(|header| data[start..].starts_with(header))
+            else {
+                continue;
+            };
+            // Vendor string, then comment count and comments, each string starting with its length
+            let vendor = start + header.len();
+            let count = length(vendor)
+                .and_then(|size| length(vendor + 4 + size))
+                .unwrap_or(0);
+            let mut offset = vendor;
+            for i in 0..=count {
+                let Some(size) = length(offset) else { break };
+                let end = offset + 4 + size;
+                if end > data.len() {
+                    break;
+                }
+                if size >= 4 {
+                    slot = Some(end - 4);
+                }
+                offset = if i == 0 { end + 4 } else { end };
+            }
+        }
+        slot
+    }
+
+    /// Patch 4 bytes of page `index` data at offset `offset` so that its CRC is `target_crc`, and store it.
+    /// By default, the last bytes of the last comment of a comment packet starting in this page are patched.
+    pub fn forge_page(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let page = self.pages.get(index).ok_or(Error::OutOfBoundsError)?;
+        let offset = match offset {
+            Some(offset) => offset,
+            None => self.comment_slot(index).ok_or(Error::UnsupportedError)?,
+        };
+        let offset = (HEADER_SIZE + page.lacing.len())
+            .checked_add(offset)
+            .ok_or(Error::OutOfBoundsError)?;
+        let patch = self
+            .crc
+            .compute_patch(&self.zeroed(page), offset, target_crc)?;
+        let start = page.offset + offset;
+        self.data[start..start + 4].copy_from_slice(&patch);
+        self.pages[index].crc.computed = target_crc;
+        self.write_crc(index, target_crc);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::CRC32;
+    use crate::formats::ogg::{OGG_CRC, Ogg};
+
+    /// Build Ogg page of bitstream 1 with sequence number `sequence`, holding complete packets `packets`.
+    fn page(sequence: u32, packets: &[&[u8]]) -> Vec<u8> {
+        let crc = CRC32::new(OGG_CRC).unwrap();
+        let mut lacing = Vec::new();
+        for packet in packets {
+            lacing.extend(vec![255; packet.len() / 255]);
+            lacing.push((packet.len() % 255) as u8);
+        }
+        let mut data = [&b"OggS\0\0"[..], &[0; 8], &1u32.to_le_bytes()].concat();
+        data.extend(sequence.to_le_bytes());
+        data.extend([0; 4]);
+        data.push(lacing.len() as u8);
+        data.extend(lacing);
+        data.extend(packets.concat());
+        let c = crc.checksum(data.bytes()).unwrap();
+        data[22..26].copy_from_slice(&c.to_le_bytes());
+        data
+    }
+
+    /// Build Ogg file with an identification header page, a comment header page and an audio page.
+    fn ogg() -> Vec<u8> {
+        let comments = [
+            &b"\x03vorbis"[..],
+            b"\x06\0\0\0vendor\x02\0\0\0",
+            b"\x0b\0\0\0TITLE=lorem",
+            b"\x0c\0\0\0ARTIST=ipsum",
+            b"\x01",
+        ]
+        .concat();
+        let audio = vec![0x42; 300];
+        [
+            page(0, &[b"\x01vorbis"]),
+            page(1, &[&comments, b"\x05vorbis"]),
+            page(2, &[&audio]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let ogg = Ogg::parse(ogg()).expect("Failed to parse Ogg");
+        let sequences = ogg.pages().iter().map(|p| p.sequence).collect::<Vec<_>>();
+        assert_eq!(sequences, [0, 1, 2]);
+        assert!(ogg.pages().iter().all(|p| p.is_valid()));
+        assert_eq!(ogg.pages()[1].packet_starts(false), [0, 53]);
+        assert!(Ogg::parse(b"OggS\0".to_vec()).is_err());
+
+        let crc = CRC32::new(OGG_CRC).unwrap();
+        assert_eq!(crc.checksum(b"123456789".bytes()).unwrap(), 0x89a1897f);
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = ogg();
+        let offset = data.windows(5).position(|w| w == b"lorem").unwrap();
+        data[offset] = b'L';
+        let mut ogg = Ogg::parse(data).expect("Failed to parse Ogg");
+        assert!(!ogg.pages()[1].is_valid());
+        assert_eq!(ogg.repair(), 1);
+        let ogg = Ogg::parse(ogg.data().to_vec()).unwrap();
+        assert!(ogg.pages().iter().all(|p| p.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge_page() {
+        let mut ogg = Ogg::parse(ogg()).expect("Failed to parse Ogg");
+        ogg.forge_page(1, None, 0x42424242)
+            .expect("Failed to forge page");
+        ogg.forge_page(2, Some(10), 0x13371337)
+            .expect("Failed to forge page");
+        assert!(ogg.forge_page(0, None, 0).is_err());
+        assert!(ogg.forge_page(2, Some(usize::MAX), 0).is_err());
+
+        let data = ogg.data().to_vec();
+        let ogg = Ogg::parse(data.clone()).unwrap();
+        assert!(ogg.pages().iter().all(|p| p.is_valid()));
+        assert_eq!(ogg.pages()[1].crc.stored, 0x42424242);
+        assert_eq!(ogg.pages()[2].crc.stored, 0x13371337);
+        // Last comment is patched
+        let offset = data.windows(7).position(|w| w == b"ARTIST=").unwrap();
+        assert_eq!(&data[offset..offset + 8], b"ARTIST=i");
+        assert_ne!(&data[offset + 8..offset + 12], b"psum");
+    }
+}
@@ -0,0 +1,487 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+
+/// Magic numbers of pcap files, with microsecond and nanosecond timestamps, as written by the capturing host.
+const PCAP_MAGICS: [u32; 2] = [0xa1b2c3d4, 0xa1b23c4d];
+
+/// Size of the pcap global header.
+const PCAP_HEADER_SIZE: usize = 24;
+
+/// Size of a pcap record header.
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+/// Flag of the pcap link type field telling that the FCS length is given, in its 4 most significant bits.
+const PCAP_FCS_PRESENT: u32 = 1 << 26;
+
+/// pcapng block types.
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const SIMPLE_PACKET_BLOCK: u32 = 3;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+
+/// pcapng byte order magic, in section header blocks.
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+/// pcapng interface description option giving the FCS length.
+const IF_FCSLEN: u32 = 13;
+
+/// Link type of Ethernet frames.
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Size of Ethernet frame headers, left untouched when forging the FCS.
+const ETHERNET_HEADER_SIZE: usize = 14;
+
+/// Size of the Ethernet FCS.
+const FCS_SIZE: usize = 4;
+
+/// Byte order of a capture file, given by its magic number.
+fn byte_order(magics: &[u32], magic: &[u8]) -> Option<fn([u8; 4]) -> u32> {
+    let magic: [u8; 4] = magic.get(..4)?.try_into().ok()?;
+    if magics.contains(&u32::from_le_bytes(magic)) {
+        Some(u32::from_le_bytes)
+    } else if magics.contains(&u32::from_be_bytes(magic)) {
+        Some(u32::from_be_bytes)
+    } else {
+        None
+    }
+}
+
+/// Check whether `data` is a pcap or pcapng file.
+pub fn is_pcap(data: &[u8]) -> bool {
+    byte_order(&PCAP_MAGICS, data).is_some()
+        || data.starts_with(&SECTION_HEADER_BLOCK.to_le_bytes())
+}
+
+/// Packet of a capture file.
+struct Packet {
+    data: Range<usize>, // Range of packet data in file
+    linktype: u32,
+    fcs: Option<usize>, // Length of the FCS ending packet data, when known
+    truncated: bool,    // Whether the packet was captured shorter than its original length
+}
+
+/// Parse classic pcap file `data` into its packets.
+fn parse_classic(data: &[u8]) -> CRCResult<Vec<Packet>> {
+    let read = byte_order(&PCAP_MAGICS, data).ok_or(Error::ParseError)?;
+    let field = |offset: usize| -> CRCResult<u32> {
+        let bytes = data.get(offset..offset + 4).ok_or(Error::ParseError)?;
+        Ok(read(bytes.try_into().unwrap()))
+    };
+    let linktype = field(20)?;
+    let fcs = (linktype & PCAP_FCS_PRESENT != 0).then_some(2 * (linktype >> 28) as usize);
+
+    let mut packets = Vec::new();
+    let mut offset = PCAP_HEADER_SIZE;
+    while offset < data.len() {
+        let size = field(offset + 8)? as usize;
+        let original = field(offset + 12)? as usize;
+        let start = offset + PCAP_RECORD_HEADER_SIZE;
+        if start + size > data.len() {
+            return Err(Error::ParseError);
+        }
+        packets.push(Packet {
+            data: start..start + size,
+            linktype: linktype & 0xffff,
+            fcs,
+            truncated: size < original,
+        });
+        offset = start + size;
+    }
+    Ok(packets)
+}
+
+/// Parse pcapng file `data` into the packets of its enhanced and simple packet blocks.
+fn parse_pcapng(data: &[u8]) -> CRCResult<Vec<Packet>> {
+    let mut read: fn([u8; 4]) -> u32 = u32::from_le_bytes;
+    let mut interfaces = Vec::<(u32, Option<usize>)>::new(); // Link type and FCS length of interfaces of the section
+    let mut packets = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let block_type = data.get(offset..offset + 4).ok_or(Error::ParseError)?;
+        if block_type == SECTION_HEADER_BLOCK.to_le_bytes() {
+            read = byte_order(
+                &[PCAPNG_BYTE_ORDER_MAGIC],
+                data.get(offset + 8..).unwrap_or_default(),
+            )
+            .ok_or(Error::ParseError)?;
+            interfaces.clear();
+        }
+        let field = |offset: usize| -> CRCResult<u32> {
+            let bytes = data.get(offset..offset + 4).ok_or(Error::ParseError)?;
+            Ok(read(bytes.try_into().unwrap()))
+        };
+        let length = field(offset + 4)? as usize;
+        if length < 12 || offset + length > data.len() {
+            return Err(Error::ParseError);
+        }
+        let body = offset + 8..offset + length - 4;
+
+        match field(offset)? {
+            INTERFACE_DESCRIPTION_BLOCK => {
+                let linktype = field(body.start)? & 0xffff;
+                // Options are made of a 16 bit code and length, then a value padded to 32 bits
+                let mut fcs = None;
+                let mut option = body.start + 8;
+                while option + 4 <= body.end {
+                    let value = field(option)?;
+                    let (code, size) = match read(1u32.to_le_bytes()) == 1 {
+                        true => (value & 0xffff, value >> 16),
+                        false => (value >> 16, value & 0xffff),
+                    };
+                    if code == IF_FCSLEN && size >= 1 {
+                        fcs = data.get(option + 4).map(|length| *length as usize);
+                    }
+                    option += 4 + (size as usize).div_ceil(4) * 4;
+                }
+                interfaces.push((linktype, fcs));
+            }
+            ENHANCED_PACKET_BLOCK => {
+                let interface = field(body.start)? as usize;
+                let size = field(body.start + 12)? as usize;
+                let original = field(body.start + 16)? as usize;
+                let start = body.start + 20;
+                if start + size > body.end {
+                    return Err(Error::ParseError);
+                }
+                let (linktype, fcs) = *interfaces.get(interface).ok_or(Error::ParseError)?;
+                packets.push(Packet {
+                    data: start..start + size,
+                    linktype,
+                    fcs,
+                    truncated: size < original,
+                });
+            }
+            SIMPLE_PACKET_BLOCK => {
+                let start = body.start + 4;
+                let original = field(body.start)? as usize;
+                let size = original.min(body.end.saturating_sub(start));
+                let (linktype, fcs) = *interfaces.first().ok_or(Error::ParseError)?;
+                packets.push(Packet {
+                    data: start..start + size,
+                    linktype,
+                    fcs,
+                    truncated: size < original,
+                });
+            }
+            _ => (),
+        }
+        offset += length;
+    }
+    Ok(packets)
+}
+
+/// Parse pcap or pcapng file `data` into its packets.
+fn parse_packets(data: &[u8]) -> CRCResult<Vec<Packet>> {
+    match data.starts_with(&SECTION_HEADER_BLOCK.to_le_bytes()) {
+        true => parse_pcapng(data),
+        false => parse_classic(data),
+    }
+}
+
+/// Parse pcap or pcapng file `data` into its packets.
+pub fn parse_pcap(data: &[u8]) -> CRCResult<Vec<Vec<u8>>> {
+    Ok(parse_packets(data)?
+        .into_iter()
+        .map(|packet| data[packet.data].to_vec())
+        .collect())
+}
+
+/// Ethernet frame of a capture, with its stored and actual FCS.
+#[derive(Clone)]
+pub struct Frame {
+    pub packet: usize,  // Index of the packet in capture
+    data: Range<usize>, // Range of the frame in file, FCS included
+    pub crc: Crc<u32>,  // FCS ending the frame, and CRC of the frame, up to the FCS
+}
+
+impl Frame {
+    /// Offset of the FCS in file.
+    fn fcs_offset(&self) -> usize {
+        self.data.end - FCS_SIZE
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  packet {}  {} bytes  fcs={}",
+            self.data.start,
+            self.packet,
+            self.data.len(),
+            self.crc
+        )
+    }
+}
+
+/// Capture file, with its Ethernet frames ending with a FCS.
+pub struct Capture {
+    data: Vec<u8>,
+    frames: Vec<Frame>,
+    crc: CRC32,
+}
+
+impl Capture {
+    /// Parse pcap or pcapng file `data`.
+    /// Ethernet frames end with a FCS when the capture tells so, or when `fcs` is set and the capture does not tell
+    /// otherwise. Truncated packets are skipped, as their FCS was not captured.
+    pub fn parse(data: Vec<u8>, fcs: bool) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let mut frames = Vec::new();
+        for (i, packet) in parse_packets(&data)?.into_iter().enumerate() {
+            if packet.linktype != LINKTYPE_ETHERNET
+                || packet.fcs.unwrap_or(if fcs { FCS_SIZE } else { 0 }) != FCS_SIZE
+                || packet.truncated
+                || packet.data.len() < ETHERNET_HEADER_SIZE + FCS_SIZE
+            {
+                continue;
+            }
+            let (frame, fcs) = data[packet.data.clone()].split_at(packet.data.len() - FCS_SIZE);
+            frames.push(Frame {
+                packet: i,
+                data: packet.data,
+                crc: Crc::new(
+                    u32::from_le_bytes(fcs.try_into().unwrap()),
+                    crc.checksum(frame.bytes())?,
+                ),
+            });
+        }
+        Ok(Self { data, frames, crc })
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Write FCS `fcs` of frame `index`.
+    fn write_fcs(&mut self, index: usize, fcs: u32) {
+        let frame = &mut self.frames[index];
+        let offset = frame.fcs_offset();
+        frame.crc.stored = fcs;
+        self.data[offset..offset + FCS_SIZE].copy_from_slice(&fcs.to_le_bytes());
+    }
+
+    /// Rewrite all invalid FCS, returning the number of repaired frames.
+    pub fn repair(&mut self) -> usize {
+        let invalid = (0..self.frames.len())
+            .filter(|i| !self.frames[*i].is_valid())
+            .collect::<Vec<_>>();
+        for i in &invalid {
+            self.write_fcs(*i, self.frames[*i].crc.computed);
+        }
+        invalid.len()
+    }
+
+    /// Patch 4 bytes of frame `index` at offset `offset` so that its FCS is `target_crc`, and store it.
+    /// The last 4 bytes before the FCS, payload or padding, are patched by default.
+    pub fn forge_frame(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let frame = self.frames.get(index).ok_or(Error::OutOfBoundsError)?;
+        let range = frame.data.start..frame.fcs_offset();
+        let offset = match offset {
+            Some(offset) => offset,
+            None => range.len() - 4,
+        };
+        if offset < ETHERNET_HEADER_SIZE {
+            return Err(Error::OutOfBoundsError);
+        }
+        let patch = self
+            .crc
+            .compute_patch(&self.data[range.clone()], offset, target_crc)?;
+        let start = range.start + offset;
+        self.data[start..start + 4].copy_from_slice(&patch);
+        self.frames[index].crc.computed = target_crc;
+        self.write_fcs(index, target_crc);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::pcap::{Capture, is_pcap, parse_pcap};
+
+    /// Build pcap file with link type `linktype` holding `packets`, with big-endian headers.
+    fn pcap(linktype: u32, packets: &[&[u8]]) -> Vec<u8> {
+        // Magic, version 2.4, time zone, timestamps accuracy, snapshot length and link type
+        let mut data = [0xa1b2c3d4u32, 0x00020004, 0, 0, 0xffff, linktype]
+            .iter()
+            .flat_map(|field| field.to_be_bytes())
+            .collect::<Vec<u8>>();
+        for packet in packets {
+            for field in [0, 0, packet.len() as u32, packet.len() as u32] {
+                data.extend(field.to_be_bytes());
+            }
+            data.extend(*packet);
+        }
+        data
+    }
+
+    /// Build little-endian pcapng file with an Ethernet interface holding `packets`, with options `options`.
+    fn pcapng(options: &[u8], packets: &[&[u8]]) -> Vec<u8> {
+        let block = |kind: u32, body: &[u8]| {
+            let length = (12 + body.len()) as u32;
+            [
+                &kind.to_le_bytes()[..],
+                &length.to_le_bytes(),
+                body,
+                &length.to_le_bytes(),
+            ]
+            .concat()
+        };
+        let mut data = block(
+            0x0a0d0d0a,
+            b"\x4d\x3c\x2b\x1a\x01\x00\x00\x00\xff\xff\xff\xff\xff\xff\xff\xff",
+        );
+        data.extend(block(
+            1,
+            &[b"\x01\x00\x00\x00\xff\xff\x00\x00", options].concat(),
+        ));
+        for packet in packets {
+            let mut body = vec![0; 12];
+            body.extend((packet.len() as u32).to_le_bytes());
+            body.extend((packet.len() as u32).to_le_bytes());
+            body.extend(*packet);
+            body.resize(body.len().div_ceil(4) * 4, 0);
+            data.extend(block(6, &body));
+        }
+        data
+    }
+
+    /// Build Ethernet frame with payload `payload`, ending with its FCS.
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let crc = CRC32::new(CRC32Properties::default()).unwrap();
+        let mut frame = [&[0xff; 6][..], &[0x02, 0, 0, 0, 0, 1], b"\x08\x00", payload].concat();
+        frame.resize(frame.len().max(60), 0);
+        let fcs = crc.checksum(frame.bytes()).unwrap();
+        frame.extend(fcs.to_le_bytes());
+        frame
+    }
+
+    #[test]
+    pub fn test_parse_pcap() {
+        let data = pcap(147, &[b"\x01\x02\x03", b"", b"\x04"]);
+        assert!(is_pcap(&data));
+        let packets = parse_pcap(&data).expect("Failed to parse pcap");
+        assert_eq!(packets, [vec![1, 2, 3], vec![], vec![4]]);
+
+        let data = pcapng(b"", &[b"\x01\x02\x03", b"\x04"]);
+        assert!(is_pcap(&data));
+        let packets = parse_pcap(&data).expect("Failed to parse pcapng");
+        assert_eq!(packets, [vec![1, 2, 3], vec![4]]);
+    }
+
+    #[test]
+    pub fn test_truncated_pcap() {
+        let mut data = pcap(1, &[b"\x01\x02\x03"]);
+        data.pop();
+        assert!(parse_pcap(&data).is_err());
+        assert!(!is_pcap(b"0102"));
+    }
+
+    #[test]
+    pub fn test_fcs() {
+        let frames = [frame(b"lorem ipsum"), frame(&[0x42; 100])];
+        let frames = frames.iter().map(|f| &f[..]).collect::<Vec<_>>();
+        for data in [pcap(1, &frames), pcapng(b"", &frames)] {
+            let capture = Capture::parse(data, true).expect("Failed to parse capture");
+            assert_eq!(capture.frames().len(), 2);
+            assert!(capture.frames().iter().all(|f| f.is_valid()));
+        }
+
+        // Captures telling frames end with a FCS, through their link type or interface
+        let linktype = 1 | 1 << 26 | 2 << 28;
+        let fcslen = b"\x0d\x00\x01\x00\x04\x00\x00\x00\x00\x00\x00\x00";
+        for data in [pcap(linktype, &frames), pcapng(fcslen, &frames)] {
+            assert_eq!(Capture::parse(data, false).unwrap().frames().len(), 2);
+        }
+
+        // Captures which do not tell, telling frames have no FCS, or which are not Ethernet ones
+        assert!(
+            Capture::parse(pcap(1, &frames), false)
+                .unwrap()
+                .frames()
+                .is_empty()
+        );
+        let declared = pcapng(b"\x0d\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00", &frames);
+        assert!(Capture::parse(declared, true).unwrap().frames().is_empty());
+        assert!(
+            Capture::parse(pcap(147, &frames), true)
+                .unwrap()
+                .frames()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    pub fn test_truncated_frame() {
+        let frames = [frame(b"lorem ipsum"), frame(&[0x42; 100])];
+        let frames = frames.iter().map(|f| &f[..]).collect::<Vec<_>>();
+
+        // Original length of the first packet, in its record header or enhanced packet block
+        let mut data = pcap(1, &frames);
+        data[36..40].copy_from_slice(&100u32.to_be_bytes());
+        let capture = Capture::parse(data, true).unwrap();
+        assert_eq!(capture.frames().len(), 1);
+        assert_eq!(capture.frames()[0].packet, 1);
+
+        let mut data = pcapng(b"", &frames);
+        data[72..76].copy_from_slice(&100u32.to_le_bytes());
+        let capture = Capture::parse(data, true).unwrap();
+        assert_eq!(capture.frames().len(), 1);
+        assert_eq!(capture.frames()[0].packet, 1);
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = pcapng(b"", &[&frame(b"lorem ipsum")]);
+        let offset = data.windows(5).position(|w| w == b"lorem").unwrap();
+        data[offset] = b'L';
+        let mut capture = Capture::parse(data, true).expect("Failed to parse capture");
+        assert!(!capture.frames()[0].is_valid());
+        assert_eq!(capture.repair(), 1);
+        let capture = Capture::parse(capture.data().to_vec(), true).unwrap();
+        assert!(capture.frames()[0].is_valid());
+    }
+
+    #[test]
+    pub fn test_forge_frame() {
+        let mut capture = Capture::parse(
+            pcap(1, &[&frame(b"lorem ipsum"), &frame(&[0x42; 100])]),
+            true,
+        )
+        .unwrap();
+        capture
+            .forge_frame(0, None, 0x42424242)
+            .expect("Failed to forge frame");
+        capture
+            .forge_frame(1, Some(20), 0x13371337)
+            .expect("Failed to forge frame");
+        assert!(capture.forge_frame(1, Some(10), 0).is_err());
+
+        let data = capture.data().to_vec();
+        let capture = Capture::parse(data.clone(), true).unwrap();
+        assert!(capture.frames().iter().all(|f| f.is_valid()));
+        assert_eq!(capture.frames()[0].crc.stored, 0x42424242);
+        assert_eq!(capture.frames()[1].crc.stored, 0x13371337);
+        // Padding is patched, payload is kept
+        assert!(data.windows(11).any(|w| w == b"lorem ipsum"));
+    }
+}
@@ -0,0 +1,408 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::math::solve_gf2;
+
+/// PNG file signature.
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Type of the chunk ending PNG files.
+const IEND: &[u8; 4] = b"IEND";
+
+/// Chunk type used to forge the file CRC: ancillary, private, and safe to copy.
+const FORGE_CHUNK: &[u8; 4] = b"crFg";
+
+/// Keyword of the tEXt chunk used to forge the file CRC.
+const FORGE_KEYWORD: &[u8] = b"crc-forge";
+
+/// Number of bytes adjusted in each chunk used to forge the file CRC.
+const FORGE_SIZE: usize = 4;
+
+/// Number of chunks used to forge the file CRC.
+/// A chunk followed by its big-endian CRC only changes the file CRC through 16 bits, since the file CRC would not depend
+/// on it at all if its CRC was stored little-endian. Two chunks often share some of these bits, hence three chunks.
+const FORGE_CHUNKS: usize = 3;
+
+/// PNG chunk, with its stored and actual CRC.
+#[derive(Clone)]
+pub struct Chunk {
+    pub offset: usize, // Offset of the chunk length field in file
+    pub kind: [u8; 4], // Chunk type
+    pub length: usize, // Length of chunk data
+    pub crc: Crc<u32>, // CRC stored after chunk data, and CRC of chunk type and data
+}
+
+impl Chunk {
+    /// Range of the chunk type and data in file, over which the CRC is computed.
+    fn body(&self) -> Range<usize> {
+        self.offset + 4..self.offset + 8 + self.length
+    }
+
+    /// Range of the chunk data in file.
+    fn data(&self) -> Range<usize> {
+        self.offset + 8..self.offset + 8 + self.length
+    }
+
+    /// Offset of the CRC field in file.
+    fn crc_offset(&self) -> usize {
+        self.offset + 8 + self.length
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  {}  {} bytes  crc={}",
+            self.offset,
+            String::from_utf8_lossy(&self.kind),
+            self.length,
+            self.crc
+        )
+    }
+}
+
+/// PNG file, split into chunks.
+/// Any data following the IEND chunk is kept as is.
+pub struct Png {
+    data: Vec<u8>,
+    chunks: Vec<Chunk>,
+    crc: CRC32,
+}
+
+impl Png {
+    /// Parse PNG file `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        if !data.starts_with(PNG_SIGNATURE) {
+            return Err(Error::ParseError);
+        }
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let mut png = Self {
+            data,
+            chunks: Vec::new(),
+            crc,
+        };
+        png.parse_chunks()?;
+        Ok(png)
+    }
+
+    /// Parse chunks of the file, up to the IEND chunk or the end of file.
+    fn parse_chunks(&mut self) -> CRCResult<()> {
+        let field = |offset: usize| -> CRCResult<[u8; 4]> {
+            let bytes = self.data.get(offset..offset + 4);
+            Ok(bytes.ok_or(Error::ParseError)?.try_into().unwrap())
+        };
+
+        let mut chunks = Vec::new();
+        let mut offset = PNG_SIGNATURE.len();
+        while offset < self.data.len() {
+            let length = u32::from_be_bytes(field(offset)?) as usize;
+            let kind = field(offset + 4)?;
+            let mut chunk = Chunk {
+                offset,
+                kind,
+                length,
+                crc: Crc::default(),
+            };
+            chunk.crc.stored = u32::from_be_bytes(field(chunk.crc_offset())?);
+            chunk.crc.computed = self.crc.checksum(self.data[chunk.body()].bytes())?;
+            offset = chunk.crc_offset() + 4;
+            chunks.push(chunk);
+            if &kind == IEND {
+                break;
+            }
+        }
+        self.chunks = chunks;
+        Ok(())
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Write CRC `crc` of chunk `index`.
+    fn write_crc(&mut self, index: usize, crc: u32) {
+        let chunk = &mut self.chunks[index];
+        let offset = chunk.crc_offset();
+        chunk.crc.stored = crc;
+        self.data[offset..offset + 4].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    /// Rewrite all invalid chunk CRCs, returning the number of repaired chunks.
+    pub fn repair(&mut self) -> usize {
+        let invalid = (0..self.chunks.len())
+            .filter(|i| !self.chunks[*i].is_valid())
+            .collect::<Vec<_>>();
+        for i in &invalid {
+            self.write_crc(*i, self.chunks[*i].crc.computed);
+        }
+        invalid.len()
+    }
+
+    /// Patch 4 bytes of chunk `index` data at offset `offset` so that its CRC is `target_crc`, and store it.
+    /// The last 4 bytes of chunk data are patched by default.
+    pub fn forge_chunk(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let chunk = self.chunks.get(index).ok_or(Error::OutOfBoundsError)?;
+        let offset = match offset {
+            Some(offset) => offset,
+            None => chunk.length.checked_sub(4).ok_or(Error::OutOfBoundsError)?,
+        };
+        if offset.checked_add(4).is_none_or(|end| end > chunk.length) {
+            return Err(Error::OutOfBoundsError);
+        }
+
+        // CRC covers chunk type, then data
+        let body = chunk.body();
+        let patch = self
+            .crc
+            .compute_patch(&self.data[body.clone()], 4 + offset, target_crc)?;
+        let start = body.start + 4 + offset;
+        self.data[start..start + 4].copy_from_slice(&patch);
+        self.chunks[index].crc.computed = target_crc;
+        self.write_crc(index, target_crc);
+        Ok(())
+    }
+
+    /// Find chunks used to forge the file CRC: private `FORGE_CHUNK` chunks, or tEXt chunks with keyword
+    /// `FORGE_KEYWORD` if `text` is set.
+    fn forge_chunk_indices(&self, text: bool) -> Vec<usize> {
+        (0..self.chunks.len())
+            .filter(|i| {
+                let chunk = &self.chunks[*i];
+                let data = &self.data[chunk.data()];
+                match text {
+                    true => {
+                        &chunk.kind == b"tEXt"
+                            && data.len() >= FORGE_KEYWORD.len() + 1 + FORGE_SIZE
+                            && data.starts_with(FORGE_KEYWORD)
+                            && data[FORGE_KEYWORD.len()] == 0
+                    }
+                    false => &chunk.kind == FORGE_CHUNK && data.len() >= FORGE_SIZE,
+                }
+            })
+            .collect()
+    }
+
+    /// Insert chunk of type `kind` with data `data` before the IEND chunk, or at the end of chunks.
+    fn insert_chunk(&mut self, kind: &[u8; 4], data: &[u8]) -> CRCResult<usize> {
+        let index = match self.chunks.last() {
+            Some(chunk) if &chunk.kind == IEND => self.chunks.len() - 1,
+            _ => self.chunks.len(),
+        };
+        let offset = match self.chunks.get(index) {
+            Some(chunk) => chunk.offset,
+            None => self
+                .chunks
+                .last()
+                .map_or(PNG_SIGNATURE.len(), |chunk| chunk.crc_offset() + 4),
+        };
+        let body = [&kind[..], data].concat();
+        let chunk = [
+            &(data.len() as u32).to_be_bytes()[..],
+            &body,
+            &self.crc.checksum(body.bytes())?.to_be_bytes(),
+        ]
+        .concat();
+        self.data.splice(offset..offset, chunk);
+        self.parse_chunks()?;
+        Ok(index)
+    }
+
+    /// Forge the CRC of the whole file to `target_crc` while keeping all chunk CRCs valid, by adjusting the last bytes of
+    /// ancillary chunks: private chunks, or tEXt chunks if `text` is set. Chunks are inserted before IEND if missing.
+    ///
+    /// The file CRC is an affine function of the adjusted bytes, since chunk CRCs themselves are: a linear system over F2
+    /// gives the adjusted bytes from the effect of each of their bits on the file CRC.
+    pub fn forge_file(&mut self, target_crc: u32, text: bool) -> CRCResult<()> {
+        let mut indices = self.forge_chunk_indices(text);
+        while indices.len() < FORGE_CHUNKS {
+            let data = match text {
+                true => [FORGE_KEYWORD, &[0], &[0; FORGE_SIZE]].concat(),
+                false => vec![0; FORGE_SIZE],
+            };
+            let kind = if text { b"tEXt" } else { FORGE_CHUNK };
+            self.insert_chunk(kind, &data)?;
+            indices = self.forge_chunk_indices(text);
+        }
+        let chunks = indices[indices.len() - FORGE_CHUNKS..]
+            .iter()
+            .map(|i| self.chunks[*i].body())
+            .collect::<Vec<_>>();
+
+        // Adjusted bytes ending each chunk of file `data` for adjusted bytes `x`, followed by the chunk CRC
+        let patches = |data: &[u8], x: u128| -> CRCResult<Vec<[u8; 2 * FORGE_SIZE]>> {
+            let mut patches = Vec::new();
+            for (k, body) in chunks.iter().enumerate() {
+                let bytes = ((x >> (32 * k)) as u32).to_le_bytes();
+                let data = &data[body.start..body.end - FORGE_SIZE];
+                let crc = self
+                    .crc
+                    .checksum(data.iter().chain(&bytes).map(|b| Ok(*b)))?;
+                let mut patch = [0; 2 * FORGE_SIZE];
+                patch[..FORGE_SIZE].copy_from_slice(&bytes);
+                patch[FORGE_SIZE..].copy_from_slice(&crc.to_be_bytes());
+                patches.push(patch);
+            }
+            Ok(patches)
+        };
+        let write = |data: &mut [u8], patches: &[[u8; 2 * FORGE_SIZE]], start: usize| {
+            for (body, patch) in chunks.iter().zip(patches) {
+                let offset = body.end - FORGE_SIZE - start;
+                data[offset..offset + patch.len()].copy_from_slice(patch);
+            }
+        };
+
+        // Since CRC is affine, CRC(file(x)) = CRC(file(0)) + CRC0(file(x) + file(0)), where CRC0 has null init and final
+        // XOR, and leading zeros do not change it. The difference is null before adjusted bytes, so that it is only
+        // written from there, into a buffer shared by all the columns.
+        let crc0 = CRC32::new(CRC32Properties {
+            i: 0,
+            f: 0,
+            ..Default::default()
+        })?;
+        let patches0 = patches(&self.data, 0)?;
+        let start = chunks[0].end - FORGE_SIZE;
+        let mut diff = vec![0; self.data.len() - start];
+        let mut columns = Vec::new();
+        for i in 0..8 * FORGE_SIZE * FORGE_CHUNKS {
+            let patches = patches(&self.data, 1 << i)?
+                .iter()
+                .zip(&patches0)
+                .map(|(a, b)| std::array::from_fn(|j| a[j] ^ b[j]))
+                .collect::<Vec<_>>();
+            write(&mut diff, &patches, start);
+            columns.push(u64::from(crc0.checksum(diff.bytes())?));
+            diff.fill(0);
+        }
+
+        // Solve CRC0(file(x) + file(0)) = target + CRC(file(0)). Adjusted bytes of tEXt chunks are Latin-1 text, which
+        // excludes NUL: other solutions are tried, adding to x a combination `r` of columns and solving for the rest.
+        write(&mut self.data, &patches0, 0);
+        let rhs = u64::from(target_crc ^ self.crc.checksum(self.data.bytes())?);
+        let x = (0..=u8::MAX as u128)
+            .map(|r| r * 0x0101_0101_0101_0101_0101_0101)
+            .filter_map(|r| {
+                let image = (0..columns.len())
+                    .filter(|i| r >> i & 1 == 1)
+                    .fold(0, |acc, i| acc ^ columns[i]);
+                solve_gf2(&columns, rhs ^ image).map(|y| y ^ r)
+            })
+            .find(|x| {
+                !text
+                    || x.to_le_bytes()[..FORGE_SIZE * FORGE_CHUNKS]
+                        .iter()
+                        .all(|b| *b != 0)
+            })
+            .ok_or(Error::NonInvertibleError)?;
+        let patches = patches(&self.data, x)?;
+        write(&mut self.data, &patches, 0);
+        self.parse_chunks()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::png::Png;
+
+    /// Build PNG file with an empty IHDR, some IDAT data and IEND chunks.
+    fn png() -> Vec<u8> {
+        let crc = CRC32::new(CRC32Properties::default()).unwrap();
+        let chunk = |kind: &[u8], data: &[u8]| {
+            let body = [kind, data].concat();
+            let c = crc.checksum(body.bytes()).unwrap();
+            [
+                &(data.len() as u32).to_be_bytes()[..],
+                &body,
+                &c.to_be_bytes(),
+            ]
+            .concat()
+        };
+        [
+            &b"\x89PNG\r\n\x1a\n"[..],
+            &chunk(b"IHDR", &[0; 13]),
+            &chunk(b"IDAT", b"lorem ipsum dolor sit amet"),
+            &chunk(b"IEND", b""),
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let png = Png::parse(png()).expect("Failed to parse PNG");
+        let kinds = png.chunks().iter().map(|c| &c.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert!(png.chunks().iter().all(|c| c.is_valid()));
+        assert!(Png::parse(b"GIF89a".to_vec()).is_err());
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = png();
+        data[0x30] ^= 1;
+        let mut png = Png::parse(data).expect("Failed to parse PNG");
+        assert!(!png.chunks()[1].is_valid());
+        assert_eq!(png.repair(), 1);
+        let png = Png::parse(png.data().to_vec()).unwrap();
+        assert!(png.chunks().iter().all(|c| c.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge_chunk() {
+        let mut png = Png::parse(png()).expect("Failed to parse PNG");
+        assert!(png.forge_chunk(1, Some(usize::MAX), 0x42424242).is_err());
+        png.forge_chunk(1, Some(6), 0x42424242)
+            .expect("Failed to forge chunk");
+        let png = Png::parse(png.data().to_vec()).unwrap();
+        assert_eq!(png.chunks()[1].crc.stored, 0x42424242);
+        assert!(png.chunks().iter().all(|c| c.is_valid()));
+        assert_eq!(&png.data()[png.chunks()[1].offset + 8..][..6], b"lorem ");
+    }
+
+    #[test]
+    pub fn test_forge_file() {
+        let crc = CRC32::new(CRC32Properties::default()).unwrap();
+        for text in [false, true] {
+            let mut png = Png::parse(png()).expect("Failed to parse PNG");
+            png.forge_file(0x42424242, text)
+                .expect("Failed to forge file");
+            assert_eq!(crc.checksum(png.data().bytes()).unwrap(), 0x42424242);
+            assert_eq!(png.chunks().len(), 6);
+            assert!(png.chunks().iter().all(|c| c.is_valid()));
+
+            // Forging chunks are adjusted rather than inserted again
+            png.forge_file(0x13371337, text)
+                .expect("Failed to forge file");
+            assert_eq!(crc.checksum(png.data().bytes()).unwrap(), 0x13371337);
+            assert_eq!(png.chunks().len(), 6);
+        }
+
+        // Text of tEXt chunks never holds NUL
+        let mut png = Png::parse(png()).unwrap();
+        for target in (0..64u32).map(|i| i.wrapping_mul(0x04c11db7)) {
+            png.forge_file(target, true).expect("Failed to forge file");
+            assert_eq!(crc.checksum(png.data().bytes()).unwrap(), target);
+            for chunk in &png.chunks()[2..5] {
+                let end = chunk.offset + 8 + chunk.length;
+                assert!(png.data()[end - 4..end].iter().all(|b| *b != 0));
+            }
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+
+/// Signature starting RAR5 archives, possibly after a self-extracting module.
+const SIGNATURE: &[u8; 8] = b"Rar!\x1a\x07\x01\x00";
+
+/// Header types.
+const HEADER_MAIN: u64 = 1;
+const HEADER_FILE: u64 = 2;
+const HEADER_SERVICE: u64 = 3;
+const HEADER_ENCRYPTION: u64 = 4;
+const HEADER_END: u64 = 5;
+
+/// Header flags: extra area and data area presence, and data split across volumes.
+const FLAG_EXTRA: u64 = 0x01;
+const FLAG_DATA: u64 = 0x02;
+const FLAG_SPLIT: u64 = 0x08 | 0x10;
+
+/// File flags: modification time and data CRC presence.
+const FILE_TIME: u64 = 0x02;
+const FILE_CRC: u64 = 0x04;
+
+/// Compression method bits of the compression information, where 0 stands for stored data.
+const METHOD_SHIFT: u64 = 7;
+const METHOD_MASK: u64 = 0x07;
+
+/// Type of the file encryption extra record.
+const EXTRA_ENCRYPTION: u64 = 0x01;
+
+/// Read variable-length integer at offset `*pos` of `data`, advancing `pos`.
+fn read_vint(data: &[u8], pos: &mut usize) -> CRCResult<u64> {
+    let mut value = 0;
+    for i in 0..10 {
+        let byte = *data.get(*pos).ok_or(Error::ParseError)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::ParseError)
+}
+
+/// Read variable-length integer used as a size.
+fn read_size(data: &[u8], pos: &mut usize) -> CRCResult<usize> {
+    usize::try_from(read_vint(data, pos)?).map_err(|_| Error::ParseError)
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> CRCResult<u32> {
+    let bytes = data.get(*pos..*pos + 4).ok_or(Error::ParseError)?;
+    *pos += 4;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Type of a block, given by its header type.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Main,
+    File(String),
+    Service(String), // Service header, such as an archive comment (CMT) or quick open data (QO)
+    End,
+    Unknown(u64),
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Main => write!(f, "main"),
+            Kind::File(name) => write!(f, "file {:?}", name),
+            Kind::Service(name) => write!(f, "service {:?}", name),
+            Kind::End => write!(f, "end"),
+            Kind::Unknown(header_type) => write!(f, "type {}", header_type),
+        }
+    }
+}
+
+/// CRC of the data of a file or service block, with its stored and actual value.
+#[derive(Clone)]
+pub struct DataCrc {
+    offset: usize, // Offset of the CRC field
+    pub stored: u32,
+    pub computed: Option<u32>, // Known for stored data, neither encrypted nor split across volumes
+}
+
+/// RAR5 block, with its header CRC and data CRC.
+#[derive(Clone)]
+pub struct Block {
+    pub offset: usize,
+    pub kind: Kind,
+    header: Range<usize>, // Header covered by its CRC, which precedes it
+    data: Range<usize>,
+    pub method: Option<u64>, // Compression method of file and service data
+    pub header_crc: Crc<u32>,
+    pub data_crc: Option<DataCrc>,
+}
+
+impl Block {
+    pub fn is_valid(&self) -> bool {
+        self.header_crc.is_valid()
+            && self
+                .data_crc
+                .as_ref()
+                .is_none_or(|crc| crc.computed.is_none_or(|computed| computed == crc.stored))
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{:08x}  {}", self.offset, self.kind)?;
+        if let Some(method) = self.method {
+            match method {
+                0 => write!(f, "  {} bytes  stored", self.data.len())?,
+                _ => write!(f, "  {} bytes  method {}", self.data.len(), method)?,
+            }
+        }
+        write!(f, "  hcrc={}", self.header_crc)?;
+        if let Some(crc) = &self.data_crc {
+            match crc.computed {
+                None => write!(f, "  dcrc=0x{:08x}  unchecked", crc.stored)?,
+                Some(computed) => write!(f, "  dcrc={}", Crc::new(crc.stored, computed))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// RAR5 archive: a signature followed by blocks, each made of a header protected by a CRC32 and an optional data area.
+pub struct Rar5 {
+    data: Vec<u8>,
+    blocks: Vec<Block>,
+    crc: CRC32,
+}
+
+impl Rar5 {
+    /// Parse RAR5 archive `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let mut rar = Self {
+            data,
+            blocks: Vec::new(),
+            crc,
+        };
+        rar.parse_blocks()?;
+        Ok(rar)
+    }
+
+    fn parse_blocks(&mut self) -> CRCResult<()> {
+        let start = self
+            .data
+            .windows(SIGNATURE.len())
+            .position(|window| window == SIGNATURE)
+            .ok_or(Error::ParseError)?;
+        let mut offset = start + SIGNATURE.len();
+        self.blocks.clear();
+        while offset < self.data.len() {
+            let block = self.parse_block(offset)?;
+            offset = block.data.end;
+            let end = block.kind == Kind::End;
+            self.blocks.push(block);
+            if end {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse block at offset `offset`.
+    fn parse_block(&self, offset: usize) -> CRCResult<Block> {
+        let data = &self.data;
+        let mut pos = offset;
+        let stored_crc = read_u32(data, &mut pos)?;
+        let size = read_size(data, &mut pos)?;
+        let header = offset + 4..pos.checked_add(size).ok_or(Error::ParseError)?;
+        let header_data = data.get(..header.end).ok_or(Error::ParseError)?;
+        let header_type = read_vint(header_data, &mut pos)?;
+        let flags = read_vint(header_data, &mut pos)?;
+        let extra_size = match flags & FLAG_EXTRA {
+            0 => 0,
+            _ => read_size(header_data, &mut pos)?,
+        };
+        let data_size = match flags & FLAG_DATA {
+            0 => 0,
+            _ => read_size(header_data, &mut pos)?,
+        };
+        let extra = header
+            .end
+            .checked_sub(extra_size)
+            .filter(|start| *start >= pos)
+            .ok_or(Error::ParseError)?..header.end;
+        let area = header.end
+            ..header
+                .end
+                .checked_add(data_size)
+                .filter(|end| *end <= data.len())
+                .ok_or(Error::ParseError)?;
+
+        let mut block = Block {
+            offset,
+            kind: Kind::Unknown(header_type),
+            header: header.clone(),
+            data: area.clone(),
+            method: None,
+            header_crc: Crc::new(stored_crc, self.crc.checksum(data[header].bytes())?),
+            data_crc: None,
+        };
+        match header_type {
+            HEADER_MAIN => block.kind = Kind::Main,
+            HEADER_END => block.kind = Kind::End,
+            HEADER_ENCRYPTION => return Err(Error::UnsupportedError),
+            HEADER_FILE | HEADER_SERVICE => {
+                let file_flags = read_vint(header_data, &mut pos)?;
+                read_vint(header_data, &mut pos)?; // Unpacked size
+                read_vint(header_data, &mut pos)?; // Attributes
+                if file_flags & FILE_TIME != 0 {
+                    read_u32(header_data, &mut pos)?;
+                }
+                let crc_offset = pos;
+                let data_crc = match file_flags & FILE_CRC {
+                    0 => None,
+                    _ => Some(read_u32(header_data, &mut pos)?),
+                };
+                let method = (read_vint(header_data, &mut pos)? >> METHOD_SHIFT) & METHOD_MASK;
+                read_vint(header_data, &mut pos)?; // Host OS
+                let name_size = read_size(header_data, &mut pos)?;
+                let name_end = pos
+                    .checked_add(name_size)
+                    .filter(|end| *end <= extra.start)
+                    .ok_or(Error::ParseError)?;
+                let name = String::from_utf8_lossy(&header_data[pos..name_end]).into_owned();
+                block.kind = match header_type {
+                    HEADER_FILE => Kind::File(name),
+                    _ => Kind::Service(name),
+                };
+                block.method = Some(method);
+                let checked = method == 0
+                    && flags & FLAG_SPLIT == 0
+                    && !Self::extra_types(&data[extra])?.contains(&EXTRA_ENCRYPTION);
+                block.data_crc = match data_crc {
+                    Some(stored) => Some(DataCrc {
+                        offset: crc_offset,
+                        stored,
+                        computed: match checked {
+                            true => Some(self.crc.checksum(data[area].bytes())?),
+                            false => None,
+                        },
+                    }),
+                    None => None,
+                };
+            }
+            _ => (),
+        }
+        Ok(block)
+    }
+
+    /// Types of the records of extra area `extra`.
+    fn extra_types(extra: &[u8]) -> CRCResult<Vec<u64>> {
+        let mut types = Vec::new();
+        let mut pos = 0;
+        while pos < extra.len() {
+            let size = read_size(extra, &mut pos)?;
+            let end = pos.checked_add(size).ok_or(Error::ParseError)?;
+            types.push(read_vint(&extra[..end.min(extra.len())], &mut pos)?);
+            pos = end;
+        }
+        Ok(types)
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Rewrite data CRC `crc` of block `index`, then its header CRC.
+    fn write_crcs(&mut self, index: usize, data_crc: Option<u32>) -> CRCResult<()> {
+        let block = &self.blocks[index];
+        if let (Some(crc), Some(field)) = (data_crc, &block.data_crc) {
+            self.data[field.offset..field.offset + 4].copy_from_slice(&crc.to_le_bytes());
+        }
+        let header_crc = self.crc.checksum(self.data[block.header.clone()].bytes())?;
+        let offset = block.offset;
+        self.data[offset..offset + 4].copy_from_slice(&header_crc.to_le_bytes());
+        self.blocks[index] = self.parse_block(offset)?;
+        Ok(())
+    }
+
+    /// Rewrite invalid data CRCs of stored files, then invalid header CRCs, which cover data CRCs.
+    /// Returns the number of rewritten CRCs.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let mut repaired = 0;
+        for index in 0..self.blocks.len() {
+            let block = &self.blocks[index];
+            let data_crc = block
+                .data_crc
+                .as_ref()
+                .and_then(|crc| crc.computed.filter(|computed| *computed != crc.stored));
+            if data_crc.is_none() && block.header_crc.is_valid() {
+                continue;
+            }
+            let header_crc = block.header_crc.stored;
+            self.write_crcs(index, data_crc)?;
+            repaired += usize::from(data_crc.is_some())
+                + usize::from(header_crc != self.blocks[index].header_crc.stored);
+        }
+        Ok(repaired)
+    }
+
+    /// Patch 4 bytes of stored data of file block `index` at offset `offset`, or its last 4 bytes by default, so that
+    /// its data CRC is `target_crc`. The data CRC and the header CRC are rewritten.
+    pub fn forge_data(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let block = self.blocks.get(index).ok_or(Error::OutOfBoundsError)?;
+        if block
+            .data_crc
+            .as_ref()
+            .is_none_or(|crc| crc.computed.is_none())
+        {
+            return Err(Error::UnsupportedError);
+        }
+        let data = block.data.clone();
+        let offset = match offset {
+            Some(offset) => offset,
+            None => data.len().checked_sub(4).ok_or(Error::OutOfBoundsError)?,
+        };
+        let patch = self
+            .crc
+            .compute_patch(&self.data[data.clone()], offset, target_crc)?;
+        self.data[data.start + offset..data.start + offset + 4].copy_from_slice(&patch);
+        self.write_crcs(index, Some(target_crc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::rar5::{Kind, Rar5};
+
+    fn vint(mut value: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+        out
+    }
+
+    /// Block of type `header_type` with fields `fields`, extra area `extra` and data area `data`.
+    fn block(header_type: usize, fields: &[u8], extra: &[u8], data: &[u8]) -> Vec<u8> {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        let flags = usize::from(!extra.is_empty()) | (usize::from(!data.is_empty()) << 1);
+        let mut header = [vint(header_type), vint(flags)].concat();
+        if !extra.is_empty() {
+            header.extend(vint(extra.len()));
+        }
+        if !data.is_empty() {
+            header.extend(vint(data.len()));
+        }
+        header.extend(fields);
+        header.extend(extra);
+        let header = [vint(header.len()), header].concat();
+        let crc = crc32.checksum(header.bytes()).unwrap();
+        [&crc.to_le_bytes()[..], &header, data].concat()
+    }
+
+    /// File block of stored data `content` named `name`, with extra area `extra`.
+    fn file(name: &str, content: &[u8], extra: &[u8]) -> Vec<u8> {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        let crc = crc32.checksum(content.bytes()).unwrap();
+        let fields = [
+            &vint(0x06)[..], // Modification time and CRC
+            &vint(content.len()),
+            &vint(0o100644),
+            &0x65000000u32.to_le_bytes(),
+            &crc.to_le_bytes(),
+            &vint(0), // Stored
+            &vint(1), // Unix
+            &vint(name.len()),
+            name.as_bytes(),
+        ]
+        .concat();
+        block(2, &fields, extra, content)
+    }
+
+    fn archive(extra: &[u8]) -> Vec<u8> {
+        [
+            &b"Rar!\x1a\x07\x01\x00"[..],
+            &block(1, &vint(0), &[], &[]),
+            &file("lorem.txt", b"lorem ipsum dolor sit amet\n", extra),
+            &file("hello.txt", b"hello world\n", &[]),
+            &block(5, &vint(0), &[], &[]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let rar = Rar5::parse(archive(&[])).expect("Failed to parse archive");
+        let kinds = rar.blocks().iter().map(|block| block.kind.clone());
+        assert_eq!(
+            kinds.collect::<Vec<_>>(),
+            [
+                Kind::Main,
+                Kind::File("lorem.txt".to_string()),
+                Kind::File("hello.txt".to_string()),
+                Kind::End
+            ]
+        );
+        assert!(rar.blocks().iter().all(|block| block.is_valid()));
+        assert_eq!(
+            rar.blocks()[2].data_crc.as_ref().unwrap().stored,
+            0xaf083b2d
+        );
+
+        // Encrypted data is not checked
+        let rar = Rar5::parse(archive(b"\x02\x01\x00")).unwrap();
+        assert_eq!(rar.blocks()[1].data_crc.as_ref().unwrap().computed, None);
+        assert!(Rar5::parse(archive(&[])[..60].to_vec()).is_err());
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = archive(&[]);
+        let name = data.windows(9).position(|w| w == b"hello.txt").unwrap();
+        data[name] = b'j';
+        let content = data.windows(11).position(|w| w == b"lorem ipsum").unwrap();
+        data[content] = b'L';
+        let mut rar = Rar5::parse(data).expect("Failed to parse archive");
+        let invalid = rar
+            .blocks()
+            .iter()
+            .filter(|block| !block.is_valid())
+            .count();
+        assert_eq!(invalid, 2);
+        assert_eq!(rar.repair().unwrap(), 3); // Data and header CRCs, then header CRC
+        let rar = Rar5::parse(rar.data().to_vec()).unwrap();
+        assert!(rar.blocks().iter().all(|block| block.is_valid()));
+        assert_eq!(rar.blocks()[2].kind, Kind::File("jello.txt".to_string()));
+    }
+
+    #[test]
+    pub fn test_forge_data() {
+        let mut rar = Rar5::parse(archive(&[])).expect("Failed to parse archive");
+        rar.forge_data(1, None, 0xdeadbeef)
+            .expect("Failed to forge data");
+        rar.forge_data(2, Some(2), 0x13371337)
+            .expect("Failed to forge data");
+        let rar = Rar5::parse(rar.data().to_vec()).unwrap();
+        assert!(rar.blocks().iter().all(|block| block.is_valid()));
+        let crcs = rar.blocks()[1..3]
+            .iter()
+            .map(|block| block.data_crc.as_ref().unwrap().stored);
+        assert_eq!(crcs.collect::<Vec<_>>(), [0xdeadbeef, 0x13371337]);
+        let data = rar.data();
+        assert!(data.windows(23).any(|w| w == b"lorem ipsum dolor sit a"));
+        assert!(data.windows(6).any(|w| w == b"world\n"));
+        assert!(!data.windows(6).any(|w| w == b"hello "));
+
+        let mut rar = Rar5::parse(archive(&[])).unwrap();
+        assert!(rar.forge_data(0, None, 0).is_err());
+    }
+}
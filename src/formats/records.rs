@@ -0,0 +1,577 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties, mask, unmask};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+
+/// LevelDB log block size: records never straddle blocks, whose trailers too small for a header are zero-filled.
+const LOG_BLOCK_SIZE: usize = 0x8000;
+
+/// LevelDB log record header sizes, RocksDB recyclable records adding the log number.
+const LOG_HEADER_SIZE: usize = 7;
+const LOG_RECYCLABLE_HEADER_SIZE: usize = 11;
+
+/// Snappy framing stream identifier chunk.
+const SNAPPY_IDENTIFIER: &[u8; 10] = b"\xff\x06\x00\x00sNaPpY";
+
+/// Snappy framing chunk types.
+const SNAPPY_COMPRESSED: u8 = 0x00;
+const SNAPPY_UNCOMPRESSED: u8 = 0x01;
+const SNAPPY_SKIPPABLE: u8 = 0x80;
+
+/// Record file format, all storing masked CRC32C.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Format {
+    #[value(name = "leveldb", alias = "rocksdb")]
+    LevelDb, // LevelDB and RocksDB write-ahead logs and MANIFEST files
+    Snappy, // Snappy framing format
+    #[value(name = "tfrecord")]
+    TfRecord, // TensorFlow TFRecord files
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::LevelDb => write!(f, "leveldb"),
+            Format::Snappy => write!(f, "snappy"),
+            Format::TfRecord => write!(f, "tfrecord"),
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Kind of a checksummed field.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Record(u8), // LevelDB log record, by record type
+    Compressed, // Snappy compressed chunk, whose CRC covers uncompressed data
+    Uncompressed,
+    Length, // TFRecord record length
+    Data,   // TFRecord record data
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Record(1) => write!(f, "full"),
+            Kind::Record(2) => write!(f, "first"),
+            Kind::Record(3) => write!(f, "middle"),
+            Kind::Record(4) => write!(f, "last"),
+            Kind::Record(5) => write!(f, "recyclable full"),
+            Kind::Record(6) => write!(f, "recyclable first"),
+            Kind::Record(7) => write!(f, "recyclable middle"),
+            Kind::Record(8) => write!(f, "recyclable last"),
+            Kind::Record(record_type) => write!(f, "type {}", record_type),
+            Kind::Compressed => write!(f, "compressed"),
+            Kind::Uncompressed => write!(f, "uncompressed"),
+            Kind::Length => write!(f, "length"),
+            Kind::Data => write!(f, "data"),
+        }
+    }
+}
+
+/// Field covered by a masked CRC32C, with its stored and actual masked CRC.
+#[derive(Clone)]
+pub struct Field {
+    pub offset: usize, // Offset of the record or chunk
+    pub kind: Kind,
+    value: usize,           // Offset of the CRC
+    covered: Range<usize>,  // Bytes covered by the CRC, once uncompressed for compressed chunks
+    pub data: Range<usize>, // Payload, within covered bytes
+    pub crc: Crc<u32>,
+}
+
+impl Field {
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  {}  {} bytes  crc={}",
+            self.offset,
+            self.kind,
+            self.data.len(),
+            self.crc
+        )
+    }
+}
+
+/// Decompress raw Snappy block `data`.
+fn snappy_decompress(data: &[u8]) -> CRCResult<Vec<u8>> {
+    let mut pos = 0;
+    let mut size = 0;
+    for shift in (0..32).step_by(7) {
+        let byte = *data.get(pos).ok_or(Error::ParseError)?;
+        pos += 1;
+        size |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut out = Vec::with_capacity(size.min(data.len() * 64));
+    while pos < data.len() {
+        let tag = data[pos];
+        pos += 1;
+        let (length, offset) = match tag & 0x03 {
+            0 => {
+                let mut length = usize::from(tag >> 2);
+                if length >= 60 {
+                    let bytes = length - 59;
+                    let field = data.get(pos..pos + bytes).ok_or(Error::ParseError)?;
+                    length = field
+                        .iter()
+                        .rev()
+                        .fold(0, |length, byte| (length << 8) | usize::from(*byte));
+                    pos += bytes;
+                }
+                let literal = pos
+                    .checked_add(length + 1)
+                    .and_then(|end| data.get(pos..end))
+                    .ok_or(Error::ParseError)?;
+                out.extend_from_slice(literal);
+                pos += literal.len();
+                continue;
+            }
+            1 => {
+                let low = *data.get(pos).ok_or(Error::ParseError)?;
+                pos += 1;
+                let length = usize::from((tag >> 2) & 0x07) + 4;
+                (length, (usize::from(tag >> 5) << 8) | usize::from(low))
+            }
+            tag_type => {
+                let bytes = match tag_type {
+                    2 => 2,
+                    _ => 4,
+                };
+                let field = data.get(pos..pos + bytes).ok_or(Error::ParseError)?;
+                pos += bytes;
+                let offset = field
+                    .iter()
+                    .rev()
+                    .fold(0, |offset, byte| (offset << 8) | usize::from(*byte));
+                (usize::from(tag >> 2) + 1, offset)
+            }
+        };
+        if offset == 0 || offset > out.len() || out.len() + length > size {
+            return Err(Error::ParseError);
+        }
+        for _ in 0..length {
+            out.push(out[out.len() - offset]);
+        }
+    }
+    match out.len() == size {
+        true => Ok(out),
+        false => Err(Error::ParseError),
+    }
+}
+
+/// Record file whose records or chunks are protected by masked CRC32C: LevelDB and RocksDB logs, Snappy framed
+/// streams and TFRecord files.
+pub struct Records {
+    data: Vec<u8>,
+    format: Format,
+    fields: Vec<Field>,
+    crc: CRC32,
+}
+
+impl Records {
+    /// Parse record file `data` of format `format`, detected if not given.
+    pub fn parse(data: Vec<u8>, format: Option<Format>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::castagnoli())?;
+        let mut records = Self {
+            data,
+            format: Format::LevelDb,
+            fields: Vec::new(),
+            crc,
+        };
+        (records.format, records.fields) = match format {
+            Some(format) => (format, records.parse_format(format)?),
+            None => records.detect()?,
+        };
+        Ok(records)
+    }
+
+    fn parse_format(&self, format: Format) -> CRCResult<Vec<Field>> {
+        match format {
+            Format::LevelDb => self.parse_log(),
+            Format::Snappy => self.parse_snappy(),
+            Format::TfRecord => self.parse_tfrecord(),
+        }
+    }
+
+    /// Detect the format of the file and parse it. Snappy streams start with a stream identifier. Otherwise, the file
+    /// is parsed both as TFRecord records and LevelDB log records: the format whose CRCs are all valid is preferred,
+    /// else the only format whose parse succeeds. Ambiguous files require their format to be given.
+    fn detect(&self) -> CRCResult<(Format, Vec<Field>)> {
+        if self.data.starts_with(SNAPPY_IDENTIFIER) {
+            return Ok((Format::Snappy, self.parse_snappy()?));
+        }
+        let parsed = [Format::TfRecord, Format::LevelDb]
+            .into_iter()
+            .filter_map(|format| Some((format, self.parse_format(format).ok()?)))
+            .collect::<Vec<_>>();
+        if let Some(valid) = parsed
+            .iter()
+            .find(|(_, fields)| fields.iter().all(|field| field.is_valid()))
+        {
+            return Ok(valid.clone());
+        }
+        match parsed.len() {
+            0 => Err(Error::ParseError),
+            1 => Ok(parsed.into_iter().next().unwrap()),
+            _ => Err(Error::UnsupportedError),
+        }
+    }
+
+    /// Masked CRC32C of `bytes`.
+    fn masked_crc(&self, bytes: &[u8]) -> CRCResult<u32> {
+        Ok(mask(self.crc.checksum(bytes.bytes())?))
+    }
+
+    /// Parse log records, up to a truncated header at end of file.
+    fn parse_log(&self) -> CRCResult<Vec<Field>> {
+        let data = &self.data;
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        while offset + LOG_HEADER_SIZE <= data.len() {
+            let block_end = (offset / LOG_BLOCK_SIZE + 1) * LOG_BLOCK_SIZE;
+            if block_end - offset < LOG_HEADER_SIZE {
+                offset = block_end;
+                continue;
+            }
+            let length = usize::from(u16::from_le_bytes([data[offset + 4], data[offset + 5]]));
+            let record_type = data[offset + 6];
+            if data[offset..offset + LOG_HEADER_SIZE]
+                .iter()
+                .all(|byte| *byte == 0)
+            {
+                // Preallocated space
+                offset = block_end;
+                continue;
+            }
+            let header_size = match record_type {
+                5..=8 | 11 => LOG_RECYCLABLE_HEADER_SIZE,
+                _ => LOG_HEADER_SIZE,
+            };
+            let end = offset + header_size + length;
+            if end > block_end.min(data.len()) {
+                return Err(Error::ParseError);
+            }
+            let covered = offset + 6..end;
+            fields.push(Field {
+                offset,
+                kind: Kind::Record(record_type),
+                value: offset,
+                covered: covered.clone(),
+                data: offset + header_size..end,
+                crc: Crc::new(read_u32(data, offset), self.masked_crc(&data[covered])?),
+            });
+            offset = end;
+        }
+        Ok(fields)
+    }
+
+    /// Parse Snappy framed chunks, checking CRCs of compressed and uncompressed data chunks.
+    fn parse_snappy(&self) -> CRCResult<Vec<Field>> {
+        let data = &self.data;
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let header = data.get(offset..offset + 4).ok_or(Error::ParseError)?;
+            let chunk_type = header[0];
+            let length = u32::from_le_bytes([header[1], header[2], header[3], 0]) as usize;
+            let end = offset + 4 + length;
+            if end > data.len() {
+                return Err(Error::ParseError);
+            }
+            match chunk_type {
+                SNAPPY_COMPRESSED | SNAPPY_UNCOMPRESSED if length >= 4 => {
+                    let chunk = offset + 8..end;
+                    let (kind, computed) = match chunk_type {
+                        SNAPPY_COMPRESSED => (
+                            Kind::Compressed,
+                            self.masked_crc(&snappy_decompress(&data[chunk.clone()])?)?,
+                        ),
+                        _ => (Kind::Uncompressed, self.masked_crc(&data[chunk.clone()])?),
+                    };
+                    fields.push(Field {
+                        offset,
+                        kind,
+                        value: offset + 4,
+                        covered: chunk.clone(),
+                        data: chunk,
+                        crc: Crc::new(read_u32(data, offset + 4), computed),
+                    });
+                }
+                0xff if data[offset..end] != SNAPPY_IDENTIFIER[..] => {
+                    return Err(Error::ParseError);
+                }
+                SNAPPY_SKIPPABLE.. => (), // Stream identifier, padding and skippable chunks
+                _ => return Err(Error::ParseError),
+            }
+            offset = end;
+        }
+        Ok(fields)
+    }
+
+    /// Parse TFRecord records, each with a CRC of its length and a CRC of its data.
+    fn parse_tfrecord(&self) -> CRCResult<Vec<Field>> {
+        let data = &self.data;
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let header = data.get(offset..offset + 12).ok_or(Error::ParseError)?;
+            let length = usize::try_from(u64::from_le_bytes(header[..8].try_into().unwrap()))
+                .map_err(|_| Error::ParseError)?;
+            let record = offset + 12
+                ..(offset + 12)
+                    .checked_add(length)
+                    .filter(|end| end.checked_add(4).is_some_and(|end| end <= data.len()))
+                    .ok_or(Error::ParseError)?;
+            fields.push(Field {
+                offset,
+                kind: Kind::Length,
+                value: offset + 8,
+                covered: offset..offset + 8,
+                data: offset..offset + 8,
+                crc: Crc::new(
+                    read_u32(data, offset + 8),
+                    self.masked_crc(&data[offset..offset + 8])?,
+                ),
+            });
+            fields.push(Field {
+                offset,
+                kind: Kind::Data,
+                value: record.end,
+                covered: record.clone(),
+                data: record.clone(),
+                crc: Crc::new(
+                    read_u32(data, record.end),
+                    self.masked_crc(&data[record.clone()])?,
+                ),
+            });
+            offset = record.end + 4;
+        }
+        Ok(fields)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Rewrite invalid CRCs. Returns the number of rewritten CRCs.
+    pub fn repair(&mut self) -> usize {
+        let mut repaired = 0;
+        for field in self.fields.iter_mut().filter(|field| !field.is_valid()) {
+            self.data[field.value..field.value + 4]
+                .copy_from_slice(&field.crc.computed.to_le_bytes());
+            field.crc.stored = field.crc.computed;
+            repaired += 1;
+        }
+        repaired
+    }
+
+    /// Patch 4 bytes of the payload of field `index` at offset `offset`, or its last 4 bytes by default, so that its
+    /// stored masked CRC is `target_crc`. Compressed chunks and TFRecord lengths cannot be forged.
+    pub fn forge(&mut self, index: usize, offset: Option<usize>, target_crc: u32) -> CRCResult<()> {
+        let field = self.fields.get(index).ok_or(Error::OutOfBoundsError)?;
+        if matches!(field.kind, Kind::Compressed | Kind::Length) {
+            return Err(Error::UnsupportedError);
+        }
+        let offset = match offset {
+            Some(offset) => offset,
+            None => field
+                .data
+                .len()
+                .checked_sub(4)
+                .ok_or(Error::OutOfBoundsError)?,
+        };
+        if offset
+            .checked_add(4)
+            .is_none_or(|end| end > field.data.len())
+        {
+            return Err(Error::OutOfBoundsError);
+        }
+        let start = field.data.start - field.covered.start + offset;
+        let patch =
+            self.crc
+                .compute_patch(&self.data[field.covered.clone()], start, unmask(target_crc))?;
+        let patched = field.data.start + offset;
+        let value = field.value;
+        self.data[patched..patched + 4].copy_from_slice(&patch);
+        self.data[value..value + 4].copy_from_slice(&target_crc.to_le_bytes());
+        let field = &mut self.fields[index];
+        field.crc.stored = target_crc;
+        field.crc.computed = target_crc;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties, mask};
+    use crate::error::Error;
+    use crate::formats::records::{Format, Kind, Records, snappy_decompress};
+
+    fn masked_crc(bytes: &[u8]) -> u32 {
+        let crc32 = CRC32::new(CRC32Properties::castagnoli()).unwrap();
+        mask(crc32.checksum(bytes.bytes()).unwrap())
+    }
+
+    /// LevelDB log record of type `record_type` holding `payload`, with log number `log_number` if recyclable.
+    fn log_record(record_type: u8, log_number: &[u8], payload: &[u8]) -> Vec<u8> {
+        let crc = masked_crc(&[&[record_type][..], log_number, payload].concat());
+        let length = (payload.len() as u16).to_le_bytes();
+        [
+            &crc.to_le_bytes()[..],
+            &length,
+            &[record_type],
+            log_number,
+            payload,
+        ]
+        .concat()
+    }
+
+    fn tfrecord(payload: &[u8]) -> Vec<u8> {
+        let length = (payload.len() as u64).to_le_bytes();
+        [
+            &length[..],
+            &masked_crc(&length).to_le_bytes(),
+            payload,
+            &masked_crc(payload).to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Snappy framed stream of an uncompressed chunk holding "hello world" and a compressed chunk holding "lorem
+    /// ipsum lorem ipsum".
+    fn snappy() -> Vec<u8> {
+        let hello = b"hello world";
+        let lorem = b"\x17\x2clorem ipsum \x2a\x0c\x00";
+        [
+            &b"\xff\x06\x00\x00sNaPpY"[..],
+            &[0x01, 4 + hello.len() as u8, 0, 0],
+            &masked_crc(hello).to_le_bytes(),
+            hello,
+            &[0xfe, 2, 0, 0, 0, 0],
+            &[0x00, 4 + lorem.len() as u8, 0, 0],
+            &masked_crc(b"lorem ipsum lorem ipsum").to_le_bytes(),
+            lorem,
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        assert_eq!(
+            snappy_decompress(b"\x17\x2clorem ipsum \x2a\x0c\x00").unwrap(),
+            b"lorem ipsum lorem ipsum"
+        );
+
+        let mut log = [
+            log_record(1, &[], b"lorem ipsum"),
+            log_record(2, &[], &[0x61; 0x7fe1]),
+        ]
+        .concat();
+        log.extend([0; 6]); // Block trailer
+        log.extend(log_record(5, &[0x2a, 0, 0, 0], b"bc"));
+        let records = Records::parse(log, None).expect("Failed to parse log");
+        assert_eq!(records.format(), Format::LevelDb);
+        let kinds = records.fields().iter().map(|field| field.kind);
+        assert_eq!(
+            kinds.collect::<Vec<_>>(),
+            [Kind::Record(1), Kind::Record(2), Kind::Record(5)]
+        );
+        assert_eq!(records.fields()[2].offset, 0x8000);
+        assert_eq!(records.fields()[2].data, 0x800b..0x800d);
+        assert!(records.fields().iter().all(|field| field.is_valid()));
+
+        let records = Records::parse(snappy(), None).expect("Failed to parse stream");
+        assert_eq!(records.format(), Format::Snappy);
+        let kinds = records.fields().iter().map(|field| field.kind);
+        assert_eq!(
+            kinds.collect::<Vec<_>>(),
+            [Kind::Uncompressed, Kind::Compressed]
+        );
+        assert!(records.fields().iter().all(|field| field.is_valid()));
+
+        let data = [tfrecord(b"lorem ipsum"), tfrecord(b"")].concat();
+        let records = Records::parse(data, None).expect("Failed to parse records");
+        assert_eq!(records.format(), Format::TfRecord);
+        assert_eq!(records.fields().len(), 4);
+        assert_eq!(records.fields()[3].offset, 0x1b);
+        assert!(records.fields().iter().all(|field| field.is_valid()));
+
+        // Record length whose end overflows
+        let mut data = (usize::MAX as u64 - 13).to_le_bytes().to_vec();
+        data.extend([0; 12]);
+        assert!(matches!(
+            Records::parse(data, Some(Format::TfRecord)),
+            Err(Error::ParseError)
+        ));
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = [tfrecord(b"lorem ipsum"), tfrecord(b"hello world")].concat();
+        data[0x08] ^= 0x01;
+        data[0x0c] ^= 0x01;
+        let mut records = Records::parse(data, None).unwrap();
+        assert_eq!(records.format(), Format::TfRecord);
+        assert!(!records.fields()[0].is_valid());
+        assert!(!records.fields()[1].is_valid());
+        assert_eq!(records.repair(), 2);
+        let records = Records::parse(records.data().to_vec(), None).unwrap();
+        assert!(records.fields().iter().all(|field| field.is_valid()));
+
+        let mut data = snappy();
+        data[0x12] ^= 0x01;
+        data[0x2a] ^= 0x01;
+        let mut records = Records::parse(data, None).unwrap();
+        assert_eq!(records.fields().iter().filter(|f| !f.is_valid()).count(), 2);
+        assert_eq!(records.repair(), 2);
+        let records = Records::parse(records.data().to_vec(), None).unwrap();
+        assert!(records.fields().iter().all(|field| field.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge() {
+        let log = [
+            log_record(1, &[], b"lorem ipsum"),
+            log_record(1, &[], b"hello world"),
+        ]
+        .concat();
+        let mut records = Records::parse(log, None).unwrap();
+        records.forge(1, Some(2), 0xdeadbeef).unwrap();
+        let records = Records::parse(records.data().to_vec(), None).unwrap();
+        assert_eq!(records.fields()[1].crc.stored, 0xdeadbeef);
+        assert!(records.fields().iter().all(|field| field.is_valid()));
+        assert_eq!(&records.data()[0x19..0x1b], b"he");
+        assert_eq!(&records.data()[0x1f..], b"world");
+
+        let mut records = Records::parse(snappy(), None).unwrap();
+        assert!(records.forge(1, None, 0xdeadbeef).is_err());
+        records.forge(0, None, 0xcafebabe).unwrap();
+        let records = Records::parse(records.data().to_vec(), None).unwrap();
+        assert_eq!(records.fields()[0].crc.stored, 0xcafebabe);
+        assert!(records.fields()[0].is_valid());
+        assert_eq!(&records.data()[0x12..0x19], b"hello w");
+    }
+}
@@ -0,0 +1,984 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::formats::deflate::inflate;
+use crate::formats::lzma::{lzma_decode, lzma2_decode};
+
+/// Signature starting 7z archives.
+const SIGNATURE: &[u8; 6] = b"7z\xbc\xaf\x27\x1c";
+
+/// Size of the signature header, and offsets of its fields.
+const SIGNATURE_HEADER_SIZE: usize = 32;
+const START_HEADER_CRC: usize = 8;
+const NEXT_HEADER_OFFSET: usize = 12;
+const NEXT_HEADER_SIZE: usize = 20;
+const NEXT_HEADER_CRC: usize = 28;
+
+/// Property identifiers of headers.
+const ID_END: u64 = 0x00;
+const ID_HEADER: u64 = 0x01;
+const ID_ARCHIVE_PROPERTIES: u64 = 0x02;
+const ID_MAIN_STREAMS_INFO: u64 = 0x04;
+const ID_FILES_INFO: u64 = 0x05;
+const ID_PACK_INFO: u64 = 0x06;
+const ID_UNPACK_INFO: u64 = 0x07;
+const ID_SUBSTREAMS_INFO: u64 = 0x08;
+const ID_SIZE: u64 = 0x09;
+const ID_CRC: u64 = 0x0a;
+const ID_FOLDER: u64 = 0x0b;
+const ID_CODERS_UNPACK_SIZE: u64 = 0x0c;
+const ID_NUM_UNPACK_STREAM: u64 = 0x0d;
+const ID_EMPTY_STREAM: u64 = 0x0e;
+const ID_NAMES: u64 = 0x11;
+const ID_ENCODED_HEADER: u64 = 0x17;
+
+/// Coder flags: size of the method identifier, complex coder, and coder properties.
+const CODER_ID_SIZE_MASK: u8 = 0x0f;
+const CODER_COMPLEX: u8 = 0x10;
+const CODER_PROPERTIES: u8 = 0x20;
+const CODER_ALTERNATIVE: u8 = 0x80;
+
+/// Method identifiers of supported coders.
+const METHOD_COPY: &[u8] = &[0x00];
+const METHOD_LZMA: &[u8] = &[0x03, 0x01, 0x01];
+const METHOD_LZMA2: &[u8] = &[0x21];
+const METHOD_DEFLATE: &[u8] = &[0x04, 0x01, 0x08];
+const METHOD_BCJ: &[u8] = &[0x03, 0x03, 0x01, 0x03];
+
+/// Structure protected by a CRC field.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    StartHeader,
+    NextHeader,
+    EncodedHeader,     // Decoded header of an archive with a compressed header
+    PackStream(usize), // Packed stream, rarely checked
+    Folder(usize),     // Unpacked data of a folder holding several files
+    File {
+        index: usize,
+        name: String,
+        size: u64,
+        method: String,
+    },
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::StartHeader => write!(f, "start header"),
+            Kind::NextHeader => write!(f, "next header"),
+            Kind::EncodedHeader => write!(f, "encoded header"),
+            Kind::PackStream(index) => write!(f, "pack stream {}", index),
+            Kind::Folder(index) => write!(f, "folder {}", index),
+            Kind::File {
+                index,
+                name,
+                size,
+                method,
+            } => write!(f, "file {}  {:?}  {} bytes  {}", index, name, size, method),
+        }
+    }
+}
+
+/// Location of a CRC field: in the archive, or in the plain header, which may be stored encoded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Location {
+    Archive(usize),
+    Header(usize),
+}
+
+/// CRC field of a 7z archive, with its stored and actual value.
+#[derive(Clone)]
+pub struct Field {
+    pub kind: Kind,
+    location: Location,
+    pub stored: u32,
+    pub computed: Option<u32>, // Unknown for folders of unsupported coders
+}
+
+impl Field {
+    pub fn is_valid(&self) -> bool {
+        self.computed.is_none_or(|computed| computed == self.stored)
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}  crc=", self.kind)?;
+        match self.computed {
+            None => write!(f, "0x{:08x}  unchecked (unsupported coders)", self.stored),
+            Some(computed) => write!(f, "{}", Crc::new(self.stored, computed)),
+        }
+    }
+}
+
+/// CRC value stored in a header, with its offset.
+#[derive(Clone, Copy)]
+struct Digest {
+    value: u32,
+    offset: usize,
+}
+
+/// Coder of a folder.
+struct Coder {
+    method: Vec<u8>,
+    properties: Vec<u8>,
+    streams: (usize, usize), // Number of input and output streams
+}
+
+/// Folder: coders turning packed streams into unpacked data.
+struct Folder {
+    coders: Vec<Coder>,
+    pack_streams: usize,
+    unpack_size: u64, // Size of the main output stream, unbound to other coders
+    digest: Option<Digest>,
+}
+
+/// Unpacked stream of a folder, holding the content of a file.
+struct Substream {
+    folder: usize,
+    offset: u64, // Offset in unpacked folder data
+    size: u64,
+    digest: Option<Digest>,
+}
+
+/// Streams info: packed streams, folders and their substreams.
+#[derive(Default)]
+struct Streams {
+    pack_pos: u64,
+    pack_sizes: Vec<u64>,
+    pack_digests: Vec<Option<Digest>>,
+    folders: Vec<Folder>,
+    substreams: Vec<Substream>,
+}
+
+/// Reader of header properties.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> CRCResult<u8> {
+        let byte = *self.data.get(self.pos).ok_or(Error::ParseError)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, size: usize) -> CRCResult<&'a [u8]> {
+        let end = self.pos.checked_add(size).ok_or(Error::ParseError)?;
+        let bytes = self.data.get(self.pos..end).ok_or(Error::ParseError)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Read a number, whose first byte tells the number of following little-endian bytes by its leading ones.
+    fn number(&mut self) -> CRCResult<u64> {
+        let first = self.byte()?;
+        let mut value = 0;
+        for i in 0..8 {
+            let mask = 0x80 >> i;
+            if first & mask == 0 {
+                return Ok(value | (u64::from(first & (mask - 1)) << (8 * i)));
+            }
+            value |= u64::from(self.byte()?) << (8 * i);
+        }
+        Ok(value)
+    }
+
+    /// Read a number used as a count or a size of header items.
+    fn size(&mut self) -> CRCResult<usize> {
+        usize::try_from(self.number()?)
+            .ok()
+            .filter(|size| *size <= self.data.len())
+            .ok_or(Error::ParseError)
+    }
+
+    fn expect(&mut self, id: u64) -> CRCResult<()> {
+        match self.number()? == id {
+            true => Ok(()),
+            false => Err(Error::ParseError),
+        }
+    }
+
+    /// Read a vector of `count` bits, most significant bit first.
+    fn bits(&mut self, count: usize) -> CRCResult<Vec<bool>> {
+        let bytes = self.bytes(count.div_ceil(8))?;
+        Ok((0..count)
+            .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+            .collect())
+    }
+
+    /// Read `count` optional digests, preceded by a vector telling which ones are defined, unless all are.
+    fn digests(&mut self, count: usize) -> CRCResult<Vec<Option<Digest>>> {
+        let defined = match self.byte()? {
+            0 => self.bits(count)?,
+            _ => vec![true; count],
+        };
+        defined
+            .into_iter()
+            .map(|defined| match defined {
+                false => Ok(None),
+                true => {
+                    let offset = self.pos;
+                    let bytes = self.bytes(4)?;
+                    let value = u32::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(Some(Digest { value, offset }))
+                }
+            })
+            .collect()
+    }
+
+    fn folder(&mut self) -> CRCResult<(Folder, usize)> {
+        let mut coders = Vec::new();
+        for _ in 0..self.size()? {
+            let flags = self.byte()?;
+            if flags & CODER_ALTERNATIVE != 0 {
+                return Err(Error::UnsupportedError);
+            }
+            let method = self.bytes((flags & CODER_ID_SIZE_MASK) as usize)?.to_vec();
+            let streams = match flags & CODER_COMPLEX {
+                0 => (1, 1),
+                _ => (self.size()?, self.size()?),
+            };
+            let properties = match flags & CODER_PROPERTIES {
+                0 => Vec::new(),
+                _ => {
+                    let size = self.size()?;
+                    self.bytes(size)?.to_vec()
+                }
+            };
+            coders.push(Coder {
+                method,
+                properties,
+                streams,
+            });
+        }
+        let inputs = coders.iter().map(|coder| coder.streams.0).sum::<usize>();
+        let outputs = coders.iter().map(|coder| coder.streams.1).sum::<usize>();
+        let bind_pairs = outputs.checked_sub(1).ok_or(Error::ParseError)?;
+        let pack_streams = inputs.checked_sub(bind_pairs).ok_or(Error::ParseError)?;
+        let mut bound = vec![false; outputs];
+        for _ in 0..bind_pairs {
+            self.number()?;
+            *bound.get_mut(self.size()?).ok_or(Error::ParseError)? = true;
+        }
+        if pack_streams > 1 {
+            for _ in 0..pack_streams {
+                self.number()?;
+            }
+        }
+        let main = bound
+            .iter()
+            .position(|bound| !bound)
+            .ok_or(Error::ParseError)?;
+        let folder = Folder {
+            coders,
+            pack_streams,
+            unpack_size: 0,
+            digest: None,
+        };
+        Ok((folder, main))
+    }
+
+    fn streams(&mut self) -> CRCResult<Streams> {
+        let mut streams = Streams::default();
+        let mut id = self.number()?;
+        if id == ID_PACK_INFO {
+            streams.pack_pos = self.number()?;
+            let count = self.size()?;
+            loop {
+                match self.number()? {
+                    ID_END => break,
+                    ID_SIZE => {
+                        streams.pack_sizes = (0..count)
+                            .map(|_| self.number())
+                            .collect::<CRCResult<_>>()?
+                    }
+                    ID_CRC => streams.pack_digests = self.digests(count)?,
+                    _ => return Err(Error::ParseError),
+                }
+            }
+            if streams.pack_sizes.len() != count {
+                return Err(Error::ParseError);
+            }
+            streams.pack_digests.resize(count, None);
+            id = self.number()?;
+        }
+        if id == ID_UNPACK_INFO {
+            self.expect(ID_FOLDER)?;
+            let count = self.size()?;
+            if self.byte()? != 0 {
+                return Err(Error::UnsupportedError); // External folders
+            }
+            let folders = (0..count)
+                .map(|_| self.folder())
+                .collect::<CRCResult<Vec<_>>>()?;
+            self.expect(ID_CODERS_UNPACK_SIZE)?;
+            for (mut folder, main) in folders {
+                let outputs = folder.coders.iter().map(|coder| coder.streams.1).sum();
+                for output in 0..outputs {
+                    let size = self.number()?;
+                    if output == main {
+                        folder.unpack_size = size;
+                    }
+                }
+                streams.folders.push(folder);
+            }
+            match self.number()? {
+                ID_END => (),
+                ID_CRC => {
+                    for (folder, digest) in streams.folders.iter_mut().zip(self.digests(count)?) {
+                        folder.digest = digest;
+                    }
+                    self.expect(ID_END)?;
+                }
+                _ => return Err(Error::ParseError),
+            }
+            id = self.number()?;
+        }
+        let mut counts = vec![1; streams.folders.len()];
+        let mut sizes = None;
+        let mut digests = None;
+        if id == ID_SUBSTREAMS_INFO {
+            loop {
+                match self.number()? {
+                    ID_END => break,
+                    ID_NUM_UNPACK_STREAM => {
+                        counts = (0..counts.len())
+                            .map(|_| self.size())
+                            .collect::<CRCResult<_>>()?
+                    }
+                    ID_SIZE => {
+                        let count = counts.iter().map(|n| n.saturating_sub(1)).sum();
+                        sizes = Some(
+                            (0..count)
+                                .map(|_| self.number())
+                                .collect::<CRCResult<Vec<_>>>()?,
+                        );
+                    }
+                    ID_CRC => {
+                        let count = streams
+                            .folders
+                            .iter()
+                            .zip(&counts)
+                            .filter(|(folder, n)| **n != 1 || folder.digest.is_none())
+                            .map(|(_, n)| n)
+                            .sum();
+                        digests = Some(self.digests(count)?);
+                    }
+                    _ => return Err(Error::ParseError),
+                }
+            }
+            id = self.number()?;
+        }
+        if id != ID_END {
+            return Err(Error::ParseError);
+        }
+
+        // Substream sizes are given but for the last one of each folder
+        let mut sizes = sizes.unwrap_or_default().into_iter();
+        let mut digests = digests.unwrap_or_default().into_iter();
+        for (index, (folder, count)) in streams.folders.iter().zip(counts).enumerate() {
+            let mut offset = 0;
+            for i in 0..count {
+                let size = match i + 1 == count {
+                    true => folder
+                        .unpack_size
+                        .checked_sub(offset)
+                        .ok_or(Error::ParseError)?,
+                    false => sizes.next().ok_or(Error::ParseError)?,
+                };
+                let digest = match (count, folder.digest) {
+                    (1, Some(digest)) => Some(digest),
+                    _ => digests.next().flatten(),
+                };
+                streams.substreams.push(Substream {
+                    folder: index,
+                    offset,
+                    size,
+                    digest,
+                });
+                offset = offset.checked_add(size).ok_or(Error::ParseError)?;
+            }
+        }
+        Ok(streams)
+    }
+}
+
+/// Name of coders `coders`.
+fn method_name(coders: &[Coder]) -> String {
+    let names = coders.iter().map(|coder| match coder.method.as_slice() {
+        METHOD_COPY => "copy".to_string(),
+        METHOD_LZMA => "LZMA".to_string(),
+        METHOD_LZMA2 => "LZMA2".to_string(),
+        METHOD_DEFLATE => "deflate".to_string(),
+        METHOD_BCJ => "BCJ".to_string(),
+        method => format!(
+            "method {}",
+            method
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        ),
+    });
+    names.collect::<Vec<_>>().join("+")
+}
+
+/// File of an archive, with its substream if not empty.
+struct File {
+    name: String,
+    substream: Option<usize>,
+}
+
+/// 7z archive with its header, decoded if stored compressed.
+/// Files are checked by decoding folders of a single copy, LZMA, LZMA2 or deflate coder.
+pub struct SevenZip {
+    data: Vec<u8>,
+    header: Vec<u8>,        // Plain header
+    next: Range<usize>,     // Next header, plain or encoded, in archive
+    encoded: Option<usize>, // Start of the packed header and its description, for encoded headers
+    streams: Streams,
+    files: Vec<File>,
+    fields: Vec<Field>,
+    crc: CRC32,
+}
+
+impl SevenZip {
+    /// Parse 7z archive `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let mut archive = Self {
+            data,
+            header: Vec::new(),
+            next: 0..0,
+            encoded: None,
+            streams: Streams::default(),
+            files: Vec::new(),
+            fields: Vec::new(),
+            crc,
+        };
+        archive.parse_archive()?;
+        Ok(archive)
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Ranges of the packed streams of `streams`, in archive.
+    fn pack_ranges(&self, streams: &Streams) -> CRCResult<Vec<Range<usize>>> {
+        let mut start = usize::try_from(streams.pack_pos)?
+            .checked_add(SIGNATURE_HEADER_SIZE)
+            .ok_or(Error::ParseError)?;
+        let mut ranges = Vec::new();
+        for size in &streams.pack_sizes {
+            let end = start
+                .checked_add(usize::try_from(*size)?)
+                .filter(|end| *end <= self.data.len())
+                .ok_or(Error::ParseError)?;
+            ranges.push(start..end);
+            start = end;
+        }
+        Ok(ranges)
+    }
+
+    /// Decode folders of `streams`, which are `None` if using unsupported coders.
+    fn decode(&self, streams: &Streams) -> CRCResult<Vec<Option<Vec<u8>>>> {
+        let ranges = self.pack_ranges(streams)?;
+        let mut pack_stream = 0;
+        let mut folders = Vec::new();
+        for folder in &streams.folders {
+            let packed = ranges.get(pack_stream).ok_or(Error::ParseError)?;
+            let packed = &self.data[packed.clone()];
+            pack_stream += folder.pack_streams;
+            let size = usize::try_from(folder.unpack_size)?;
+            let unpacked = match folder.coders.as_slice() {
+                [coder] if folder.pack_streams == 1 => match coder.method.as_slice() {
+                    METHOD_COPY => Some(packed.to_vec()),
+                    METHOD_LZMA => {
+                        let properties = *coder.properties.first().ok_or(Error::ParseError)?;
+                        Some(lzma_decode(properties, packed, size)?)
+                    }
+                    METHOD_LZMA2 => Some(lzma2_decode(packed)?.0),
+                    METHOD_DEFLATE => Some(inflate(packed)?.0),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if unpacked
+                .as_ref()
+                .is_some_and(|unpacked| unpacked.len() != size)
+            {
+                return Err(Error::ParseError);
+            }
+            folders.push(unpacked);
+        }
+        Ok(folders)
+    }
+
+    fn parse_archive(&mut self) -> CRCResult<()> {
+        if !self.data.starts_with(SIGNATURE) || self.data.len() < SIGNATURE_HEADER_SIZE {
+            return Err(Error::ParseError);
+        }
+        let field = |offset: usize| {
+            let bytes = &self.data[offset..offset + 8];
+            usize::try_from(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let start = SIGNATURE_HEADER_SIZE
+            .checked_add(field(NEXT_HEADER_OFFSET)?)
+            .ok_or(Error::ParseError)?;
+        let end = start
+            .checked_add(field(NEXT_HEADER_SIZE)?)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(Error::ParseError)?;
+        self.next = start..end;
+        let next = &self.data[self.next.clone()];
+        self.fields = vec![
+            Field {
+                kind: Kind::StartHeader,
+                location: Location::Archive(START_HEADER_CRC),
+                stored: Self::u32_at(&self.data, START_HEADER_CRC),
+                computed: Some(
+                    self.crc
+                        .checksum(self.data[NEXT_HEADER_OFFSET..32].bytes())?,
+                ),
+            },
+            Field {
+                kind: Kind::NextHeader,
+                location: Location::Archive(NEXT_HEADER_CRC),
+                stored: Self::u32_at(&self.data, NEXT_HEADER_CRC),
+                computed: Some(self.crc.checksum(next.bytes())?),
+            },
+        ];
+
+        let mut reader = Reader { data: next, pos: 0 };
+        match reader.number()? {
+            ID_HEADER => {
+                self.header = next.to_vec();
+                self.encoded = None;
+            }
+            ID_ENCODED_HEADER => {
+                let streams = reader.streams()?;
+                let header = match self.decode(&streams)?.as_slice() {
+                    [Some(header)] if streams.substreams.len() == 1 => header.clone(),
+                    _ => return Err(Error::UnsupportedError),
+                };
+                if let Some(digest) = streams.substreams[0].digest {
+                    self.fields.push(Field {
+                        kind: Kind::EncodedHeader,
+                        location: Location::Archive(self.next.start + digest.offset),
+                        stored: digest.value,
+                        computed: Some(self.crc.checksum(header.bytes())?),
+                    });
+                }
+                let packed = self.pack_ranges(&streams)?;
+                self.encoded = packed.iter().map(|range| range.start).min();
+                self.header = header;
+            }
+            _ => return Err(Error::ParseError),
+        }
+        self.parse_header()
+    }
+
+    /// Parse plain header, listing files and their CRCs.
+    fn parse_header(&mut self) -> CRCResult<()> {
+        let header = self.header.clone();
+        let mut reader = Reader {
+            data: &header,
+            pos: 0,
+        };
+        reader.expect(ID_HEADER)?;
+        let mut streams = Streams::default();
+        let mut files = Vec::new();
+        loop {
+            match reader.number()? {
+                ID_END => break,
+                ID_ARCHIVE_PROPERTIES => {
+                    while reader.number()? != ID_END {
+                        let size = reader.size()?;
+                        reader.bytes(size)?;
+                    }
+                }
+                ID_MAIN_STREAMS_INFO => streams = reader.streams()?,
+                ID_FILES_INFO => files = Self::parse_files(&mut reader)?,
+                _ => return Err(Error::UnsupportedError),
+            }
+        }
+
+        // Files with a stream are mapped to substreams in order
+        let mut substreams = 0..streams.substreams.len();
+        for file in &mut files {
+            if file.substream.is_some() {
+                file.substream = Some(substreams.next().ok_or(Error::ParseError)?);
+            }
+        }
+
+        let folders = self.decode(&streams)?;
+        let packed = self.pack_ranges(&streams)?;
+        for (index, digest) in streams.pack_digests.iter().enumerate() {
+            if let Some(digest) = digest {
+                self.fields.push(Field {
+                    kind: Kind::PackStream(index),
+                    location: Location::Header(digest.offset),
+                    stored: digest.value,
+                    computed: Some(
+                        self.crc
+                            .checksum(self.data[packed[index].clone()].bytes())?,
+                    ),
+                });
+            }
+        }
+        for (index, folder) in streams.folders.iter().enumerate() {
+            let shared = streams
+                .substreams
+                .iter()
+                .filter(|s| s.folder == index)
+                .count()
+                == 1;
+            if let Some(digest) = folder.digest.filter(|_| !shared) {
+                self.fields.push(Field {
+                    kind: Kind::Folder(index),
+                    location: Location::Header(digest.offset),
+                    stored: digest.value,
+                    computed: folders[index]
+                        .as_ref()
+                        .map(|data| self.crc.checksum(data.bytes()))
+                        .transpose()?,
+                });
+            }
+        }
+        for (index, file) in files.iter().enumerate() {
+            let Some(substream) = file.substream.map(|i| &streams.substreams[i]) else {
+                continue;
+            };
+            let Some(digest) = substream.digest else {
+                continue;
+            };
+            let range = substream.offset as usize..(substream.offset + substream.size) as usize;
+            self.fields.push(Field {
+                kind: Kind::File {
+                    index,
+                    name: file.name.clone(),
+                    size: substream.size,
+                    method: method_name(&streams.folders[substream.folder].coders),
+                },
+                location: Location::Header(digest.offset),
+                stored: digest.value,
+                computed: folders[substream.folder]
+                    .as_ref()
+                    .map(|data| self.crc.checksum(data[range].bytes()))
+                    .transpose()?,
+            });
+        }
+        self.streams = streams;
+        self.files = files;
+        Ok(())
+    }
+
+    /// Parse files info: names, and which files have a stream.
+    fn parse_files(reader: &mut Reader) -> CRCResult<Vec<File>> {
+        let count = reader.size()?;
+        let mut files = (0..count)
+            .map(|_| File {
+                name: String::new(),
+                substream: Some(0),
+            })
+            .collect::<Vec<_>>();
+        loop {
+            let id = reader.number()?;
+            if id == ID_END {
+                return Ok(files);
+            }
+            let size = reader.size()?;
+            let mut property = Reader {
+                data: reader.bytes(size)?,
+                pos: 0,
+            };
+            match id {
+                ID_EMPTY_STREAM => {
+                    for (file, empty) in files.iter_mut().zip(property.bits(count)?) {
+                        if empty {
+                            file.substream = None;
+                        }
+                    }
+                }
+                ID_NAMES => {
+                    if property.byte()? != 0 {
+                        return Err(Error::UnsupportedError); // External names
+                    }
+                    let units = property.data[1..]
+                        .chunks_exact(2)
+                        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                        .collect::<Vec<_>>();
+                    for (file, name) in files.iter_mut().zip(units.split(|unit| *unit == 0)) {
+                        file.name = String::from_utf16_lossy(name);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Rewrite invalid CRCs in dependency order: CRCs of files, folders and packed streams in the header, then the
+    /// next header CRC, then the start header CRC. An encoded header is replaced by the plain header if it changes.
+    /// Returns the number of rewritten CRCs.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let before = self.fields.clone();
+        let mut header_changed = false;
+        for field in &before {
+            match (field.location, field.computed) {
+                (Location::Header(offset), Some(computed)) if computed != field.stored => {
+                    self.header[offset..offset + 4].copy_from_slice(&computed.to_le_bytes());
+                    header_changed = true;
+                }
+                _ => (),
+            }
+        }
+        match self.encoded {
+            Some(start) if header_changed => {
+                if self.next.end != self.data.len() || self.next.start < start {
+                    return Err(Error::UnsupportedError);
+                }
+                self.data.truncate(start);
+                self.next = start..start + self.header.len();
+                self.data.extend(&self.header);
+                let offset = (start - SIGNATURE_HEADER_SIZE) as u64;
+                self.data[NEXT_HEADER_OFFSET..NEXT_HEADER_OFFSET + 8]
+                    .copy_from_slice(&offset.to_le_bytes());
+                self.data[NEXT_HEADER_SIZE..NEXT_HEADER_SIZE + 8]
+                    .copy_from_slice(&(self.header.len() as u64).to_le_bytes());
+            }
+            Some(_) => {
+                // The encoded header CRC is stored in the next header, along with the packed header description
+                for field in &before {
+                    if let (Kind::EncodedHeader, Location::Archive(offset)) =
+                        (&field.kind, field.location)
+                    {
+                        let computed = field.computed.unwrap();
+                        self.data[offset..offset + 4].copy_from_slice(&computed.to_le_bytes());
+                    }
+                }
+            }
+            None => self.data[self.next.clone()].copy_from_slice(&self.header),
+        }
+        let next_crc = self.crc.checksum(self.data[self.next.clone()].bytes())?;
+        self.data[NEXT_HEADER_CRC..NEXT_HEADER_CRC + 4].copy_from_slice(&next_crc.to_le_bytes());
+        let start_crc = self
+            .crc
+            .checksum(self.data[NEXT_HEADER_OFFSET..SIGNATURE_HEADER_SIZE].bytes())?;
+        self.data[START_HEADER_CRC..START_HEADER_CRC + 4].copy_from_slice(&start_crc.to_le_bytes());
+        self.parse_archive()?;
+
+        let rewritten = self.fields.iter().filter(|field| {
+            before
+                .iter()
+                .any(|old| old.kind == field.kind && old.stored != field.stored)
+        });
+        Ok(rewritten.count())
+    }
+
+    /// Forge the CRC of file `index` to `target_crc`, by patching 4 bytes of its content at offset `offset`, or its last
+    /// 4 bytes by default. The file must be stored with the copy method. Its CRC is recorded, then the CRCs depending on
+    /// it are updated.
+    pub fn forge_file(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let file = self.files.get(index).ok_or(Error::OutOfBoundsError)?;
+        let substream = &self.streams.substreams[file.substream.ok_or(Error::OutOfBoundsError)?];
+        substream.digest.ok_or(Error::UnsupportedError)?;
+        let folder = &self.streams.folders[substream.folder];
+        if folder.coders.len() != 1 || folder.coders[0].method != METHOD_COPY {
+            return Err(Error::UnsupportedError);
+        }
+        let pack_stream = self.streams.folders[..substream.folder]
+            .iter()
+            .map(|folder| folder.pack_streams)
+            .sum::<usize>();
+        let start = self.pack_ranges(&self.streams)?[pack_stream].start + substream.offset as usize;
+        let content = start..start + substream.size as usize;
+        let offset = match offset {
+            Some(offset) => offset,
+            None => content
+                .len()
+                .checked_sub(4)
+                .ok_or(Error::OutOfBoundsError)?,
+        };
+        let patch = self
+            .crc
+            .compute_patch(&self.data[content.clone()], offset, target_crc)?;
+        self.data[content.start + offset..content.start + offset + 4].copy_from_slice(&patch);
+
+        // The file CRC now differs from the recorded one, which the repair rewrites along with dependent CRCs
+        self.parse_archive()?;
+        self.repair()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::sevenzip::{Kind, SevenZip};
+
+    /// Archive written by libarchive, storing "lorem ipsum dolor sit amet\n", "hello world\n" and an empty file in two
+    /// copy folders, with a plain header.
+    const STORED: &[u8] = b"\
+        \x37\x7a\xbc\xaf\x27\x1c\x00\x03\x17\x1c\xb3\x11\x27\x00\x00\x00\x00\x00\x00\x00\xb9\x00\x00\x00\
+        \x00\x00\x00\x00\x28\x20\x5e\xc2\x6c\x6f\x72\x65\x6d\x20\x69\x70\x73\x75\x6d\x20\x64\x6f\x6c\x6f\
+        \x72\x20\x73\x69\x74\x20\x61\x6d\x65\x74\x0a\x68\x65\x6c\x6c\x6f\x20\x77\x6f\x72\x6c\x64\x0a\x01\
+        \x04\x06\x00\x02\x09\x1b\x0c\x00\x07\x0b\x02\x00\x01\x01\x00\x01\x01\x00\x0c\x1b\x0c\x00\x08\x0a\
+        \x01\x23\x28\xd4\xb4\x2d\x3b\x08\xaf\x00\x00\x05\x03\x0e\x01\x20\x0f\x01\x80\x11\x25\x00\x61\x00\
+        \x2e\x00\x74\x00\x78\x00\x74\x00\x00\x00\x62\x00\x2e\x00\x74\x00\x78\x00\x74\x00\x00\x00\x65\x00\
+        \x6d\x00\x70\x00\x74\x00\x79\x00\x00\x00\x14\x1a\x01\x00\x14\x09\x2a\xcd\x09\x5f\xdd\x01\x14\x09\
+        \x2a\xcd\x09\x5f\xdd\x01\x14\x09\x2a\xcd\x09\x5f\xdd\x01\x12\x1a\x01\x00\x14\x09\x2a\xcd\x09\x5f\
+        \xdd\x01\x14\x09\x2a\xcd\x09\x5f\xdd\x01\x14\x09\x2a\xcd\x09\x5f\xdd\x01\x13\x1a\x01\x00\xf1\x22\
+        \x29\xcd\x09\x5f\xdd\x01\x14\x09\x2a\xcd\x09\x5f\xdd\x01\x14\x09\x2a\xcd\x09\x5f\xdd\x01\x15\x0e\
+        \x01\x00\x20\x80\xa4\x81\x20\x80\xa4\x81\x20\x80\xa4\x81\x00\x00";
+
+    /// Archive written by libarchive, with the same two files compressed with LZMA, and a LZMA encoded header.
+    const COMPRESSED: &[u8] = b"\
+        \x37\x7a\xbc\xaf\x27\x1c\x00\x03\x03\x0f\x57\x73\x98\x00\x00\x00\x00\x00\x00\x00\x21\x00\x00\x00\
+        \x00\x00\x00\x00\x1c\x83\xc4\x33\x00\x36\x1b\xca\xaa\xe8\x0a\x36\x37\x7a\xd6\x07\xef\x5c\xca\x6a\
+        \xc3\x4d\x09\x0d\x5e\x73\x84\xa1\x3d\x8e\xaf\x68\xfd\x7f\xe9\x9b\x01\x41\x83\x2f\x4b\xce\x86\xe2\
+        \x2d\x57\x47\xff\xf0\x02\xb8\x00\x00\x00\x81\x33\x07\xae\x0f\xd1\x00\xd4\x3c\xa0\x90\xa0\x77\xb0\
+        \xfe\x93\x87\xe1\xaa\x1f\x27\x6a\x2d\x4a\xb2\x1f\x58\xe5\x43\xcd\x99\x46\x50\xcc\xa5\x10\x27\x83\
+        \x9f\x41\x3e\x1b\xff\x51\xff\x57\x6c\xbc\x57\xe8\x8e\xc0\xc6\x5d\x78\x81\xd9\x61\x72\x98\xa1\xb6\
+        \x43\xfc\x88\x68\xcf\x13\xcd\xd5\xc5\x9a\x74\x95\xdd\xf7\x43\x54\x3a\x9c\x63\x25\x97\x63\xdb\x2b\
+        \xca\xe3\xfc\x7b\x92\x62\xa4\xb3\xe0\xb3\xff\xff\xa6\xeb\x80\x00\x17\x06\x30\x01\x09\x68\x00\x07\
+        \x0b\x01\x00\x01\x23\x03\x01\x01\x05\x5d\x00\x00\x80\x00\x0c\x80\x92\x0a\x01\x37\x68\x40\xf9\x00\
+        \x00";
+
+    /// Number of 7z header, on 1 or 2 bytes.
+    fn number(value: usize) -> Vec<u8> {
+        match value {
+            0..0x80 => vec![value as u8],
+            _ => vec![0x80 | (value >> 8) as u8, value as u8],
+        }
+    }
+
+    /// Replace the plain header of `archive` by an encoded header, storing it with the copy method.
+    fn encode_header(archive: &[u8]) -> Vec<u8> {
+        let offset = u64::from_le_bytes(archive[12..20].try_into().unwrap()) as usize;
+        let size = u64::from_le_bytes(archive[20..28].try_into().unwrap()) as usize;
+        let header = &archive[32 + offset..32 + offset + size];
+        let next = [
+            &[0x17, 0x06][..],
+            &number(offset),
+            &[0x01, 0x09],
+            &number(size),
+            &[0x00, 0x07, 0x0b, 0x01, 0x00, 0x01, 0x01, 0x00, 0x0c],
+            &number(size),
+            &[0x0a, 0x01],
+            &crc(header).to_le_bytes(),
+            &[0x00, 0x00],
+        ]
+        .concat();
+        let mut archive = [&archive[..32 + offset + size], &next].concat();
+        archive[12..20].copy_from_slice(&((offset + size) as u64).to_le_bytes());
+        archive[20..28].copy_from_slice(&(next.len() as u64).to_le_bytes());
+        archive[28..32].copy_from_slice(&crc(&next).to_le_bytes());
+        let start_crc = crc(&archive[12..32]);
+        archive[8..12].copy_from_slice(&start_crc.to_le_bytes());
+        archive
+    }
+
+    fn crc(data: &[u8]) -> u32 {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        crc32.checksum(data.bytes()).unwrap()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let archive = SevenZip::parse(STORED.to_vec()).expect("Failed to parse archive");
+        let kinds = archive.fields().iter().map(|f| f.kind.to_string());
+        assert_eq!(
+            kinds.collect::<Vec<_>>(),
+            [
+                "start header",
+                "next header",
+                "file 0  \"a.txt\"  27 bytes  copy",
+                "file 1  \"b.txt\"  12 bytes  copy",
+            ]
+        );
+        assert!(archive.fields().iter().all(|f| f.is_valid()));
+        assert_eq!(archive.fields()[3].stored, crc(b"hello world\n"));
+
+        let archive = SevenZip::parse(COMPRESSED.to_vec()).expect("Failed to parse archive");
+        assert_eq!(archive.fields().len(), 5);
+        assert_eq!(archive.fields()[2].kind, Kind::EncodedHeader);
+        assert!(archive.fields().iter().all(|f| f.is_valid()));
+        assert_eq!(
+            archive.fields()[3].stored,
+            crc(b"lorem ipsum dolor sit amet\n")
+        );
+        assert!(SevenZip::parse(STORED[..200].to_vec()).is_err());
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = STORED.to_vec();
+        data[0x20..0x25].copy_from_slice(b"LOREM");
+        let mut archive = SevenZip::parse(data).expect("Failed to parse archive");
+        let invalid = archive.fields().iter().filter(|f| !f.is_valid()).count();
+        assert_eq!(invalid, 1);
+        assert_eq!(archive.repair().unwrap(), 3); // File, next header and start header
+        let archive = SevenZip::parse(archive.data().to_vec()).unwrap();
+        assert!(archive.fields().iter().all(|f| f.is_valid()));
+        assert_eq!(
+            archive.fields()[2].stored,
+            crc(b"LOREM ipsum dolor sit amet\n")
+        );
+        let repaired = archive.data();
+
+        // An encoded header is kept when only its CRCs change, else it is replaced by the plain header
+        let mut data = COMPRESSED.to_vec();
+        data[0x1c] = 0x42;
+        let mut archive = SevenZip::parse(data).unwrap();
+        assert!(!archive.fields()[0].is_valid());
+        assert_eq!(archive.repair().unwrap(), 1); // The start header CRC is valid again
+        assert_eq!(archive.data(), COMPRESSED);
+        let mut data = encode_header(STORED);
+        data[0x20..0x25].copy_from_slice(b"LOREM");
+        let mut encoded = SevenZip::parse(data).unwrap();
+        assert_eq!(encoded.fields()[2].kind, Kind::EncodedHeader);
+        assert_eq!(encoded.repair().unwrap(), 3);
+        assert_eq!(encoded.data(), repaired);
+    }
+
+    #[test]
+    pub fn test_forge() {
+        let mut archive = SevenZip::parse(STORED.to_vec()).expect("Failed to parse archive");
+        archive
+            .forge_file(1, None, 0xdeadbeef)
+            .expect("Failed to forge file");
+        archive
+            .forge_file(0, Some(6), 0x13371337)
+            .expect("Failed to forge file");
+        let archive = SevenZip::parse(archive.data().to_vec()).unwrap();
+        assert!(archive.fields().iter().all(|f| f.is_valid()));
+        assert_eq!(archive.fields()[2].stored, 0x13371337);
+        assert_eq!(archive.fields()[3].stored, 0xdeadbeef);
+        assert!(archive.data()[0x20..].starts_with(b"lorem "));
+        assert!(archive.data()[0x3b..].starts_with(b"hello wo"));
+
+        let mut archive = SevenZip::parse(STORED.to_vec()).unwrap();
+        assert!(archive.forge_file(2, None, 0).is_err()); // Empty file
+        let mut archive = SevenZip::parse(COMPRESSED.to_vec()).unwrap();
+        assert!(archive.forge_file(0, None, 0).is_err()); // Compressed file
+    }
+}
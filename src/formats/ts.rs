@@ -0,0 +1,413 @@
+use std::{collections::HashMap, fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+
+/// Size of transport stream packets.
+const PACKET_SIZE: usize = 188;
+
+/// Sync byte starting packets.
+const SYNC_BYTE: u8 = 0x47;
+
+/// PIDs below this one are reserved for PSI and SI tables (PAT, CAT, NIT, SDT, EIT...).
+const SI_PIDS: u16 = 0x20;
+
+/// Table identifier of stuffing bytes following sections.
+const STUFFING: u8 = 0xff;
+
+/// Table identifiers of program association and program map sections.
+const PAT: u8 = 0x00;
+const PMT: u8 = 0x02;
+
+/// Table identifier of time offset sections, the only ones having a CRC without the section syntax indicator.
+const TOT: u8 = 0x73;
+
+/// CRC-32/MPEG-2: non-reflected, without final XOR.
+const MPEG2_CRC: CRC32Properties = CRC32Properties {
+    g: 0x04c11db7,
+    i: 0xffffffff,
+    f: 0,
+    refin: false,
+    refout: false,
+};
+
+/// PSI section ending with a CRC, reassembled from transport stream packets.
+#[derive(Clone)]
+pub struct Section {
+    pub pid: u16,
+    pub table_id: u8,
+    offsets: Vec<usize>, // Offset in file of each section byte
+    pub crc: Crc<u32>, // CRC stored at the end of the section, and CRC of the section, up to the CRC field
+}
+
+impl Section {
+    pub fn is_valid(&self) -> bool {
+        self.crc.is_valid()
+    }
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  pid=0x{:04x}  table 0x{:02x}  {} bytes  crc={}",
+            self.offsets[0],
+            self.pid,
+            self.table_id,
+            self.offsets.len(),
+            self.crc
+        )
+    }
+}
+
+/// Read 12 bit length field at offset `offset` of `bytes`.
+fn length12(bytes: &[u8], offset: usize) -> usize {
+    (usize::from(bytes[offset] & 0x0f) << 8) | usize::from(bytes[offset + 1])
+}
+
+/// Ranges of the descriptor loops of section `bytes`, for tables holding descriptors.
+fn descriptor_loops(bytes: &[u8]) -> Vec<Range<usize>> {
+    let end = bytes.len() - 4;
+    let mut loops = Vec::new();
+    // Loops of entries made of `header` bytes, whose descriptor loop length is at offset `length` of the entry
+    let entries = |mut i: usize, header: usize, length: usize, loops: &mut Vec<Range<usize>>| {
+        while i + header <= end {
+            let size = length12(bytes, i + length);
+            loops.push(i + header..i + header + size);
+            i += header + size;
+        }
+    };
+    match bytes[0] {
+        // CAT
+        0x01 => loops.push(8..end),
+        PMT if end >= 12 => {
+            let size = length12(bytes, 10);
+            loops.push(12..12 + size);
+            entries(12 + size, 5, 3, &mut loops);
+        }
+        // NIT
+        0x40 | 0x41 if end >= 10 => {
+            let size = length12(bytes, 8);
+            loops.push(10..10 + size);
+            entries(12 + size, 6, 4, &mut loops);
+        }
+        // SDT
+        0x42 | 0x46 => entries(11, 5, 3, &mut loops),
+        // EIT
+        0x4e..=0x6f => entries(14, 12, 10, &mut loops),
+        TOT if end >= 10 => loops.push(10..10 + length12(bytes, 8)),
+        _ => (),
+    }
+    loops
+        .into_iter()
+        .map(|range| range.start.min(end)..range.end.min(end))
+        .collect()
+}
+
+/// MPEG transport stream, with PSI sections reassembled from packets.
+pub struct Ts {
+    data: Vec<u8>,
+    sections: Vec<Section>,
+    crc: CRC32,
+}
+
+impl Ts {
+    /// Parse transport stream `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        if data.is_empty() || !data.len().is_multiple_of(PACKET_SIZE) {
+            return Err(Error::ParseError);
+        }
+        let crc = CRC32::new(MPEG2_CRC)?;
+        let mut ts = Self {
+            data,
+            sections: Vec::new(),
+            crc,
+        };
+        ts.parse_sections()?;
+        Ok(ts)
+    }
+
+    /// Bytes of section `section`.
+    fn bytes(&self, section: &Section) -> Vec<u8> {
+        section
+            .offsets
+            .iter()
+            .map(|offset| self.data[*offset])
+            .collect()
+    }
+
+    /// Extract complete sections from bytes `pending` of PID `pid`, keeping the bytes of an incomplete one.
+    fn extract(
+        &self,
+        pid: u16,
+        pending: &mut Vec<usize>,
+        sections: &mut Vec<Section>,
+    ) -> CRCResult<()> {
+        while pending.len() >= 3 {
+            let table_id = self.data[pending[0]];
+            if table_id == STUFFING {
+                pending.clear();
+                break;
+            }
+            let header = [self.data[pending[1]], self.data[pending[2]]];
+            let length = 3 + length12(&header, 0);
+            if pending.len() < length {
+                break;
+            }
+            let offsets = pending.drain(..length).collect::<Vec<_>>();
+            // Sections without syntax indicator have no CRC, except time offset ones
+            if header[0] & 0x80 == 0 && table_id != TOT || length < 3 + 4 {
+                continue;
+            }
+            let mut section = Section {
+                pid,
+                table_id,
+                offsets,
+                crc: Crc::default(),
+            };
+            let bytes = self.bytes(&section);
+            let (body, crc) = bytes.split_at(bytes.len() - 4);
+            section.crc.stored = u32::from_be_bytes(crc.try_into().unwrap());
+            section.crc.computed = self.crc.checksum(body.bytes())?;
+            sections.push(section);
+        }
+        Ok(())
+    }
+
+    /// Reassemble sections of PSI PIDs, and of program map PIDs as announced by program association sections.
+    fn parse_sections(&mut self) -> CRCResult<()> {
+        let mut sections = Vec::new();
+        let mut pending = HashMap::<u16, Vec<usize>>::new();
+        let mut pmt_pids = Vec::new();
+
+        for offset in (0..self.data.len()).step_by(PACKET_SIZE) {
+            let packet = &self.data[offset..offset + PACKET_SIZE];
+            if packet[0] != SYNC_BYTE {
+                return Err(Error::ParseError);
+            }
+            let pid = (u16::from(packet[1] & 0x1f) << 8) | u16::from(packet[2]);
+            let unit_start = packet[1] & 0x40 != 0;
+            if pid >= SI_PIDS && !pmt_pids.contains(&pid) {
+                continue;
+            }
+
+            // Skip adaptation field, if any
+            let mut start = 4;
+            if packet[3] & 0x20 != 0 {
+                start += 1 + packet[4] as usize;
+            }
+            if packet[3] & 0x10 == 0 || start >= PACKET_SIZE {
+                continue;
+            }
+
+            let pending = pending.entry(pid).or_default();
+            let count = sections.len();
+            match unit_start {
+                true => {
+                    // Pointer field gives the end of the previous section
+                    let pointer = start + 1 + packet[start] as usize;
+                    pending.extend(offset + start + 1..offset + pointer.min(PACKET_SIZE));
+                    self.extract(pid, pending, &mut sections)?;
+                    pending.clear();
+                    pending.extend(offset + pointer.min(PACKET_SIZE)..offset + PACKET_SIZE);
+                }
+                false if !pending.is_empty() => {
+                    pending.extend(offset + start..offset + PACKET_SIZE)
+                }
+                false => (),
+            }
+            self.extract(pid, pending, &mut sections)?;
+
+            // Collect program map PIDs
+            for section in sections[count..].iter().filter(|s| s.table_id == PAT) {
+                let bytes = self.bytes(section);
+                for program in bytes
+                    .get(8..bytes.len() - 4)
+                    .unwrap_or_default()
+                    .chunks_exact(4)
+                {
+                    let number = u16::from_be_bytes([program[0], program[1]]);
+                    let pid = (u16::from(program[2] & 0x1f) << 8) | u16::from(program[3]);
+                    if number != 0 && !pmt_pids.contains(&pid) {
+                        pmt_pids.push(pid);
+                    }
+                }
+            }
+        }
+        self.sections = sections;
+        Ok(())
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Write CRC `crc` of section `index`.
+    fn write_crc(&mut self, index: usize, crc: u32) {
+        let section = &mut self.sections[index];
+        section.crc.stored = crc;
+        let offsets = &section.offsets[section.offsets.len() - 4..];
+        for (offset, b) in offsets.iter().zip(crc.to_be_bytes()) {
+            self.data[*offset] = b;
+        }
+    }
+
+    /// Rewrite all invalid section CRCs, returning the number of repaired sections.
+    pub fn repair(&mut self) -> usize {
+        let invalid = (0..self.sections.len())
+            .filter(|i| !self.sections[*i].is_valid())
+            .collect::<Vec<_>>();
+        for i in &invalid {
+            self.write_crc(*i, self.sections[*i].crc.computed);
+        }
+        invalid.len()
+    }
+
+    /// Patch 4 bytes of section `index` at offset `offset` so that its CRC is `target_crc`, and store it.
+    /// By default, the last 4 bytes of the last descriptor of the section large enough are patched.
+    pub fn forge_section(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let section = self.sections.get(index).ok_or(Error::OutOfBoundsError)?;
+        let bytes = self.bytes(section);
+        let body = &bytes[..bytes.len() - 4];
+        let offset = match offset {
+            Some(offset) => offset,
+            None => {
+                // Descriptors are made of a tag, a length and data
+                let mut slot = None;
+                for range in descriptor_loops(&bytes) {
+                    let mut i = range.start;
+                    while i + 2 <= range.end {
+                        let end = i + 2 + bytes[i + 1] as usize;
+                        if end <= range.end && bytes[i + 1] >= 4 {
+                            slot = Some(end - 4);
+                        }
+                        i = end;
+                    }
+                }
+                slot.ok_or(Error::UnsupportedError)?
+            }
+        };
+        let patch = self.crc.compute_patch(body, offset, target_crc)?;
+        let offsets = section
+            .offsets
+            .get(offset..offset + 4)
+            .ok_or(Error::OutOfBoundsError)?;
+        for (offset, b) in offsets.iter().zip(patch) {
+            self.data[*offset] = b;
+        }
+        self.sections[index].crc.computed = target_crc;
+        self.write_crc(index, target_crc);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::CRC32;
+    use crate::formats::ts::{MPEG2_CRC, PACKET_SIZE, Ts};
+
+    /// Build section of table `table_id` with syntax indicator, holding `payload` after the table extension fields.
+    fn section(table_id: u8, payload: &[u8]) -> Vec<u8> {
+        let crc = CRC32::new(MPEG2_CRC).unwrap();
+        let length = (5 + payload.len() + 4) as u16;
+        let mut bytes = vec![table_id];
+        bytes.extend((0xb000 | length).to_be_bytes());
+        bytes.extend([0, 1, 0xc1, 0, 0]);
+        bytes.extend(payload);
+        let c = crc.checksum(bytes.bytes()).unwrap();
+        bytes.extend(c.to_be_bytes());
+        bytes
+    }
+
+    /// Split section `bytes` into packets of PID `pid`, padded with stuffing bytes.
+    fn packets(pid: u16, bytes: &[u8]) -> Vec<u8> {
+        let payload = [&[0][..], bytes].concat();
+        let mut data = Vec::new();
+        for (counter, chunk) in payload.chunks(PACKET_SIZE - 4).enumerate() {
+            let start = if counter == 0 { 0x40 } else { 0 };
+            data.extend([
+                0x47,
+                start | (pid >> 8) as u8,
+                pid as u8,
+                0x10 | counter as u8,
+            ]);
+            data.extend(chunk);
+            data.extend(vec![0xff; PACKET_SIZE - 4 - chunk.len()]);
+        }
+        data
+    }
+
+    /// Build stream with a program association section and a program map section spanning two packets.
+    fn ts() -> Vec<u8> {
+        let pat = section(0x00, b"\x00\x01\xe1\x00");
+        let mut pmt = b"\xe1\x01\xf0\x06\x05\x04CUEI".to_vec();
+        for i in 0..30u8 {
+            pmt.extend([0x1b, 0xe1, 0x01 + i, 0xf0, 0x03, 0x0a, 0x01, i]);
+        }
+        let pmt = section(0x02, &pmt);
+        [packets(0, &pat), packets(0x100, &pmt)].concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let ts = Ts::parse(ts()).expect("Failed to parse stream");
+        let tables = ts
+            .sections()
+            .iter()
+            .map(|s| (s.pid, s.table_id))
+            .collect::<Vec<_>>();
+        assert_eq!(tables, [(0, 0x00), (0x100, 0x02)]);
+        assert!(ts.sections().iter().all(|s| s.is_valid()));
+        assert!(Ts::parse(vec![0x47; 100]).is_err());
+
+        let crc = CRC32::new(MPEG2_CRC).unwrap();
+        assert_eq!(crc.checksum(b"123456789".bytes()).unwrap(), 0x0376e6e7);
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = ts();
+        let offset = data.windows(4).position(|w| w == b"CUEI").unwrap();
+        data[offset] = b'c';
+        let mut ts = Ts::parse(data).expect("Failed to parse stream");
+        assert!(!ts.sections()[1].is_valid());
+        assert_eq!(ts.repair(), 1);
+        let ts = Ts::parse(ts.data().to_vec()).unwrap();
+        assert!(ts.sections().iter().all(|s| s.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge_section() {
+        let original = ts();
+        let mut ts = Ts::parse(original.clone()).expect("Failed to parse stream");
+        assert!(ts.forge_section(0, None, 0).is_err());
+        ts.forge_section(0, Some(3), 0x13371337)
+            .expect("Failed to forge section");
+        // Program info descriptor is the only one large enough
+        ts.forge_section(1, None, 0x42424242)
+            .expect("Failed to forge section");
+
+        let data = ts.data().to_vec();
+        let ts = Ts::parse(data.clone()).unwrap();
+        assert!(ts.sections().iter().all(|s| s.is_valid()));
+        assert_eq!(ts.sections()[0].crc.stored, 0x13371337);
+        assert_eq!(ts.sections()[1].crc.stored, 0x42424242);
+        assert!(!data.windows(4).any(|w| w == b"CUEI"));
+        // Packet headers are left untouched
+        for offset in (0..data.len()).step_by(PACKET_SIZE) {
+            assert_eq!(data[offset..offset + 4], original[offset..offset + 4]);
+        }
+    }
+}
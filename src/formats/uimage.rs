@@ -0,0 +1,243 @@
+use std::{fmt::Display, io::Read};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+
+/// Magic number starting legacy U-Boot image headers.
+const IH_MAGIC: &[u8; 4] = b"\x27\x05\x19\x56";
+
+/// Size of image headers.
+const HEADER_SIZE: usize = 64;
+
+/// Offsets of big-endian fields in image headers.
+const IH_HCRC: usize = 4;
+const IH_SIZE: usize = 12;
+const IH_DCRC: usize = 24;
+const IH_NAME: usize = 32;
+
+/// The last 4 bytes of the null-terminated image name, used to forge the header CRC.
+const NAME_SLOT: usize = HEADER_SIZE - 4;
+
+/// Legacy U-Boot image, with stored and actual header and data CRCs.
+#[derive(Clone)]
+pub struct Image {
+    pub name: String,
+    pub size: usize,      // Size of the image data, following the header
+    pub header: Crc<u32>, // Header CRC
+    pub data: Crc<u32>,   // Data CRC
+}
+
+impl Image {
+    pub fn is_valid(&self) -> bool {
+        self.header.is_valid() && self.data.is_valid()
+    }
+}
+
+impl Display for Image {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}  {} bytes  hcrc={}  dcrc={}",
+            self.name, self.size, self.header, self.data
+        )
+    }
+}
+
+/// Legacy U-Boot image file (uImage): a 64 byte header followed by image data.
+/// Any data following the image data is kept as is.
+pub struct UImage {
+    data: Vec<u8>,
+    image: Image,
+    crc: CRC32,
+}
+
+impl UImage {
+    /// Parse image file `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let image = Image {
+            name: String::new(),
+            size: 0,
+            header: Crc::default(),
+            data: Crc::default(),
+        };
+        let mut uimage = Self { data, image, crc };
+        uimage.parse_image()?;
+        Ok(uimage)
+    }
+
+    fn field(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Compute header CRC of `header`, with the header CRC field zeroed.
+    fn compute_header_crc(&self, header: &[u8]) -> CRCResult<u32> {
+        let mut header = header[..HEADER_SIZE].to_vec();
+        header[IH_HCRC..IH_HCRC + 4].fill(0);
+        self.crc.checksum(header.bytes())
+    }
+
+    fn parse_image(&mut self) -> CRCResult<()> {
+        if !self.data.starts_with(IH_MAGIC) || self.data.len() < HEADER_SIZE {
+            return Err(Error::ParseError);
+        }
+        let size = self.field(IH_SIZE) as usize;
+        let payload = self
+            .data
+            .get(HEADER_SIZE..HEADER_SIZE + size)
+            .ok_or(Error::ParseError)?;
+        let name = &self.data[IH_NAME..HEADER_SIZE];
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+        self.image = Image {
+            name: String::from_utf8_lossy(name).into_owned(),
+            size,
+            header: Crc::new(self.field(IH_HCRC), self.compute_header_crc(&self.data)?),
+            data: Crc::new(self.field(IH_DCRC), self.crc.checksum(payload.bytes())?),
+        };
+        Ok(())
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn write_field(&mut self, offset: usize, value: u32) {
+        self.data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Rewrite the data CRC, then the header CRC which covers it, returning the number of rewritten CRCs.
+    pub fn repair(&mut self) -> CRCResult<usize> {
+        let stored = (self.image.header.stored, self.image.data.stored);
+        self.write_field(IH_DCRC, self.image.data.computed);
+        let header_crc = self.compute_header_crc(&self.data)?;
+        self.write_field(IH_HCRC, header_crc);
+        self.parse_image()?;
+        Ok(usize::from(stored.0 != self.image.header.stored)
+            + usize::from(stored.1 != self.image.data.stored))
+    }
+
+    /// Forge the data CRC to `target_crc`, by patching 4 bytes at offset `offset` of image data, or by appending 4
+    /// bytes to it by default. The image size and the header CRC are updated.
+    pub fn forge_data(&mut self, offset: Option<usize>, target_crc: u32) -> CRCResult<()> {
+        let end = HEADER_SIZE + self.image.size;
+        match offset {
+            Some(offset) => {
+                let payload = &self.data[HEADER_SIZE..end];
+                let patch = self.crc.compute_patch(payload, offset, target_crc)?;
+                let offset = HEADER_SIZE + offset;
+                self.data[offset..offset + 4].copy_from_slice(&patch);
+            }
+            None => {
+                let size = u32::try_from(self.image.size + 4)?;
+                let payload = &self.data[HEADER_SIZE..end];
+                let suffix = self.crc.compute_suffix(payload.bytes(), target_crc)?;
+                self.data.splice(end..end, suffix);
+                self.write_field(IH_SIZE, size);
+            }
+        }
+        self.write_field(IH_DCRC, target_crc);
+        let header_crc = self.compute_header_crc(&self.data)?;
+        self.write_field(IH_HCRC, header_crc);
+        self.parse_image()
+    }
+
+    /// Forge the header CRC to `target_crc`, by patching the last 4 bytes of the image name field, which must be
+    /// left unused by the null-terminated name. The data CRC is repaired first, since the header CRC covers it.
+    pub fn forge_header(&mut self, target_crc: u32) -> CRCResult<()> {
+        if !self.data[IH_NAME..NAME_SLOT].contains(&0) {
+            return Err(Error::OutOfBoundsError);
+        }
+        self.write_field(IH_DCRC, self.image.data.computed);
+        let mut header = self.data[..HEADER_SIZE].to_vec();
+        header[IH_HCRC..IH_HCRC + 4].fill(0);
+        let patch = self.crc.compute_patch(&header, NAME_SLOT, target_crc)?;
+        self.data[NAME_SLOT..HEADER_SIZE].copy_from_slice(&patch);
+        self.write_field(IH_HCRC, target_crc);
+        self.parse_image()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::Crc;
+    use crate::formats::uimage::UImage;
+
+    /// Build image of data `payload` named `name`, followed by `trailer`.
+    fn image_file(name: &[u8], payload: &[u8], trailer: &[u8]) -> Vec<u8> {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        let mut header = b"\x27\x05\x19\x56\0\0\0\0\x65\x43\x21\x00".to_vec();
+        header.extend((payload.len() as u32).to_be_bytes());
+        header.extend(b"\x80\x00\x80\x00\x80\x00\x80\x00");
+        header.extend(crc32.checksum(payload.bytes()).unwrap().to_be_bytes());
+        header.extend(b"\x05\x02\x02\x00");
+        header.extend(name);
+        header.resize(64, 0);
+        let crc = crc32.checksum(header.bytes()).unwrap();
+        header[4..8].copy_from_slice(&crc.to_be_bytes());
+        [&header, payload, trailer].concat()
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let uimage = UImage::parse(image_file(b"Linux-6.1", b"lorem ipsum", b"\xff\xff"))
+            .expect("Failed to parse uImage");
+        let image = uimage.image();
+        assert!(image.is_valid());
+        assert_eq!(image.name, "Linux-6.1");
+        assert_eq!(image.size, 11);
+        assert!(
+            UImage::parse(image_file(b"Linux-6.1", b"lorem ipsum", b"")[..70].to_vec()).is_err()
+        );
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = image_file(b"Linux-6.1", b"lorem ipsum dolor sit amet", b"");
+        let offset = data.windows(5).position(|w| w == b"dolor").unwrap();
+        data[offset] = b'D';
+        let mut uimage = UImage::parse(data).expect("Failed to parse uImage");
+        assert!(!uimage.image().is_valid());
+        assert_eq!(uimage.repair().unwrap(), 2);
+        let uimage = UImage::parse(uimage.data().to_vec()).unwrap();
+        assert!(uimage.image().is_valid());
+        assert_eq!(&uimage.data()[26 + 64 - 4..], b"amet");
+    }
+
+    #[test]
+    pub fn test_forge() {
+        let data = image_file(b"Linux-6.1", b"lorem ipsum dolor sit amet", b"\xff\xff");
+        let mut uimage = UImage::parse(data).expect("Failed to parse uImage");
+        uimage
+            .forge_data(None, 0x42424242)
+            .expect("Failed to forge data");
+        assert_eq!(uimage.image().data, Crc::new(0x42424242, 0x42424242));
+        assert_eq!(uimage.image().size, 30);
+        uimage
+            .forge_data(Some(6), 0x13371337)
+            .expect("Failed to forge data");
+        uimage
+            .forge_header(0xdeadbeef)
+            .expect("Failed to forge header");
+        let uimage = UImage::parse(uimage.data().to_vec()).unwrap();
+        let image = uimage.image();
+        assert!(image.is_valid());
+        assert_eq!(
+            (image.header.stored, image.data.stored),
+            (0xdeadbeef, 0x13371337)
+        );
+        assert_eq!(image.name, "Linux-6.1");
+        assert!(uimage.data().ends_with(b"\xff\xff"));
+
+        let data = image_file(&[b'x'; 28], b"lorem ipsum", b"");
+        let mut uimage = UImage::parse(data).unwrap();
+        assert!(uimage.forge_header(0).is_err());
+    }
+}
@@ -0,0 +1,579 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::Crc;
+use crate::formats::lzma::{lzma2_decode, lzma2_stored};
+use crate::generic::{CRC, CRCProperties};
+
+/// Magic numbers starting stream headers and ending stream footers.
+const HEADER_MAGIC: &[u8; 6] = b"\xfd7zXZ\0";
+const FOOTER_MAGIC: &[u8; 2] = b"YZ";
+
+/// Size of stream headers and footers.
+const STREAM_HEADER_SIZE: usize = 12;
+
+/// Block header flags: number of filters minus one, and optional size fields.
+const FILTER_COUNT_MASK: u8 = 0x03;
+const COMPRESSED_SIZE: u8 = 1 << 6;
+const UNCOMPRESSED_SIZE: u8 = 1 << 7;
+
+/// Identifier of the LZMA2 filter.
+const FILTER_LZMA2: u64 = 0x21;
+
+/// Check types of stream flags.
+const CHECK_CRC32: u8 = 0x01;
+const CHECK_CRC64: u8 = 0x04;
+
+/// Block checks and index sizes are padded to multiples of 4 bytes.
+const ALIGNMENT: usize = 4;
+
+/// CRC-64/XZ, used by CRC64 block checks.
+const CRC64_XZ: CRCProperties = CRCProperties {
+    width: 64,
+    g: 0x42f0e1eba9ea3693,
+    i: 0xffffffffffffffff,
+    f: 0xffffffffffffffff,
+    refin: true,
+    refout: true,
+};
+
+/// Size of block checks of check type `check`.
+fn check_size(check: u8) -> usize {
+    match check {
+        0 => 0,
+        _ => 4 << ((check - 1) / 3),
+    }
+}
+
+/// Read variable-length integer at offset `*pos` of `data`, advancing `pos`.
+fn read_vli(data: &[u8], pos: &mut usize) -> CRCResult<u64> {
+    let mut value = 0;
+    for i in 0..9 {
+        let byte = *data.get(*pos).ok_or(Error::ParseError)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::ParseError)
+}
+
+fn write_vli(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Structure protected by a CRC field.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    StreamHeader,
+    BlockHeader,
+    BlockCheck,
+    Index,
+    StreamFooter,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Kind::StreamHeader => "stream header",
+            Kind::BlockHeader => "block header",
+            Kind::BlockCheck => "block check",
+            Kind::Index => "index",
+            Kind::StreamFooter => "stream footer",
+        };
+        write!(f, "{:<13}", name)
+    }
+}
+
+/// CRC field of an xz file, with its stored and actual value.
+#[derive(Clone)]
+pub struct Field {
+    pub offset: usize, // Offset of the field in file
+    pub kind: Kind,
+    size: usize, // Size of the field: 4 bytes for CRC32, 8 bytes for CRC64
+    pub stored: u64,
+    pub computed: Option<u64>, // Unknown for blocks of unsupported filters
+}
+
+impl Field {
+    pub fn is_valid(&self) -> bool {
+        self.computed.is_none_or(|computed| computed == self.stored)
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = 2 * self.size;
+        write!(
+            f,
+            "0x{:08x}  {}  crc{}=",
+            self.offset,
+            self.kind,
+            8 * self.size
+        )?;
+        match self.computed {
+            None => write!(
+                f,
+                "0x{:0digits$x}  unchecked (unsupported filters)",
+                self.stored
+            ),
+            Some(computed) => write!(f, "{:digits$}", Crc::new(self.stored, computed)),
+        }
+    }
+}
+
+/// Block of an xz stream.
+struct Block {
+    header: Range<usize>,  // Block header, CRC included
+    filters: Range<usize>, // Filter flags in block header
+    data: Range<usize>,    // Compressed data, without padding
+    end: usize,            // End of the block, after its check
+    lzma2: bool,           // Filter chain is made of a single LZMA2 filter
+    uncompressed: u64,     // Uncompressed size, from the index
+}
+
+/// xz stream.
+struct Stream {
+    flags: [u8; 2],
+    blocks: Vec<Block>,
+    index: Range<usize>,
+    end: usize, // End of the stream, after its footer
+}
+
+impl Block {
+    /// Size of the block without its padding, as recorded in the index.
+    fn unpadded(&self, check: u8) -> usize {
+        self.data.end - self.header.start + check_size(check)
+    }
+}
+
+impl Stream {
+    fn check(&self) -> u8 {
+        self.flags[1] & 0x0f
+    }
+}
+
+/// xz file, split into streams.
+/// Any data following the last stream and its padding is kept as is.
+pub struct Xz {
+    data: Vec<u8>,
+    streams: Vec<Stream>,
+    fields: Vec<Field>,
+    crc: CRC32,
+    crc64: CRC,
+}
+
+impl Xz {
+    /// Parse xz file `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let crc64 = CRC::new(CRC64_XZ)?;
+        let mut xz = Self {
+            data,
+            streams: Vec::new(),
+            fields: Vec::new(),
+            crc,
+            crc64,
+        };
+        xz.parse_streams()?;
+        Ok(xz)
+    }
+
+    /// Build CRC32 field at offset `offset`, computed over `range`.
+    fn crc32_field(&self, kind: Kind, offset: usize, range: Range<usize>) -> CRCResult<Field> {
+        let stored = self.data.get(offset..offset + 4).ok_or(Error::ParseError)?;
+        Ok(Field {
+            offset,
+            kind,
+            size: 4,
+            stored: u64::from(u32::from_le_bytes(stored.try_into().unwrap())),
+            computed: Some(u64::from(self.crc.checksum(self.data[range].bytes())?)),
+        })
+    }
+
+    /// Compute block check of type `check` of uncompressed data `data`, if a CRC.
+    fn compute_check(&self, check: u8, data: &[u8]) -> CRCResult<Option<u64>> {
+        Ok(match check {
+            CHECK_CRC32 => Some(u64::from(self.crc.checksum(data.bytes())?)),
+            CHECK_CRC64 => Some(self.crc64.checksum(data.bytes())?),
+            _ => None,
+        })
+    }
+
+    /// Parse block at offset `offset` of a stream of check type `check`, appending its CRC fields to `fields`.
+    fn parse_block(&self, offset: usize, check: u8, fields: &mut Vec<Field>) -> CRCResult<Block> {
+        let size = (self.data[offset] as usize + 1) * 4;
+        let header = offset..offset + size;
+        let flags = *self.data.get(offset + 1).ok_or(Error::ParseError)?;
+        if header.end > self.data.len() || flags & 0x3c != 0 {
+            return Err(Error::ParseError);
+        }
+        fields.push(self.crc32_field(Kind::BlockHeader, header.end - 4, offset..header.end - 4)?);
+
+        let mut pos = offset + 2;
+        let compressed = match flags & COMPRESSED_SIZE {
+            0 => None,
+            _ => Some(read_vli(&self.data, &mut pos)? as usize),
+        };
+        if flags & UNCOMPRESSED_SIZE != 0 {
+            read_vli(&self.data, &mut pos)?;
+        }
+        let filters_start = pos;
+        let count = (flags & FILTER_COUNT_MASK) as usize + 1;
+        let mut ids = Vec::new();
+        for _ in 0..count {
+            ids.push(read_vli(&self.data, &mut pos)?);
+            pos += read_vli(&self.data, &mut pos)? as usize;
+        }
+        if pos > header.end - 4 {
+            return Err(Error::ParseError);
+        }
+
+        // LZMA2 is the last filter of any chain, so that the end of compressed data is known by decoding it
+        let decoded = match ids.last() {
+            Some(&FILTER_LZMA2) => Some(lzma2_decode(&self.data[header.end..])?),
+            _ => None,
+        };
+        let length = match (compressed, &decoded) {
+            (Some(length), _) => length,
+            (None, Some((_, length))) => *length,
+            (None, None) => return Err(Error::UnsupportedError),
+        };
+        let data = header.end..header.end + length;
+        let check_offset = offset + (size + length).div_ceil(ALIGNMENT) * ALIGNMENT;
+        let end = check_offset + check_size(check);
+        if end > self.data.len() {
+            return Err(Error::ParseError);
+        }
+
+        let lzma2 = ids == [FILTER_LZMA2];
+        if matches!(check, CHECK_CRC32 | CHECK_CRC64) {
+            let size = check_size(check);
+            let mut stored = [0; 8];
+            stored[..size].copy_from_slice(&self.data[check_offset..end]);
+            let computed = match (lzma2, decoded) {
+                (true, Some((uncompressed, _))) => self.compute_check(check, &uncompressed)?,
+                _ => None,
+            };
+            fields.push(Field {
+                offset: check_offset,
+                kind: Kind::BlockCheck,
+                size,
+                stored: u64::from_le_bytes(stored),
+                computed,
+            });
+        }
+        Ok(Block {
+            header,
+            filters: filters_start..pos,
+            data,
+            end,
+            lzma2,
+            uncompressed: 0,
+        })
+    }
+
+    /// Parse stream at offset `offset`, appending its CRC fields to `fields`.
+    fn parse_stream(&self, offset: usize, fields: &mut Vec<Field>) -> CRCResult<Stream> {
+        let header = self
+            .data
+            .get(offset..offset + STREAM_HEADER_SIZE)
+            .ok_or(Error::ParseError)?;
+        let flags = [header[6], header[7]];
+        if !header.starts_with(HEADER_MAGIC) || flags[0] != 0 || flags[1] & 0xf0 != 0 {
+            return Err(Error::ParseError);
+        }
+        fields.push(self.crc32_field(Kind::StreamHeader, offset + 8, offset + 6..offset + 8)?);
+        let check = flags[1] & 0x0f;
+
+        // Blocks, up to the index indicator
+        let mut blocks = Vec::new();
+        let mut pos = offset + STREAM_HEADER_SIZE;
+        while *self.data.get(pos).ok_or(Error::ParseError)? != 0 {
+            let block = self.parse_block(pos, check, fields)?;
+            pos = block.end;
+            blocks.push(block);
+        }
+
+        // Index records give the unpadded and uncompressed sizes of blocks
+        let index_start = pos;
+        pos += 1;
+        if read_vli(&self.data, &mut pos)? != blocks.len() as u64 {
+            return Err(Error::ParseError);
+        }
+        for block in blocks.iter_mut() {
+            let unpadded = read_vli(&self.data, &mut pos)?;
+            block.uncompressed = read_vli(&self.data, &mut pos)?;
+            if unpadded != block.unpadded(check) as u64 {
+                return Err(Error::ParseError);
+            }
+        }
+        pos = index_start + (pos - index_start).div_ceil(ALIGNMENT) * ALIGNMENT;
+        fields.push(self.crc32_field(Kind::Index, pos, index_start..pos)?);
+        let index = index_start..pos + 4;
+
+        let footer = self
+            .data
+            .get(index.end..index.end + STREAM_HEADER_SIZE)
+            .ok_or(Error::ParseError)?;
+        let backward = u32::from_le_bytes(footer[4..8].try_into().unwrap()) as usize;
+        if !footer.ends_with(FOOTER_MAGIC)
+            || footer[8..10] != flags
+            || (backward + 1) * 4 != index.len()
+        {
+            return Err(Error::ParseError);
+        }
+        fields.push(self.crc32_field(
+            Kind::StreamFooter,
+            index.end,
+            index.end + 4..index.end + 10,
+        )?);
+        Ok(Stream {
+            flags,
+            blocks,
+            end: index.end + STREAM_HEADER_SIZE,
+            index,
+        })
+    }
+
+    /// Parse streams and the stream padding following them, up to the end of file or to trailing data.
+    fn parse_streams(&mut self) -> CRCResult<()> {
+        let mut fields = Vec::new();
+        let mut streams = vec![self.parse_stream(0, &mut fields)?];
+        loop {
+            let mut offset = streams.last().unwrap().end;
+            while self.data[offset..].starts_with(&[0; ALIGNMENT]) {
+                offset += ALIGNMENT;
+            }
+            if !self.data[offset..].starts_with(HEADER_MAGIC) {
+                break;
+            }
+            streams.push(self.parse_stream(offset, &mut fields)?);
+        }
+        self.streams = streams;
+        self.fields = fields;
+        Ok(())
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Rewrite all invalid CRC fields, returning their number.
+    pub fn repair(&mut self) -> usize {
+        let mut repaired = 0;
+        for field in self.fields.iter_mut().filter(|field| !field.is_valid()) {
+            let computed = field.computed.unwrap();
+            let bytes = &computed.to_le_bytes()[..field.size];
+            self.data[field.offset..field.offset + field.size].copy_from_slice(bytes);
+            field.stored = computed;
+            repaired += 1;
+        }
+        repaired
+    }
+
+    /// Replace block `block` of stream `index` by one of uncompressed data `data`, stored in uncompressed LZMA2
+    /// chunks. Size fields and CRCs of the block header, the index and the stream footer are updated.
+    fn replace_block(&mut self, index: usize, block: usize, data: &[u8]) -> CRCResult<()> {
+        let stream = &self.streams[index];
+        let b = &stream.blocks[block];
+        let flags = self.data[b.header.start + 1];
+        let compressed = lzma2_stored(data);
+        let mut header = vec![0, flags];
+        if flags & COMPRESSED_SIZE != 0 {
+            write_vli(&mut header, compressed.len() as u64);
+        }
+        if flags & UNCOMPRESSED_SIZE != 0 {
+            write_vli(&mut header, data.len() as u64);
+        }
+        header.extend(&self.data[b.filters.clone()]);
+        header.resize((header.len() + 4).div_ceil(ALIGNMENT) * ALIGNMENT - 4, 0);
+        header[0] = (header.len() / 4) as u8; // Size of the header with its CRC, in 4 byte units, minus one
+        header.extend(self.crc.checksum(header.bytes())?.to_le_bytes());
+
+        let unpadded = header.len() + compressed.len() + check_size(stream.check());
+        let mut bytes = [header, compressed].concat();
+        bytes.resize(bytes.len().div_ceil(ALIGNMENT) * ALIGNMENT, 0);
+        let check = self.compute_check(stream.check(), data)?.unwrap_or(0);
+        bytes.extend(&check.to_le_bytes()[..check_size(stream.check())]);
+
+        // Index of the sizes of all blocks, and footer
+        let mut index_bytes = vec![0];
+        write_vli(&mut index_bytes, stream.blocks.len() as u64);
+        for (i, other) in stream.blocks.iter().enumerate() {
+            let sizes = match i == block {
+                true => (unpadded, data.len() as u64),
+                false => (other.unpadded(stream.check()), other.uncompressed),
+            };
+            write_vli(&mut index_bytes, sizes.0 as u64);
+            write_vli(&mut index_bytes, sizes.1);
+        }
+        index_bytes.resize(index_bytes.len().div_ceil(ALIGNMENT) * ALIGNMENT, 0);
+        index_bytes.extend(self.crc.checksum(index_bytes.bytes())?.to_le_bytes());
+        let mut footer = ((index_bytes.len() / 4 - 1) as u32).to_le_bytes().to_vec();
+        footer.extend(stream.flags);
+        let footer = [
+            &self.crc.checksum(footer.bytes())?.to_le_bytes()[..],
+            &footer,
+            FOOTER_MAGIC,
+        ]
+        .concat();
+
+        let (range, index_range) = (b.header.start..b.end, stream.index.start..stream.end);
+        self.data
+            .splice(index_range, [index_bytes, footer].concat());
+        self.data.splice(range, bytes);
+        self.parse_streams()
+    }
+
+    /// Forge the check of block `index`, counted from the start of file, to `target_crc`, by patching 4 or 8 bytes
+    /// at offset `offset` of its uncompressed data, or by appending them by default.
+    /// The block is stored again in uncompressed LZMA2 chunks.
+    pub fn forge_check(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u64,
+    ) -> CRCResult<()> {
+        let (s, b) = self
+            .streams
+            .iter()
+            .enumerate()
+            .flat_map(|(s, stream)| (0..stream.blocks.len()).map(move |b| (s, b)))
+            .nth(index)
+            .ok_or(Error::OutOfBoundsError)?;
+        let stream = &self.streams[s];
+        let block = &stream.blocks[b];
+        let crc = match stream.check() {
+            CHECK_CRC32 => CRC::new(CRC32Properties::default().into())?,
+            CHECK_CRC64 => CRC::new(CRC64_XZ)?,
+            _ => return Err(Error::UnsupportedError),
+        };
+        if !block.lzma2 {
+            return Err(Error::UnsupportedError);
+        }
+        if crc.props().width < 64 && target_crc >> crc.props().width != 0 {
+            return Err(Error::OverflowError(None));
+        }
+
+        let (mut data, _) = lzma2_decode(&self.data[block.data.clone()])?;
+        let size = check_size(stream.check());
+        let offset = match offset {
+            Some(offset) => offset,
+            None => {
+                data.resize(data.len() + size, 0);
+                data.len() - size
+            }
+        };
+        let patch = crc.compute_patch(&data, offset, target_crc)?;
+        data[offset..offset + size].copy_from_slice(&patch);
+        self.replace_block(s, b, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::formats::xz::{CRC64_XZ, Kind, Xz};
+    use crate::generic::CRC;
+
+    /// Stream compressed by liblzma with a CRC64 check, of 2 blocks:
+    /// "lorem ipsum dolor sit amet, lorem ipsum\n" and "consectetur adipiscing elit, consectetur adipiscing elit,
+    /// consectetur\n".
+    const XZ: &[u8] = b"\xfd\x37\x7a\x58\x5a\x00\x00\x04\xe6\xd6\xb4\x46\x02\xc0\x2b\x28\
+                        \x21\x01\x1c\x00\x9e\x29\xfb\x88\xe0\x00\x27\x00\x23\x5d\x00\x36\
+                        \x1b\xca\xaa\xe8\x0a\x36\x37\x7a\xd6\x07\xef\x5c\xca\x6a\xc3\x4d\
+                        \x09\x0d\x5e\x73\x84\xa1\x3d\x8e\xaf\xe7\xf5\x70\x3a\xcc\xc4\x38\
+                        \x00\x00\x00\x00\x5b\x57\xa9\xd6\xab\xe9\x8c\x54\x02\xc0\x2d\x46\
+                        \x21\x01\x1c\x00\xaf\x03\xa0\x78\xe0\x00\x45\x00\x25\x5d\x00\x31\
+                        \x9b\xca\x19\xc5\x54\x56\x30\x12\xfa\x41\x44\x24\x66\x5f\xcd\xc0\
+                        \x21\x72\xd9\x26\x0f\x56\x4b\xea\xfd\x50\x7f\x38\x7e\x0d\x20\xeb\
+                        \x1b\x20\x00\x00\x00\x00\x00\x00\x1c\x2f\x83\x35\xf8\x73\xc1\x5b\
+                        \x00\x02\x3f\x28\x41\x46\x00\x00\xd7\xd1\x9f\xa1\xb1\xc4\x67\xfb\
+                        \x02\x00\x00\x00\x00\x04\x59\x5a";
+
+    #[test]
+    pub fn test_parse() {
+        let xz = Xz::parse(XZ.to_vec()).expect("Failed to parse xz");
+        let kinds = xz.fields().iter().map(|f| f.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                Kind::StreamHeader,
+                Kind::BlockHeader,
+                Kind::BlockCheck,
+                Kind::BlockHeader,
+                Kind::BlockCheck,
+                Kind::Index,
+                Kind::StreamFooter
+            ]
+        );
+        assert!(
+            xz.fields()
+                .iter()
+                .all(|f| f.is_valid() && f.computed.is_some())
+        );
+        assert_eq!(xz.fields()[2].stored, 0x548ce9abd6a9575b);
+        assert!(Xz::parse(XZ[..100].to_vec()).is_err());
+
+        let crc = CRC::new(CRC64_XZ).unwrap();
+        assert_eq!(
+            crc.checksum(b"123456789".bytes()).unwrap(),
+            0x995dc9bbdf1939fa
+        );
+    }
+
+    #[test]
+    pub fn test_repair() {
+        // Store blocks uncompressed, then edit a chunk and corrupt the stream header CRC
+        let mut xz = Xz::parse(XZ.to_vec()).expect("Failed to parse xz");
+        xz.forge_check(0, None, 0).expect("Failed to forge check");
+        let mut data = xz.data().to_vec();
+        let offset = data.windows(5).position(|w| w == b"dolor").unwrap();
+        data[offset] = b'D';
+        data[8] ^= 1;
+        let mut xz = Xz::parse(data).expect("Failed to parse xz");
+        let invalid = xz.fields().iter().filter(|f| !f.is_valid()).map(|f| f.kind);
+        assert_eq!(
+            invalid.collect::<Vec<_>>(),
+            [Kind::StreamHeader, Kind::BlockCheck]
+        );
+        assert_eq!(xz.repair(), 2);
+        let xz = Xz::parse(xz.data().to_vec()).unwrap();
+        assert!(xz.fields().iter().all(|f| f.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge_check() {
+        let mut xz = Xz::parse(XZ.to_vec()).expect("Failed to parse xz");
+        xz.forge_check(1, None, 0x4242424242424242)
+            .expect("Failed to forge check");
+        xz.forge_check(0, Some(6), 0x1337133713371337)
+            .expect("Failed to forge check");
+        assert!(xz.forge_check(2, None, 0).is_err());
+
+        let data = xz.data().to_vec();
+        let xz = Xz::parse(data.clone()).unwrap();
+        assert!(xz.fields().iter().all(|f| f.is_valid()));
+        let checks = xz.fields().iter().filter(|f| f.kind == Kind::BlockCheck);
+        let checks = checks.map(|f| f.stored).collect::<Vec<_>>();
+        assert_eq!(checks, [0x1337133713371337, 0x4242424242424242]);
+        // Blocks are stored uncompressed, the second one with 8 appended bytes
+        let offset = data
+            .windows(12)
+            .position(|w| w == b"consectetur\n")
+            .unwrap();
+        assert_eq!(data[offset + 20], 0x00);
+    }
+}
@@ -0,0 +1,360 @@
+use std::{fmt::Display, io::Read, ops::Range};
+
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::formats::le;
+
+/// Signature of local file headers.
+const LOCAL_SIGNATURE: u32 = 0x04034b50;
+
+/// Signature of central directory headers.
+const CENTRAL_SIGNATURE: u32 = 0x02014b50;
+
+/// Signature of the end of central directory record.
+const END_SIGNATURE: u32 = 0x06054b50;
+
+/// Optional signature of data descriptors.
+const DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+
+/// Size of the end of central directory record, without comment.
+const END_SIZE: usize = 22;
+
+/// Compression method of stored (uncompressed) members.
+const STORED: u16 = 0;
+
+/// General purpose flag telling that CRC and sizes follow member data, in a data descriptor.
+const DESCRIPTOR_FLAG: u16 = 1 << 3;
+
+/// ZIP archive member, with the offsets of all its CRC fields.
+#[derive(Clone)]
+pub struct Entry {
+    pub name: String,
+    pub offset: usize, // Offset of the local file header
+    pub method: u16,   // Compression method
+    data: Range<usize>,
+    crc_offsets: Vec<usize>, // Offsets of the CRC fields holding a value, from local header to central directory
+    pub stored: Vec<u32>,    // Values of these CRC fields
+    pub computed: Option<u32>, // CRC of member data, for stored members
+}
+
+impl Entry {
+    /// Content CRC of the member: the CRC of its data if stored, else the one recorded in the central directory.
+    pub fn crc(&self) -> u32 {
+        self.computed.unwrap_or(*self.stored.last().unwrap())
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.stored.iter().all(|crc| *crc == self.crc())
+    }
+
+    pub fn is_stored(&self) -> bool {
+        self.method == STORED
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  {}  {} bytes  {}  crc=",
+            self.offset,
+            self.name,
+            self.data.len(),
+            match self.is_stored() {
+                true => "stored".to_string(),
+                false => format!("method {}", self.method),
+            }
+        )?;
+        let crcs = self
+            .stored
+            .iter()
+            .map(|crc| format!("0x{:08x}", crc))
+            .collect::<Vec<_>>();
+        write!(f, "{}  ", crcs.join("/"))?;
+        match self.is_valid() {
+            true => write!(f, "ok"),
+            false => write!(f, "BAD (expected 0x{:08x})", self.crc()),
+        }
+    }
+}
+
+/// ZIP archive, with members listed by its central directory.
+pub struct Zip {
+    data: Vec<u8>,
+    entries: Vec<Entry>,
+    crc: CRC32,
+}
+
+/// Read little-endian field of `N` bytes at offset `offset` of `data`.
+fn field<const N: usize>(data: &[u8], offset: usize) -> CRCResult<u64> {
+    let bytes = data.get(offset..offset + N).ok_or(Error::ParseError)?;
+    Ok(le::<N>(bytes, 0))
+}
+
+impl Zip {
+    /// Parse ZIP archive `data`.
+    pub fn parse(data: Vec<u8>) -> CRCResult<Self> {
+        let crc = CRC32::new(CRC32Properties::default())?;
+        let mut zip = Self {
+            data,
+            entries: Vec::new(),
+            crc,
+        };
+        zip.parse_entries()?;
+        Ok(zip)
+    }
+
+    /// Find the end of central directory record, searching backwards through the archive comment.
+    fn find_end(&self) -> CRCResult<usize> {
+        let last = self
+            .data
+            .len()
+            .checked_sub(END_SIZE)
+            .ok_or(Error::ParseError)?;
+        (last.saturating_sub(0xffff)..=last)
+            .rev()
+            .find(|offset| field::<4>(&self.data, *offset).ok() == Some(u64::from(END_SIGNATURE)))
+            .ok_or(Error::ParseError)
+    }
+
+    /// Parse members from the central directory, along with their local headers and data descriptors.
+    /// ZIP64 archives are not supported.
+    fn parse_entries(&mut self) -> CRCResult<()> {
+        let data = &self.data;
+        let end = self.find_end()?;
+        let count = field::<2>(data, end + 10)? as usize;
+        let mut central = field::<4>(data, end + 16)? as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            if field::<4>(data, central)? != u64::from(CENTRAL_SIGNATURE) {
+                return Err(Error::ParseError);
+            }
+            let flags = field::<2>(data, central + 8)? as u16;
+            let method = field::<2>(data, central + 10)? as u16;
+            let size = field::<4>(data, central + 20)? as usize;
+            let name_length = field::<2>(data, central + 28)? as usize;
+            let extra_length = field::<2>(data, central + 30)? as usize;
+            let comment_length = field::<2>(data, central + 32)? as usize;
+            let offset = field::<4>(data, central + 42)? as usize;
+            let name = data
+                .get(central + 46..central + 46 + name_length)
+                .ok_or(Error::ParseError)?;
+            if size == 0xffffffff || offset == 0xffffffff {
+                return Err(Error::ParseError);
+            }
+
+            if field::<4>(data, offset)? != u64::from(LOCAL_SIGNATURE) {
+                return Err(Error::ParseError);
+            }
+            let start = offset
+                + 30
+                + field::<2>(data, offset + 26)? as usize
+                + field::<2>(data, offset + 28)? as usize;
+            let range = start..start + size;
+            if range.end > data.len() {
+                return Err(Error::ParseError);
+            }
+
+            // Local header CRC is zero when deferred to the data descriptor
+            let mut crc_offsets = Vec::new();
+            let descriptor = flags & DESCRIPTOR_FLAG != 0;
+            if !descriptor || field::<4>(data, offset + 14)? != 0 {
+                crc_offsets.push(offset + 14);
+            }
+            if descriptor {
+                match field::<4>(data, range.end)? == u64::from(DESCRIPTOR_SIGNATURE) {
+                    true => crc_offsets.push(range.end + 4),
+                    false => crc_offsets.push(range.end),
+                }
+            }
+            crc_offsets.push(central + 16);
+
+            let stored = crc_offsets
+                .iter()
+                .map(|offset| Ok(field::<4>(data, *offset)? as u32))
+                .collect::<CRCResult<Vec<_>>>()?;
+            let computed = match method {
+                STORED => Some(self.crc.checksum(data[range.clone()].bytes())?),
+                _ => None,
+            };
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                offset,
+                method,
+                data: range,
+                crc_offsets,
+                stored,
+                computed,
+            });
+            central += 46 + name_length + extra_length + comment_length;
+        }
+        self.entries = entries;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Write CRC `crc` in all CRC fields of member `index`.
+    fn write_crc(&mut self, index: usize, crc: u32) {
+        let entry = &mut self.entries[index];
+        for (offset, stored) in entry.crc_offsets.iter().zip(entry.stored.iter_mut()) {
+            self.data[*offset..offset + 4].copy_from_slice(&crc.to_le_bytes());
+            *stored = crc;
+        }
+    }
+
+    /// Rewrite CRC fields of stored members whose data changed, and make CRC fields of other members consistent with
+    /// the central directory. Returns the number of repaired members.
+    pub fn repair(&mut self) -> usize {
+        let invalid = (0..self.entries.len())
+            .filter(|i| !self.entries[*i].is_valid())
+            .collect::<Vec<_>>();
+        for i in &invalid {
+            self.write_crc(*i, self.entries[*i].crc());
+        }
+        invalid.len()
+    }
+
+    /// Patch 4 bytes of stored member `index` data at offset `offset` so that its content CRC is `target_crc`, and
+    /// write it in all CRC fields. The last 4 bytes of member data are patched by default.
+    pub fn forge_entry(
+        &mut self,
+        index: usize,
+        offset: Option<usize>,
+        target_crc: u32,
+    ) -> CRCResult<()> {
+        let entry = self.entries.get(index).ok_or(Error::OutOfBoundsError)?;
+        if !entry.is_stored() {
+            return Err(Error::UnsupportedError);
+        }
+        let data = entry.data.clone();
+        let offset = match offset {
+            Some(offset) => offset,
+            None => data.len().checked_sub(4).ok_or(Error::OutOfBoundsError)?,
+        };
+        let patch = self
+            .crc
+            .compute_patch(&self.data[data.clone()], offset, target_crc)?;
+        self.data[data.start + offset..data.start + offset + 4].copy_from_slice(&patch);
+        self.entries[index].computed = Some(target_crc);
+        self.write_crc(index, target_crc);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::formats::zip::Zip;
+
+    /// Build ZIP archive of stored members `(name, data, descriptor)`, with a data descriptor when set.
+    fn zip(members: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        let (mut data, mut central) = (Vec::new(), Vec::new());
+        for (name, content, descriptor) in members {
+            let crc = crc32.checksum(content.bytes()).unwrap();
+            let size = content.len() as u32;
+            let flags: u16 = if *descriptor { 1 << 3 } else { 0 };
+            let offset = data.len() as u32;
+
+            // Version, flags, method, time and date, CRC and sizes, name and extra lengths
+            let fields = |header: &mut Vec<u8>, local: bool| {
+                header.extend(20u16.to_le_bytes());
+                header.extend(flags.to_le_bytes());
+                header.extend([0; 6]);
+                let crc = if local && *descriptor { 0 } else { crc };
+                header.extend(crc.to_le_bytes());
+                header.extend(size.to_le_bytes());
+                header.extend(size.to_le_bytes());
+                header.extend((name.len() as u16).to_le_bytes());
+                header.extend(0u16.to_le_bytes());
+            };
+            data.extend(0x04034b50u32.to_le_bytes());
+            fields(&mut data, true);
+            data.extend(name.as_bytes());
+            data.extend(*content);
+            if *descriptor {
+                data.extend(0x08074b50u32.to_le_bytes());
+                for field in [crc, size, size] {
+                    data.extend(field.to_le_bytes());
+                }
+            }
+
+            // Version made by, common fields, comment length, disk, attributes and local header offset
+            central.extend(0x02014b50u32.to_le_bytes());
+            central.extend(20u16.to_le_bytes());
+            fields(&mut central, false);
+            central.extend([0; 10]);
+            central.extend(offset.to_le_bytes());
+            central.extend(name.as_bytes());
+        }
+        let (count, size, offset) = (
+            members.len() as u16,
+            central.len() as u32,
+            data.len() as u32,
+        );
+        data.extend(central);
+        data.extend(0x06054b50u32.to_le_bytes());
+        data.extend([0; 4]);
+        data.extend(count.to_le_bytes());
+        data.extend(count.to_le_bytes());
+        data.extend(size.to_le_bytes());
+        data.extend(offset.to_le_bytes());
+        data.extend([0; 2]);
+        data
+    }
+
+    fn members() -> Vec<u8> {
+        zip(&[
+            ("lorem.txt", b"lorem ipsum dolor sit amet", false),
+            ("ipsum.txt", b"consectetur adipiscing elit", true),
+        ])
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let zip = Zip::parse(members()).expect("Failed to parse ZIP");
+        let names = zip.entries().iter().map(|e| &e.name).collect::<Vec<_>>();
+        assert_eq!(names, ["lorem.txt", "ipsum.txt"]);
+        assert!(zip.entries().iter().all(|e| e.is_valid()));
+        assert_eq!(zip.entries()[0].stored.len(), 2);
+        assert_eq!(zip.entries()[1].stored.len(), 2);
+        assert!(Zip::parse(b"PK\x03\x04".to_vec()).is_err());
+    }
+
+    #[test]
+    pub fn test_repair() {
+        let mut data = members();
+        let offset = data.windows(5).position(|w| w == b"ipsum").unwrap();
+        data[offset] = b'I';
+        let mut zip = Zip::parse(data).expect("Failed to parse ZIP");
+        assert!(!zip.entries()[0].is_valid());
+        assert_eq!(zip.repair(), 1);
+        let zip = Zip::parse(zip.data().to_vec()).unwrap();
+        assert!(zip.entries().iter().all(|e| e.is_valid()));
+    }
+
+    #[test]
+    pub fn test_forge_entry() {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        let mut zip = Zip::parse(members()).expect("Failed to parse ZIP");
+        assert!(zip.forge_entry(1, Some(25), 0).is_err());
+        zip.forge_entry(1, Some(0), 0x42424242)
+            .expect("Failed to forge entry");
+        let zip = Zip::parse(zip.data().to_vec()).unwrap();
+        let entry = &zip.entries()[1];
+        assert!(entry.is_valid());
+        assert_eq!(entry.stored, [0x42424242; 2]);
+        let content = &zip.data()[entry.data.clone()];
+        assert_eq!(crc32.checksum(content.bytes()).unwrap(), 0x42424242);
+    }
+}
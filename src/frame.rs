@@ -0,0 +1,267 @@
+use std::{io::Read, ops::Range};
+
+use crate::catalogue::find;
+use crate::error::{CRCResult, Error};
+use crate::generic::CRC;
+
+/// Bytes starting DNP3 link frames.
+const DNP3_START: [u8; 2] = [0x05, 0x64];
+
+/// Size of the DNP3 link header fields (control, destination and source) following the length byte.
+const DNP3_HEADER_SIZE: usize = 5;
+
+/// Maximum size of DNP3 user data blocks, each followed by its CRC.
+const DNP3_BLOCK_SIZE: usize = 16;
+
+/// Bytes starting 128 byte XMODEM blocks and 1024 byte YMODEM blocks.
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+
+/// Byte padding the data of XMODEM and YMODEM blocks.
+const SUB: u8 = 0x1a;
+
+/// Serial protocol protecting frames with CRC-16 checksums.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Protocol {
+    Modbus, // Modbus RTU: payload followed by its CRC, low byte first
+    Dnp3, // DNP3 link frame: header and 16 byte blocks of user data, each followed by its CRC, low byte first
+    Xmodem, // XMODEM block: header and 128 bytes of data followed by their CRC, high byte first
+    Ymodem, // YMODEM block: header and 1024 bytes of data followed by their CRC, high byte first
+}
+
+impl Protocol {
+    /// Catalogue name of the CRC model.
+    fn model(&self) -> &'static str {
+        match self {
+            Self::Modbus => "CRC-16/MODBUS",
+            Self::Dnp3 => "CRC-16/DNP",
+            Self::Xmodem | Self::Ymodem => "CRC-16/XMODEM",
+        }
+    }
+
+    /// Size of the data of XMODEM and YMODEM blocks.
+    fn block_size(&self) -> usize {
+        match self {
+            Self::Ymodem => 1024,
+            _ => 128,
+        }
+    }
+}
+
+/// Range of a frame covered by a CRC, which follows it, holding a range of the payload.
+struct Segment {
+    covered: Range<usize>, // Range of frame bytes covered by the CRC
+    payload: Range<usize>, // Range of payload bytes held by the covered range
+    at: usize,             // Offset in frame of the first payload byte
+}
+
+/// Frame builder and forger for a protocol, built on the width-generic CRC engine.
+/// Payloads are the protocol fields not derived from other fields: the whole Modbus RTU message, the DNP3 control,
+/// destination, source and user data, and the XMODEM and YMODEM block data.
+pub struct Framer {
+    protocol: Protocol,
+    crc: CRC,
+}
+
+impl Framer {
+    pub fn new(protocol: Protocol) -> CRCResult<Self> {
+        let model = find(protocol.model()).ok_or(Error::UnknownModelError)?;
+        Ok(Self {
+            protocol,
+            crc: CRC::new(model.props)?,
+        })
+    }
+
+    /// Segments of frames of payloads of `size` bytes.
+    fn segments(&self, size: usize) -> CRCResult<Vec<Segment>> {
+        match self.protocol {
+            Protocol::Modbus if size > 0 => Ok(vec![Segment {
+                covered: 0..size,
+                payload: 0..size,
+                at: 0,
+            }]),
+            Protocol::Dnp3 if (DNP3_HEADER_SIZE..=0xff).contains(&size) => {
+                let header = Segment {
+                    covered: 0..3 + DNP3_HEADER_SIZE,
+                    payload: 0..DNP3_HEADER_SIZE,
+                    at: 3,
+                };
+                let blocks = (DNP3_HEADER_SIZE..size)
+                    .step_by(DNP3_BLOCK_SIZE)
+                    .enumerate()
+                    .map(|(k, start)| {
+                        let end = size.min(start + DNP3_BLOCK_SIZE);
+                        let at = 5 + DNP3_HEADER_SIZE + k * (DNP3_BLOCK_SIZE + 2);
+                        Segment {
+                            covered: at..at + end - start,
+                            payload: start..end,
+                            at,
+                        }
+                    });
+                Ok([header].into_iter().chain(blocks).collect())
+            }
+            Protocol::Xmodem | Protocol::Ymodem if size == self.protocol.block_size() => {
+                Ok(vec![Segment {
+                    covered: 3..3 + size,
+                    payload: 0..size,
+                    at: 3,
+                }])
+            }
+            _ => Err(Error::OutOfBoundsError),
+        }
+    }
+
+    /// Write CRC `crc` of a segment at offset `offset` of `frame`.
+    fn write_crc(&self, frame: &mut [u8], offset: usize, crc: u16) {
+        let bytes = match self.protocol {
+            Protocol::Modbus | Protocol::Dnp3 => crc.to_le_bytes(),
+            Protocol::Xmodem | Protocol::Ymodem => crc.to_be_bytes(),
+        };
+        frame[offset..offset + 2].copy_from_slice(&bytes);
+    }
+
+    /// Read CRC of a segment at offset `offset` of `frame`.
+    fn read_crc(&self, frame: &[u8], offset: usize) -> u16 {
+        let bytes = [frame[offset], frame[offset + 1]];
+        match self.protocol {
+            Protocol::Modbus | Protocol::Dnp3 => u16::from_le_bytes(bytes),
+            Protocol::Xmodem | Protocol::Ymodem => u16::from_be_bytes(bytes),
+        }
+    }
+
+    /// Frame `payload`, as block number `block` for XMODEM and YMODEM, whose data is padded.
+    pub fn frame(&self, payload: &[u8], block: u8) -> CRCResult<Vec<u8>> {
+        let mut payload = payload.to_vec();
+        let mut frame = match self.protocol {
+            Protocol::Modbus => Vec::new(),
+            Protocol::Dnp3 => {
+                let size = u8::try_from(payload.len()).map_err(|_| Error::OutOfBoundsError)?;
+                [&DNP3_START[..], &[size]].concat()
+            }
+            Protocol::Xmodem | Protocol::Ymodem => {
+                if payload.len() > self.protocol.block_size() {
+                    return Err(Error::OutOfBoundsError);
+                }
+                payload.resize(self.protocol.block_size(), SUB);
+                let start = match self.protocol {
+                    Protocol::Xmodem => SOH,
+                    _ => STX,
+                };
+                vec![start, block, !block]
+            }
+        };
+        for segment in self.segments(payload.len())? {
+            frame.resize(segment.at, 0);
+            frame.extend(&payload[segment.payload]);
+            let crc = self.crc.checksum(frame[segment.covered.clone()].bytes())?;
+            frame.extend([0; 2]);
+            self.write_crc(&mut frame, segment.covered.end, crc as u16);
+        }
+        Ok(frame)
+    }
+
+    /// Size of the payload of `frame`, checking its framing.
+    fn payload_size(&self, frame: &[u8]) -> CRCResult<usize> {
+        let size = match self.protocol {
+            Protocol::Modbus => frame.len().checked_sub(2).ok_or(Error::ParseError)?,
+            Protocol::Dnp3 if frame.starts_with(&DNP3_START) && frame.len() > 2 => {
+                usize::from(frame[2])
+            }
+            Protocol::Xmodem if frame.first() == Some(&SOH) => self.protocol.block_size(),
+            Protocol::Ymodem if frame.first() == Some(&STX) => self.protocol.block_size(),
+            _ => return Err(Error::ParseError),
+        };
+        let segments = self.segments(size).map_err(|_| Error::ParseError)?;
+        match segments.last() {
+            Some(last) if last.covered.end + 2 == frame.len() => Ok(size),
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    /// Forge `frame` by patching 2 bytes at offset `offset` of its payload, so that the CRC covering them is
+    /// `target_crc`, or keeps its stored value by default. Other CRCs are left unchanged.
+    pub fn forge(
+        &self,
+        frame: &[u8],
+        offset: usize,
+        target_crc: Option<u16>,
+    ) -> CRCResult<Vec<u8>> {
+        let segments = self.segments(self.payload_size(frame)?)?;
+        let end = offset.checked_add(2).ok_or(Error::OutOfBoundsError)?;
+        let segment = segments
+            .iter()
+            .find(|segment| segment.payload.start <= offset && end <= segment.payload.end)
+            .ok_or(Error::OutOfBoundsError)?;
+        let target_crc = target_crc.unwrap_or(self.read_crc(frame, segment.covered.end));
+        let offset = segment.at + offset - segment.payload.start;
+        let covered = &frame[segment.covered.clone()];
+        let patch = self.crc.compute_patch(
+            covered,
+            offset - segment.covered.start,
+            u64::from(target_crc),
+        )?;
+        let mut frame = frame.to_vec();
+        frame[offset..offset + 2].copy_from_slice(&patch);
+        self.write_crc(&mut frame, segment.covered.end, target_crc);
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frame::{Framer, Protocol};
+    use crate::recover::decode_hex;
+
+    #[test]
+    pub fn test_modbus() {
+        let framer = Framer::new(Protocol::Modbus).unwrap();
+        let frame = framer
+            .frame(&decode_hex("010300000002").unwrap(), 0)
+            .unwrap();
+        assert_eq!(frame, decode_hex("010300000002c40b").unwrap());
+        let forged = framer
+            .forge(&frame, 2, None)
+            .expect("Failed to forge frame");
+        assert_eq!(forged[6..], frame[6..]);
+        assert_eq!(framer.frame(&forged[..6], 0).unwrap(), forged);
+        assert!(framer.forge(&frame, 5, None).is_err());
+        assert!(framer.forge(&frame, usize::MAX, None).is_err());
+    }
+
+    #[test]
+    pub fn test_dnp3() {
+        let framer = Framer::new(Protocol::Dnp3).unwrap();
+        let frame = framer.frame(&decode_hex("c001000004").unwrap(), 0).unwrap();
+        assert_eq!(frame, decode_hex("056405c001000004e921").unwrap());
+
+        // Header and 2 blocks of user data
+        let payload = (0..30).collect::<Vec<u8>>();
+        let frame = framer.frame(&payload, 0).unwrap();
+        assert_eq!(frame.len(), 10 + 18 + 11);
+        assert_eq!(frame[2], 30);
+        let forged = framer
+            .forge(&frame, 25, Some(0x1337))
+            .expect("Failed to forge frame");
+        assert_eq!(forged[37..], [0x37, 0x13]);
+        let mut patched = payload.clone();
+        patched[25..27].copy_from_slice(&forged[32..34]);
+        assert_eq!(framer.frame(&patched, 0).unwrap(), forged);
+        assert!(framer.forge(&frame, 20, None).is_err()); // Across blocks
+    }
+
+    #[test]
+    pub fn test_xmodem() {
+        let framer = Framer::new(Protocol::Xmodem).unwrap();
+        let frame = framer.frame(b"lorem ipsum", 2).unwrap();
+        assert_eq!(frame.len(), 133);
+        assert_eq!(frame[..3], [0x01, 0x02, 0xfd]);
+        assert_eq!(frame[14..131], [0x1a; 117]);
+        let forged = framer.forge(&frame, 6, Some(0xbeef)).unwrap();
+        assert_eq!(forged[131..], [0xbe, 0xef]);
+        assert_eq!(framer.frame(&forged[3..131], 2).unwrap(), forged);
+        assert!(framer.forge(&frame[..132], 6, None).is_err());
+        let framer = Framer::new(Protocol::Ymodem).unwrap();
+        assert_eq!(framer.frame(&[0; 1024], 1).unwrap().len(), 1029);
+        assert!(framer.frame(&[0; 1025], 1).is_err());
+    }
+}
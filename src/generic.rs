@@ -0,0 +1,340 @@
+use std::{fmt::Display, io::Read};
+
+use crate::core::CRC32Properties;
+use crate::error::{CRCResult, Error};
+use crate::math::{reverse_bits, solve_gf2};
+
+const CRC_LOOKUP_SIZE: usize = 0x100;
+
+/// Properties of a CRC of any width up to 64 bits, as in Rocksoft notation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CRCProperties {
+    pub width: u32,   // Width of the CRC register in bits, from 1 to 64
+    pub g: u64, // Generator polynomial with highest term stripped (implicitely of degree `width`)
+    pub i: u64, // I, initial register value
+    pub f: u64, // F, value eventually xored with output
+    pub refin: bool, // Input bytes are processed LSB first
+    pub refout: bool, // Register is reflected before final xor
+}
+
+impl From<CRC32Properties> for CRCProperties {
+    fn from(props: CRC32Properties) -> Self {
+        Self {
+            width: 32,
+            g: u64::from(props.g),
+            i: u64::from(props.i),
+            f: u64::from(props.f),
+            refin: props.refin,
+            refout: props.refout,
+        }
+    }
+}
+
+/// Display properties in Rocksoft notation.
+impl Display for CRCProperties {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.width.div_ceil(4) as usize;
+        write!(
+            f,
+            "width={}  poly=0x{:0digits$x}  init=0x{:0digits$x}  refin={}  refout={}  xorout=0x{:0digits$x}",
+            self.width, self.g, self.i, self.refin, self.refout, self.f
+        )
+    }
+}
+
+/// Table-driven CRC implementation for any width up to 64 bits.
+/// The register is kept in reverse representation, non-reflected CRCs being computed over reflected input bytes.
+#[allow(clippy::upper_case_acronyms)]
+pub struct CRC {
+    props: CRCProperties,
+    init: u64,                     // Initial register, in reverse representation
+    g: u64,                        // Stripped generator polynomial, in reverse representation
+    table: [u64; CRC_LOOKUP_SIZE], // 8 bit lookup side
+}
+
+/// Compute register mask in reverse table at index `index`.
+/// `g` is the (stripped) CRC generator polynomial in reverse representation.
+fn precompute_table(index: u8, g: u64) -> u64 {
+    let mut register = u64::from(index);
+    for _ in 0..8 {
+        let div = register & 1;
+        register >>= 1;
+        if div == 1 {
+            register ^= g;
+        }
+    }
+    register
+}
+
+impl CRC {
+    /// Create CRC instance with properties `props`.
+    pub fn new(props: CRCProperties) -> CRCResult<Self> {
+        if props.width == 0 || props.width > 64 {
+            return Err(Error::OverflowError(None));
+        }
+
+        // Precompute table
+        let g = reverse_bits(props.g, props.width);
+        let mut table = [0u64; CRC_LOOKUP_SIZE];
+        for (i, mask) in table.iter_mut().enumerate() {
+            *mask = precompute_table(i as u8, g);
+        }
+
+        Ok(Self {
+            props,
+            init: reverse_bits(props.i, props.width),
+            g,
+            table,
+        })
+    }
+
+    pub fn props(&self) -> &CRCProperties {
+        &self.props
+    }
+
+    /// Map input byte `b` to its reverse representation, where LSB is the term of highest degree.
+    fn input(&self, b: u8) -> u8 {
+        match self.props.refin {
+            true => b,
+            false => b.reverse_bits(),
+        }
+    }
+
+    /// Map register `reg` to CRC output, applying output reflection and final XOR.
+    fn output(&self, reg: u64) -> u64 {
+        match self.props.refout {
+            true => reg ^ self.props.f,
+            false => reverse_bits(reg, self.props.width) ^ self.props.f,
+        }
+    }
+
+    /// Map CRC value `crc` back to its register, the inverse of `output`.
+    pub fn register(&self, crc: u64) -> u64 {
+        match self.props.refout {
+            true => crc ^ self.props.f,
+            false => reverse_bits(crc ^ self.props.f, self.props.width),
+        }
+    }
+
+    /// Perform a single one-byte division step using table.
+    /// Register is the remainder itself, so that input is added before reduction.
+    fn step(&self, reg: &mut u64, next_byte: u8) {
+        let index = (*reg ^ u64::from(next_byte)) & 0xff;
+        *reg = self.table[index as usize] ^ (*reg >> 8);
+    }
+
+    /// Efficiently compute remainder of data times X^N by generator polynomial using precomputed tables.
+    /// `data` is the data to process and `reg` is the initial CRC register.
+    fn fast_rem<T>(&self, data: T, mut reg: u64) -> CRCResult<u64>
+    where
+        T: Iterator<Item = std::io::Result<u8>>,
+    {
+        for b in data {
+            self.step(&mut reg, self.input(b?));
+        }
+        Ok(reg)
+    }
+
+    /// Efficiently compute CRC checksum of `data` using precomputed tables.
+    pub fn checksum<T>(&self, data: T) -> CRCResult<u64>
+    where
+        T: Iterator<Item = std::io::Result<u8>>,
+    {
+        Ok(self.output(self.fast_rem(data, self.init)?))
+    }
+
+    /*
+     * Combination of registers
+     */
+
+    /// Initial register value, in reverse representation.
+    pub fn init(&self) -> u64 {
+        self.init
+    }
+
+    /// Compute registers of all prefixes of `data`, starting from register `reg`, so that `data.len() + 1` registers are returned.
+    /// With a zero initial register, register `P[s]` is the remainder of the `s` first bytes times X^N,
+    /// and the register of any range `[a, b)` starting from register `I` is `P[b] + (P[a] + I) X^8(b-a)` mod G.
+    pub fn prefix_registers(&self, data: &[u8], mut reg: u64) -> CRCResult<Vec<u64>> {
+        let mut registers = Vec::with_capacity(data.len() + 1);
+        registers.push(reg);
+        for b in data {
+            reg = self.fast_rem([Ok(*b)].into_iter(), reg)?;
+            registers.push(reg);
+        }
+        Ok(registers)
+    }
+
+    /// Register of the constant polynomial 1.
+    pub fn one(&self) -> u64 {
+        1 << (self.props.width - 1)
+    }
+
+    /// Multiply registers `a` and `b` mod G.
+    pub fn multiply(&self, a: u64, mut b: u64) -> u64 {
+        let mut res = 0;
+        // Step through terms of `a` from X^0 to X^(N-1), while `b` is multiplied by X
+        let mut m = self.one();
+        while m != 0 {
+            if a & m != 0 {
+                res ^= b;
+            }
+            m >>= 1;
+            b = match b & 1 {
+                1 => (b >> 1) ^ self.g,
+                _ => b >> 1,
+            };
+        }
+        res
+    }
+
+    /// Compute register of X^8n mod G with exponentiation by squaring.
+    pub fn x8n(&self, mut n: usize) -> u64 {
+        // X^8 is the register of 1 after a zero byte
+        let mut x8 = self.one();
+        self.step(&mut x8, 0);
+        let mut res = self.one();
+        while n != 0 {
+            if n & 1 == 1 {
+                res = self.multiply(res, x8);
+            }
+            x8 = self.multiply(x8, x8);
+            n >>= 1;
+        }
+        res
+    }
+
+    /// Compute X^-8 mod G, which exists as long as G has a constant term.
+    pub fn x8_inv(&self) -> CRCResult<u64> {
+        if self.props.g & 1 == 0 {
+            return Err(Error::NonInvertibleError);
+        }
+        // X (X^(N-1) + (G - 1 - X^N) / X) = G - 1 = 1 mod G
+        let x_inv = reverse_bits(
+            (self.props.g >> 1) | (1 << (self.props.width - 1)),
+            self.props.width,
+        );
+        Ok((0..3).fold(x_inv, |x, _| self.multiply(x, x)))
+    }
+
+    /*
+     * Patched data computation
+     */
+
+    /// Compute the `width / 8` bytes (rounded up) overwriting `data` at offset `offset` so that resulting CRC is
+    /// `target_crc`.
+    /// The CRC is an affine function of the patched bits, the effect of each bit only depending on the number of
+    /// bytes following it: a linear system over F2 gives the patch.
+    pub fn compute_patch(&self, data: &[u8], offset: usize, target_crc: u64) -> CRCResult<Vec<u8>> {
+        let size = self.props.width.div_ceil(8) as usize;
+        let end = offset
+            .checked_add(size)
+            .filter(|end| *end <= data.len())
+            .ok_or(Error::OutOfBoundsError)?;
+        let mut zeroed = data.to_vec();
+        zeroed[offset..end].fill(0);
+        let crc0 = self.checksum(zeroed.bytes())?;
+
+        // Register of each bit from a null register, times X^8 for each following byte
+        let tail = self.x8n(data.len() - end);
+        let columns = (0..8 * size)
+            .map(|j| {
+                let mut bytes = vec![0; size];
+                bytes[j / 8] = 1 << (j % 8);
+                let reg = self.multiply(self.fast_rem(bytes.bytes(), 0)?, tail);
+                Ok(self.output(reg) ^ self.output(0))
+            })
+            .collect::<CRCResult<Vec<_>>>()?;
+        let x = solve_gf2(&columns, target_crc ^ crc0).ok_or(Error::NonInvertibleError)?;
+        Ok((0..size).map(|i| (x >> (8 * i)) as u8).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::catalogue::find;
+    use crate::core::{CRC32, CRC32Properties};
+    use crate::generic::{CRC, CRCProperties};
+
+    #[test]
+    pub fn test_crc32_equivalent() {
+        let crc32 = CRC32::new(CRC32Properties::default()).unwrap();
+        let crc = CRC::new(CRC32Properties::default().into()).unwrap();
+        let data = b"lorem ipsum".to_owned();
+        assert_eq!(
+            crc.checksum(data.bytes())
+                .expect("Failed to compute checksum"),
+            u64::from(
+                crc32
+                    .checksum(data.bytes())
+                    .expect("Failed to compute checksum")
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_small_width() {
+        // CRC-5/USB
+        let crc = CRC::new(CRCProperties {
+            width: 5,
+            g: 0x05,
+            i: 0x1f,
+            f: 0x1f,
+            refin: true,
+            refout: true,
+        })
+        .unwrap();
+        assert_eq!(
+            crc.checksum(b"123456789".to_owned().bytes())
+                .expect("Failed to compute checksum"),
+            0x19
+        );
+    }
+
+    #[test]
+    pub fn test_combine() {
+        let crc = CRC::new(find("CRC-16/XMODEM").unwrap().props).unwrap();
+        let data = b"lorem ipsum dolor sit amet";
+        let p = crc.prefix_registers(data, 0).unwrap();
+        assert_eq!(p.len(), data.len() + 1);
+
+        // Register of range [a, b) is P[b] + (P[a] + I) X^8(b-a), hence (P[a] + I) X^-8a = (R + P[b]) X^-8b
+        let (a, b) = (6, 17);
+        let r = crc.register(crc.checksum(data[a..b].bytes()).unwrap());
+        let x8_inv = crc.x8_inv().unwrap();
+        let pow = |n| (0..n).fold(crc.one(), |x, _| crc.multiply(x, x8_inv));
+        assert_eq!(crc.multiply(pow(a), crc.x8n(a)), crc.one());
+        assert_eq!(
+            crc.multiply(p[a] ^ crc.init(), pow(a)),
+            crc.multiply(r ^ p[b], pow(b))
+        );
+    }
+
+    #[test]
+    pub fn test_patch() {
+        let mut data = b"lorem ipsum dolor sit amet".to_vec();
+        for name in ["CRC-64/XZ", "CRC-16/XMODEM", "CRC-8/DARC"] {
+            let crc = CRC::new(find(name).unwrap().props).unwrap();
+            let target = 0x4242424242424242 >> (64 - crc.props().width);
+            let patch = crc.compute_patch(&data, 6, target).unwrap();
+            data[6..6 + patch.len()].copy_from_slice(&patch);
+            assert_eq!(crc.checksum(data.bytes()).unwrap(), target);
+            assert!(crc.compute_patch(&data, data.len(), 0).is_err());
+        }
+    }
+
+    #[test]
+    pub fn test_bad_width() {
+        let props = CRCProperties {
+            width: 65,
+            g: 0,
+            i: 0,
+            f: 0,
+            refin: true,
+            refout: true,
+        };
+        assert!(CRC::new(props).is_err());
+    }
+}
@@ -1,15 +1,48 @@
 use core::{CRC32, CRC32Properties};
+pub use core::{mask, unmask};
+use detect::Detection;
+use formats::btrfs::{Btrfs, Field as BtrfsField};
+use formats::bzip2::{Bzip2, Stream};
+use formats::flac::{Flac, Frame as FlacFrame};
+use formats::fsimage::{Field as FsField, FsImage};
+use formats::gpt::{Gpt, Header};
+use formats::gzip::{Gzip, Member};
+use formats::hdlc::{Frame as HdlcFrame, Hdlc};
+use formats::kafka::{Batch, Kafka};
+use formats::mkv::{Field as MkvField, Mkv};
+use formats::ogg::{Ogg, Page};
+use formats::pcap::{Capture, Frame};
+use formats::png::{Chunk, Png};
+use formats::rar5::{Block, Rar5};
+pub use formats::records::Format as RecordsFormat;
+use formats::records::{Field as RecordField, Records};
+use formats::sevenzip::{Field as SevenZipField, SevenZip};
+use formats::ts::{Section, Ts};
+use formats::uimage::{Image, UImage};
+use formats::xz::{Field, Xz};
+use formats::zip::{Entry, Zip};
+use frame::Framer;
+pub use frame::Protocol;
+use recover::RecoveredModel;
+use scan::Location;
 use std::{
     fs::File,
     io::{BufReader, BufWriter, Read, Seek, Write},
     path::PathBuf,
 };
 
+pub mod catalogue;
 mod core;
+mod detect;
 pub mod error;
+mod formats;
+mod frame;
+pub mod generic;
 mod math;
+mod recover;
+mod scan;
 
-use error::CRCResult;
+use error::{CRCResult, Error};
 
 const BUF_SIZE: usize = 0x1000;
 
@@ -26,10 +59,7 @@ pub fn force_crc_append(
         g: generator,
         ..Default::default()
     })?;
-    let suffix = crc.compute_suffix(
-        reader.bytes().map(|res| res.map_err(std::io::Error::into)),
-        target_crc,
-    )?;
+    let suffix = crc.compute_suffix(reader.bytes(), target_crc)?;
 
     // Then copy original file to output file and append suffix
     let output_file = File::create(output_path)?;
@@ -63,11 +93,7 @@ pub fn force_crc_insert(
         g: generator,
         ..Default::default()
     })?;
-    let inserted_bytes = crc.compute_inserted(
-        reader.bytes().map(|res| res.map_err(std::io::Error::into)),
-        offset,
-        target_crc,
-    )?;
+    let inserted_bytes = crc.compute_inserted(reader.bytes(), offset, target_crc)?;
 
     // Copy prefix
     let output_file = File::create(output_path)?;
@@ -98,3 +124,602 @@ pub fn force_crc_insert(
 
     Ok(())
 }
+
+/// Read the whole input file.
+fn read_input(input_file: &File) -> CRCResult<Vec<u8>> {
+    let mut reader = BufReader::new(input_file);
+    reader.seek(std::io::SeekFrom::Start(0))?;
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Write `data` to output file.
+fn write_output(output_path: &PathBuf, data: &[u8]) -> CRCResult<()> {
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    writer.write_all(data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Copy input file to output file, returning the output file opened for reading and writing.
+/// Large files are streamed rather than read to memory.
+/// The copy is made to a temporary file renamed to the output path, which may thus be the input path.
+fn copy_output(input_file: &File, output_path: &PathBuf) -> CRCResult<File> {
+    let mut temp_path = output_path.clone().into_os_string();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let output_file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp_path)?;
+    let copy = || -> CRCResult<()> {
+        let mut reader = BufReader::new(input_file);
+        reader.seek(std::io::SeekFrom::Start(0))?;
+        let mut writer = BufWriter::new(&output_file);
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+        Ok(std::fs::rename(&temp_path, output_path)?)
+    };
+    if let Err(err) = copy() {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    Ok(output_file)
+}
+
+pub fn recover_models(input_file: &File) -> CRCResult<Vec<RecoveredModel>> {
+    let samples = recover::parse_samples(BufReader::new(input_file))?;
+    recover::recover_models(&samples)
+}
+
+pub fn solve_models(
+    input_file: &File,
+    generator: u32,
+    refin: bool,
+    refout: bool,
+) -> CRCResult<Vec<CRC32Properties>> {
+    let samples = recover::parse_samples(BufReader::new(input_file))?;
+    recover::solve_init_xorout(&samples, generator, refin, refout)
+}
+
+pub fn detect_models(input_file: &File, trailer: Option<usize>) -> CRCResult<Vec<Detection>> {
+    let frames = detect::parse_frames(BufReader::new(input_file))?;
+    detect::detect(&frames, trailer)
+}
+
+pub fn analyze_frames(input_file: &File) -> CRCResult<Vec<Detection>> {
+    let data = read_input(input_file)?;
+    let frames = match formats::pcap::is_pcap(&data) {
+        true => formats::pcap::parse_pcap(&data)?,
+        false => detect::parse_frames(&data[..])?,
+    };
+    detect::analyze(&frames)
+}
+
+pub fn frame_build(input_file: &File, protocol: Protocol, block: u8) -> CRCResult<Vec<Vec<u8>>> {
+    let framer = Framer::new(protocol)?;
+    let payloads = detect::parse_frames(BufReader::new(input_file))?;
+    payloads
+        .iter()
+        .map(|payload| framer.frame(payload, block))
+        .collect()
+}
+
+pub fn frame_forge(
+    input_file: &File,
+    protocol: Protocol,
+    offset: usize,
+    target_crc: Option<u16>,
+) -> CRCResult<Vec<Vec<u8>>> {
+    let framer = Framer::new(protocol)?;
+    let frames = detect::parse_frames(BufReader::new(input_file))?;
+    frames
+        .iter()
+        .map(|frame| framer.forge(frame, offset, target_crc))
+        .collect()
+}
+
+pub fn hdlc_frames(input_file: &File, fcs: u32) -> CRCResult<Vec<HdlcFrame>> {
+    let hdlc = Hdlc::parse(read_input(input_file)?, fcs, formats::hdlc::DEFAULT_ACCM)?;
+    Ok(hdlc.frames().to_vec())
+}
+
+pub fn hdlc_repair(
+    input_file: &File,
+    output_path: &PathBuf,
+    fcs: u32,
+    accm: u32,
+) -> CRCResult<usize> {
+    let mut hdlc = Hdlc::parse(read_input(input_file)?, fcs, accm)?;
+    let repaired = hdlc.repair()?;
+    write_output(output_path, hdlc.data())?;
+    Ok(repaired)
+}
+
+pub fn hdlc_forge(
+    input_file: &File,
+    output_path: &PathBuf,
+    fcs: u32,
+    accm: u32,
+    index: usize,
+    offset: usize,
+    target_crc: Option<u32>,
+) -> CRCResult<()> {
+    let mut hdlc = Hdlc::parse(read_input(input_file)?, fcs, accm)?;
+    hdlc.forge(index, offset, target_crc)?;
+    write_output(output_path, hdlc.data())
+}
+
+pub fn scan_file(
+    input_file: &File,
+    width: u32,
+    model: Option<&str>,
+    align: usize,
+) -> CRCResult<Vec<Location>> {
+    let models = match model {
+        Some(name) => vec![catalogue::find(name).ok_or(Error::UnknownModelError)?],
+        None => catalogue::CATALOGUE
+            .iter()
+            .filter(|model| model.props.width == width)
+            .collect(),
+    };
+    let data = read_input(input_file)?;
+
+    let mut locations = Vec::new();
+    for model in models {
+        locations.extend(scan::scan(&data, model, align)?);
+    }
+    locations.sort_by_key(|location| location.offset);
+    Ok(locations)
+}
+
+pub fn png_chunks(input_file: &File) -> CRCResult<Vec<Chunk>> {
+    let png = Png::parse(read_input(input_file)?)?;
+    Ok(png.chunks().to_vec())
+}
+
+pub fn png_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut png = Png::parse(read_input(input_file)?)?;
+    let repaired = png.repair();
+    write_output(output_path, png.data())?;
+    Ok(repaired)
+}
+
+pub fn png_forge_chunk(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut png = Png::parse(read_input(input_file)?)?;
+    png.forge_chunk(index, offset, target_crc)?;
+    write_output(output_path, png.data())
+}
+
+pub fn png_forge_file(
+    input_file: &File,
+    output_path: &PathBuf,
+    target_crc: u32,
+    text: bool,
+) -> CRCResult<()> {
+    let mut png = Png::parse(read_input(input_file)?)?;
+    png.forge_file(target_crc, text)?;
+    write_output(output_path, png.data())
+}
+
+pub fn zip_entries(input_file: &File) -> CRCResult<Vec<Entry>> {
+    let zip = Zip::parse(read_input(input_file)?)?;
+    Ok(zip.entries().to_vec())
+}
+
+pub fn zip_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut zip = Zip::parse(read_input(input_file)?)?;
+    let repaired = zip.repair();
+    write_output(output_path, zip.data())?;
+    Ok(repaired)
+}
+
+pub fn zip_forge_entry(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut zip = Zip::parse(read_input(input_file)?)?;
+    zip.forge_entry(index, offset, target_crc)?;
+    write_output(output_path, zip.data())
+}
+
+pub fn sevenzip_fields(input_file: &File) -> CRCResult<Vec<SevenZipField>> {
+    let archive = SevenZip::parse(read_input(input_file)?)?;
+    Ok(archive.fields().to_vec())
+}
+
+pub fn sevenzip_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut archive = SevenZip::parse(read_input(input_file)?)?;
+    let repaired = archive.repair()?;
+    write_output(output_path, archive.data())?;
+    Ok(repaired)
+}
+
+pub fn sevenzip_forge_file(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut archive = SevenZip::parse(read_input(input_file)?)?;
+    archive.forge_file(index, offset, target_crc)?;
+    write_output(output_path, archive.data())
+}
+
+pub fn rar5_blocks(input_file: &File) -> CRCResult<Vec<Block>> {
+    let rar = Rar5::parse(read_input(input_file)?)?;
+    Ok(rar.blocks().to_vec())
+}
+
+pub fn rar5_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut rar = Rar5::parse(read_input(input_file)?)?;
+    let repaired = rar.repair()?;
+    write_output(output_path, rar.data())?;
+    Ok(repaired)
+}
+
+pub fn rar5_forge_data(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut rar = Rar5::parse(read_input(input_file)?)?;
+    rar.forge_data(index, offset, target_crc)?;
+    write_output(output_path, rar.data())
+}
+
+pub fn records_fields(
+    input_file: &File,
+    format: Option<RecordsFormat>,
+) -> CRCResult<(RecordsFormat, Vec<RecordField>)> {
+    let records = Records::parse(read_input(input_file)?, format)?;
+    Ok((records.format(), records.fields().to_vec()))
+}
+
+pub fn records_repair(
+    input_file: &File,
+    output_path: &PathBuf,
+    format: Option<RecordsFormat>,
+) -> CRCResult<usize> {
+    let mut records = Records::parse(read_input(input_file)?, format)?;
+    let repaired = records.repair();
+    write_output(output_path, records.data())?;
+    Ok(repaired)
+}
+
+pub fn records_forge(
+    input_file: &File,
+    output_path: &PathBuf,
+    format: Option<RecordsFormat>,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut records = Records::parse(read_input(input_file)?, format)?;
+    records.forge(index, offset, target_crc)?;
+    write_output(output_path, records.data())
+}
+
+pub fn kafka_batches(input_file: &File) -> CRCResult<Vec<Batch>> {
+    let kafka = Kafka::parse(read_input(input_file)?)?;
+    Ok(kafka.batches().to_vec())
+}
+
+pub fn kafka_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut kafka = Kafka::parse(read_input(input_file)?)?;
+    let repaired = kafka.repair();
+    write_output(output_path, kafka.data())?;
+    Ok(repaired)
+}
+
+pub fn kafka_forge(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut kafka = Kafka::parse(read_input(input_file)?)?;
+    kafka.forge(index, target_crc)?;
+    write_output(output_path, kafka.data())
+}
+
+pub fn gzip_members(input_file: &File) -> CRCResult<Vec<Member>> {
+    let gzip = Gzip::parse(read_input(input_file)?)?;
+    Ok(gzip.members().to_vec())
+}
+
+pub fn gzip_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut gzip = Gzip::parse(read_input(input_file)?)?;
+    let repaired = gzip.repair();
+    write_output(output_path, gzip.data())?;
+    Ok(repaired)
+}
+
+pub fn gzip_forge_payload(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut gzip = Gzip::parse(read_input(input_file)?)?;
+    gzip.forge_payload(index, offset, target_crc)?;
+    write_output(output_path, gzip.data())
+}
+
+pub fn gzip_forge_header(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    target_crc: u16,
+    comment: bool,
+) -> CRCResult<()> {
+    let mut gzip = Gzip::parse(read_input(input_file)?)?;
+    gzip.forge_header(index, target_crc, comment)?;
+    write_output(output_path, gzip.data())
+}
+
+pub fn ogg_pages(input_file: &File) -> CRCResult<Vec<Page>> {
+    let ogg = Ogg::parse(read_input(input_file)?)?;
+    Ok(ogg.pages().to_vec())
+}
+
+pub fn ogg_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut ogg = Ogg::parse(read_input(input_file)?)?;
+    let repaired = ogg.repair();
+    write_output(output_path, ogg.data())?;
+    Ok(repaired)
+}
+
+pub fn ogg_forge_page(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut ogg = Ogg::parse(read_input(input_file)?)?;
+    ogg.forge_page(index, offset, target_crc)?;
+    write_output(output_path, ogg.data())
+}
+
+pub fn flac_frames(input_file: &File) -> CRCResult<Vec<FlacFrame>> {
+    let flac = Flac::parse(read_input(input_file)?)?;
+    Ok(flac.frames().to_vec())
+}
+
+pub fn flac_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut flac = Flac::parse(read_input(input_file)?)?;
+    let repaired = flac.repair()?;
+    write_output(output_path, flac.data())?;
+    Ok(repaired)
+}
+
+pub fn mkv_fields(input_file: &File) -> CRCResult<Vec<MkvField>> {
+    let mkv = Mkv::parse(read_input(input_file)?)?;
+    Ok(mkv.fields().to_vec())
+}
+
+pub fn mkv_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut mkv = Mkv::parse(read_input(input_file)?)?;
+    let repaired = mkv.repair()?;
+    write_output(output_path, mkv.data())?;
+    Ok(repaired)
+}
+
+pub fn mkv_forge(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut mkv = Mkv::parse(read_input(input_file)?)?;
+    mkv.forge(index, target_crc)?;
+    write_output(output_path, mkv.data())
+}
+
+pub fn ts_sections(input_file: &File) -> CRCResult<Vec<Section>> {
+    let ts = Ts::parse(read_input(input_file)?)?;
+    Ok(ts.sections().to_vec())
+}
+
+pub fn ts_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut ts = Ts::parse(read_input(input_file)?)?;
+    let repaired = ts.repair();
+    write_output(output_path, ts.data())?;
+    Ok(repaired)
+}
+
+pub fn ts_forge_section(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut ts = Ts::parse(read_input(input_file)?)?;
+    ts.forge_section(index, offset, target_crc)?;
+    write_output(output_path, ts.data())
+}
+
+pub fn bzip2_streams(input_file: &File) -> CRCResult<Vec<Stream>> {
+    let bzip2 = Bzip2::parse(read_input(input_file)?)?;
+    Ok(bzip2.streams().to_vec())
+}
+
+pub fn bzip2_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut bzip2 = Bzip2::parse(read_input(input_file)?)?;
+    let repaired = bzip2.repair()?;
+    write_output(output_path, bzip2.data())?;
+    Ok(repaired)
+}
+
+pub fn bzip2_forge_stream(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    block: Option<usize>,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut bzip2 = Bzip2::parse(read_input(input_file)?)?;
+    bzip2.forge_stream(index, block, offset, target_crc)?;
+    write_output(output_path, bzip2.data())
+}
+
+pub fn pcap_frames(input_file: &File, fcs: bool) -> CRCResult<Vec<Frame>> {
+    let capture = Capture::parse(read_input(input_file)?, fcs)?;
+    Ok(capture.frames().to_vec())
+}
+
+pub fn pcap_repair(input_file: &File, output_path: &PathBuf, fcs: bool) -> CRCResult<usize> {
+    let mut capture = Capture::parse(read_input(input_file)?, fcs)?;
+    let repaired = capture.repair();
+    write_output(output_path, capture.data())?;
+    Ok(repaired)
+}
+
+pub fn pcap_forge_frame(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    fcs: bool,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut capture = Capture::parse(read_input(input_file)?, fcs)?;
+    capture.forge_frame(index, offset, target_crc)?;
+    write_output(output_path, capture.data())
+}
+
+pub fn gpt_headers(input_file: &File) -> CRCResult<Vec<Header>> {
+    let gpt = Gpt::parse(input_file)?;
+    Ok(gpt.headers().to_vec())
+}
+
+pub fn gpt_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let output_file = copy_output(input_file, output_path)?;
+    Gpt::parse(&output_file)?.repair()
+}
+
+pub fn gpt_forge_header(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let output_file = copy_output(input_file, output_path)?;
+    Gpt::parse(&output_file)?.forge_header(index, target_crc)
+}
+
+pub fn xz_fields(input_file: &File) -> CRCResult<Vec<Field>> {
+    let xz = Xz::parse(read_input(input_file)?)?;
+    Ok(xz.fields().to_vec())
+}
+
+pub fn xz_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut xz = Xz::parse(read_input(input_file)?)?;
+    let repaired = xz.repair();
+    write_output(output_path, xz.data())?;
+    Ok(repaired)
+}
+
+pub fn xz_forge_check(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    offset: Option<usize>,
+    target_crc: u64,
+) -> CRCResult<()> {
+    let mut xz = Xz::parse(read_input(input_file)?)?;
+    xz.forge_check(index, offset, target_crc)?;
+    write_output(output_path, xz.data())
+}
+
+pub fn uimage_image(input_file: &File) -> CRCResult<Image> {
+    let uimage = UImage::parse(read_input(input_file)?)?;
+    Ok(uimage.image().clone())
+}
+
+pub fn uimage_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let mut uimage = UImage::parse(read_input(input_file)?)?;
+    let repaired = uimage.repair()?;
+    write_output(output_path, uimage.data())?;
+    Ok(repaired)
+}
+
+pub fn uimage_forge_data(
+    input_file: &File,
+    output_path: &PathBuf,
+    offset: Option<usize>,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut uimage = UImage::parse(read_input(input_file)?)?;
+    uimage.forge_data(offset, target_crc)?;
+    write_output(output_path, uimage.data())
+}
+
+pub fn uimage_forge_header(
+    input_file: &File,
+    output_path: &PathBuf,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let mut uimage = UImage::parse(read_input(input_file)?)?;
+    uimage.forge_header(target_crc)?;
+    write_output(output_path, uimage.data())
+}
+
+pub fn fsimage_fields(input_file: &File) -> CRCResult<Vec<FsField>> {
+    let fs = FsImage::parse(input_file)?;
+    Ok(fs.fields().to_vec())
+}
+
+pub fn fsimage_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let output_file = copy_output(input_file, output_path)?;
+    FsImage::parse(&output_file)?.repair()
+}
+
+pub fn fsimage_forge(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let output_file = copy_output(input_file, output_path)?;
+    FsImage::parse(&output_file)?.forge(index, target_crc)
+}
+
+pub fn btrfs_fields(input_file: &File) -> CRCResult<Vec<BtrfsField>> {
+    let btrfs = Btrfs::parse(input_file)?;
+    Ok(btrfs.fields().to_vec())
+}
+
+pub fn btrfs_repair(input_file: &File, output_path: &PathBuf) -> CRCResult<usize> {
+    let output_file = copy_output(input_file, output_path)?;
+    Btrfs::parse(&output_file)?.repair()
+}
+
+pub fn btrfs_forge(
+    input_file: &File,
+    output_path: &PathBuf,
+    index: usize,
+    target_crc: u32,
+) -> CRCResult<()> {
+    let output_file = copy_output(input_file, output_path)?;
+    Btrfs::parse(&output_file)?.forge(index, target_crc)
+}
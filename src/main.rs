@@ -1,5 +1,6 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, error::ErrorKind};
 use crc_forge::error::{CRCResult, Error};
+use crc_forge::{Protocol, RecordsFormat};
 use std::{fs::File, path::PathBuf};
 
 #[derive(Parser)]
@@ -14,11 +15,11 @@ struct Cli {
     output_file: Option<PathBuf>,
 
     /// Target crc
-    #[arg(short, long, value_parser = hex_arg_parser)]
-    target_crc: u32,
+    #[arg(short, long, value_parser = hex_arg_parser::<u64>)]
+    target_crc: Option<u64>,
 
     /// Generator polynomial
-    #[arg(short, long, default_value_t = 0x04c11db7u32, value_parser = hex_arg_parser)]
+    #[arg(short, long, default_value_t = 0x04c11db7u32, value_parser = hex_arg_parser::<u32>)]
     generator: u32,
 
     /// Turn debugging information on
@@ -29,27 +30,527 @@ struct Cli {
     command: Command,
 }
 
-fn hex_arg_parser(arg: &str) -> Result<u32, clap::error::Error> {
+fn hex_arg_parser<T: TryFrom<u64>>(arg: &str) -> Result<T, clap::error::Error> {
     let parsed = match arg.strip_prefix("0x") {
-        Some(arg) => u32::from_str_radix(arg, 0x10),
-        None => u32::from_str_radix(arg, 10),
+        Some(arg) => u64::from_str_radix(arg, 0x10),
+        None => arg.parse::<u64>(),
     };
-    parsed.map_err(|_| clap::error::Error::new(clap::error::ErrorKind::InvalidValue))
+    parsed
+        .ok()
+        .and_then(|parsed| T::try_from(parsed).ok())
+        .ok_or(clap::error::Error::new(
+            clap::error::ErrorKind::InvalidValue,
+        ))
 }
 
 #[derive(Subcommand)]
 enum Command {
     /// Appends 4 bytes at end of file to match target CRC
-    Append,
+    Append {
+        /// Target CRC is masked, as stored CRC32C in LevelDB, Snappy framing and TFRecord files
+        #[arg(long)]
+        masked: bool,
+    },
     /// Inserts 4 bytes at given offset to match target CRC
-    Insert { offset: usize },
+    Insert {
+        offset: usize,
+        /// Target CRC is masked, as stored CRC32C in LevelDB, Snappy framing and TFRecord files
+        #[arg(long)]
+        masked: bool,
+    },
+    /// Recovers CRC models from samples in input file, one `<message hex> <crc hex>` per line
+    Recover,
+    /// Solves CRC32 init and final XOR values from samples in input file, given the generator
+    Solve {
+        /// Input bytes are reflected
+        #[arg(long, default_value_t = true, action = ArgAction::Set)]
+        refin: bool,
+        /// Output register is reflected
+        #[arg(long, default_value_t = true, action = ArgAction::Set)]
+        refout: bool,
+    },
+    /// Detects CRC width, byte order and model from frames in input file, one hex string per line
+    Detect {
+        /// Number of bytes following the CRC field in frames (all small values are tried by default)
+        #[arg(long)]
+        trailer: Option<usize>,
+    },
+    /// Analyzes frames in input file (one hex string per line, or a pcap or pcapng file) to find their CRC field and model
+    Analyze,
+    /// Verifies, repairs and forges CRCs of PNG chunks
+    Png {
+        #[command(subcommand)]
+        command: PngCommand,
+    },
+    /// Verifies, repairs and forges CRCs of ZIP archive members
+    Zip {
+        #[command(subcommand)]
+        command: ZipCommand,
+    },
+    /// Verifies, repairs and forges start header, next header and file CRCs of 7z archives
+    #[command(name = "7z")]
+    SevenZip {
+        #[command(subcommand)]
+        command: SevenZipCommand,
+    },
+    /// Verifies, repairs and forges header and stored file CRCs of RAR5 archives
+    Rar5 {
+        #[command(subcommand)]
+        command: Rar5Command,
+    },
+    /// Verifies, repairs and forges CRCs of gzip members
+    Gzip {
+        #[command(subcommand)]
+        command: GzipCommand,
+    },
+    /// Verifies, repairs and forges CRCs of Ogg pages
+    Ogg {
+        #[command(subcommand)]
+        command: OggCommand,
+    },
+    /// Verifies and repairs header CRC-8 and frame CRC-16 of FLAC frames
+    Flac {
+        #[command(subcommand)]
+        command: FlacCommand,
+    },
+    /// Verifies, repairs and forges CRC-32 elements of Matroska and WebM files
+    Mkv {
+        #[command(subcommand)]
+        command: MkvCommand,
+    },
+    /// Verifies, repairs and forges masked CRC32C of LevelDB and RocksDB logs, Snappy framed streams and TFRecord files
+    Records {
+        /// Format of the file, rocksdb being an alias of leveldb (detected by default)
+        #[arg(long, ignore_case = true)]
+        format: Option<RecordsFormat>,
+        #[command(subcommand)]
+        command: RecordsCommand,
+    },
+    /// Verifies, repairs and forges CRC32C of Kafka record batches in log segments
+    Kafka {
+        #[command(subcommand)]
+        command: KafkaCommand,
+    },
+    /// Verifies, repairs and forges CRCs of MPEG transport stream PSI sections
+    Ts {
+        #[command(subcommand)]
+        command: TsCommand,
+    },
+    /// Verifies, repairs and forges CRCs of GUID partition tables in disk images
+    Gpt {
+        #[command(subcommand)]
+        command: GptCommand,
+    },
+    /// Verifies, repairs and forges FCS of Ethernet frames in pcap and pcapng captures
+    Pcap {
+        /// Assume Ethernet frames end with a FCS when the capture does not tell
+        #[arg(long)]
+        fcs: bool,
+        #[command(subcommand)]
+        command: PcapCommand,
+    },
+    /// Verifies, repairs and forges block and combined CRCs of bzip2 streams
+    Bzip2 {
+        #[command(subcommand)]
+        command: Bzip2Command,
+    },
+    /// Verifies, repairs and forges header, index and block check CRCs of xz files
+    Xz {
+        #[command(subcommand)]
+        command: XzCommand,
+    },
+    /// Verifies, repairs and forges header and data CRCs of legacy U-Boot images
+    Uimage {
+        #[command(subcommand)]
+        command: UimageCommand,
+    },
+    /// Verifies, repairs and forges metadata checksums of ext4 and XFS filesystem images
+    ///
+    /// XFS checksums are only checked for superblocks and allocation group headers (AGF, AGI and AGFL): inodes,
+    /// directory, attribute and btree blocks are not.
+    Fsimage {
+        #[command(subcommand)]
+        command: FsimageCommand,
+    },
+    /// Verifies, repairs and forges superblock and tree block checksums of btrfs filesystem images
+    Btrfs {
+        #[command(subcommand)]
+        command: BtrfsCommand,
+    },
+    /// Frames Modbus RTU, DNP3, XMODEM and YMODEM payloads in input file, one hex string per line, with their CRCs
+    Frame {
+        #[command(subcommand)]
+        command: FrameCommand,
+    },
+    /// Verifies, repairs and forges FCS of byte-stuffed HDLC frames, as sent by PPP over serial lines
+    Hdlc {
+        /// Width of the FCS: 16 or 32
+        #[arg(long, default_value_t = 16)]
+        fcs: u32,
+        /// Async control character map of control characters escaped when stuffing rewritten bytes
+        #[arg(long, default_value = "0xffffffff", value_parser = hex_arg_parser::<u32>)]
+        accm: u32,
+        #[command(subcommand)]
+        command: HdlcCommand,
+    },
+    /// Scans input file for CRC fields computed over some range of the file
+    Scan {
+        /// Width in bits of the catalogue models to try
+        #[arg(long, default_value_t = 32)]
+        width: u32,
+        /// Catalogue model to try, instead of all models of given width
+        #[arg(long)]
+        model: Option<String>,
+        /// Alignment of CRC fields and range starts, in bytes
+        #[arg(long, default_value_t = 1)]
+        align: usize,
+    },
 }
 
-fn main() -> CRCResult<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum PngCommand {
+    /// Lists chunks and checks their CRC
+    Verify,
+    /// Rewrites invalid chunk CRCs
+    Repair,
+    /// Patches 4 bytes of chunk data so that the chunk CRC matches target CRC
+    ForgeChunk {
+        /// Index of the chunk, starting from 0
+        index: usize,
+        /// Offset of patched bytes in chunk data (defaults to the last 4 bytes)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+    /// Adjusts an ancillary chunk, inserted if needed, so that the whole file CRC matches target CRC
+    ForgeFile {
+        /// Use a tEXt chunk instead of a private one
+        #[arg(long)]
+        text: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ZipCommand {
+    /// Lists members and checks their CRC fields
+    Verify,
+    /// Rewrites stale CRC fields of members
+    Repair,
+    /// Patches 4 bytes of stored member data so that its content CRC matches target CRC
+    Forge {
+        /// Index of the member in central directory, starting from 0
+        index: usize,
+        /// Offset of patched bytes in member data (defaults to the last 4 bytes)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SevenZipCommand {
+    /// Lists CRC fields, from the start header to file CRCs, and checks them
+    Verify,
+    /// Rewrites invalid CRCs, file CRCs first, then the next header and start header CRCs
+    Repair,
+    /// Patches 4 bytes of a file stored with the copy method so that its CRC matches target CRC
+    Forge {
+        /// Index of the file in archive, starting from 0
+        index: usize,
+        /// Offset of patched bytes in file content (defaults to the last 4 bytes)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Rar5Command {
+    /// Lists blocks and checks their header CRC and stored data CRC
+    Verify,
+    /// Rewrites invalid data CRCs of stored files, then header CRCs of edited blocks
+    Repair,
+    /// Patches 4 bytes of a stored file so that its data CRC matches target CRC
+    Forge {
+        /// Index of the block in archive, starting from 0 with the main header
+        index: usize,
+        /// Offset of patched bytes in file data (defaults to the last 4 bytes)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum GzipCommand {
+    /// Lists members and checks their trailer and header CRC
+    Verify,
+    /// Rewrites trailers and header CRCs of edited members
+    Repair,
+    /// Patches or appends 4 bytes of member payload so that its CRC matches target CRC, storing it uncompressed
+    ForgePayload {
+        /// Index of the member, starting from 0
+        index: usize,
+        /// Offset of patched bytes in payload (appends 4 bytes by default)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+    /// Adjusts an extra subfield (or the comment) so that the 16 bit header CRC matches target CRC
+    ForgeHeader {
+        /// Index of the member, starting from 0
+        index: usize,
+        /// Adjust comment instead of extra field
+        #[arg(long)]
+        comment: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum OggCommand {
+    /// Lists pages and checks their CRC
+    Verify,
+    /// Rewrites invalid page CRCs
+    Repair,
+    /// Patches 4 bytes of page data so that the page CRC matches target CRC
+    Forge {
+        /// Index of the page in file, starting from 0
+        index: usize,
+        /// Offset of patched bytes in page data (defaults to the end of the last comment starting in the page)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum FlacCommand {
+    /// Lists frames, numbered, and checks their header CRC-8 and frame CRC-16
+    Verify {
+        /// Only list invalid frames
+        #[arg(long)]
+        invalid: bool,
+    },
+    /// Rewrites invalid header CRC-8, then frame CRC-16
+    Repair,
+}
+
+#[derive(Subcommand)]
+enum MkvCommand {
+    /// Lists master elements holding a CRC-32 element and checks it
+    Verify,
+    /// Rewrites invalid CRC-32 elements, innermost first
+    Repair,
+    /// Patches the last 4 bytes of a Void element or binary tag value so that a CRC-32 element matches target CRC
+    Forge {
+        /// Index of the CRC-32 element in file, starting from 0
+        index: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum RecordsCommand {
+    /// Lists records or chunks, numbered, and checks their masked CRCs
+    Verify {
+        /// Only list invalid CRCs
+        #[arg(long)]
+        invalid: bool,
+    },
+    /// Rewrites invalid masked CRCs
+    Repair,
+    /// Patches 4 bytes of a record so that its stored masked CRC matches target CRC
+    Forge {
+        /// Number of the CRC, as listed by verify
+        index: usize,
+        /// Offset of patched bytes in record data (defaults to the last 4 bytes)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum KafkaCommand {
+    /// Lists record batches, numbered, and checks their CRC
+    Verify {
+        /// Only list invalid batches
+        #[arg(long)]
+        invalid: bool,
+    },
+    /// Rewrites invalid batch CRCs, such as after editing records
+    Repair,
+    /// Patches a record header value, or adds one, so that a batch CRC matches target CRC
+    Forge {
+        /// Number of the batch, as listed by verify
+        index: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum TsCommand {
+    /// Lists PSI sections and checks their CRC
+    Verify,
+    /// Rewrites invalid section CRCs
+    Repair,
+    /// Patches 4 bytes of a section so that its CRC matches target CRC
+    Forge {
+        /// Index of the section in stream, starting from 0
+        index: usize,
+        /// Offset of patched bytes in section (defaults to the end of the last descriptor of at least 4 bytes)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum GptCommand {
+    /// Lists primary and backup headers and checks their CRCs
+    Verify,
+    /// Rewrites partition entry array and header CRCs
+    Repair,
+    /// Patches reserved header bytes so that the header CRC matches target CRC
+    Forge {
+        /// Header to forge: 0 for the primary one, 1 for the backup one
+        index: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum PcapCommand {
+    /// Lists Ethernet frames and checks their FCS
+    Verify,
+    /// Rewrites invalid frame FCS
+    Repair,
+    /// Patches 4 bytes of a frame so that its FCS matches target CRC
+    Forge {
+        /// Index of the Ethernet frame in capture, starting from 0
+        index: usize,
+        /// Offset of patched bytes in frame (defaults to the last 4 bytes before the FCS)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Bzip2Command {
+    /// Lists streams and their blocks, and checks their CRCs
+    Verify,
+    /// Rewrites invalid block CRCs and combined stream CRCs
+    Repair,
+    /// Patches or appends 4 bytes of block data so that the combined stream CRC matches target CRC
+    Forge {
+        /// Index of the stream in file, starting from 0
+        index: usize,
+        /// Index of the adjusted block in stream (defaults to the last one)
+        #[arg(long)]
+        block: Option<usize>,
+        /// Offset of patched bytes in decompressed block data (appended to it by default)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
 
-    let output_path = match cli.output_file {
-        Some(output_file) => output_file,
+#[derive(Subcommand)]
+enum XzCommand {
+    /// Lists CRC fields of streams, blocks and indexes, and checks them
+    Verify,
+    /// Rewrites invalid CRC32 fields and block checks
+    Repair,
+    /// Patches or appends 4 or 8 bytes of block data so that the block check matches target CRC
+    Forge {
+        /// Index of the block in file, starting from 0
+        index: usize,
+        /// Offset of patched bytes in decompressed block data (appended to it by default)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum UimageCommand {
+    /// Prints the image name and size, and checks its header and data CRCs
+    Verify,
+    /// Rewrites the data CRC, then the header CRC
+    Repair,
+    /// Patches or appends 4 bytes of image data so that its CRC matches target CRC, updating the header
+    ForgeData {
+        /// Offset of patched bytes in image data (appends 4 bytes by default)
+        #[arg(long)]
+        offset: Option<usize>,
+    },
+    /// Adjusts the unused end of the image name so that the header CRC matches target CRC
+    ForgeHeader,
+}
+
+#[derive(Subcommand)]
+enum FsimageCommand {
+    /// Lists metadata checksums, numbered, and checks them
+    Verify {
+        /// Only list invalid checksums
+        #[arg(long)]
+        invalid: bool,
+    },
+    /// Rewrites invalid metadata checksums
+    Repair,
+    /// Patches reserved bytes of a structure so that its checksum matches target CRC
+    Forge {
+        /// Number of the checksum, as listed by verify
+        index: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum BtrfsCommand {
+    /// Lists superblock and tree block checksums, numbered, and checks them
+    Verify {
+        /// Only list invalid checksums
+        #[arg(long)]
+        invalid: bool,
+    },
+    /// Rewrites invalid superblock and tree block checksums
+    Repair,
+    /// Patches slack space of a superblock or tree block so that its checksum matches target CRC
+    Forge {
+        /// Number of the checksum, as listed by verify
+        index: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum FrameCommand {
+    /// Prints the frame of each payload, with its CRCs
+    Build {
+        /// Protocol of frames
+        #[arg(ignore_case = true)]
+        protocol: Protocol,
+        /// Block number of XMODEM and YMODEM blocks
+        #[arg(long, default_value_t = 1)]
+        block: u8,
+    },
+    /// Patches 2 payload bytes of each frame so that the CRC covering them keeps its value, or matches target CRC
+    Forge {
+        /// Protocol of frames
+        #[arg(ignore_case = true)]
+        protocol: Protocol,
+        /// Offset of patched bytes in payload
+        #[arg(long)]
+        offset: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum HdlcCommand {
+    /// Lists frames and checks their FCS
+    Verify,
+    /// Rewrites invalid FCS, stuffing them again
+    Repair,
+    /// Patches unescaped frame bytes so that the FCS keeps its value, or matches target CRC, avoiding escaped bytes
+    Forge {
+        /// Index of the frame in stream, starting from 0
+        index: usize,
+        /// Offset of patched bytes in unescaped frame, address field included
+        #[arg(long)]
+        offset: usize,
+    },
+}
+
+/// Output path, defaulting to `<INPUT_FILE>.patched`.
+fn output_path(cli: &Cli) -> CRCResult<PathBuf> {
+    let output_path = match &cli.output_file {
+        Some(output_file) => output_file.clone(),
         None => PathBuf::from(format!(
             "{}.patched",
             cli.input_file
@@ -58,25 +559,591 @@ fn main() -> CRCResult<()> {
                 .ok_or(Error::EncodingError)?
         )),
     };
+    println!("Output file: {:?}", output_path);
+    Ok(output_path)
+}
 
-    let input_file = File::open(cli.input_file)?;
+/// Target CRC, which is required by forging commands.
+fn target_crc(cli: &Cli) -> u32 {
+    u32::try_from(target_crc64(cli)).unwrap_or_else(|_| {
+        Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "--target-crc must fit in 32 bits for this command",
+            )
+            .exit()
+    })
+}
 
-    println!("Output file: {:?}", output_path);
-    println!("Target crc: 0x{:08x}", cli.target_crc);
+/// Unmask target CRC `crc` if `masked`, as forging works on plain CRCs.
+fn unmasked_crc(crc: u32, masked: bool) -> u32 {
+    if !masked {
+        return crc;
+    }
+    let crc = crc_forge::unmask(crc);
+    println!("Unmasked crc: 0x{crc:08x}");
+    crc
+}
+
+/// 16-bit target CRC, which is required by forging commands.
+fn target_crc16(cli: &Cli) -> u16 {
+    u16::try_from(target_crc64(cli)).unwrap_or_else(|_| {
+        Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "--target-crc must fit in 16 bits for this command",
+            )
+            .exit()
+    })
+}
+
+/// Hexadecimal string of `bytes`.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Target CRC of up to 64 bits, for forging commands supporting wider CRCs.
+fn target_crc64(cli: &Cli) -> u64 {
+    let target_crc = cli.target_crc.unwrap_or_else(|| {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--target-crc is required by this command",
+            )
+            .exit()
+    });
+    println!("Target crc: 0x{:08x}", target_crc);
+    target_crc
+}
+
+fn main() -> CRCResult<()> {
+    let cli = Cli::parse();
+
+    let input_file = File::open(&cli.input_file)?;
 
     match cli.command {
-        Command::Append => {
-            crc_forge::force_crc_append(&input_file, &output_path, cli.target_crc, cli.generator)?;
+        Command::Append { masked } => {
+            let target_crc = unmasked_crc(target_crc(&cli), masked);
+            crc_forge::force_crc_append(
+                &input_file,
+                &output_path(&cli)?,
+                target_crc,
+                cli.generator,
+            )?;
         }
-        Command::Insert { offset } => {
+        Command::Insert { offset, masked } => {
+            let target_crc = unmasked_crc(target_crc(&cli), masked);
             crc_forge::force_crc_insert(
                 &input_file,
-                &output_path,
+                &output_path(&cli)?,
                 offset,
-                cli.target_crc,
+                target_crc,
                 cli.generator,
             )?;
         }
+        Command::Recover => {
+            let models = crc_forge::recover_models(&input_file)?;
+            if models.is_empty() {
+                println!("No model found");
+            }
+            for model in models {
+                println!("{}", model);
+            }
+        }
+        Command::Solve { refin, refout } => {
+            let solutions = crc_forge::solve_models(&input_file, cli.generator, refin, refout)?;
+            match solutions.len() {
+                0 => println!("No init and xorout consistent with samples"),
+                1 => (),
+                n => println!("Samples leave init and xorout ambiguous, {} solutions:", n),
+            }
+            for props in solutions {
+                println!("{}", props);
+            }
+        }
+        Command::Detect { trailer } => {
+            let detections = crc_forge::detect_models(&input_file, trailer)?;
+            if detections.is_empty() {
+                println!("No plausible model found");
+            }
+            for detection in detections {
+                println!("{}", detection);
+            }
+        }
+        Command::Analyze => {
+            let detections = crc_forge::analyze_frames(&input_file)?;
+            if detections.is_empty() {
+                println!("No plausible model found");
+            }
+            for detection in detections {
+                println!("{}", detection);
+            }
+        }
+        Command::Png { ref command } => match command {
+            PngCommand::Verify => {
+                for chunk in crc_forge::png_chunks(&input_file)? {
+                    println!("{}", chunk);
+                }
+            }
+            PngCommand::Repair => {
+                let repaired = crc_forge::png_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} chunks", repaired);
+            }
+            PngCommand::ForgeChunk { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::png_forge_chunk(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+            PngCommand::ForgeFile { text } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::png_forge_file(&input_file, &output_path(&cli)?, target_crc, *text)?;
+            }
+        },
+        Command::Zip { ref command } => match command {
+            ZipCommand::Verify => {
+                for entry in crc_forge::zip_entries(&input_file)? {
+                    println!("{}", entry);
+                }
+            }
+            ZipCommand::Repair => {
+                let repaired = crc_forge::zip_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} members", repaired);
+            }
+            ZipCommand::Forge { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::zip_forge_entry(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::SevenZip { ref command } => match command {
+            SevenZipCommand::Verify => {
+                for field in crc_forge::sevenzip_fields(&input_file)? {
+                    println!("{}", field);
+                }
+            }
+            SevenZipCommand::Repair => {
+                let repaired = crc_forge::sevenzip_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} CRCs", repaired);
+            }
+            SevenZipCommand::Forge { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::sevenzip_forge_file(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Rar5 { ref command } => match command {
+            Rar5Command::Verify => {
+                for block in crc_forge::rar5_blocks(&input_file)? {
+                    println!("{}", block);
+                }
+            }
+            Rar5Command::Repair => {
+                let repaired = crc_forge::rar5_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} CRCs", repaired);
+            }
+            Rar5Command::Forge { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::rar5_forge_data(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Gzip { ref command } => match command {
+            GzipCommand::Verify => {
+                for member in crc_forge::gzip_members(&input_file)? {
+                    println!("{}", member);
+                }
+            }
+            GzipCommand::Repair => {
+                let repaired = crc_forge::gzip_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} members", repaired);
+            }
+            GzipCommand::ForgePayload { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::gzip_forge_payload(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+            GzipCommand::ForgeHeader { index, comment } => {
+                let target_crc = target_crc16(&cli);
+                crc_forge::gzip_forge_header(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    target_crc,
+                    *comment,
+                )?;
+            }
+        },
+        Command::Ogg { ref command } => match command {
+            OggCommand::Verify => {
+                for page in crc_forge::ogg_pages(&input_file)? {
+                    println!("{}", page);
+                }
+            }
+            OggCommand::Repair => {
+                let repaired = crc_forge::ogg_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} pages", repaired);
+            }
+            OggCommand::Forge { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::ogg_forge_page(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Flac { ref command } => match command {
+            FlacCommand::Verify { invalid } => {
+                let frames = crc_forge::flac_frames(&input_file)?;
+                for (index, frame) in frames.iter().enumerate() {
+                    if !invalid || !frame.is_valid() {
+                        println!("{:>6}  {}", index, frame);
+                    }
+                }
+                let count = frames.iter().filter(|frame| !frame.is_valid()).count();
+                println!("{} frames, {} invalid", frames.len(), count);
+            }
+            FlacCommand::Repair => {
+                let repaired = crc_forge::flac_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} frames", repaired);
+            }
+        },
+        Command::Mkv { ref command } => match command {
+            MkvCommand::Verify => {
+                for field in crc_forge::mkv_fields(&input_file)? {
+                    println!("{}", field);
+                }
+            }
+            MkvCommand::Repair => {
+                let repaired = crc_forge::mkv_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} CRCs", repaired);
+            }
+            MkvCommand::Forge { index } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::mkv_forge(&input_file, &output_path(&cli)?, *index, target_crc)?;
+            }
+        },
+        Command::Records {
+            ref format,
+            ref command,
+        } => match command {
+            RecordsCommand::Verify { invalid } => {
+                let (format, fields) = crc_forge::records_fields(&input_file, *format)?;
+                println!("Format: {}", format);
+                for (index, field) in fields.iter().enumerate() {
+                    if !invalid || !field.is_valid() {
+                        println!("{:>6}  {}", index, field);
+                    }
+                }
+                let count = fields.iter().filter(|field| !field.is_valid()).count();
+                println!("{} CRCs, {} invalid", fields.len(), count);
+            }
+            RecordsCommand::Repair => {
+                let repaired =
+                    crc_forge::records_repair(&input_file, &output_path(&cli)?, *format)?;
+                println!("Repaired {} CRCs", repaired);
+            }
+            RecordsCommand::Forge { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::records_forge(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *format,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Kafka { ref command } => match command {
+            KafkaCommand::Verify { invalid } => {
+                let batches = crc_forge::kafka_batches(&input_file)?;
+                for (index, batch) in batches.iter().enumerate() {
+                    if !invalid || !batch.is_valid() {
+                        println!("{:>6}  {}", index, batch);
+                    }
+                }
+                let count = batches.iter().filter(|batch| !batch.is_valid()).count();
+                println!("{} batches, {} invalid", batches.len(), count);
+            }
+            KafkaCommand::Repair => {
+                let repaired = crc_forge::kafka_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} batches", repaired);
+            }
+            KafkaCommand::Forge { index } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::kafka_forge(&input_file, &output_path(&cli)?, *index, target_crc)?;
+            }
+        },
+        Command::Ts { ref command } => match command {
+            TsCommand::Verify => {
+                for section in crc_forge::ts_sections(&input_file)? {
+                    println!("{}", section);
+                }
+            }
+            TsCommand::Repair => {
+                let repaired = crc_forge::ts_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} sections", repaired);
+            }
+            TsCommand::Forge { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::ts_forge_section(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Gpt { ref command } => match command {
+            GptCommand::Verify => {
+                for header in crc_forge::gpt_headers(&input_file)? {
+                    println!("{}", header);
+                }
+            }
+            GptCommand::Repair => {
+                let repaired = crc_forge::gpt_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} headers", repaired);
+            }
+            GptCommand::Forge { index } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::gpt_forge_header(&input_file, &output_path(&cli)?, *index, target_crc)?;
+            }
+        },
+        Command::Pcap { fcs, ref command } => match command {
+            PcapCommand::Verify => {
+                for frame in crc_forge::pcap_frames(&input_file, fcs)? {
+                    println!("{}", frame);
+                }
+            }
+            PcapCommand::Repair => {
+                let repaired = crc_forge::pcap_repair(&input_file, &output_path(&cli)?, fcs)?;
+                println!("Repaired {} frames", repaired);
+            }
+            PcapCommand::Forge { index, offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::pcap_forge_frame(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    fcs,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Bzip2 { ref command } => match command {
+            Bzip2Command::Verify => {
+                for stream in crc_forge::bzip2_streams(&input_file)? {
+                    println!("{}", stream);
+                    for block in &stream.blocks {
+                        println!("  {}", block);
+                    }
+                }
+            }
+            Bzip2Command::Repair => {
+                let repaired = crc_forge::bzip2_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} CRCs", repaired);
+            }
+            Bzip2Command::Forge {
+                index,
+                block,
+                offset,
+            } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::bzip2_forge_stream(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *block,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Xz { ref command } => match command {
+            XzCommand::Verify => {
+                for field in crc_forge::xz_fields(&input_file)? {
+                    println!("{}", field);
+                }
+            }
+            XzCommand::Repair => {
+                let repaired = crc_forge::xz_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} CRCs", repaired);
+            }
+            XzCommand::Forge { index, offset } => {
+                let target_crc = target_crc64(&cli);
+                crc_forge::xz_forge_check(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Uimage { ref command } => match command {
+            UimageCommand::Verify => {
+                println!("{}", crc_forge::uimage_image(&input_file)?);
+            }
+            UimageCommand::Repair => {
+                let repaired = crc_forge::uimage_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} CRCs", repaired);
+            }
+            UimageCommand::ForgeData { offset } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::uimage_forge_data(
+                    &input_file,
+                    &output_path(&cli)?,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+            UimageCommand::ForgeHeader => {
+                let target_crc = target_crc(&cli);
+                crc_forge::uimage_forge_header(&input_file, &output_path(&cli)?, target_crc)?;
+            }
+        },
+        Command::Fsimage { ref command } => match command {
+            FsimageCommand::Verify { invalid } => {
+                let fields = crc_forge::fsimage_fields(&input_file)?;
+                for (index, field) in fields.iter().enumerate() {
+                    if !invalid || !field.is_valid() {
+                        println!("{:>6}  {}", index, field);
+                    }
+                }
+                let count = fields.iter().filter(|field| !field.is_valid()).count();
+                println!("{} checksums, {} invalid", fields.len(), count);
+            }
+            FsimageCommand::Repair => {
+                let repaired = crc_forge::fsimage_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} checksums", repaired);
+            }
+            FsimageCommand::Forge { index } => {
+                let target_crc = target_crc(&cli);
+                // Some ext4 checksums only keep the low 16 bits of the CRC-32C
+                let fields = crc_forge::fsimage_fields(&input_file)?;
+                if let Some(field) = fields
+                    .get(*index)
+                    .filter(|field| u64::from(target_crc) >> field.width() != 0)
+                {
+                    Cli::command()
+                        .error(
+                            ErrorKind::InvalidValue,
+                            format!(
+                                "--target-crc must fit in {} bits for the {} checksum",
+                                field.width(),
+                                field.kind
+                            ),
+                        )
+                        .exit()
+                }
+                crc_forge::fsimage_forge(&input_file, &output_path(&cli)?, *index, target_crc)?;
+            }
+        },
+        Command::Btrfs { ref command } => match command {
+            BtrfsCommand::Verify { invalid } => {
+                let fields = crc_forge::btrfs_fields(&input_file)?;
+                for (index, field) in fields.iter().enumerate() {
+                    if !invalid || !field.is_valid() {
+                        println!("{:>6}  {}", index, field);
+                    }
+                }
+                let count = fields.iter().filter(|field| !field.is_valid()).count();
+                println!("{} checksums, {} invalid", fields.len(), count);
+            }
+            BtrfsCommand::Repair => {
+                let repaired = crc_forge::btrfs_repair(&input_file, &output_path(&cli)?)?;
+                println!("Repaired {} checksums", repaired);
+            }
+            BtrfsCommand::Forge { index } => {
+                let target_crc = target_crc(&cli);
+                crc_forge::btrfs_forge(&input_file, &output_path(&cli)?, *index, target_crc)?;
+            }
+        },
+        Command::Frame { ref command } => {
+            let frames = match command {
+                FrameCommand::Build { protocol, block } => {
+                    crc_forge::frame_build(&input_file, *protocol, *block)?
+                }
+                FrameCommand::Forge { protocol, offset } => {
+                    // Stored CRCs are kept without a target CRC
+                    let target_crc = cli.target_crc.map(|_| target_crc16(&cli));
+                    crc_forge::frame_forge(&input_file, *protocol, *offset, target_crc)?
+                }
+            };
+            for frame in frames {
+                println!("{}", hex(&frame));
+            }
+        }
+        Command::Hdlc {
+            fcs,
+            accm,
+            ref command,
+        } => match command {
+            HdlcCommand::Verify => {
+                for frame in crc_forge::hdlc_frames(&input_file, fcs)? {
+                    println!("{}", frame);
+                }
+            }
+            HdlcCommand::Repair => {
+                let repaired = crc_forge::hdlc_repair(&input_file, &output_path(&cli)?, fcs, accm)?;
+                println!("Repaired {} frames", repaired);
+            }
+            HdlcCommand::Forge { index, offset } => {
+                let target_crc = cli.target_crc.map(|_| target_crc(&cli));
+                crc_forge::hdlc_forge(
+                    &input_file,
+                    &output_path(&cli)?,
+                    fcs,
+                    accm,
+                    *index,
+                    *offset,
+                    target_crc,
+                )?;
+            }
+        },
+        Command::Scan {
+            width,
+            ref model,
+            align,
+        } => {
+            let locations = crc_forge::scan_file(&input_file, width, model.as_deref(), align)?;
+            if locations.is_empty() {
+                println!("No CRC field found");
+            }
+            for location in locations {
+                println!("{}", location);
+            }
+        }
     };
 
     Ok(())
@@ -120,6 +120,8 @@ where
     T: Copy,
 {
     type Output = Polynomial<T>;
+    // Addition in F2[X] is a XOR of coefficients
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn add(self, rhs: Polynomial<T>) -> Self::Output {
         Polynomial(self.repr() ^ rhs.repr())
     }
@@ -162,7 +164,7 @@ impl Mul<Polynomial<u64>> for Polynomial<u32> {
     type Output = Polynomial<u128>;
     fn mul(self, rhs: Polynomial<u64>) -> Self::Output {
         let self_u64: Polynomial<u64> = self.into();
-        (self_u64 * rhs).try_into().unwrap()
+        self_u64 * rhs
     }
 }
 
@@ -181,8 +183,12 @@ where
         let mut res: u128 = 0;
         let mut self_bits = self.repr();
         let rhs_deg = rhs.deg();
+        // Dividing by a constant one leaves polynomial unchanged
+        if rhs_deg == 0 {
+            return self;
+        }
         // Number of check steps to perform
-        let steps = (128 - rhs_deg) as u32;
+        let steps = 128 - rhs_deg;
         // Remove highest degree term (it is shifted anyway)
         let (rhs_bits, _) = rhs.repr().overflowing_shr(steps);
 
@@ -234,8 +240,12 @@ impl Rem<Polynomial<u128>> for Polynomial<u128> {
     fn rem(self, modulo: Polynomial<u128>) -> Self::Output {
         let mut self_bits: u128 = self.repr();
         let modulo_deg = modulo.deg();
+        // Any polynomial is divisible by a constant one
+        if modulo_deg == 0 {
+            return Polynomial(0);
+        }
         // Number of check steps to perform
-        let steps = (128 - modulo_deg) as u32;
+        let steps = 128 - modulo_deg;
         // Remove the highest degree term (it is shifted anyway)
         let (modulo_bits, _) = modulo.repr().overflowing_shr(steps);
 
@@ -351,6 +361,9 @@ impl Polynomial<u64> {
 
         // First get remainder by current polynomial to ensure `deg(self) < deg(p)`
         let mut b = self % a;
+        if b == one {
+            return Ok(one);
+        }
 
         // Then initialize sequence
         let mut vn = Polynomial::from(PolynomialRepr::Normal(0u64));
@@ -377,11 +390,22 @@ impl Polynomial<u64> {
             }
 
             // Update a and b
-            a = b.into();
+            a = b;
             b = r;
         }
     }
 
+    /// Compute greatest common divisor of `self` and `p`.
+    pub fn gcd(self, p: Polynomial<u64>) -> Polynomial<u64> {
+        let (mut a, mut b) = (self, p);
+        while b.repr() != 0 {
+            let r = a % b;
+            a = b;
+            b = r;
+        }
+        a
+    }
+
     /// Compute `self^e mod p` efficiently with exponentiation by squaring
     pub fn pow(self, mut e: u64, p: Polynomial<u64>) -> Polynomial<u64> {
         let mut base = self;
@@ -393,7 +417,192 @@ impl Polynomial<u64> {
             e >>= 1;
             base = (base * base) % p;
         }
-        return ret;
+        ret
+    }
+}
+
+/*********************************************
+ * Polynomials of arbitrary length in F2[X] *
+ ********************************************/
+
+/// Coefficients of a polynomial of arbitrary degree.
+/// Unlike fixed size polynomials, there is no highest degree to reverse against,
+/// so coefficients are stored in normal order: bit `i` of word `k` is the coefficient of X^(64k+i).
+/// Trailing zero words are always trimmed, so that the zero polynomial has no words.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Unbounded(Vec<u64>);
+
+impl Polynomial<Unbounded> {
+    /// Build polynomial from its normal representation.
+    pub fn from_normal(val: u64) -> Self {
+        Polynomial(Unbounded(vec![val])).trimmed()
+    }
+
+    /// Build polynomial from `bytes`, the first byte holding the terms of highest degree.
+    /// When `reflected` is set, the LSB of each byte is its term of highest degree (as in reflected CRCs).
+    pub fn from_bytes(bytes: &[u8], reflected: bool) -> Self {
+        let mut words = vec![0u64; (8 * bytes.len()).div_ceil(64)];
+        for (k, b) in bytes.iter().rev().enumerate() {
+            let b = if reflected { b.reverse_bits() } else { *b };
+            words[k / 8] |= u64::from(b) << (8 * (k % 8));
+        }
+        Polynomial(Unbounded(words)).trimmed()
+    }
+
+    /// Try to get the normal representation of the polynomial.
+    pub fn to_normal(&self) -> CRCResult<u64> {
+        match self.0.0.len() {
+            0 => Ok(0),
+            1 => Ok(self.0.0[0]),
+            _ => Err(Error::OverflowError(None)),
+        }
+    }
+
+    /// Remove leading zero coefficients.
+    fn trimmed(mut self) -> Self {
+        while self.0.0.last() == Some(&0) {
+            self.0.0.pop();
+        }
+        self
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.0.is_empty()
+    }
+
+    pub fn deg(&self) -> u32 {
+        match self.0.0.last() {
+            Some(word) => 64 * (self.0.0.len() as u32 - 1) + 63 - word.leading_zeros(),
+            None => 0,
+        }
+    }
+
+    fn coef(&self, i: u32) -> bool {
+        let k = (i / 64) as usize;
+        k < self.0.0.len() && (self.0.0[k] >> (i % 64)) & 1 == 1
+    }
+
+    /// Add `rhs * X^n` to polynomial, without trimming.
+    fn add_shifted(&mut self, rhs: &Self, n: u32) {
+        let (words, bits) = ((n / 64) as usize, n % 64);
+        let len = rhs.0.0.len() + words + 1;
+        if self.0.0.len() < len {
+            self.0.0.resize(len, 0);
+        }
+        for (k, word) in rhs.0.0.iter().enumerate() {
+            self.0.0[k + words] ^= word << bits;
+            if bits != 0 {
+                self.0.0[k + words + 1] ^= word >> (64 - bits);
+            }
+        }
+    }
+
+    /// Multiply polynomial by X^n.
+    pub fn shl(&self, n: u32) -> Self {
+        let mut res = Polynomial(Unbounded(Vec::new()));
+        res.add_shifted(self, n);
+        res.trimmed()
+    }
+
+    /// Compute both quotient and remainder of euclidian division by `rhs`.
+    pub fn div_rem(&self, rhs: &Self) -> (Self, Self) {
+        assert!(!rhs.is_zero(), "division by zero polynomial");
+        let rhs_deg = rhs.deg();
+        let mut q = Polynomial(Unbounded(Vec::new()));
+        let mut r = self.clone();
+        if r.is_zero() || r.deg() < rhs_deg {
+            return (q, r);
+        }
+        for i in (rhs_deg..=r.deg()).rev() {
+            if r.coef(i) {
+                r.add_shifted(rhs, i - rhs_deg);
+                q.add_shifted(&Polynomial::from_normal(1), i - rhs_deg);
+            }
+        }
+        (q.trimmed(), r.trimmed())
+    }
+
+    /// Greatest common divisor with `rhs`.
+    pub fn gcd(self, rhs: Self) -> Self {
+        let (mut a, mut b) = (self, rhs);
+        while !b.is_zero() {
+            let (_, r) = a.div_rem(&b);
+            a = b;
+            b = r;
+        }
+        a
+    }
+}
+
+impl Add<Polynomial<Unbounded>> for Polynomial<Unbounded> {
+    type Output = Polynomial<Unbounded>;
+    fn add(mut self, rhs: Polynomial<Unbounded>) -> Self::Output {
+        self.add_shifted(&rhs, 0);
+        self.trimmed()
+    }
+}
+
+impl Mul<Polynomial<Unbounded>> for Polynomial<Unbounded> {
+    type Output = Polynomial<Unbounded>;
+    fn mul(self, rhs: Polynomial<Unbounded>) -> Self::Output {
+        let mut res = Polynomial(Unbounded(Vec::new()));
+        if self.is_zero() {
+            return res;
+        }
+        for i in 0..=self.deg() {
+            if self.coef(i) {
+                res.add_shifted(&rhs, i);
+            }
+        }
+        res.trimmed()
+    }
+}
+
+impl Div<Polynomial<Unbounded>> for Polynomial<Unbounded> {
+    type Output = Polynomial<Unbounded>;
+    fn div(self, rhs: Polynomial<Unbounded>) -> Self::Output {
+        self.div_rem(&rhs).0
+    }
+}
+
+impl Rem<Polynomial<Unbounded>> for Polynomial<Unbounded> {
+    type Output = Polynomial<Unbounded>;
+    fn rem(self, modulo: Polynomial<Unbounded>) -> Self::Output {
+        self.div_rem(&modulo).1
+    }
+}
+
+/*******************************
+ * Linear systems of equations *
+ ******************************/
+
+/// Solve linear system over F2 with at most 128 unknowns, given the image of each unknown bit in `columns`.
+/// Returns unknowns `x` such that the sum of `columns[i]` for all bits `i` set in `x` is `rhs`, if any.
+pub fn solve_gf2(columns: &[u64], rhs: u64) -> Option<u128> {
+    // Echelon basis of column combinations, indexed by their highest bit
+    let mut basis: [Option<(u64, u128)>; 64] = [None; 64];
+    let reduce = |basis: &[Option<(u64, u128)>; 64], mut v: u64, mut comb: u128| {
+        while v != 0 {
+            let pivot = 63 - v.leading_zeros() as usize;
+            match basis[pivot] {
+                Some((b, c)) => {
+                    v ^= b;
+                    comb ^= c;
+                }
+                None => break,
+            }
+        }
+        (v, comb)
+    };
+    for (i, column) in columns.iter().enumerate() {
+        let (v, comb) = reduce(&basis, *column, 1 << i);
+        if v != 0 {
+            basis[63 - v.leading_zeros() as usize] = Some((v, comb));
+        }
+    }
+    match reduce(&basis, rhs, 0) {
+        (0, comb) => Some(comb),
+        _ => None,
     }
 }
 
@@ -413,7 +622,7 @@ impl Debug for Polynomial<u128> {
             bits >>= 1;
         }
 
-        if terms.len() == 0 {
+        if terms.is_empty() {
             write!(f, "0")?;
         } else {
             write!(
@@ -433,13 +642,31 @@ impl Debug for Polynomial<u128> {
 
 impl Debug for Polynomial<u64> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", Polynomial::<u128>::from(self.clone()))
+        write!(f, "{:?}", Polynomial::<u128>::from(*self))
     }
 }
 
 impl Debug for Polynomial<u32> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", Polynomial::<u128>::from(self.clone()))
+        write!(f, "{:?}", Polynomial::<u128>::from(*self))
+    }
+}
+
+impl Debug for Polynomial<Unbounded> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        write!(
+            f,
+            "{}",
+            (0..=self.deg())
+                .rev()
+                .filter(|i| self.coef(*i))
+                .map(|i| format!("X^{}", i))
+                .collect::<Vec<String>>()
+                .join(" + ")
+        )
     }
 }
 
@@ -467,6 +694,11 @@ pub fn reverse_u64(n: u64) -> u64 {
     res
 }
 
+/// Reverse bit notation of the `width` lowest bits of a u64
+pub fn reverse_bits(n: u64, width: u32) -> u64 {
+    reverse_u64(n) >> (64 - width)
+}
+
 /// Reverse bit notation in a u128
 pub fn reverse_u128(n: u128) -> u128 {
     let mut res = 0u128;
@@ -479,7 +711,7 @@ pub fn reverse_u128(n: u128) -> u128 {
 
 #[cfg(test)]
 mod tests {
-    use crate::math::{Polynomial, PolynomialRepr};
+    use crate::math::{Polynomial, PolynomialRepr, Unbounded, solve_gf2};
 
     #[test]
     pub fn test_simple_add() {
@@ -541,6 +773,14 @@ mod tests {
         assert_eq!(a % b, Polynomial::from(PolynomialRepr::Normal(0x14c2238)));
     }
 
+    #[test]
+    pub fn test_constant_div_rem() {
+        let a = Polynomial::from(PolynomialRepr::Normal(0x123412341237u64));
+        let one = Polynomial::from(PolynomialRepr::Normal(1u64));
+        assert_eq!(a / one, a);
+        assert_eq!((a % one).repr(), 0);
+    }
+
     #[test]
     pub fn test_degree() {
         let a = Polynomial::from(PolynomialRepr::Normal(0x04c11db7u32));
@@ -571,6 +811,25 @@ mod tests {
         );
     }
 
+    #[test]
+    pub fn test_inv_mod_one() {
+        let generator = Polynomial::from(PolynomialRepr::Normal(0x104c11db7u64));
+        let one = Polynomial::from(PolynomialRepr::Normal(1u64));
+        assert_eq!(one.inv_mod(generator).unwrap(), one);
+    }
+
+    #[test]
+    pub fn test_gcd() {
+        let a = Polynomial::from(PolynomialRepr::Normal(0x3u64));
+        let b = Polynomial::from(PolynomialRepr::Normal(0x104c11db7u64));
+        let c = Polynomial::from(PolynomialRepr::Normal(0x1814141abu64));
+        assert_eq!(a.gcd(b), Polynomial::from(PolynomialRepr::Normal(1)));
+        assert_eq!(c.gcd(a), a);
+        let ab: Polynomial<u64> = (a * b).try_into().unwrap();
+        let ac: Polynomial<u64> = (a * c).try_into().unwrap();
+        assert_eq!(ab.gcd(ac), a);
+    }
+
     #[test]
     pub fn test_mod_exp() {
         let generator = Polynomial::from(PolynomialRepr::Normal(0x04c11db7u32));
@@ -585,4 +844,48 @@ mod tests {
         let a_square = (a * a) % generator;
         assert_eq!(a.pow(2, generator), a_square);
     }
+
+    #[test]
+    pub fn test_unbounded_div_rem() {
+        let a = Polynomial::<Unbounded>::from_bytes(b"lorem ipsum dolor sit amet", false);
+        let b = Polynomial::<Unbounded>::from_normal(0x104c11db7);
+        let (q, r) = a.div_rem(&b);
+        assert!(r.deg() < b.deg());
+        assert_eq!(q * b + r, a);
+
+        // Agrees with fixed size remainder
+        let a = Polynomial::<Unbounded>::from_normal(0x123412341237);
+        let b = Polynomial::<Unbounded>::from_normal(0x04c11db7);
+        assert_eq!((a % b).to_normal().unwrap(), 0x14c2238);
+    }
+
+    #[test]
+    pub fn test_unbounded_gcd() {
+        let g = Polynomial::<Unbounded>::from_normal(0x104c11db7);
+        let a = Polynomial::<Unbounded>::from_bytes(b"foo", true);
+        let b = Polynomial::<Unbounded>::from_bytes(b"barbaz", true);
+        let gcd = (a.clone() * g.clone()).gcd(b.clone() * g.clone());
+        assert_eq!(gcd.clone() % g.clone(), Polynomial::from_normal(0));
+        assert_eq!(gcd / g, a.gcd(b));
+    }
+
+    #[test]
+    pub fn test_unbounded_from_bytes() {
+        let a = Polynomial::<Unbounded>::from_bytes(&[0x80, 0x01], false);
+        assert_eq!(format!("{:?}", a), "X^15 + X^0");
+        let a = Polynomial::<Unbounded>::from_bytes(&[0x80, 0x01], true);
+        assert_eq!(format!("{:?}", a), "X^8 + X^7");
+        assert_eq!(a.shl(100).deg(), 108);
+    }
+
+    #[test]
+    pub fn test_solve_gf2() {
+        let columns = [0b011, 0b110, 0b101, 0b100];
+        let x = solve_gf2(&columns, 0b111).expect("Failed to solve");
+        let image = (0..columns.len())
+            .filter(|i| (x >> i) & 1 == 1)
+            .fold(0, |acc, i| acc ^ columns[i]);
+        assert_eq!(image, 0b111);
+        assert!(solve_gf2(&[0b01, 0b01], 0b10).is_none());
+    }
 }
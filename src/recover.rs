@@ -0,0 +1,678 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{BufRead, Read},
+};
+
+use crate::catalogue::CATALOGUE;
+use crate::core::{CRC32, CRC32Properties};
+use crate::error::{CRCResult, Error};
+use crate::generic::{CRC, CRCProperties};
+use crate::math::{Polynomial, PolynomialRepr, Unbounded, reverse_bits, reverse_u32};
+
+/// Highest degree of the spurious common factor searched for when the samples GCD is not the generator itself.
+const MAX_COFACTOR_DEG: u32 = 16;
+
+/// Highest number of solutions (as a power of 2) enumerated when samples leave init and final XOR values ambiguous.
+const MAX_AMBIGUITY_DEG: u32 = 16;
+
+/// A captured message along with its CRC.
+#[derive(Clone)]
+pub struct Sample {
+    pub message: Vec<u8>,
+    pub crc: u64,   // CRC value, as printed (most significant digit first)
+    pub width: u32, // CRC width in bits
+}
+
+/// Decode hexadecimal string `s` into bytes.
+pub(crate) fn decode_hex(s: &str) -> CRCResult<Vec<u8>> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if !s.len().is_multiple_of(2) {
+        return Err(Error::ParseError);
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 0x10).map_err(|_| Error::ParseError))
+        .collect()
+}
+
+/// Parse samples, one per line as `<message hex> <crc hex>`.
+/// The CRC width is given by the number of hex digits of the CRC.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn parse_samples<R>(reader: R) -> CRCResult<Vec<Sample>>
+where
+    R: BufRead,
+{
+    let mut samples = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(message), Some(crc), None) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(Error::ParseError);
+        };
+        let crc = crc.strip_prefix("0x").unwrap_or(crc);
+        let width = 4 * crc.len() as u32;
+        if width == 0 || width > 64 {
+            return Err(Error::ParseError);
+        }
+        samples.push(Sample {
+            message: decode_hex(message)?,
+            crc: u64::from_str_radix(crc, 0x10).map_err(|_| Error::ParseError)?,
+            width,
+        });
+    }
+    Ok(samples)
+}
+
+/// CRC model recovered from samples. Unknown parameters are left to `None`.
+pub struct RecoveredModel {
+    pub width: u32,
+    pub poly: u64, // Generator polynomial in normal representation, highest term stripped
+    pub init: Option<u64>,
+    pub refin: bool,
+    pub refout: bool,
+    pub xorout: Option<u64>,
+}
+
+impl RecoveredModel {
+    /// Full CRC properties, if all parameters were recovered.
+    pub fn props(&self) -> Option<CRCProperties> {
+        Some(CRCProperties {
+            width: self.width,
+            g: self.poly,
+            i: self.init?,
+            f: self.xorout?,
+            refin: self.refin,
+            refout: self.refout,
+        })
+    }
+}
+
+impl From<CRCProperties> for RecoveredModel {
+    fn from(props: CRCProperties) -> Self {
+        Self {
+            width: props.width,
+            poly: props.g,
+            init: Some(props.i),
+            refin: props.refin,
+            refout: props.refout,
+            xorout: Some(props.f),
+        }
+    }
+}
+
+/// Display model in Rocksoft notation.
+impl Display for RecoveredModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.width.div_ceil(4) as usize;
+        let hex = |val: Option<u64>| match val {
+            Some(val) => format!("0x{:0digits$x}", val),
+            None => "?".to_string(),
+        };
+        write!(
+            f,
+            "width={}  poly={}  init={}  refin={}  refout={}  xorout={}",
+            self.width,
+            hex(Some(self.poly)),
+            hex(self.init),
+            self.refin,
+            self.refout,
+            hex(self.xorout)
+        )
+    }
+}
+
+/// Polynomial of the register value `crc` before output reflection, as seen in F2[X].
+fn crc_polynomial(crc: u64, width: u32, refout: bool) -> Polynomial<Unbounded> {
+    match refout {
+        true => Polynomial::from_normal(reverse_bits(crc, width)),
+        false => Polynomial::from_normal(crc),
+    }
+}
+
+/// Compute GCD of all the `(A + B) X^N + C_A + C_B` polynomials, for each pair of same-length samples.
+/// Init and final XOR both cancel out in these sums, so that the generator divides all of them.
+/// Returns `None` if samples do not contain any useful pair.
+fn samples_gcd(samples: &[Sample], refin: bool, refout: bool) -> Option<Polynomial<Unbounded>> {
+    let mut groups = BTreeMap::<usize, Vec<&Sample>>::new();
+    for sample in samples {
+        groups.entry(sample.message.len()).or_default().push(sample);
+    }
+
+    let mut gcd = Polynomial::from_normal(0);
+    for group in groups.values() {
+        let first = group[0];
+        let a = Polynomial::from_bytes(&first.message, refin);
+        for other in &group[1..] {
+            let b = Polynomial::from_bytes(&other.message, refin);
+            let c = crc_polynomial(first.crc ^ other.crc, first.width, refout);
+            let d = (a.clone() + b).shl(first.width) + c;
+            if !d.is_zero() {
+                gcd = gcd.gcd(d);
+            }
+        }
+    }
+    if gcd.is_zero() {
+        return None;
+    }
+
+    // Generators have a constant term, strip all X factors
+    let x = Polynomial::from_normal(2);
+    loop {
+        let (q, r) = gcd.div_rem(&x);
+        if !r.is_zero() {
+            break;
+        }
+        gcd = q;
+    }
+    Some(gcd)
+}
+
+/// List the divisors of degree `width` of `gcd`, assuming the spurious common factor has low degree.
+fn generator_candidates(gcd: Polynomial<Unbounded>, width: u32) -> Vec<Polynomial<Unbounded>> {
+    let deg = gcd.deg();
+    if deg < width || deg - width > MAX_COFACTOR_DEG {
+        return Vec::new();
+    }
+    if deg == width {
+        return vec![gcd];
+    }
+
+    // Try all cofactors with a constant term
+    let cofactor_deg = deg - width;
+    ((1u64 << cofactor_deg) | 1..1u64 << (cofactor_deg + 1))
+        .step_by(2)
+        .filter_map(|q| {
+            let (g, r) = gcd.div_rem(&Polynomial::from_normal(q));
+            r.is_zero().then_some(g)
+        })
+        .collect()
+}
+
+/// Recover candidate generator polynomials from `samples`, trying all reflection settings.
+/// At least two samples of the same length are required.
+pub fn recover_generators(samples: &[Sample]) -> CRCResult<Vec<RecoveredModel>> {
+    let width = samples.first().ok_or(Error::InsufficientDataError)?.width;
+    if samples.iter().any(|sample| sample.width != width) {
+        return Err(Error::ParseError);
+    }
+
+    let mut models = Vec::new();
+    let mut found_pairs = false;
+    for (refin, refout) in [(false, false), (true, true), (false, true), (true, false)] {
+        let Some(gcd) = samples_gcd(samples, refin, refout) else {
+            continue;
+        };
+        found_pairs = true;
+        let xn = Polynomial::from_normal(1).shl(width);
+        let mut polys = generator_candidates(gcd, width)
+            .into_iter()
+            .map(|g| (g + xn.clone()).to_normal())
+            .collect::<CRCResult<Vec<u64>>>()?;
+        polys.sort();
+        models.extend(polys.into_iter().map(|poly| RecoveredModel {
+            width,
+            poly,
+            init: None,
+            refin,
+            refout,
+            xorout: None,
+        }));
+    }
+
+    if !found_pairs {
+        return Err(Error::InsufficientDataError);
+    }
+    Ok(models)
+}
+
+/// Pick the most likely of several init and final XOR `solutions`: the only one from the catalogue,
+/// or else the only one with conventional values (all zeros or all ones).
+/// Ambiguity is unavoidable for generators divisible by X + 1, since (I + I') (X^8 + 1) = 0 mod G makes I and I'
+/// interchangeable for messages of any length, with final XOR adjusted accordingly.
+fn pick_solution(solutions: &[CRCProperties]) -> Option<CRCProperties> {
+    let pick = |f: &dyn Fn(&CRCProperties) -> bool| match solutions
+        .iter()
+        .filter(|props| f(props))
+        .collect::<Vec<_>>()[..]
+    {
+        [props] => Some(*props),
+        _ => None,
+    };
+    let conventional = |props: &CRCProperties| {
+        let mask = u64::MAX >> (64 - props.width);
+        [0, mask].contains(&props.i) && [0, mask].contains(&props.f)
+    };
+    pick(&|_| true)
+        .or_else(|| pick(&|props| CATALOGUE.iter().any(|model| model.props == *props)))
+        .or_else(|| pick(&conventional))
+}
+
+/// Recover candidate models from `samples`.
+/// Models are completed with init and final XOR values when samples allow to solve them unambiguously.
+pub fn recover_models(samples: &[Sample]) -> CRCResult<Vec<RecoveredModel>> {
+    let mut models = recover_generators(samples)?;
+    for model in models.iter_mut() {
+        let solutions = solve_affine(samples, model.width, model.poly, model.refin, model.refout);
+        if let Some(props) = solutions.ok().and_then(|s| pick_solution(&s)) {
+            model.init = Some(props.i);
+            model.xorout = Some(props.f);
+        }
+    }
+    Ok(models)
+}
+
+/// Solve init and final XOR values of a CRC32 with generator `g` and reflection settings `refin` and `refout`.
+/// Since CRC(M) = M X^N + I X^8L + F mod G, samples of at least two different lengths are needed to tell I and F apart.
+/// Returns all properties consistent with `samples`: several of them means that samples leave I and F ambiguous,
+/// and none that the generator or reflection settings are wrong.
+pub fn solve_init_xorout(
+    samples: &[Sample],
+    g: u32,
+    refin: bool,
+    refout: bool,
+) -> CRCResult<Vec<CRC32Properties>> {
+    if samples.iter().any(|sample| sample.width != 32) {
+        return Err(Error::ParseError);
+    }
+
+    // Null init and final XOR, no output reflection: CRC is M X^N mod G in reverse representation
+    let crc = CRC32::new(CRC32Properties {
+        g,
+        i: 0,
+        f: 0,
+        refin,
+        refout: true,
+    })?;
+    let xn = Polynomial::from(PolynomialRepr::Normal(1u64 << 32));
+    let generator = xn + Polynomial::from(PolynomialRepr::Normal(g)).into();
+    let x = Polynomial::from(PolynomialRepr::Normal(2u64));
+
+    // For each sample, compute the pair (X^8L, M X^N + C) so that M X^N + C = I X^8L + F
+    let mut terms = BTreeMap::<usize, Vec<(Polynomial<u32>, Polynomial<u32>)>>::new();
+    for sample in samples {
+        let len = sample.message.len();
+        let xl = x.pow(8 * len as u64, generator).try_into()?;
+        let raw = crc.checksum(sample.message.bytes())?;
+        let c = match refout {
+            true => sample.crc as u32,
+            false => reverse_u32(sample.crc as u32),
+        };
+        let y = Polynomial::from(PolynomialRepr::Reverse(raw ^ c));
+        terms.entry(len).or_default().push((xl, y));
+    }
+
+    // Pick two lengths for which X^8La + X^8Lb shares the fewest factors with G
+    let mut best: Option<(usize, usize, Polynomial<u64>)> = None;
+    for (a, (xa, _)) in terms.iter().map(|(len, t)| (len, t[0])) {
+        for (b, (xb, _)) in terms.range(a + 1..).map(|(len, t)| (len, t[0])) {
+            let h = Polynomial::<u64>::from(xa + xb).gcd(generator);
+            if best.is_none_or(|(_, _, best_h)| h.deg() < best_h.deg()) {
+                best = Some((*a, *b, h));
+            }
+        }
+    }
+    let Some((a, b, h)) = best else {
+        return Err(Error::InsufficientDataError);
+    };
+    if h.deg() > MAX_AMBIGUITY_DEG {
+        return Err(Error::InsufficientDataError);
+    }
+
+    // Solve I (X^8La + X^8Lb) = Ya + Yb mod G
+    // Dividing everything by H = gcd(X^8La + X^8Lb, G) leaves 2^deg(H) solutions mod G
+    let (xa, ya) = terms[&a][0];
+    let (xb, yb) = terms[&b][0];
+    let d = Polynomial::<u64>::from(xa + xb);
+    let y = Polynomial::<u64>::from(ya + yb);
+    if (y % h).repr() != 0 {
+        return Ok(Vec::new());
+    }
+    let gh = generator / h;
+    let i0: Polynomial<u64> = (y / h) * (d / h).inv_mod(gh)? % gh;
+
+    let mut solutions = Vec::new();
+    for k in 0..1u64 << h.deg() {
+        let i: Polynomial<u32> =
+            (i0 + Polynomial::from(PolynomialRepr::Normal(k)) * gh % generator).try_into()?;
+        let f = ya + (i * xa % generator).try_into()?;
+        let consistent = terms.values().flatten().all(|(xl, y)| {
+            Polynomial::<u32>::try_from(i * *xl % generator).is_ok_and(|ixl| ixl + f == *y)
+        });
+        if consistent {
+            solutions.push(CRC32Properties {
+                g,
+                i: reverse_u32(i.repr()),
+                f: match refout {
+                    true => f.repr(),
+                    false => reverse_u32(f.repr()),
+                },
+                refin,
+                refout,
+            });
+        }
+    }
+    Ok(solutions)
+}
+
+/// Solve init and final XOR values of a CRC of any width with generator `g` and reflection settings `refin` and `refout`.
+/// CRC(M) = M X^N + I X^8L + F mod G is affine in the bits of I and F: each sample gives N linear equations over F2,
+/// which are solved by Gaussian elimination.
+/// Returns all properties consistent with `samples`, as `solve_init_xorout` does, which solves CRC32 ones.
+pub fn solve_affine(
+    samples: &[Sample],
+    width: u32,
+    g: u64,
+    refin: bool,
+    refout: bool,
+) -> CRCResult<Vec<CRCProperties>> {
+    if samples.iter().any(|sample| sample.width != width) {
+        return Err(Error::ParseError);
+    }
+    if width == 32 {
+        let solutions = solve_init_xorout(samples, u32::try_from(g)?, refin, refout)?;
+        return Ok(solutions.into_iter().map(CRCProperties::from).collect());
+    }
+
+    // Null init and final XOR, no output reflection: CRC is the register of M X^N mod G
+    let crc = CRC::new(CRCProperties {
+        width,
+        g,
+        i: 0,
+        f: 0,
+        refin,
+        refout: true,
+    })?;
+
+    // Unknowns are the bits of registers I (bits 0 to N-1) and F (bits N to 2N-1).
+    // Equations are kept in echelon form, indexed by their lowest unknown, along with their right-hand side.
+    let mut basis: Vec<Option<(u128, bool)>> = vec![None; 2 * width as usize];
+    let mut columns = BTreeMap::<usize, Vec<u64>>::new(); // Registers of X^k X^8L for each length L
+    for sample in samples {
+        let len = sample.message.len();
+        let columns = columns.entry(len).or_insert_with(|| {
+            let xl = crc.x8n(len);
+            (0..width).map(|k| crc.multiply(1 << k, xl)).collect()
+        });
+        let c = match refout {
+            true => sample.crc,
+            false => reverse_bits(sample.crc, width),
+        };
+        let y = crc.checksum(sample.message.bytes())? ^ c;
+
+        // Bit j of the register gives equation (I X^8L)_j + F_j = Y_j
+        for j in 0..width {
+            let mut row = columns
+                .iter()
+                .enumerate()
+                .filter(|(_, column)| (*column >> j) & 1 == 1)
+                .fold(1u128 << (width + j), |row, (k, _)| row | (1 << k));
+            let mut rhs = (y >> j) & 1 == 1;
+            while row != 0 {
+                let pivot = row.trailing_zeros() as usize;
+                match basis[pivot] {
+                    Some((r, b)) => {
+                        row ^= r;
+                        rhs ^= b;
+                    }
+                    None => {
+                        basis[pivot] = Some((row, rhs));
+                        break;
+                    }
+                }
+            }
+            if row == 0 && rhs {
+                return Ok(Vec::new());
+            }
+        }
+    }
+
+    // Enumerate values of free unknowns, then solve other ones from the highest
+    let free = (0..basis.len())
+        .filter(|p| basis[*p].is_none())
+        .collect::<Vec<_>>();
+    if free.len() as u32 > MAX_AMBIGUITY_DEG {
+        return Err(Error::InsufficientDataError);
+    }
+    let mask = u64::MAX >> (64 - width);
+    let mut solutions = Vec::new();
+    for k in 0..1u64 << free.len() {
+        let mut x = free
+            .iter()
+            .enumerate()
+            .filter(|(b, _)| (k >> b) & 1 == 1)
+            .fold(0u128, |x, (_, p)| x | (1 << p));
+        for (p, equation) in basis.iter().enumerate().rev() {
+            if let Some((row, rhs)) = equation
+                && *rhs ^ ((row & x).count_ones() & 1 == 1)
+            {
+                x |= 1 << p;
+            }
+        }
+        let (i, f) = (x as u64 & mask, (x >> width) as u64 & mask);
+        solutions.push(CRCProperties {
+            width,
+            g,
+            i: reverse_bits(i, width),
+            f: match refout {
+                true => f,
+                false => reverse_bits(f, width),
+            },
+            refin,
+            refout,
+        });
+    }
+    Ok(solutions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::CRC32Properties;
+    use crate::generic::CRCProperties;
+    use crate::math::reverse_bits;
+    use crate::recover::{
+        Sample, parse_samples, recover_generators, recover_models, solve_affine, solve_init_xorout,
+    };
+
+    /// Bitwise reference CRC implementation, used to generate samples.
+    fn reference_crc(
+        data: &[u8],
+        width: u32,
+        poly: u64,
+        init: u64,
+        refin: bool,
+        xorout: u64,
+    ) -> u64 {
+        let top = 1u64 << (width - 1);
+        let mask = top | (top - 1);
+        let mut reg = init;
+        for b in data {
+            let b = if refin { b.reverse_bits() } else { *b };
+            for i in (0..8).rev() {
+                let bit = ((reg & top) != 0) ^ ((b >> i) & 1 == 1);
+                reg = (reg << 1) & mask;
+                if bit {
+                    reg ^= poly;
+                }
+            }
+        }
+        if refin {
+            reg = reverse_bits(reg, width);
+        }
+        reg ^ xorout
+    }
+
+    fn samples(width: u32, poly: u64, init: u64, refin: bool, xorout: u64) -> Vec<Sample> {
+        [
+            &b"lorem ipsum"[..],
+            b"dolor sit a",
+            b"consectetur",
+            b"adipiscing",
+            b"elit",
+        ]
+        .into_iter()
+        .map(|message| Sample {
+            message: message.to_vec(),
+            crc: reference_crc(message, width, poly, init, refin, xorout),
+            width,
+        })
+        .collect()
+    }
+
+    #[test]
+    pub fn test_reference_crc() {
+        assert_eq!(
+            reference_crc(b"123456789", 32, 0x04c11db7, 0xffffffff, true, 0xffffffff),
+            0xcbf43926
+        );
+        assert_eq!(reference_crc(b"123456789", 16, 0x1021, 0, false, 0), 0x31c3);
+    }
+
+    #[test]
+    pub fn test_recover_crc32() {
+        let models = recover_generators(&samples(32, 0x04c11db7, 0xffffffff, true, 0xffffffff))
+            .expect("Failed to recover generator");
+        assert!(
+            models
+                .iter()
+                .any(|m| m.poly == 0x04c11db7 && m.refin && m.refout)
+        );
+        assert_eq!(
+            models[0].to_string(),
+            "width=32  poly=0x04c11db7  init=?  refin=true  refout=true  xorout=?"
+        );
+    }
+
+    #[test]
+    pub fn test_recover_crc16() {
+        let models = recover_generators(&samples(16, 0x1021, 0x1d0f, false, 0))
+            .expect("Failed to recover generator");
+        assert!(
+            models
+                .iter()
+                .any(|m| m.poly == 0x1021 && !m.refin && !m.refout)
+        );
+    }
+
+    #[test]
+    pub fn test_recover_no_pair() {
+        let mut samples = samples(32, 0x04c11db7, 0xffffffff, true, 0xffffffff);
+        samples.truncate(1);
+        assert!(recover_generators(&samples).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_samples() {
+        let samples = parse_samples(&b"# comment\n\n313233 0x1234\n00ff cbf43926\n"[..])
+            .expect("Failed to parse samples");
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].message, b"123");
+        assert_eq!((samples[0].crc, samples[0].width), (0x1234, 16));
+        assert_eq!((samples[1].crc, samples[1].width), (0xcbf43926, 32));
+        assert!(parse_samples(&b"31323 1234\n"[..]).is_err());
+    }
+
+    #[test]
+    pub fn test_solve_crc32() {
+        let samples = samples(32, 0x04c11db7, 0xffffffff, true, 0xffffffff);
+        let solutions =
+            solve_init_xorout(&samples, 0x04c11db7, true, true).expect("Failed to solve");
+        assert_eq!(solutions, [CRC32Properties::default()]);
+    }
+
+    #[test]
+    pub fn test_solve_unreflected() {
+        let samples = samples(32, 0x04c11db7, 0x12345678, false, 0xabcdef01);
+        let solutions =
+            solve_init_xorout(&samples, 0x04c11db7, false, false).expect("Failed to solve");
+        assert_eq!(
+            solutions,
+            [CRC32Properties {
+                g: 0x04c11db7,
+                i: 0x12345678,
+                f: 0xabcdef01,
+                refin: false,
+                refout: false,
+            }]
+        );
+        let solutions =
+            solve_init_xorout(&samples, 0x04c11db7, true, true).expect("Failed to solve");
+        assert!(solutions.is_empty());
+    }
+
+    #[test]
+    pub fn test_solve_ambiguous() {
+        // CRC-32Q generator is divisible by X + 1, as is X^8La + X^8Lb
+        let samples = samples(32, 0x814141ab, 0, false, 0);
+        let solutions =
+            solve_init_xorout(&samples, 0x814141ab, false, false).expect("Failed to solve");
+        assert_eq!(solutions.len(), 2);
+        assert!(solutions.iter().any(|props| props.i == 0 && props.f == 0));
+    }
+
+    #[test]
+    pub fn test_solve_same_length() {
+        let mut samples = samples(32, 0x04c11db7, 0xffffffff, true, 0xffffffff);
+        samples.truncate(3);
+        assert!(solve_init_xorout(&samples, 0x04c11db7, true, true).is_err());
+    }
+
+    #[test]
+    pub fn test_solve_affine() {
+        let narrow = samples(16, 0x8bb7, 0x1234, false, 0x5678);
+        let solutions = solve_affine(&narrow, 16, 0x8bb7, false, false).expect("Failed to solve");
+        assert_eq!(
+            solutions,
+            [CRCProperties {
+                width: 16,
+                g: 0x8bb7,
+                i: 0x1234,
+                f: 0x5678,
+                refin: false,
+                refout: false,
+            }]
+        );
+
+        // CRC-16/GENIBUS generator is divisible by X + 1, so that init and xorout can never be told apart
+        let narrow = samples(16, 0x1021, 0xffff, false, 0xffff);
+        let solutions = solve_affine(&narrow, 16, 0x1021, false, false).expect("Failed to solve");
+        assert_eq!(solutions.len(), 2);
+        assert!(
+            solutions
+                .iter()
+                .any(|props| props.i == 0xffff && props.f == 0xffff)
+        );
+
+        // CRC-32/BZIP2, solved as CRC32
+        let crc32 = samples(32, 0x04c11db7, 0xffffffff, false, 0xffffffff);
+        let solutions =
+            solve_affine(&crc32, 32, 0x04c11db7, false, false).expect("Failed to solve");
+        assert!(
+            solutions
+                .iter()
+                .any(|props| props.i == 0xffffffff && props.f == 0xffffffff)
+        );
+
+        // CRC-64/XZ, wide registers
+        let wide = samples(64, 0x42f0e1eba9ea3693, u64::MAX, true, u64::MAX);
+        let solutions =
+            solve_affine(&wide, 64, 0x42f0e1eba9ea3693, true, true).expect("Failed to solve");
+        assert!(
+            solutions
+                .iter()
+                .any(|props| props.i == u64::MAX && props.f == u64::MAX)
+        );
+    }
+
+    #[test]
+    pub fn test_recover_models_crc16() {
+        let models = recover_models(&samples(16, 0x8005, 0xffff, true, 0))
+            .expect("Failed to recover models");
+        assert!(models.iter().any(|m| m.to_string()
+            == "width=16  poly=0x8005  init=0xffff  refin=true  refout=true  xorout=0x0000"));
+    }
+}
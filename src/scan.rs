@@ -0,0 +1,181 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::catalogue::Model;
+use crate::detect::Endianness;
+use crate::error::{CRCResult, Error};
+use crate::generic::CRC;
+
+/// CRC field found in data, along with the range it is computed over.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Location {
+    pub name: &'static str, // Catalogue name of the model
+    pub offset: usize,      // Offset of the CRC field
+    pub endianness: Endianness,
+    pub start: usize, // Start of the range, inclusive
+    pub end: usize,   // End of the range, exclusive
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "0x{:08x}  {}  {}  range 0x{:08x}..0x{:08x} ({} bytes)",
+            self.offset,
+            self.name,
+            self.endianness,
+            self.start,
+            self.end,
+            self.end - self.start
+        )
+    }
+}
+
+/// Find fields of `data` holding the CRC of some range of `data` under `model`.
+/// Fields and range starts are taken at offsets multiple of `align`.
+///
+/// Ranges are searched right before the field, from the start of data up to the field, or from after the field up to the
+/// end of data. Using prefix registers `P`, the register of range `[a, b)` is `P[b] + Q[a] X^8b` mod G where
+/// `Q[a] = (P[a] + I) X^-8a`: normalized start registers `Q` are indexed once, so that each field and range end only
+/// costs a lookup instead of a CRC computation over the whole range.
+/// Fields whose bytes are all equal, such as padding, are skipped.
+pub fn scan(data: &[u8], model: &Model, align: usize) -> CRCResult<Vec<Location>> {
+    if align == 0 {
+        return Err(Error::OutOfBoundsError);
+    }
+    let crc = CRC::new(model.props)?;
+    let size = (model.props.width / 8) as usize;
+    let n = data.len();
+    let p = crc.prefix_registers(data, 0)?;
+    let x8_inv = crc.x8_inv()?;
+
+    // Index normalized start registers, along with powers X^-8s
+    let mut xs_inv = Vec::with_capacity(n + 1);
+    let mut starts = HashMap::<u64, (usize, usize)>::new(); // First and last start for each register
+    let mut x_inv = crc.one();
+    for (s, p) in p.iter().enumerate() {
+        if s % align == 0 {
+            let q = crc.multiply(p ^ crc.init(), x_inv);
+            starts
+                .entry(q)
+                .and_modify(|(_, last)| *last = s)
+                .or_insert((s, s));
+        }
+        xs_inv.push(x_inv);
+        x_inv = crc.multiply(x_inv, x8_inv);
+    }
+
+    // Index registers of ranges starting at offset 0
+    let mut ends = HashMap::<u64, usize>::new(); // First end for each register
+    for (e, r) in crc
+        .prefix_registers(data, crc.init())?
+        .into_iter()
+        .enumerate()
+        .skip(1)
+    {
+        ends.entry(r).or_insert(e);
+    }
+
+    let mut locations = Vec::new();
+    if n < size {
+        return Ok(locations);
+    }
+    for offset in (0..n - size + 1).step_by(align) {
+        let field = &data[offset..offset + size];
+        if field.iter().all(|b| *b == field[0]) {
+            continue;
+        }
+        for endianness in Endianness::candidates(size) {
+            let r = crc.register(endianness.read(field));
+            let mut ranges = Vec::new();
+
+            // Range right before the field
+            if let Some((first, _)) = starts.get(&crc.multiply(r ^ p[offset], xs_inv[offset]))
+                && *first < offset
+            {
+                ranges.push((*first, offset));
+            }
+            // Range from the start of data, before the field
+            if let Some(end) = ends.get(&r)
+                && *end <= offset
+            {
+                ranges.push((0, *end));
+            }
+            // Range up to the end of data, after the field
+            if let Some((_, last)) = starts.get(&crc.multiply(r ^ p[n], xs_inv[n]))
+                && *last >= offset + size
+                && *last < n
+            {
+                ranges.push((*last, n));
+            }
+
+            ranges.dedup();
+            locations.extend(ranges.into_iter().map(|(start, end)| Location {
+                name: model.name,
+                offset,
+                endianness: *endianness,
+                start,
+                end,
+            }));
+        }
+    }
+    Ok(locations)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::catalogue::find;
+    use crate::detect::Endianness;
+    use crate::generic::CRC;
+    use crate::scan::{Location, scan};
+
+    fn blob() -> Vec<u8> {
+        (0..200u32).map(|i| (i * i * 7 + i / 3) as u8).collect()
+    }
+
+    #[test]
+    pub fn test_scan_trailer() {
+        let model = find("CRC-32/ISO-HDLC").unwrap();
+        let crc = CRC::new(model.props).unwrap();
+        let mut data = blob();
+        let c = crc.checksum(data[40..100].bytes()).unwrap() as u32;
+        data[100..104].copy_from_slice(&c.to_le_bytes());
+
+        let locations = scan(&data, model, 1).expect("Failed to scan");
+        assert!(locations.contains(&Location {
+            name: model.name,
+            offset: 100,
+            endianness: Endianness::Little,
+            start: 40,
+            end: 100,
+        }));
+    }
+
+    #[test]
+    pub fn test_scan_header() {
+        // Header CRC over the rest of data, and CRC of data start stored further
+        let model = find("CRC-16/XMODEM").unwrap();
+        let crc = CRC::new(model.props).unwrap();
+        let mut data = blob();
+        let c = crc.checksum(data[16..].bytes()).unwrap() as u16;
+        data[4..6].copy_from_slice(&c.to_be_bytes());
+        let c = crc.checksum(data[..8].bytes()).unwrap() as u16;
+        data[12..14].copy_from_slice(&c.to_le_bytes());
+
+        let locations = scan(&data, model, 2).expect("Failed to scan");
+        assert!(
+            locations
+                .iter()
+                .any(|l| (l.offset, l.start, l.end) == (4, 16, 200))
+        );
+        assert!(locations.iter().any(
+            |l| (l.offset, l.start, l.end) == (12, 0, 8) && l.endianness == Endianness::Little
+        ));
+    }
+
+    #[test]
+    pub fn test_scan_align() {
+        assert!(scan(&blob(), find("CRC-32").unwrap(), 0).is_err());
+    }
+}